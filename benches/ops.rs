@@ -1,5 +1,12 @@
 #![allow(elided_lifetimes_in_paths)]
 
+// The history-rewriting benchmarks write objects into `PATH_TO_REPO`.  Nothing references them,
+// so `git gc` cleans them up.  The repo needs `user.name` and `user.email` for the new commits.
+
+// Report allocations alongside timings, to keep hot paths like tree diffs from regressing
+#[global_allocator]
+static ALLOC: divan::AllocProfiler = divan::AllocProfiler::system();
+
 #[divan::bench]
 fn bench_get_changed_paths_between_trees(bencher: divan::Bencher) {
     let repo = get_repo();
@@ -19,6 +26,170 @@ fn bench_get_changed_paths_between_trees(bencher: divan::Bencher) {
     });
 }
 
+#[divan::bench(args = [1, 10, 100])]
+fn bench_get_changed_paths_between_ancestors(bencher: divan::Bencher, distance: usize) {
+    let repo = get_repo();
+    let commit = repo.head().unwrap().peel_to_commit().unwrap();
+    let mut ancestor = commit.clone();
+    for _ in 0..distance {
+        match ancestor.parent(0) {
+            Ok(parent) => ancestor = parent,
+            Err(_) => break,
+        }
+    }
+    let ancestor_tree = ancestor.tree().unwrap();
+    let commit_tree = commit.tree().unwrap();
+
+    bencher.bench_local(|| {
+        git2_ext::tree::get_changed_paths_between_trees(
+            &repo,
+            Some(&ancestor_tree),
+            Some(&commit_tree),
+        )
+        .unwrap()
+    });
+}
+
+const STRATEGIES: [git2_ext::tree::DiffStrategy; 3] = [
+    git2_ext::tree::DiffStrategy::Auto,
+    git2_ext::tree::DiffStrategy::TreeWalk,
+    git2_ext::tree::DiffStrategy::Diff,
+];
+
+#[divan::bench(args = STRATEGIES)]
+fn bench_diff_strategy_parent(bencher: divan::Bencher, strategy: git2_ext::tree::DiffStrategy) {
+    let repo = get_repo();
+    let commit = repo.head().unwrap().peel_to_commit().unwrap();
+    let parent_tree = commit.parent(0).unwrap().tree().unwrap();
+    let commit_tree = commit.tree().unwrap();
+
+    bencher.bench_local(|| {
+        git2_ext::tree::get_changed_paths_between_trees_with(
+            &repo,
+            Some(&parent_tree),
+            Some(&commit_tree),
+            None,
+            strategy,
+        )
+        .unwrap()
+    });
+}
+
+#[divan::bench(args = STRATEGIES)]
+fn bench_diff_strategy_whole_tree(bencher: divan::Bencher, strategy: git2_ext::tree::DiffStrategy) {
+    let repo = get_repo();
+    let tree = repo.head().unwrap().peel_to_tree().unwrap();
+
+    bencher.bench_local(|| {
+        git2_ext::tree::get_changed_paths_between_trees_with(
+            &repo,
+            None,
+            Some(&tree),
+            None,
+            strategy,
+        )
+        .unwrap()
+    });
+}
+
+#[divan::bench(args = STRATEGIES)]
+fn bench_diff_strategy_pathspec(bencher: divan::Bencher, strategy: git2_ext::tree::DiffStrategy) {
+    let repo = get_repo();
+    let commit = repo.head().unwrap().peel_to_commit().unwrap();
+    let parent_tree = commit.parent(0).unwrap().tree().unwrap();
+    let commit_tree = commit.tree().unwrap();
+    // Limit the diff to the first directory
+    let dir = commit_tree
+        .iter()
+        .find(|entry| entry.kind() == Some(git2::ObjectType::Tree))
+        .map(|entry| entry.name().unwrap().to_owned())
+        .unwrap_or_default();
+    let pathspec = git2_ext::tree::Pathspec::new([dir]);
+
+    bencher.bench_local(|| {
+        git2_ext::tree::get_changed_paths_between_trees_with(
+            &repo,
+            Some(&parent_tree),
+            Some(&commit_tree),
+            Some(&pathspec),
+            strategy,
+        )
+        .unwrap()
+    });
+}
+
+#[divan::bench]
+fn bench_cherry_pick(bencher: divan::Bencher) {
+    let repo = get_repo();
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    // A copy of the parent, so the commit has to be replayed but always applies cleanly
+    let onto_id =
+        git2_ext::ops::reword(&repo, head.parent_id(0).unwrap(), "Reworded", None).unwrap();
+
+    bencher.bench_local(|| git2_ext::ops::cherry_pick(&repo, onto_id, head.id(), None).unwrap());
+}
+
+#[divan::bench(args = [1, 10, 50])]
+fn bench_cherry_pick_stack(bencher: divan::Bencher, count: usize) {
+    let repo = get_repo();
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    let mut walk = repo.revwalk().unwrap();
+    walk.simplify_first_parent().unwrap();
+    walk.push(head.id()).unwrap();
+    let mut cherry_ids = walk.take(count).collect::<Result<Vec<_>, _>>().unwrap();
+    cherry_ids.reverse();
+    let base_id = repo
+        .find_commit(cherry_ids[0])
+        .unwrap()
+        .parent_id(0)
+        .unwrap();
+    // A copy of the base, so every commit has to be replayed but applies cleanly
+    let onto_id = git2_ext::ops::reword(&repo, base_id, "Reworded", None).unwrap();
+
+    bencher.bench_local(|| {
+        git2_ext::ops::cherry_pick_stack(&repo, onto_id, &cherry_ids, None).unwrap()
+    });
+}
+
+#[divan::bench]
+fn bench_squash(bencher: divan::Bencher) {
+    let repo = get_repo();
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    let into_id = head.parent_id(0).unwrap();
+
+    bencher.bench_local(|| git2_ext::ops::squash(&repo, head.id(), into_id, None).unwrap());
+}
+
+#[divan::bench]
+fn bench_reword(bencher: divan::Bencher) {
+    let repo = get_repo();
+    let head_id = repo.head().unwrap().target().unwrap();
+
+    bencher.bench_local(|| git2_ext::ops::reword(&repo, head_id, "Reworded", None).unwrap());
+}
+
+#[divan::bench(args = [10, 1_000, 10_000])]
+fn bench_rebuild_tree(bencher: divan::Bencher, count: usize) {
+    let repo = get_repo();
+    let tree = repo.head().unwrap().peel_to_tree().unwrap();
+    // Point paths at themselves, so the rebuilt tree is the same size every time
+    let mut entries = std::collections::HashMap::new();
+    tree.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+        if entries.len() == count {
+            return git2::TreeWalkResult::Abort;
+        }
+        if entry.kind() == Some(git2::ObjectType::Blob) {
+            let path = std::path::Path::new(dir).join(entry.name().unwrap());
+            entries.insert(path, Some((entry.id(), entry.filemode_raw())));
+        }
+        git2::TreeWalkResult::Ok
+    })
+    .unwrap();
+
+    bencher
+        .bench_local(|| git2_ext::tree::rebuild_tree(&repo, Some(&tree), entries.clone()).unwrap());
+}
+
 fn get_repo() -> git2::Repository {
     let repo_dir =
         std::env::var("PATH_TO_REPO").expect("`PATH_TO_REPO` environment variable not set");
@@ -0,0 +1,657 @@
+use super::Askpass;
+use super::Credential;
+use super::CredentialCache;
+use super::CredentialHelper;
+use super::CredentialHelpers;
+use super::EnvCredentials;
+use super::KnownHosts;
+use super::Prompter;
+
+/// How [`callbacks`] authenticates
+#[derive(Clone)]
+pub struct AuthConfig {
+    ssh_agent: bool,
+    ssh_keys: Option<Vec<std::path::PathBuf>>,
+    credential_helpers: bool,
+    custom_helpers: Vec<std::sync::Arc<dyn CredentialHelper + Send + Sync>>,
+    env_credentials: bool,
+    cache: Option<CredentialCache>,
+    prompter: Option<std::sync::Arc<dyn Prompter + Send + Sync>>,
+    max_attempts: usize,
+    known_hosts: Option<KnownHosts>,
+}
+
+impl AuthConfig {
+    pub fn new() -> Self {
+        Self {
+            ssh_agent: true,
+            ssh_keys: None,
+            credential_helpers: true,
+            custom_helpers: Vec::new(),
+            env_credentials: true,
+            cache: None,
+            prompter: None,
+            max_attempts: 8,
+            known_hosts: None,
+        }
+    }
+
+    /// Whether to ask `ssh-agent` for SSH keys
+    pub fn ssh_agent(mut self, yes: bool) -> Self {
+        self.ssh_agent = yes;
+        self
+    }
+
+    /// SSH private keys to try, in order
+    ///
+    /// Defaults to [`ssh_identities`][super::ssh_identities] for the remote's host.
+    pub fn ssh_keys(mut self, keys: impl IntoIterator<Item = std::path::PathBuf>) -> Self {
+        self.ssh_keys = Some(keys.into_iter().collect());
+        self
+    }
+
+    /// Whether to consult the configured `credential.helper`s
+    pub fn credential_helpers(mut self, yes: bool) -> Self {
+        self.credential_helpers = yes;
+        self
+    }
+
+    /// Consult `helper` after the configured `credential.helper`s
+    pub fn credential_helper(
+        mut self,
+        helper: impl CredentialHelper + Send + Sync + 'static,
+    ) -> Self {
+        self.custom_helpers.push(std::sync::Arc::new(helper));
+        self
+    }
+
+    /// Whether to fall back to [`EnvCredentials::defaults`] after the credential helpers
+    pub fn env_credentials(mut self, yes: bool) -> Self {
+        self.env_credentials = yes;
+        self
+    }
+
+    /// Share credentials between operations through `cache`
+    ///
+    /// The cache is checked before any credential helper is run.
+    pub fn cache(mut self, cache: CredentialCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// How to ask the user for usernames and passwords
+    ///
+    /// Defaults to the askpass program `git` would use, if any.
+    pub fn prompter(mut self, prompter: impl Prompter + Send + Sync + 'static) -> Self {
+        self.prompter = Some(std::sync::Arc::new(prompter));
+        self
+    }
+
+    /// Verify SSH host keys with `known_hosts`
+    ///
+    /// Otherwise, host keys are left to the transport.
+    pub fn known_hosts(mut self, known_hosts: KnownHosts) -> Self {
+        self.known_hosts = Some(known_hosts);
+        self
+    }
+
+    /// Give up after offering `max` credentials for one operation
+    ///
+    /// Defaults to 8.
+    pub fn max_attempts(mut self, max: usize) -> Self {
+        self.max_attempts = max;
+        self
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthConfig")
+            .field("ssh_agent", &self.ssh_agent)
+            .field("ssh_keys", &self.ssh_keys)
+            .field("credential_helpers", &self.credential_helpers)
+            .field("custom_helpers", &self.custom_helpers.len())
+            .field("env_credentials", &self.env_credentials)
+            .field("cache", &self.cache)
+            .field("prompter", &self.prompter.as_ref().map(|_| "<prompter>"))
+            .field("max_attempts", &self.max_attempts)
+            .field("known_hosts", &self.known_hosts)
+            .finish()
+    }
+}
+
+/// Create [`git2::RemoteCallbacks`] that authenticate like `git` does
+///
+/// Depending on what the remote accepts, this tries, in order:
+/// - For SSH, `ssh-agent` and then each SSH key
+/// - For HTTP(S), the credentials in the URL, `credential.helper`s, environment tokens, and then
+///   prompting
+/// - Default (e.g. NTLM / Negotiate) credentials
+///
+/// When a credential is rejected, the helpers (and the cache) are told to `erase` it.  After the
+/// helpers' credential is rejected, they are asked again for the rejected username before
+/// prompting for its password.  Each method is
+/// tried at most once and attempts are capped (see [`AuthConfig::max_attempts`]) so a rejected
+/// credential doesn't loop forever.
+///
+/// `url` is the remote being connected to, for checking SSH host keys on non-default ports.
+///
+/// To have the helpers `store` the credential the remote accepted, use
+/// [`callbacks_with_approval`].
+pub fn callbacks<'a>(
+    repo: &git2::Repository,
+    url: &str,
+    config: &AuthConfig,
+) -> Result<git2::RemoteCallbacks<'a>, git2::Error> {
+    callbacks_with_approval(repo, url, config).map(|(callbacks, _)| callbacks)
+}
+
+/// Like [`callbacks`], also returning the handle to [`CredentialApproval::approve`] the
+/// credential once the operation succeeds
+pub fn callbacks_with_approval<'a>(
+    repo: &git2::Repository,
+    url: &str,
+    config: &AuthConfig,
+) -> Result<(git2::RemoteCallbacks<'a>, CredentialApproval), git2::Error> {
+    let mut state = CredentialState::new(repo, config)?;
+    let approval = CredentialApproval {
+        pending: state.pending.clone(),
+    };
+    let mut callbacks = git2::RemoteCallbacks::new();
+    callbacks.credentials(move |url, username_from_url, allowed| {
+        state.next(url, username_from_url, allowed)
+    });
+    if let Some(known_hosts) = config.known_hosts.clone() {
+        let port = super::url::url_port(url);
+        callbacks
+            .certificate_check(move |cert, host| known_hosts.certificate_check(cert, host, port));
+    }
+    Ok((callbacks, approval))
+}
+
+/// The credential last offered by [`callbacks_with_approval`], awaiting the remote's verdict
+///
+/// Like `git`, a credential from the helpers or a prompt is only `store`d once the remote
+/// accepted it; call [`CredentialApproval::approve`] after the operation succeeded.
+#[derive(Clone, Debug, Default)]
+pub struct CredentialApproval {
+    pending: PendingCredential,
+}
+
+impl CredentialApproval {
+    /// Tell the credential helpers to `store` the accepted credential, if any was offered
+    pub fn approve(&self) -> Result<(), std::io::Error> {
+        let pending = self
+            .pending
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .take();
+        match pending {
+            Some((helpers, cred)) => helpers.store(&cred),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A credential pending acceptance, with the helpers to `store` or `erase` it through
+type PendingCredential = std::sync::Arc<std::sync::Mutex<Option<(CredentialHelpers, Credential)>>>;
+
+/// Where [`CredentialState`] is in `git`s sequence of credential sources
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Stage {
+    SshAgent,
+    SshKey(usize),
+    UrlPassword,
+    Helpers,
+    /// Re-ask the helpers, now knowing the username that was rejected
+    HelpersRetry,
+    Prompt,
+    Default,
+    Exhausted,
+}
+
+struct CredentialState {
+    git_config: git2::Config,
+    ssh_agent: bool,
+    ssh_keys: Option<Vec<std::path::PathBuf>>,
+    credential_helpers: bool,
+    custom_helpers: Vec<std::sync::Arc<dyn CredentialHelper + Send + Sync>>,
+    cache: Option<CredentialCache>,
+    prompter: Option<std::sync::Arc<dyn Prompter + Send + Sync>>,
+    max_attempts: usize,
+
+    stage: Stage,
+    attempts: usize,
+    tried_username: bool,
+    /// The username of the last rejected credential
+    rejected_username: Option<String>,
+    /// The last credential offered, pending acceptance
+    pending: PendingCredential,
+}
+
+impl CredentialState {
+    fn new(repo: &git2::Repository, config: &AuthConfig) -> Result<Self, git2::Error> {
+        let git_config = repo.config()?.snapshot()?;
+        let ssh_keys = config.ssh_keys.clone();
+        let mut custom_helpers = config.custom_helpers.clone();
+        if config.env_credentials {
+            custom_helpers.push(std::sync::Arc::new(EnvCredentials::defaults()));
+        }
+        let prompter = config.prompter.clone().or_else(|| {
+            Askpass::from_env(&git_config)
+                .map(|p| std::sync::Arc::new(p) as std::sync::Arc<dyn Prompter + Send + Sync>)
+        });
+        Ok(Self {
+            git_config,
+            ssh_agent: config.ssh_agent,
+            ssh_keys,
+            credential_helpers: config.credential_helpers,
+            custom_helpers,
+            cache: config.cache.clone(),
+            prompter,
+            max_attempts: config.max_attempts,
+
+            stage: Stage::SshAgent,
+            attempts: 0,
+            tried_username: false,
+            rejected_username: None,
+            pending: PendingCredential::default(),
+        })
+    }
+
+    fn next(
+        &mut self,
+        url: &str,
+        username_from_url: Option<&str>,
+        allowed: git2::CredentialType,
+    ) -> Result<git2::Cred, git2::Error> {
+        // Being called again means the last credential was rejected
+        let rejected = self
+            .pending
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .take();
+        if let Some((helpers, cred)) = rejected {
+            if let Err(err) = helpers.erase(&cred) {
+                log::trace!("Failed to erase rejected credential: {err}");
+            }
+            let first_rejection = self.rejected_username.is_none();
+            if self.stage == Stage::Prompt && username_from_url.is_none() && first_rejection {
+                self.stage = Stage::HelpersRetry;
+            }
+            self.rejected_username = cred.username;
+        }
+
+        self.attempts += 1;
+        if self.max_attempts < self.attempts {
+            return Err(git2::Error::new(
+                git2::ErrorCode::Auth,
+                git2::ErrorClass::Net,
+                format!("too many authentication attempts for `{url}`"),
+            ));
+        }
+
+        if allowed.contains(git2::CredentialType::USERNAME) && !self.tried_username {
+            self.tried_username = true;
+            let username = self.username(username_from_url);
+            return git2::Cred::username(&username);
+        }
+
+        let ssh = allowed.contains(git2::CredentialType::SSH_KEY);
+        let userpass = allowed.contains(git2::CredentialType::USER_PASS_PLAINTEXT);
+        loop {
+            let attempt = match self.stage {
+                Stage::SshAgent => {
+                    self.stage = Stage::SshKey(0);
+                    if ssh && self.ssh_agent {
+                        self.try_ssh_agent(username_from_url)
+                    } else {
+                        None
+                    }
+                }
+                Stage::SshKey(index) => {
+                    if ssh {
+                        self.try_ssh_key(url, username_from_url, index)
+                    } else {
+                        self.stage = Stage::UrlPassword;
+                        None
+                    }
+                }
+                Stage::UrlPassword => {
+                    self.stage = Stage::Helpers;
+                    match Credential::from_url(url).filter(Credential::is_complete) {
+                        Some(cred) if userpass => Some(self.offer(
+                            url,
+                            cred.username.unwrap_or_default(),
+                            cred.password.unwrap_or_default(),
+                        )),
+                        _ => None,
+                    }
+                }
+                Stage::Helpers => {
+                    self.stage = Stage::Prompt;
+                    if userpass {
+                        self.try_helpers(url, username_from_url)
+                    } else {
+                        None
+                    }
+                }
+                Stage::HelpersRetry => {
+                    self.stage = Stage::Prompt;
+                    match self.rejected_username.clone() {
+                        Some(username) if userpass => self.try_helpers(url, Some(&username)),
+                        _ => None,
+                    }
+                }
+                Stage::Prompt => {
+                    self.stage = Stage::Default;
+                    if userpass {
+                        self.try_prompt(url, username_from_url)
+                    } else {
+                        None
+                    }
+                }
+                Stage::Default => {
+                    self.stage = Stage::Exhausted;
+                    if allowed.contains(git2::CredentialType::DEFAULT) {
+                        Some(git2::Cred::default())
+                    } else {
+                        None
+                    }
+                }
+                Stage::Exhausted => {
+                    return Err(git2::Error::new(
+                        git2::ErrorCode::Auth,
+                        git2::ErrorClass::Net,
+                        format!("no more credentials to try for `{url}`"),
+                    ));
+                }
+            };
+            if let Some(attempt) = attempt {
+                return attempt;
+            }
+        }
+    }
+
+    fn try_ssh_agent(
+        &mut self,
+        username_from_url: Option<&str>,
+    ) -> Option<Result<git2::Cred, git2::Error>> {
+        let username = self.username(username_from_url);
+        match git2::Cred::ssh_key_from_agent(&username) {
+            Ok(cred) => Some(Ok(cred)),
+            Err(err) => {
+                log::trace!("ssh-agent unavailable: {err}");
+                None
+            }
+        }
+    }
+
+    fn try_ssh_key(
+        &mut self,
+        url: &str,
+        username_from_url: Option<&str>,
+        index: usize,
+    ) -> Option<Result<git2::Cred, git2::Error>> {
+        let username = self.username(username_from_url);
+        let ssh_keys = self.ssh_keys.get_or_insert_with(|| {
+            let host = Credential::from_url(url)
+                .and_then(|cred| cred.host)
+                .unwrap_or_default();
+            let host = host.split(':').next().unwrap_or_default();
+            super::ssh_identities(&self.git_config, host)
+        });
+        let Some(key) = ssh_keys.get(index) else {
+            self.stage = Stage::UrlPassword;
+            return None;
+        };
+        self.stage = Stage::SshKey(index + 1);
+        match git2::Cred::ssh_key(&username, None, key, None) {
+            Ok(cred) => Some(Ok(cred)),
+            Err(err) => {
+                log::trace!("Skipping `{}`: {err}", key.display());
+                None
+            }
+        }
+    }
+
+    fn try_helpers(
+        &mut self,
+        url: &str,
+        username: Option<&str>,
+    ) -> Option<Result<git2::Cred, git2::Error>> {
+        let helpers = self.helpers(url)?;
+        let mut cred = helpers.credential_for(url)?;
+        if let Some(username) = username {
+            cred.username = Some(username.to_owned());
+        }
+        cred.password = None;
+        match helpers.get(&cred) {
+            Ok(cred) if cred.is_complete() => {
+                let result = to_userpass(&cred);
+                self.set_pending(helpers, cred);
+                Some(result)
+            }
+            Ok(_) => None,
+            Err(err) => {
+                log::trace!("Credential helpers failed: {err}");
+                None
+            }
+        }
+    }
+
+    fn try_prompt(
+        &mut self,
+        url: &str,
+        username_from_url: Option<&str>,
+    ) -> Option<Result<git2::Cred, git2::Error>> {
+        let prompter = self.prompter.as_ref()?;
+        let username = match username_from_url.or(self.rejected_username.as_deref()) {
+            Some(username) => username.to_owned(),
+            None => prompter.username(url)?,
+        };
+        let password = prompter.password(url, &username)?;
+        Some(self.offer(url, username, password))
+    }
+
+    /// Offer a credential from outside the helpers, which still `store` or `erase` it
+    fn offer(
+        &mut self,
+        url: &str,
+        username: String,
+        password: String,
+    ) -> Result<git2::Cred, git2::Error> {
+        let result = git2::Cred::userpass_plaintext(&username, &password);
+        if let Some(helpers) = self.helpers(url) {
+            // Match the context the helpers will look up
+            if let Some(mut cred) = helpers.credential_for(url) {
+                cred.username = Some(username);
+                cred.password = Some(password);
+                self.set_pending(helpers, cred);
+            }
+        }
+        result
+    }
+
+    /// The cache, `credential.helper`s, and custom helpers for `url`, in that order
+    fn helpers(&self, url: &str) -> Option<CredentialHelpers> {
+        let helpers = if self.credential_helpers {
+            CredentialHelpers::from_config(&self.git_config, url)
+        } else {
+            Ok(CredentialHelpers::new())
+        };
+        let helpers = match helpers {
+            Ok(helpers) => helpers,
+            Err(err) => {
+                log::trace!("Failed to read credential config: {err}");
+                return None;
+            }
+        };
+        let mut helpers = match &self.cache {
+            Some(cache) => helpers.cache(cache.clone()),
+            None => helpers,
+        };
+        for helper in &self.custom_helpers {
+            helpers = helpers.shared_helper(helper.clone());
+        }
+        Some(helpers)
+    }
+
+    fn set_pending(&self, helpers: CredentialHelpers, cred: Credential) {
+        *self
+            .pending
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = Some((helpers, cred));
+    }
+
+    fn username(&self, username_from_url: Option<&str>) -> String {
+        username_from_url
+            .map(ToOwned::to_owned)
+            .or_else(|| self.git_config.get_string("credential.username").ok())
+            .or_else(|| std::env::var("USER").ok())
+            .or_else(|| std::env::var("USERNAME").ok())
+            .unwrap_or_else(|| "git".to_owned())
+    }
+}
+
+fn to_userpass(cred: &Credential) -> Result<git2::Cred, git2::Error> {
+    git2::Cred::userpass_plaintext(
+        cred.username.as_deref().unwrap_or_default(),
+        cred.password.as_deref().unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[derive(Clone, Default)]
+    struct RecordingPrompter(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl Prompter for RecordingPrompter {
+        fn username(&self, url: &str) -> Option<String> {
+            self.0.lock().unwrap().push(format!("username {url}"));
+            Some("prompted".to_owned())
+        }
+
+        fn password(&self, url: &str, username: &str) -> Option<String> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("password {username} {url}"));
+            Some("hunter2".to_owned())
+        }
+    }
+
+    #[test]
+    fn test_credential_state_sequence() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let log = git.repo_path.join("helper.log");
+        git.run(&[
+            "config",
+            "credential.helper",
+            &format!(
+                "!f() {{ echo $1 >> '{}'; cat >/dev/null; [ $1 = get ] && echo username=helper && echo password=secret; true; }}; f",
+                log.display()
+            ),
+        ])?;
+        let repo = git.get_repo()?;
+        let prompter = RecordingPrompter::default();
+        let config = AuthConfig::new()
+            .ssh_agent(false)
+            .env_credentials(false)
+            .ssh_keys([])
+            .prompter(prompter.clone());
+        let mut state = CredentialState::new(&repo, &config)?;
+
+        let url = "https://example.com/repo.git";
+        let allowed = git2::CredentialType::USER_PASS_PLAINTEXT;
+        // helper, helper again with the rejected username, then prompt for its password
+        assert!(state.next(url, None, allowed).is_ok());
+        assert_eq!(state.stage, Stage::Prompt);
+        assert!(state.next(url, None, allowed).is_ok());
+        assert!(state.next(url, None, allowed).is_ok());
+        assert!(state.next(url, None, allowed).is_err());
+        assert_eq!(
+            std::fs::read_to_string(&log)?,
+            "get\nerase\nget\nerase\nerase\n"
+        );
+        assert_eq!(
+            *prompter.0.lock().unwrap(),
+            ["password helper https://example.com/repo.git"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_credential_state_caches_accepted() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let cache = CredentialCache::default();
+        let config = AuthConfig::new()
+            .ssh_agent(false)
+            .credential_helpers(false)
+            .env_credentials(false)
+            .ssh_keys([])
+            .cache(cache.clone())
+            .prompter(RecordingPrompter::default());
+        let url = "https://example.com/repo.git";
+        let context = Credential::from_url("https://example.com").unwrap();
+        let allowed = git2::CredentialType::USER_PASS_PLAINTEXT;
+
+        // Only cached once the remote accepted it
+        let mut state = CredentialState::new(&repo, &config)?;
+        let approval = CredentialApproval {
+            pending: state.pending.clone(),
+        };
+        assert!(state.next(url, None, allowed).is_ok());
+        assert_eq!(cache.lookup(&context), None);
+        approval.approve()?;
+        let cached = cache.lookup(&context).unwrap();
+        assert_eq!(cached.password.as_deref(), Some("hunter2"));
+
+        // Forgotten once the remote rejected it
+        let mut state = CredentialState::new(&repo, &config)?;
+        assert!(state.next(url, None, allowed).is_ok());
+        assert_eq!(state.stage, Stage::Prompt);
+        assert!(state.next(url, None, allowed).is_ok());
+        assert_eq!(cache.lookup(&context), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_credential_state_max_attempts() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let config = AuthConfig::new()
+            .ssh_agent(false)
+            .credential_helpers(false)
+            .env_credentials(false)
+            .ssh_keys([])
+            .max_attempts(1);
+        let mut state = CredentialState::new(&repo, &config)?;
+
+        let allowed = git2::CredentialType::USERNAME | git2::CredentialType::SSH_KEY;
+        assert!(state
+            .next("ssh://example.com/repo.git", None, allowed)
+            .is_ok());
+        let result = state.next("ssh://example.com/repo.git", None, allowed);
+        assert_eq!(result.err().map(|e| e.code()), Some(git2::ErrorCode::Auth));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+use super::Credential;
+use super::CredentialHelper;
+
+/// Credentials from environment variables, selected per host
+///
+/// [`EnvCredentials::defaults`] covers the variables commonly set in CI:
+/// - `GITHUB_TOKEN` / `GH_TOKEN` for `github.com`
+/// - `GITLAB_TOKEN` for `gitlab.com`
+/// - `GIT_USERNAME` / `GIT_PASSWORD` for any host
+#[derive(Clone, Debug)]
+pub struct EnvCredentials {
+    providers: Vec<EnvProvider>,
+    lookup: fn(&str) -> Option<String>,
+}
+
+#[derive(Clone, Debug)]
+struct EnvProvider {
+    host: String,
+    username: EnvUsername,
+    password_vars: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+enum EnvUsername {
+    Fixed(String),
+    Var(String),
+}
+
+impl EnvCredentials {
+    /// No providers; add them with [`EnvCredentials::token`] or [`EnvCredentials::user_pass`]
+    pub fn new() -> Self {
+        Self {
+            providers: Vec::new(),
+            lookup: |name| std::env::var(name).ok().filter(|v| !v.is_empty()),
+        }
+    }
+
+    /// Providers for common CI environment variables
+    pub fn defaults() -> Self {
+        Self::new()
+            .token("github.com", "x-access-token", ["GITHUB_TOKEN", "GH_TOKEN"])
+            .token("gitlab.com", "oauth2", ["GITLAB_TOKEN"])
+            .user_pass("*", "GIT_USERNAME", "GIT_PASSWORD")
+    }
+
+    /// Use the first of `vars` that is set as a token for `host`
+    ///
+    /// `host` may contain `*` wildcards.  Tokens are sent as the password for `username`, which
+    /// is how most forges accept them over HTTP(S).
+    pub fn token<'v>(
+        mut self,
+        host: impl Into<String>,
+        username: impl Into<String>,
+        vars: impl IntoIterator<Item = &'v str>,
+    ) -> Self {
+        self.providers.push(EnvProvider {
+            host: host.into(),
+            username: EnvUsername::Fixed(username.into()),
+            password_vars: vars.into_iter().map(ToOwned::to_owned).collect(),
+        });
+        self
+    }
+
+    /// Read the username and password for `host` from `username_var` and `password_var`
+    ///
+    /// `host` may contain `*` wildcards.
+    pub fn user_pass(
+        mut self,
+        host: impl Into<String>,
+        username_var: impl Into<String>,
+        password_var: impl Into<String>,
+    ) -> Self {
+        self.providers.push(EnvProvider {
+            host: host.into(),
+            username: EnvUsername::Var(username_var.into()),
+            password_vars: vec![password_var.into()],
+        });
+        self
+    }
+
+    fn lookup(&self, cred: &Credential) -> Option<Credential> {
+        let protocol = cred.protocol.as_deref().unwrap_or_default();
+        if !matches!(protocol, "http" | "https") {
+            return None;
+        }
+        let host = cred.host.as_deref().unwrap_or_default();
+        let host = host.split(':').next().unwrap_or_default();
+        self.providers
+            .iter()
+            .filter(|provider| crate::utils::wildcard_match(&provider.host, host, true))
+            .find_map(|provider| {
+                let password = provider
+                    .password_vars
+                    .iter()
+                    .find_map(|var| (self.lookup)(var))?;
+                let username = match &provider.username {
+                    EnvUsername::Fixed(username) => username.clone(),
+                    EnvUsername::Var(var) => (self.lookup)(var)?,
+                };
+                if cred.username.as_ref().is_some_and(|u| *u != username) {
+                    return None;
+                }
+                Some(Credential {
+                    username: Some(username),
+                    password: Some(password),
+                    ..Default::default()
+                })
+            })
+    }
+}
+
+impl Default for EnvCredentials {
+    fn default() -> Self {
+        Self::defaults()
+    }
+}
+
+impl CredentialHelper for EnvCredentials {
+    fn get(&self, cred: &Credential) -> Result<Credential, std::io::Error> {
+        Ok(self.lookup(cred).unwrap_or_default())
+    }
+
+    fn store(&self, _cred: &Credential) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+
+    fn erase(&self, _cred: &Credential) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_credentials() {
+        let mut env = EnvCredentials::defaults();
+        env.lookup = |name| match name {
+            "GH_TOKEN" => Some("gh".to_owned()),
+            "GIT_USERNAME" => Some("me".to_owned()),
+            "GIT_PASSWORD" => Some("pass".to_owned()),
+            _ => None,
+        };
+
+        let github = Credential::from_url("https://github.com/org/repo.git").unwrap();
+        let cred = env.lookup(&github).unwrap();
+        assert_eq!(cred.username.as_deref(), Some("x-access-token"));
+        assert_eq!(cred.password.as_deref(), Some("gh"));
+
+        let gitlab = Credential::from_url("https://gitlab.com/org/repo.git").unwrap();
+        let cred = env.lookup(&gitlab).unwrap();
+        assert_eq!(cred.username.as_deref(), Some("me"));
+        assert_eq!(cred.password.as_deref(), Some("pass"));
+
+        let other = Credential::from_url("https://other@example.com/repo.git").unwrap();
+        assert_eq!(env.lookup(&other), None);
+        let ssh = Credential::from_url("git@github.com:org/repo.git").unwrap();
+        assert_eq!(env.lookup(&ssh), None);
+    }
+}
@@ -0,0 +1,148 @@
+/// Extra HTTP headers to send to a remote
+///
+/// Like `git`, these come from `http.extraHeader` and `http.<url>.extraHeader`, letting
+/// header-based authentication (e.g. CI tokens) work with fetch and push.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct HttpHeaders {
+    headers: Vec<String>,
+}
+
+impl HttpHeaders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the `http.extraHeader`s that apply to `url`
+    ///
+    /// Like in config, an empty value clears the headers before it.
+    pub fn from_config(config: &git2::Config, url: &str) -> Result<Self, git2::Error> {
+        let target = super::Credential::from_url(url).unwrap_or_default();
+
+        let mut headers = Self::new();
+        let mut entries = config.entries(Some("^http\\..*extraheader$"))?;
+        while let Some(entry) = entries.next() {
+            let entry = entry?;
+            let (Some(name), Some(value)) = (entry.name(), entry.value()) else {
+                continue;
+            };
+            let Some(name) = name.strip_prefix("http.") else {
+                continue;
+            };
+            if let Some((pattern, _key)) = name.rsplit_once('.') {
+                if !super::url::context_matches(pattern, &target) {
+                    continue;
+                }
+            }
+            headers = headers.header(value);
+        }
+
+        Ok(headers)
+    }
+
+    /// Add a raw `Name: value` header
+    ///
+    /// An empty header clears the ones added before it.
+    pub fn header(mut self, header: impl Into<String>) -> Self {
+        let header = header.into();
+        if header.is_empty() {
+            self.headers.clear();
+        } else {
+            self.headers.push(header);
+        }
+        self
+    }
+
+    /// Authenticate with `Authorization: Bearer <token>`
+    pub fn bearer(self, token: &str) -> Self {
+        self.header(format!("Authorization: Bearer {token}"))
+    }
+
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    /// Send these headers when fetching
+    pub fn apply_fetch(&self, options: &mut git2::FetchOptions<'_>) {
+        options.custom_headers(&self.valid_headers());
+    }
+
+    /// Send these headers when pushing
+    pub fn apply_push(&self, options: &mut git2::PushOptions<'_>) {
+        options.custom_headers(&self.valid_headers());
+    }
+
+    fn valid_headers(&self) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|header| {
+                let valid = !header.contains(['\0', '\r', '\n']);
+                if !valid {
+                    log::warn!("Ignoring HTTP header with control characters");
+                }
+                valid
+            })
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+impl std::fmt::Debug for HttpHeaders {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Header values are usually secrets
+        let names: Vec<_> = self
+            .headers
+            .iter()
+            .map(|header| {
+                header
+                    .split_once(':')
+                    .map(|(name, _)| name)
+                    .unwrap_or(header)
+            })
+            .collect();
+        f.debug_struct("HttpHeaders")
+            .field("headers", &names)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_headers_from_config() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.run(&["config", "--add", "http.extraHeader", "X-Dropped: 1"])?;
+        git.run(&["config", "--add", "http.extraHeader", ""])?;
+        git.run(&["config", "--add", "http.extraHeader", "X-Global: 1"])?;
+        git.run(&[
+            "config",
+            "http.https://example.com/.extraHeader",
+            "X-Scoped: 1",
+        ])?;
+        git.run(&[
+            "config",
+            "http.https://other.com/.extraHeader",
+            "X-Other: 1",
+        ])?;
+
+        let repo = git.get_repo()?;
+        let config = repo.config()?;
+        let headers =
+            HttpHeaders::from_config(&config, "https://example.com/repo.git")?.bearer("secret");
+        assert_eq!(
+            headers.headers(),
+            ["X-Global: 1", "X-Scoped: 1", "Authorization: Bearer secret"]
+        );
+        assert!(!format!("{headers:?}").contains("secret"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+use super::Credential;
+
+/// An in-process credential helper
+///
+/// This lets applications put their own credential storage in the same chain as the configured
+/// `credential.helper`s.  See [`CredentialHelpers::custom_helper`].
+pub trait CredentialHelper {
+    /// Respond with what is known about `cred`, leaving unknown fields unset
+    fn get(&self, cred: &Credential) -> Result<Credential, std::io::Error>;
+
+    /// Save `cred`, which was accepted by the remote
+    fn store(&self, cred: &Credential) -> Result<(), std::io::Error>;
+
+    /// Forget `cred`, which was rejected by the remote
+    fn erase(&self, cred: &Credential) -> Result<(), std::io::Error>;
+}
+
+/// The `credential.helper`s configured for a URL
+///
+/// Helpers are run like `git` does: `!cmd` is a shell snippet, an absolute path is run as-is,
+/// and anything else is `git credential-<helper>`.
+#[derive(Clone, Debug, Default)]
+pub struct CredentialHelpers {
+    helpers: Vec<Helper>,
+    cache: Option<super::CredentialCache>,
+    username: Option<String>,
+    use_http_path: bool,
+}
+
+impl CredentialHelpers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a helper to the chain
+    ///
+    /// Like in config, an empty helper clears the ones added before it.
+    pub fn helper(mut self, helper: impl Into<String>) -> Self {
+        let helper = helper.into();
+        if helper.is_empty() {
+            self.helpers.clear();
+        } else {
+            self.helpers.push(Helper::Command(helper));
+        }
+        self
+    }
+
+    /// Add an in-process helper to the chain
+    pub fn custom_helper(self, helper: impl CredentialHelper + Send + Sync + 'static) -> Self {
+        self.shared_helper(std::sync::Arc::new(helper))
+    }
+
+    pub(crate) fn shared_helper(
+        mut self,
+        helper: std::sync::Arc<dyn CredentialHelper + Send + Sync>,
+    ) -> Self {
+        self.helpers.push(Helper::Custom(helper));
+        self
+    }
+
+    /// Check `cache` before running any helpers
+    ///
+    /// Credentials are remembered once they are [stored][CredentialHelpers::store], until they
+    /// are erased.
+    pub fn cache(mut self, cache: super::CredentialCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Read `credential.*` and `credential.<url>.*` config that applies to `url`
+    pub fn from_config(config: &git2::Config, url: &str) -> Result<Self, git2::Error> {
+        let target = Credential::from_url(url).unwrap_or_default();
+
+        let mut helpers = Self::new();
+        let mut entries = config.entries(Some("^credential\\."))?;
+        while let Some(entry) = entries.next() {
+            let entry = entry?;
+            let (Some(name), Some(value)) = (entry.name(), entry.value()) else {
+                continue;
+            };
+            let Some(name) = name.strip_prefix("credential.") else {
+                continue;
+            };
+            let key = match name.rsplit_once('.') {
+                Some((pattern, key)) => {
+                    if !super::url::context_matches(pattern, &target) {
+                        continue;
+                    }
+                    key
+                }
+                None => name,
+            };
+            match key.to_ascii_lowercase().as_str() {
+                "helper" => helpers = helpers.helper(value),
+                "username" => helpers.username = Some(value.to_owned()),
+                "usehttppath" => helpers.use_http_path = git2::Config::parse_bool(value)?,
+                _ => {}
+            }
+        }
+
+        Ok(helpers)
+    }
+
+    /// The `credential.helper` commands in the chain
+    pub fn helpers(&self) -> impl Iterator<Item = &str> + '_ {
+        self.helpers.iter().filter_map(|helper| match helper {
+            Helper::Command(command) => Some(command.as_str()),
+            Helper::Custom(_) => None,
+        })
+    }
+
+    /// Describe `url` to the helpers
+    ///
+    /// For HTTP(S), the path is dropped unless `credential.useHttpPath` is set and the
+    /// username defaults to `credential.username`.
+    pub fn credential_for(&self, url: &str) -> Option<Credential> {
+        let mut cred = Credential::from_url(url)?;
+        let is_http = matches!(cred.protocol.as_deref(), Some("http" | "https"));
+        if is_http && !self.use_http_path {
+            cred.path = None;
+        }
+        if cred.username.is_none() {
+            cred.username.clone_from(&self.username);
+        }
+        Some(cred)
+    }
+
+    /// Ask each helper in turn to fill in `cred` until it is complete
+    ///
+    /// A helper may stop the search early by responding with `quit=1`.
+    pub fn get(&self, cred: &Credential) -> Result<Credential, std::io::Error> {
+        let mut cred = cred.clone();
+        if let Some(cache) = &self.cache {
+            if !cred.is_complete() {
+                if let Some(mut cached) = cache.lookup(&cred) {
+                    cached.merge(cred);
+                    return Ok(cached);
+                }
+            }
+        }
+        for helper in &self.helpers {
+            if cred.is_complete() {
+                break;
+            }
+            let (mut response, quit) = match helper {
+                Helper::Command(command) => {
+                    let output = run_helper(command, "get", &cred)?;
+                    let quit = output
+                        .lines()
+                        .take_while(|l| !l.is_empty())
+                        .any(|l| l == "quit=1" || l == "quit=true");
+                    (Credential::parse(&output), quit)
+                }
+                Helper::Custom(helper) => (helper.get(&cred)?, false),
+            };
+            // Helpers respond with the full credential, so their answer takes precedence
+            response.merge(cred);
+            cred = response;
+            if quit {
+                break;
+            }
+        }
+        Ok(cred)
+    }
+
+    /// Like [`CredentialHelpers::get`] but without blocking an async task, see
+    /// [`crate::utils::spawn_blocking`]
+    #[cfg(feature = "tokio")]
+    pub fn get_async(
+        &self,
+        cred: &Credential,
+    ) -> crate::utils::Blocking<Result<Credential, std::io::Error>> {
+        let (helpers, cred) = (self.clone(), cred.clone());
+        crate::utils::spawn_blocking(move || helpers.get(&cred))
+    }
+
+    /// Tell all helpers that `cred` was accepted
+    pub fn store(&self, cred: &Credential) -> Result<(), std::io::Error> {
+        if !cred.is_complete() {
+            return Ok(());
+        }
+        if let Some(cache) = &self.cache {
+            cache.insert(cred);
+        }
+        for helper in &self.helpers {
+            match helper {
+                Helper::Command(command) => {
+                    run_helper(command, "store", cred)?;
+                }
+                Helper::Custom(helper) => helper.store(cred)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Like [`CredentialHelpers::store`] but without blocking an async task
+    #[cfg(feature = "tokio")]
+    pub fn store_async(
+        &self,
+        cred: &Credential,
+    ) -> crate::utils::Blocking<Result<(), std::io::Error>> {
+        let (helpers, cred) = (self.clone(), cred.clone());
+        crate::utils::spawn_blocking(move || helpers.store(&cred))
+    }
+
+    /// Tell all helpers that `cred` was rejected
+    pub fn erase(&self, cred: &Credential) -> Result<(), std::io::Error> {
+        if let Some(cache) = &self.cache {
+            cache.remove(cred);
+        }
+        for helper in &self.helpers {
+            match helper {
+                Helper::Command(command) => {
+                    run_helper(command, "erase", cred)?;
+                }
+                Helper::Custom(helper) => helper.erase(cred)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Like [`CredentialHelpers::erase`] but without blocking an async task
+    #[cfg(feature = "tokio")]
+    pub fn erase_async(
+        &self,
+        cred: &Credential,
+    ) -> crate::utils::Blocking<Result<(), std::io::Error>> {
+        let (helpers, cred) = (self.clone(), cred.clone());
+        crate::utils::spawn_blocking(move || helpers.erase(&cred))
+    }
+}
+
+#[derive(Clone)]
+enum Helper {
+    Command(String),
+    Custom(std::sync::Arc<dyn CredentialHelper + Send + Sync>),
+}
+
+impl std::fmt::Debug for Helper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Command(command) => f.debug_tuple("Command").field(command).finish(),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+fn run_helper(helper: &str, operation: &str, cred: &Credential) -> Result<String, std::io::Error> {
+    let command = if let Some(command) = helper.strip_prefix('!') {
+        command.to_owned()
+    } else if std::path::Path::new(helper.split_whitespace().next().unwrap_or_default())
+        .is_absolute()
+    {
+        helper.to_owned()
+    } else {
+        format!("git credential-{helper}")
+    };
+
+    let input = cred.to_protocol()?;
+    let output = crate::utils::run_process(
+        crate::utils::ProcessCommand::shell(&command, [operation])?
+            .stdin(input.as_bytes())
+            .stderr(crate::utils::ProcessStdio::Inherit),
+    )?;
+    if !output.status.success() {
+        // Like `git`, a failing helper is skipped rather than aborting
+        log::trace!(
+            "Credential helper `{helper} {operation}` failed with {}",
+            output.status
+        );
+        return Ok(String::new());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_helpers_from_config() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.run(&["config", "--add", "credential.helper", "first"])?;
+        git.run(&["config", "--add", "credential.helper", ""])?;
+        git.run(&["config", "--add", "credential.helper", "second"])?;
+        git.run(&["config", "credential.https://example.com.helper", "scoped"])?;
+        git.run(&["config", "credential.https://other.com.helper", "other"])?;
+        git.run(&["config", "credential.https://example.com.username", "me"])?;
+
+        let repo = git.get_repo()?;
+        let config = repo.config()?;
+        let helpers = CredentialHelpers::from_config(&config, "https://example.com/repo.git")?;
+        assert_eq!(helpers.helpers().collect::<Vec<_>>(), ["second", "scoped"]);
+        let cred = helpers
+            .credential_for("https://example.com/repo.git")
+            .unwrap();
+        assert_eq!(cred.username.as_deref(), Some("me"));
+        assert_eq!(cred.path, None);
+
+        git.run(&[
+            "config",
+            "credential.https://*.com/repo.git.useHttpPath",
+            "true",
+        ])?;
+        let config = repo.config()?;
+        let helpers = CredentialHelpers::from_config(&config, "https://example.com/repo.git")?;
+        let cred = helpers
+            .credential_for("https://example.com/repo.git")
+            .unwrap();
+        assert_eq!(cred.path.as_deref(), Some("repo.git"));
+        let helpers = CredentialHelpers::from_config(&config, "https://example.com/other.git")?;
+        let cred = helpers
+            .credential_for("https://example.com/other.git")
+            .unwrap();
+        assert_eq!(cred.path, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_helpers_get() -> eyre::Result<()> {
+        let helpers = CredentialHelpers::new()
+            .helper("!f() { cat >/dev/null; echo username=ignored; }; f")
+            .helper("!f() { cat >/dev/null; echo password=secret; }; f")
+            .helper("!f() { echo never >&2; exit 1; }; f");
+        let cred = Credential::from_url("https://user@example.com").unwrap();
+        let cred = helpers.get(&cred)?;
+        assert_eq!(cred.username.as_deref(), Some("ignored"));
+        assert_eq!(cred.password.as_deref(), Some("secret"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_helpers_reject_injected_fields() -> eyre::Result<()> {
+        let git = make_git()?;
+        let log = git.repo_path.join("helper.log");
+        let helpers = CredentialHelpers::new().helper(format!(
+            "!f() {{ cat >>'{}'; echo password=secret; }}; f",
+            log.display()
+        ));
+        let mut cred = Credential::from_url("https://u%0Ahost=evil.com@example.com/").unwrap();
+        let err = helpers.get(&cred).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        cred.password = Some("secret".to_owned());
+        assert!(helpers.store(&cred).is_err());
+        assert!(!log.exists(), "helper must not run");
+
+        Ok(())
+    }
+
+    struct Fixed;
+
+    impl CredentialHelper for Fixed {
+        fn get(&self, _cred: &Credential) -> Result<Credential, std::io::Error> {
+            Ok(Credential {
+                password: Some("from-custom".to_owned()),
+                ..Default::default()
+            })
+        }
+
+        fn store(&self, _cred: &Credential) -> Result<(), std::io::Error> {
+            Ok(())
+        }
+
+        fn erase(&self, _cred: &Credential) -> Result<(), std::io::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_custom_helper() -> eyre::Result<()> {
+        let helpers = CredentialHelpers::new()
+            .custom_helper(Fixed)
+            .helper("!f() { echo never >&2; exit 1; }; f");
+        assert_eq!(helpers.helpers().count(), 1);
+        let cred = Credential::from_url("https://user@example.com").unwrap();
+        let cred = helpers.get(&cred)?;
+        assert_eq!(cred.password.as_deref(), Some("from-custom"));
+
+        Ok(())
+    }
+}
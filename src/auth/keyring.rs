@@ -0,0 +1,103 @@
+use super::Credential;
+use super::CredentialHelper;
+
+/// Store credentials in the platform keychain
+///
+/// This uses the Secret Service on Linux, the Keychain on macOS, and the Credential Manager on
+/// Windows.  Entries are keyed by the credential's URL (without the username) and username.
+///
+/// Most keychains can't be searched, so when a lookup has no username, the one last stored for
+/// the URL is used.
+#[derive(Clone, Debug)]
+pub struct KeyringHelper {
+    service: String,
+}
+
+impl KeyringHelper {
+    /// Store entries under `service`, e.g. your application's name
+    pub fn new(service: impl Into<String>) -> Self {
+        Self {
+            service: service.into(),
+        }
+    }
+
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    fn target(&self, cred: &Credential) -> String {
+        let mut target = cred.clone();
+        target.username = None;
+        format!("{}:{}", self.service, target.to_url())
+    }
+
+    fn entry(&self, target: &str, username: &str) -> Result<keyring::Entry, std::io::Error> {
+        keyring::Entry::new(target, username).map_err(to_io_error)
+    }
+}
+
+impl Default for KeyringHelper {
+    fn default() -> Self {
+        Self::new("git2-ext")
+    }
+}
+
+impl CredentialHelper for KeyringHelper {
+    fn get(&self, cred: &Credential) -> Result<Credential, std::io::Error> {
+        let target = self.target(cred);
+        let username = match &cred.username {
+            Some(username) => username.clone(),
+            None => match self.entry(&target, "")?.get_password() {
+                Ok(username) => username,
+                Err(keyring::Error::NoEntry) => return Ok(Credential::default()),
+                Err(err) => return Err(to_io_error(err)),
+            },
+        };
+        match self.entry(&target, &username)?.get_password() {
+            Ok(password) => Ok(Credential {
+                username: Some(username),
+                password: Some(password),
+                ..Default::default()
+            }),
+            Err(keyring::Error::NoEntry) => Ok(Credential::default()),
+            Err(err) => Err(to_io_error(err)),
+        }
+    }
+
+    fn store(&self, cred: &Credential) -> Result<(), std::io::Error> {
+        let (Some(username), Some(password)) = (&cred.username, &cred.password) else {
+            return Ok(());
+        };
+        let target = self.target(cred);
+        self.entry(&target, username)?
+            .set_password(password)
+            .map_err(to_io_error)?;
+        self.entry(&target, "")?
+            .set_password(username)
+            .map_err(to_io_error)?;
+        Ok(())
+    }
+
+    fn erase(&self, cred: &Credential) -> Result<(), std::io::Error> {
+        let target = self.target(cred);
+        let Some(username) = &cred.username else {
+            return Ok(());
+        };
+        for username in [username.as_str(), ""] {
+            match self.entry(&target, username)?.delete_credential() {
+                Ok(()) | Err(keyring::Error::NoEntry) => {}
+                Err(err) => return Err(to_io_error(err)),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_io_error(err: keyring::Error) -> std::io::Error {
+    match err {
+        keyring::Error::NoStorageAccess(_) => {
+            std::io::Error::new(std::io::ErrorKind::PermissionDenied, err)
+        }
+        _ => std::io::Error::other(err),
+    }
+}
@@ -0,0 +1,539 @@
+use std::path::PathBuf;
+
+use super::Prompter;
+
+/// Verify SSH host keys against `known_hosts`, like `ssh` does
+///
+/// By default, the files come from `UserKnownHostsFile` / `GlobalKnownHostsFile` in
+/// `~/.ssh/config`, falling back to `~/.ssh/known_hosts` and `/etc/ssh/ssh_known_hosts`.
+/// Hashed entries, wildcards, non-default ports (`[host]:port`), and `@revoked` are supported.
+#[derive(Clone)]
+pub struct KnownHosts {
+    files: Option<Vec<PathBuf>>,
+    policy: UnknownHostPolicy,
+    prompter: Option<std::sync::Arc<dyn Prompter + Send + Sync>>,
+}
+
+/// What [`KnownHosts`] does with hosts that aren't in `known_hosts`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnknownHostPolicy {
+    /// Refuse to connect, like `StrictHostKeyChecking=yes`
+    Reject,
+    /// Ask through the [`Prompter`], remembering the key if accepted
+    Prompt,
+    /// Trust and remember the key on first use, like `StrictHostKeyChecking=accept-new`
+    AcceptNew,
+}
+
+/// How a host key compares to `known_hosts`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HostKeyStatus {
+    Known,
+    /// The host isn't listed with a key of this type
+    Unknown,
+    /// The host is listed with a different key of this type, a possible man-in-the-middle attack
+    Mismatch,
+    /// The key is marked `@revoked`
+    Revoked,
+}
+
+impl KnownHosts {
+    pub fn new() -> Self {
+        Self {
+            files: None,
+            policy: UnknownHostPolicy::Reject,
+            prompter: None,
+        }
+    }
+
+    /// Use these `known_hosts` files instead of those from `~/.ssh/config`
+    ///
+    /// New keys are added to the first file.
+    pub fn files(mut self, files: impl IntoIterator<Item = PathBuf>) -> Self {
+        self.files = Some(files.into_iter().collect());
+        self
+    }
+
+    /// What to do with hosts that aren't known, defaulting to [`UnknownHostPolicy::Reject`]
+    pub fn policy(mut self, policy: UnknownHostPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Confirm unknown hosts with `prompter` for [`UnknownHostPolicy::Prompt`]
+    pub fn prompter(mut self, prompter: impl Prompter + Send + Sync + 'static) -> Self {
+        self.prompter = Some(std::sync::Arc::new(prompter));
+        self
+    }
+
+    /// The `known_hosts` files that apply to `host`, user files first
+    pub fn files_for(&self, host: &str) -> Vec<PathBuf> {
+        if let Some(files) = &self.files {
+            return files.clone();
+        }
+        // `~/.ssh/config` matches on the hostname alone
+        let host = host
+            .strip_prefix('[')
+            .and_then(|host| host.rsplit_once("]:"))
+            .map(|(host, _)| host)
+            .unwrap_or(host);
+
+        let home = crate::utils::home_dir();
+        let ssh_config = home
+            .as_ref()
+            .and_then(|home| std::fs::read_to_string(home.join(".ssh").join("config")).ok())
+            .unwrap_or_default();
+        let mut files = Vec::new();
+        for (keyword, defaults) in [
+            (
+                "UserKnownHostsFile",
+                &["~/.ssh/known_hosts", "~/.ssh/known_hosts2"][..],
+            ),
+            (
+                "GlobalKnownHostsFile",
+                &["/etc/ssh/ssh_known_hosts", "/etc/ssh/ssh_known_hosts2"][..],
+            ),
+        ] {
+            let configured = super::ssh::config_values(&ssh_config, host, keyword);
+            // Like `ssh`, the first value wins
+            let paths: Vec<&str> = match configured.first() {
+                Some(value) => value.split_whitespace().collect(),
+                None => defaults.to_vec(),
+            };
+            files.extend(
+                paths
+                    .into_iter()
+                    .map(|path| super::ssh::expand_path(path, home.as_deref(), host)),
+            );
+        }
+        files
+    }
+
+    /// Compare `host`s key with `known_hosts`
+    ///
+    /// `host` is `[host]:port` for non-default ports.  `key_type` is the SSH name, like
+    /// `ssh-ed25519`, and `key` is the raw public key blob.
+    pub fn check(&self, host: &str, key_type: &str, key: &[u8]) -> HostKeyStatus {
+        let mut status = HostKeyStatus::Unknown;
+        for file in self.files_for(host) {
+            let Ok(content) = std::fs::read_to_string(&file) else {
+                continue;
+            };
+            for line in content.lines() {
+                let Some(entry) = Entry::parse(line) else {
+                    continue;
+                };
+                if !entry.matches_host(host) {
+                    continue;
+                }
+                let same_key = entry.key_type == key_type && entry.key == key;
+                match entry.marker {
+                    Some(Marker::Revoked) if entry.key == key => return HostKeyStatus::Revoked,
+                    Some(_) => {}
+                    None if same_key => status = HostKeyStatus::Known,
+                    None if entry.key_type == key_type && status != HostKeyStatus::Known => {
+                        status = HostKeyStatus::Mismatch;
+                    }
+                    None => {}
+                }
+            }
+        }
+        status
+    }
+
+    /// Remember `host`s key in the first (user) `known_hosts` file
+    pub fn add(&self, host: &str, key_type: &str, key: &[u8]) -> Result<(), std::io::Error> {
+        use std::io::Write;
+
+        let Some(file) = self.files_for(host).into_iter().next() else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "no `known_hosts` file to add to",
+            ));
+        };
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file)?;
+        writeln!(file, "{host} {key_type} {}", base64_encode(key, true))
+    }
+
+    /// A [`git2::RemoteCallbacks::certificate_check`] implementation
+    ///
+    /// `git2` passes `host` without the port, so pass the remote's `port` to check (and remember)
+    /// the key as `[host]:port` like `ssh` does.  Non-SSH certificates are left to the
+    /// transport's own verification.
+    pub fn certificate_check(
+        &self,
+        cert: &git2::cert::Cert<'_>,
+        host: &str,
+        port: Option<u16>,
+    ) -> Result<git2::CertificateCheckStatus, git2::Error> {
+        let Some(hostkey) = cert.as_hostkey() else {
+            return Ok(git2::CertificateCheckStatus::CertificatePassthrough);
+        };
+        let (Some(key_type), Some(key)) = (hostkey.hostkey_type(), hostkey.hostkey()) else {
+            log::trace!("No raw host key for `{host}`, deferring to the transport");
+            return Ok(git2::CertificateCheckStatus::CertificatePassthrough);
+        };
+        let fingerprint = || {
+            hostkey
+                .hash_sha256()
+                .map(|hash| format!("SHA256:{}", base64_encode(hash, false)))
+                .unwrap_or_default()
+        };
+        self.verify(&host_entry(host, port), key_type.name(), key, fingerprint)
+    }
+
+    fn verify(
+        &self,
+        host: &str,
+        key_type: &str,
+        key: &[u8],
+        fingerprint: impl FnOnce() -> String,
+    ) -> Result<git2::CertificateCheckStatus, git2::Error> {
+        match self.check(host, key_type, key) {
+            HostKeyStatus::Known => Ok(git2::CertificateCheckStatus::CertificateOk),
+            HostKeyStatus::Mismatch => Err(host_key_error(format!(
+                "host key for `{host}` has changed; it may be a man-in-the-middle attack"
+            ))),
+            HostKeyStatus::Revoked => Err(host_key_error(format!(
+                "host key for `{host}` has been revoked"
+            ))),
+            HostKeyStatus::Unknown => {
+                let accept = match self.policy {
+                    UnknownHostPolicy::Reject => false,
+                    UnknownHostPolicy::AcceptNew => true,
+                    UnknownHostPolicy::Prompt => {
+                        let prompt = format!(
+                            "The authenticity of host '{host}' can't be established.\n\
+                             {key_type} key fingerprint is {}.\n\
+                             Are you sure you want to continue connecting (yes/no)? ",
+                            fingerprint()
+                        );
+                        self.prompter
+                            .as_ref()
+                            .map(|prompter| prompter.confirm(&prompt))
+                            .unwrap_or(false)
+                    }
+                };
+                if !accept {
+                    return Err(host_key_error(format!(
+                        "host key for `{host}` is not in `known_hosts`"
+                    )));
+                }
+                if let Err(err) = self.add(host, key_type, key) {
+                    log::warn!("Failed to remember host key for `{host}`: {err}");
+                }
+                Ok(git2::CertificateCheckStatus::CertificateOk)
+            }
+        }
+    }
+}
+
+impl Default for KnownHosts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for KnownHosts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KnownHosts")
+            .field("files", &self.files)
+            .field("policy", &self.policy)
+            .field("prompter", &self.prompter.as_ref().map(|_| "<prompter>"))
+            .finish()
+    }
+}
+
+/// How `known_hosts` lists `host`, with `[host]:port` for non-default ports
+fn host_entry(host: &str, port: Option<u16>) -> String {
+    match port {
+        Some(port) if port != 22 => format!("[{host}]:{port}"),
+        _ => host.to_owned(),
+    }
+}
+
+fn host_key_error(message: String) -> git2::Error {
+    git2::Error::new(git2::ErrorCode::Certificate, git2::ErrorClass::Ssh, message)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Marker {
+    CertAuthority,
+    Revoked,
+}
+
+/// A line of `known_hosts`
+struct Entry<'l> {
+    marker: Option<Marker>,
+    hosts: &'l str,
+    key_type: &'l str,
+    key: Vec<u8>,
+}
+
+impl<'l> Entry<'l> {
+    fn parse(line: &'l str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let mut fields = line.split_whitespace();
+        let mut hosts = fields.next()?;
+        let marker = match hosts {
+            "@cert-authority" => Some(Marker::CertAuthority),
+            "@revoked" => Some(Marker::Revoked),
+            _ => None,
+        };
+        if marker.is_some() {
+            hosts = fields.next()?;
+        }
+        let key_type = fields.next()?;
+        let key = base64_decode(fields.next()?)?;
+        Some(Self {
+            marker,
+            hosts,
+            key_type,
+            key,
+        })
+    }
+
+    fn matches_host(&self, host: &str) -> bool {
+        if let Some(hashed) = self.hosts.strip_prefix("|1|") {
+            let Some((salt, hash)) = hashed.split_once('|') else {
+                return false;
+            };
+            let (Some(salt), Some(hash)) = (base64_decode(salt), base64_decode(hash)) else {
+                return false;
+            };
+            return hmac_sha1(&salt, host.as_bytes())[..] == hash[..];
+        }
+        super::ssh::host_matches(&self.hosts.replace(',', " "), host)
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(input: &[u8], pad: bool) -> String {
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (n >> (18 - 6 * i)) & 0x3f;
+                output.push(char::from(BASE64[index as usize]));
+            } else if pad {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=');
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in input.bytes() {
+        let value = BASE64.iter().position(|b| *b == c)? as u32;
+        buffer = (buffer << 6) | value;
+        bits += 6;
+        if 8 <= bits {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+    Some(output)
+}
+
+fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    let mut block = [0u8; 64];
+    if 64 < key.len() {
+        block[..20].copy_from_slice(&sha1(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = block.map(|b| b ^ 0x36).to_vec();
+    inner.extend_from_slice(message);
+    let mut outer = block.map(|b| b ^ 0x5c).to_vec();
+    outer.extend_from_slice(&sha1(&inner));
+    sha1(&outer)
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for chunk in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (word, bytes) in w.iter_mut().zip(chunk.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64_sha1() {
+        assert_eq!(base64_encode(b"ab", true), "YWI=");
+        assert_eq!(base64_encode(b"ab", false), "YWI");
+        assert_eq!(base64_decode("YWJj").unwrap(), b"abc");
+        assert_eq!(
+            sha1(b"abc"),
+            [
+                0xa9, 0x99, 0x3e, 0x36, 0x47, 0x06, 0x81, 0x6a, 0xba, 0x3e, 0x25, 0x71, 0x78, 0x50,
+                0xc2, 0x6c, 0x9c, 0xd0, 0xd8, 0x9d
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("known_hosts");
+        let key = b"host key";
+        let other_key = b"other key";
+        let salt = b"0123456789abcdef0123";
+        let hashed = format!(
+            "|1|{}|{}",
+            base64_encode(salt, true),
+            base64_encode(&hmac_sha1(salt, b"hashed.example.com"), true)
+        );
+        std::fs::write(
+            &file,
+            format!(
+                "# comment\n\
+                 github.com,*.github.com ssh-ed25519 {key}\n\
+                 [example.com]:2222 ssh-ed25519 {key}\n\
+                 {hashed} ssh-ed25519 {key}\n\
+                 @revoked evil.example.com ssh-ed25519 {key}\n\
+                 evil.example.com ssh-ed25519 {key}\n",
+                key = base64_encode(key, true)
+            ),
+        )?;
+        let known_hosts = KnownHosts::new().files([file.clone()]);
+
+        assert_eq!(
+            known_hosts.check("github.com", "ssh-ed25519", key),
+            HostKeyStatus::Known
+        );
+        assert_eq!(
+            known_hosts.check("ssh.github.com", "ssh-ed25519", key),
+            HostKeyStatus::Known
+        );
+        assert_eq!(
+            known_hosts.check("[example.com]:2222", "ssh-ed25519", key),
+            HostKeyStatus::Known
+        );
+        assert_eq!(
+            known_hosts.check("hashed.example.com", "ssh-ed25519", key),
+            HostKeyStatus::Known
+        );
+        assert_eq!(
+            known_hosts.check("github.com", "ssh-ed25519", other_key),
+            HostKeyStatus::Mismatch
+        );
+        assert_eq!(
+            known_hosts.check("github.com", "ssh-rsa", other_key),
+            HostKeyStatus::Unknown
+        );
+        assert_eq!(
+            known_hosts.check("evil.example.com", "ssh-ed25519", key),
+            HostKeyStatus::Revoked
+        );
+
+        known_hosts.add("new.example.com", "ssh-ed25519", other_key)?;
+        assert_eq!(
+            known_hosts.check("new.example.com", "ssh-ed25519", other_key),
+            HostKeyStatus::Known
+        );
+
+        Ok(())
+    }
+    #[test]
+    fn test_verify_non_default_port() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("known_hosts");
+        let key = b"host key";
+        std::fs::write(
+            &file,
+            format!(
+                "example.com ssh-ed25519 {}\n",
+                base64_encode(b"other key", true)
+            ),
+        )?;
+        let known_hosts = KnownHosts::new()
+            .files([file.clone()])
+            .policy(UnknownHostPolicy::AcceptNew);
+
+        let host = host_entry("example.com", Some(2222));
+        assert_eq!(host, "[example.com]:2222");
+        assert_eq!(host_entry("example.com", Some(22)), "example.com");
+        assert!(known_hosts
+            .verify(&host, "ssh-ed25519", key, String::new)
+            .is_ok());
+        assert_eq!(
+            known_hosts.check("[example.com]:2222", "ssh-ed25519", key),
+            HostKeyStatus::Known
+        );
+        assert_eq!(
+            known_hosts.check("example.com", "ssh-ed25519", key),
+            HostKeyStatus::Mismatch
+        );
+        assert!(std::fs::read_to_string(&file)?.contains("[example.com]:2222 ssh-ed25519 "));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+/// Ask the user for missing credentials
+pub trait Prompter {
+    fn username(&self, url: &str) -> Option<String>;
+
+    fn password(&self, url: &str, username: &str) -> Option<String>;
+
+    /// Ask a yes / no question, like whether to trust an unknown host
+    ///
+    /// Defaults to no.
+    fn confirm(&self, prompt: &str) -> bool {
+        let _ = prompt;
+        false
+    }
+}
+
+/// Prompt through an askpass program, like `git` does
+///
+/// The program is run with the prompt as its only argument and the first line of its output
+/// is the answer.
+#[derive(Clone, Debug)]
+pub struct Askpass {
+    program: std::path::PathBuf,
+}
+
+impl Askpass {
+    pub fn new(program: impl Into<std::path::PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+        }
+    }
+
+    /// Find the askpass program `git` would use
+    ///
+    /// In order, `$GIT_ASKPASS`, `core.askPass`, and `$SSH_ASKPASS`.
+    pub fn from_env(config: &git2::Config) -> Option<Self> {
+        let program = std::env::var_os("GIT_ASKPASS")
+            .filter(|p| !p.is_empty())
+            .map(std::path::PathBuf::from)
+            .or_else(|| config.get_path("core.askPass").ok())
+            .or_else(|| {
+                std::env::var_os("SSH_ASKPASS")
+                    .filter(|p| !p.is_empty())
+                    .map(std::path::PathBuf::from)
+            })?;
+        Some(Self::new(program))
+    }
+
+    pub fn program(&self) -> &std::path::Path {
+        &self.program
+    }
+
+    fn ask(&self, prompt: &str) -> Option<String> {
+        let output = crate::utils::run_process(
+            crate::utils::ProcessCommand::new(&self.program)
+                .arg(prompt)
+                .stderr(crate::utils::ProcessStdio::Inherit),
+        );
+        let output = match output {
+            Ok(output) => output,
+            Err(err) => {
+                log::trace!("Failed to run `{}`: {err}", self.program.display());
+                return None;
+            }
+        };
+        if !output.status.success() {
+            log::trace!("`{}` failed with {}", self.program.display(), output.status);
+            return None;
+        }
+        let answer = String::from_utf8_lossy(&output.stdout);
+        let answer = answer.lines().next().unwrap_or_default();
+        Some(answer.to_owned())
+    }
+}
+
+impl Prompter for Askpass {
+    fn username(&self, url: &str) -> Option<String> {
+        self.ask(&format!("Username for '{url}': "))
+    }
+
+    fn password(&self, url: &str, username: &str) -> Option<String> {
+        let url = match super::Credential::from_url(url) {
+            Some(mut cred) => {
+                cred.username = Some(username.to_owned());
+                cred.path = None;
+                cred.to_url()
+            }
+            None => url.to_owned(),
+        };
+        self.ask(&format!("Password for '{url}': "))
+    }
+
+    fn confirm(&self, prompt: &str) -> bool {
+        self.ask(prompt)
+            .map(|answer| matches!(answer.trim(), "yes" | "y"))
+            .unwrap_or(false)
+    }
+}
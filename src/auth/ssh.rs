@@ -0,0 +1,178 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+/// SSH private keys to try for `host`, in the order `ssh` would
+///
+/// Candidates come from, in order:
+/// - `-i` in `$GIT_SSH_COMMAND` or `core.sshCommand`
+/// - `IdentityFile` in `~/.ssh/config` for `Host`s matching `host`
+/// - The default `~/.ssh/id_*` keys
+///
+/// Only keys that exist are returned.
+pub fn ssh_identities(config: &git2::Config, host: &str) -> Vec<PathBuf> {
+    let home = crate::utils::home_dir();
+    let mut candidates = Vec::new();
+
+    let ssh_command = std::env::var("GIT_SSH_COMMAND")
+        .ok()
+        .filter(|c| !c.is_empty())
+        .or_else(|| config.get_string("core.sshCommand").ok());
+    if let Some(ssh_command) = ssh_command {
+        candidates.extend(
+            command_identities(&ssh_command)
+                .into_iter()
+                .map(|path| expand_path(&path, home.as_deref(), host)),
+        );
+    }
+
+    if let Some(home) = home.as_deref() {
+        let ssh_dir = home.join(".ssh");
+        if let Ok(content) = std::fs::read_to_string(ssh_dir.join("config")) {
+            candidates.extend(
+                config_identities(&content, host)
+                    .into_iter()
+                    .map(|path| expand_path(&path, Some(home), host)),
+            );
+        }
+        candidates.extend(
+            ["id_ed25519", "id_ecdsa", "id_rsa", "id_dsa"]
+                .into_iter()
+                .map(|name| ssh_dir.join(name)),
+        );
+    }
+
+    let mut identities: Vec<PathBuf> = Vec::with_capacity(candidates.len());
+    for candidate in candidates {
+        if candidate.is_file() && !identities.contains(&candidate) {
+            identities.push(candidate);
+        }
+    }
+    identities
+}
+
+/// `-i` arguments in an `ssh` command line
+fn command_identities(command: &str) -> Vec<String> {
+    let Some(args) = shlex::split(command) else {
+        return Vec::new();
+    };
+    let mut identities = Vec::new();
+    let mut args = args.into_iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "-i" {
+            identities.extend(args.next());
+        } else if let Some(path) = arg.strip_prefix("-i") {
+            identities.push(path.to_owned());
+        }
+    }
+    identities
+}
+
+/// `IdentityFile`s in an `ssh_config` that apply to `host`
+fn config_identities(content: &str, host: &str) -> Vec<String> {
+    config_values(content, host, "IdentityFile")
+}
+
+/// Values of `keyword` in an `ssh_config` that apply to `host`
+pub(crate) fn config_values(content: &str, host: &str, keyword: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    // Settings before the first `Host` apply to all hosts
+    let mut active = true;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match line.split_once(|c: char| c.is_whitespace() || c == '=') {
+            Some((key, value)) => (key, value.trim_start_matches([' ', '\t', '='])),
+            None => (line, ""),
+        };
+        let value = value.trim();
+        if key.eq_ignore_ascii_case("Host") {
+            active = host_matches(value, host);
+        } else if key.eq_ignore_ascii_case("Match") {
+            // `Match` criteria aren't supported, so don't guess
+            active = false;
+        } else if active && key.eq_ignore_ascii_case(keyword) {
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            values.push(value.to_owned());
+        }
+    }
+    values
+}
+
+/// Whether a `Host` pattern list applies to `host`
+pub(crate) fn host_matches(patterns: &str, host: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns.split_whitespace() {
+        if let Some(pattern) = pattern.strip_prefix('!') {
+            if crate::utils::wildcard_match(pattern, host, true) {
+                return false;
+            }
+        } else if crate::utils::wildcard_match(pattern, host, true) {
+            matched = true;
+        }
+    }
+    matched
+}
+
+/// Expand `~` and the `%d` / `%h` tokens `ssh` supports in `IdentityFile`
+pub(crate) fn expand_path(path: &str, home: Option<&Path>, host: &str) -> PathBuf {
+    let home_str = home.map(|h| h.to_string_lossy().into_owned());
+    let mut expanded = match (path.strip_prefix("~/"), &home_str) {
+        (Some(rest), Some(home)) => format!("{home}/{rest}"),
+        _ => path.to_owned(),
+    };
+    if let Some(home) = &home_str {
+        expanded = expanded.replace("%d", home);
+    }
+    expanded = expanded.replace("%h", host).replace("%%", "%");
+    PathBuf::from(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_identities() {
+        assert_eq!(
+            command_identities("ssh -o IdentitiesOnly=yes -i '/keys/deploy key' -i/keys/other"),
+            ["/keys/deploy key", "/keys/other"]
+        );
+    }
+
+    #[test]
+    fn test_config_identities() {
+        let config = "\
+IdentityFile ~/.ssh/everywhere
+
+Host github.com gh-*
+    IdentityFile ~/.ssh/github
+Host *.example.com !internal.example.com
+    IdentityFile=\"~/.ssh/%h\"
+Match user git
+    IdentityFile ~/.ssh/matched
+Host *
+    IdentityFile ~/.ssh/fallback
+";
+        assert_eq!(
+            config_identities(config, "github.com"),
+            ["~/.ssh/everywhere", "~/.ssh/github", "~/.ssh/fallback"]
+        );
+        assert_eq!(
+            config_identities(config, "git.example.com"),
+            ["~/.ssh/everywhere", "~/.ssh/%h", "~/.ssh/fallback"]
+        );
+        assert_eq!(
+            config_identities(config, "internal.example.com"),
+            ["~/.ssh/everywhere", "~/.ssh/fallback"]
+        );
+        assert_eq!(
+            expand_path("~/.ssh/%h", Some(Path::new("/home/me")), "git.example.com"),
+            PathBuf::from("/home/me/.ssh/git.example.com")
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use super::Credential;
+
+/// Whether config scoped to `pattern` (e.g. `credential.<pattern>.helper`) applies to `url`
+///
+/// This follows `git`s URL matching rules:
+/// - The scheme must match
+/// - The host must match, with `*` matching within a single dot-separated label
+/// - The port must match, with the scheme's default port being implied
+/// - If `pattern` has a username, it must match
+/// - If `pattern` has a path, it must be a prefix of `url`s path at a `/` boundary
+pub fn url_matches(pattern: &str, url: &str) -> bool {
+    let Some(target) = Credential::from_url(url) else {
+        return false;
+    };
+    context_matches(pattern, &target)
+}
+
+pub(crate) fn context_matches(pattern: &str, target: &Credential) -> bool {
+    let Some(pattern) = Credential::from_url(pattern) else {
+        return false;
+    };
+
+    let pattern_protocol = pattern.protocol.as_deref().unwrap_or_default();
+    let target_protocol = target.protocol.as_deref().unwrap_or_default();
+    if !pattern_protocol.eq_ignore_ascii_case(target_protocol) {
+        return false;
+    }
+
+    let (pattern_host, pattern_port) = split_port(pattern.host.as_deref(), pattern_protocol);
+    let (target_host, target_port) = split_port(target.host.as_deref(), target_protocol);
+    if !host_matches(pattern_host, target_host) || pattern_port != target_port {
+        return false;
+    }
+
+    if pattern.username.is_some() && pattern.username != target.username {
+        return false;
+    }
+
+    let pattern_path = pattern.path.as_deref().unwrap_or_default();
+    let pattern_path = pattern_path.trim_end_matches('/');
+    if pattern_path.is_empty() {
+        return true;
+    }
+    let target_path = target.path.as_deref().unwrap_or_default();
+    match target_path.strip_prefix(pattern_path) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// The port `url` connects to, when it names one
+pub(crate) fn url_port(url: &str) -> Option<u16> {
+    let target = Credential::from_url(url)?;
+    split_port(target.host.as_deref(), "").1
+}
+
+fn split_port<'s>(host: Option<&'s str>, protocol: &str) -> (&'s str, Option<u16>) {
+    let host = host.unwrap_or_default();
+    let (host, port) = match host.rsplit_once(':') {
+        // Don't split IPv6 addresses
+        Some((host, port)) if !port.contains(']') => (host, port.parse().ok()),
+        _ => (host, None),
+    };
+    let port = port.or_else(|| default_port(protocol));
+    (host, port)
+}
+
+fn default_port(protocol: &str) -> Option<u16> {
+    match protocol.to_ascii_lowercase().as_str() {
+        "http" => Some(80),
+        "https" => Some(443),
+        "ssh" => Some(22),
+        "git" => Some(9418),
+        "ftp" => Some(21),
+        "ftps" => Some(990),
+        _ => None,
+    }
+}
+
+fn host_matches(pattern: &str, host: &str) -> bool {
+    let pattern_labels = pattern.split('.');
+    let host_labels = host.split('.');
+    if pattern_labels.clone().count() != host_labels.clone().count() {
+        return false;
+    }
+    pattern_labels
+        .zip(host_labels)
+        .all(|(pattern, label)| crate::utils::wildcard_match(pattern, label, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_matches() {
+        let url = "https://user@git.example.com/org/repo.git";
+        assert!(url_matches("https://git.example.com", url));
+        assert!(url_matches("HTTPS://git.example.com:443/", url));
+        assert!(url_matches("https://*.example.com", url));
+        assert!(url_matches("https://user@git.example.com/org", url));
+        assert!(url_matches("https://git.example.com/org/repo.git", url));
+
+        assert!(!url_matches("http://git.example.com", url));
+        assert!(!url_matches("https://git.example.com:8443", url));
+        assert!(!url_matches("https://*.com", url));
+        assert!(!url_matches("https://example.com", url));
+        assert!(!url_matches("https://other@git.example.com", url));
+        assert!(!url_matches("https://git.example.com/or", url));
+    }
+
+    #[test]
+    fn test_url_port() {
+        assert_eq!(url_port("ssh://git@example.com:2222/repo.git"), Some(2222));
+        assert_eq!(url_port("ssh://git@example.com/repo.git"), None);
+        assert_eq!(url_port("git@example.com:repo.git"), None);
+    }
+}
@@ -0,0 +1,42 @@
+//! Object access behind a trait, for running this crate's algorithms on other `git`
+//! implementations
+//!
+//! [`ObjectStore`] is implemented for [`git2::Repository`].  For another implementation, like a
+//! `gix` repository during a migration to gitoxide, implement it on a wrapper type and use the
+//! algorithms that take an `ObjectStore`, like [`crate::tree::changed_entries_in`].  Ids are
+//! [`git2::Oid`]s, which any SHA-1 object id converts into with [`git2::Oid::from_bytes`].
+
+/// An entry of a tree, see [`ObjectStore::read_tree`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredEntry {
+    pub name: Vec<u8>,
+    pub id: git2::Oid,
+    pub mode: i32,
+}
+
+impl StoredEntry {
+    /// Whether the entry is a directory, rather than a file, symlink, or submodule
+    pub fn is_tree(&self) -> bool {
+        self.mode & 0o170000 == 0o040000
+    }
+}
+
+/// Reading `git` objects
+pub trait ObjectStore {
+    /// The entries of the tree `id`
+    fn read_tree(&self, id: git2::Oid) -> Result<Vec<StoredEntry>, git2::Error>;
+}
+
+impl ObjectStore for git2::Repository {
+    fn read_tree(&self, id: git2::Oid) -> Result<Vec<StoredEntry>, git2::Error> {
+        let tree = self.find_tree(id)?;
+        Ok(tree
+            .iter()
+            .map(|entry| StoredEntry {
+                name: entry.name_bytes().to_owned(),
+                id: entry.id(),
+                mode: entry.filemode_raw(),
+            })
+            .collect())
+    }
+}
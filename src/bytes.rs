@@ -1,13 +1,104 @@
-// From git2 crate
+/// A path from `git`, like a tree entry's, as a native path
+///
+/// `git` paths are bytes.  On Windows, native paths are Unicode, so bytes that aren't UTF-8 are
+/// an error; see [`bytes2path_lossy`].
 #[cfg(unix)]
-pub(crate) fn bytes2path(b: &[u8]) -> &std::path::Path {
+pub fn bytes2path(b: &[u8]) -> Result<&std::path::Path, git2::Error> {
     use std::os::unix::prelude::OsStrExt;
-    std::path::Path::new(std::ffi::OsStr::from_bytes(b))
+    Ok(std::path::Path::new(std::ffi::OsStr::from_bytes(b)))
 }
 
-// From git2 crate
+/// A path from `git`, like a tree entry's, as a native path
+///
+/// `git` paths are bytes.  On Windows, native paths are Unicode, so bytes that aren't UTF-8 are
+/// an error; see [`bytes2path_lossy`].
 #[cfg(windows)]
-pub(crate) fn bytes2path(b: &[u8]) -> &std::path::Path {
-    use std::str;
-    std::path::Path::new(str::from_utf8(b).unwrap())
+pub fn bytes2path(b: &[u8]) -> Result<&std::path::Path, git2::Error> {
+    let s = std::str::from_utf8(b).map_err(|_| {
+        git2::Error::new(
+            git2::ErrorCode::Invalid,
+            git2::ErrorClass::Invalid,
+            format!("path is not valid UTF-8: {}", b.escape_ascii()),
+        )
+    })?;
+    Ok(std::path::Path::new(s))
+}
+
+/// Like [`bytes2path`], replacing what can't be represented with `U+FFFD`
+pub fn bytes2path_lossy(b: &[u8]) -> std::borrow::Cow<'_, std::path::Path> {
+    match bytes2path(b) {
+        Ok(path) => std::borrow::Cow::Borrowed(path),
+        Err(_) => std::borrow::Cow::Owned(String::from_utf8_lossy(b).into_owned().into()),
+    }
+}
+
+/// A native path as a `git` path, with `/` separators
+///
+/// On Windows, a path that isn't valid Unicode is an error; see [`path2bytes_lossy`].
+#[cfg(unix)]
+pub fn path2bytes(p: &std::path::Path) -> Result<std::borrow::Cow<'_, [u8]>, git2::Error> {
+    use std::os::unix::prelude::OsStrExt;
+    Ok(std::borrow::Cow::Borrowed(p.as_os_str().as_bytes()))
+}
+
+/// A native path as a `git` path, with `/` separators
+///
+/// On Windows, a path that isn't valid Unicode is an error; see [`path2bytes_lossy`].
+#[cfg(windows)]
+pub fn path2bytes(p: &std::path::Path) -> Result<std::borrow::Cow<'_, [u8]>, git2::Error> {
+    let s = p.to_str().ok_or_else(|| {
+        git2::Error::new(
+            git2::ErrorCode::Invalid,
+            git2::ErrorClass::Invalid,
+            format!("path is not valid Unicode: {}", p.display()),
+        )
+    })?;
+    Ok(windows_separators(std::borrow::Cow::Borrowed(s)))
+}
+
+/// Like [`path2bytes`], replacing what can't be represented with `U+FFFD`
+#[cfg(unix)]
+pub fn path2bytes_lossy(p: &std::path::Path) -> std::borrow::Cow<'_, [u8]> {
+    use std::os::unix::prelude::OsStrExt;
+    std::borrow::Cow::Borrowed(p.as_os_str().as_bytes())
+}
+
+/// Like [`path2bytes`], replacing what can't be represented with `U+FFFD`
+#[cfg(windows)]
+pub fn path2bytes_lossy(p: &std::path::Path) -> std::borrow::Cow<'_, [u8]> {
+    windows_separators(p.to_string_lossy())
+}
+
+#[cfg(windows)]
+fn windows_separators(s: std::borrow::Cow<'_, str>) -> std::borrow::Cow<'_, [u8]> {
+    if s.contains('\\') {
+        std::borrow::Cow::Owned(s.replace('\\', "/").into_bytes())
+    } else {
+        match s {
+            std::borrow::Cow::Borrowed(s) => std::borrow::Cow::Borrowed(s.as_bytes()),
+            std::borrow::Cow::Owned(s) => std::borrow::Cow::Owned(s.into_bytes()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let path = bytes2path(b"dir/file.txt").unwrap();
+        assert_eq!(path, std::path::Path::new("dir/file.txt"));
+        assert_eq!(path2bytes(path).unwrap().as_ref(), b"dir/file.txt");
+        assert_eq!(path2bytes_lossy(path).as_ref(), b"dir/file.txt");
+
+        let odd = b"caf\xe9.txt";
+        let lossy = bytes2path_lossy(odd);
+        if cfg!(windows) {
+            bytes2path(odd).unwrap_err();
+            assert_eq!(lossy, std::path::Path::new("caf\u{FFFD}.txt"));
+        } else {
+            assert_eq!(path2bytes(&lossy).unwrap().as_ref(), odd);
+        }
+    }
 }
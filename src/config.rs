@@ -0,0 +1,447 @@
+//! Read `git` config with `git`s semantics
+//!
+//! [`git2::Config`] hands back raw values.  These accessors add what `git` layers on top, like
+//! multi-valued keys, size units, colors, `~` in paths, and keys scoped to a URL.  Unset keys
+//! are `None` while malformed values are errors.
+
+use std::path::PathBuf;
+
+/// The last value of `key`
+pub fn get_string(config: &git2::Config, key: &str) -> Result<Option<String>, git2::Error> {
+    not_found_as_none(config.get_string(key))
+}
+
+/// Every value of a multi-valued `key`, in the order `git` reads them
+pub fn get_all(config: &git2::Config, key: &str) -> Result<Vec<String>, git2::Error> {
+    let mut values = Vec::new();
+    let mut entries = match config.multivar(key, None) {
+        Ok(entries) => entries,
+        Err(err) if err.code() == git2::ErrorCode::NotFound => return Ok(values),
+        Err(err) => return Err(err),
+    };
+    while let Some(entry) = entries.next() {
+        let entry = entry?;
+        values.push(String::from_utf8_lossy(entry.value_bytes()).into_owned());
+    }
+    Ok(values)
+}
+
+/// `key` as a boolean, see [`parse_bool`]
+///
+/// A key without a value, like `[core] bare`, is `true`.
+pub fn get_bool(config: &git2::Config, key: &str) -> Result<Option<bool>, git2::Error> {
+    let entry = match not_found_as_none(config.get_entry(key))? {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+    if !entry.has_value() {
+        return Ok(Some(true));
+    }
+    let value = String::from_utf8_lossy(entry.value_bytes());
+    parse_bool(&value)
+        .map(Some)
+        .ok_or_else(|| invalid(key, "boolean", &value))
+}
+
+/// Parse a boolean like `git`
+///
+/// `true`, `yes`, `on`, and non-zero numbers are `true` while `false`, `no`, `off`, `0`, and
+/// the empty string are `false`, ignoring case.
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" => Some(true),
+        "false" | "no" | "off" | "" => Some(false),
+        value => value.parse::<i64>().ok().map(|value| value != 0),
+    }
+}
+
+/// `key` as a size in bytes, see [`parse_size`]
+pub fn get_size(config: &git2::Config, key: &str) -> Result<Option<u64>, git2::Error> {
+    get_string(config, key)?
+        .map(|value| parse_size(&value).ok_or_else(|| invalid(key, "size", &value)))
+        .transpose()
+}
+
+/// Parse a number with an optional `k`, `m`, or `g` unit, like `1g` for `core.bigFileThreshold`
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (number, unit) = match value.char_indices().last()? {
+        (i, unit) if unit.is_ascii_alphabetic() => (&value[..i], Some(unit)),
+        _ => (value, None),
+    };
+    let multiplier = match unit.map(|unit| unit.to_ascii_lowercase()) {
+        None => 1,
+        Some('k') => 1 << 10,
+        Some('m') => 1 << 20,
+        Some('g') => 1 << 30,
+        Some(_) => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// `key` as a path, with `~` expanded like `git` does
+///
+/// See [`crate::utils::expanduser`].
+pub fn get_path(config: &git2::Config, key: &str) -> Result<Option<PathBuf>, git2::Error> {
+    Ok(get_string(config, key)?.map(|path| crate::utils::expanduser(std::path::Path::new(&path))))
+}
+
+/// `key` as a color, see [`parse_color`]
+pub fn get_color(config: &git2::Config, key: &str) -> Result<Option<ColorSpec>, git2::Error> {
+    get_string(config, key)?
+        .map(|value| parse_color(&value).ok_or_else(|| invalid(key, "color", &value)))
+        .transpose()
+}
+
+/// Parse a color like `color.diff.old`, e.g. `bold red`, `#ff0000 ul`, or `reset`
+///
+/// The first color is the foreground and the second the background.
+pub fn parse_color(value: &str) -> Option<ColorSpec> {
+    let mut spec = ColorSpec::default();
+    let mut colors = 0;
+    for word in value.split_whitespace() {
+        let word = word.to_ascii_lowercase();
+        if word == "reset" {
+            spec.reset = true;
+            continue;
+        }
+        if let Some(color) = Color::parse(&word) {
+            match colors {
+                0 => spec.foreground = Some(color),
+                1 => spec.background = Some(color),
+                _ => return None,
+            }
+            colors += 1;
+            continue;
+        }
+        let negated = word.strip_prefix("no-").or_else(|| word.strip_prefix("no"));
+        match negated.and_then(ColorAttribute::parse) {
+            Some(attribute) => spec.negated_attributes.push(attribute),
+            None => spec.attributes.push(ColorAttribute::parse(&word)?),
+        }
+    }
+    Some(spec)
+}
+
+/// A color for one kind of output, see [`parse_color`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ColorSpec {
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub attributes: Vec<ColorAttribute>,
+    /// Attributes turned off, like `nobold`
+    pub negated_attributes: Vec<ColorAttribute>,
+    /// Reset everything before applying the rest
+    pub reset: bool,
+}
+
+impl ColorSpec {
+    /// The ANSI escape sequence `git` would write for this color
+    pub fn to_ansi(&self) -> String {
+        let mut codes = Vec::new();
+        if self.reset {
+            codes.push("0".to_owned());
+        }
+        codes.extend(
+            self.attributes
+                .iter()
+                .map(|attribute| attribute.code().to_string()),
+        );
+        codes.extend(
+            self.negated_attributes
+                .iter()
+                .map(|attribute| attribute.negated_code().to_string()),
+        );
+        codes.extend(self.foreground.and_then(|color| color.code(false)));
+        codes.extend(self.background.and_then(|color| color.code(true)));
+        if codes.is_empty() {
+            String::new()
+        } else {
+            format!("\x1b[{}m", codes.join(";"))
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Color {
+    /// Leave the color as-is
+    Normal,
+    /// The terminal's default color
+    Default,
+    /// One of the 8 basic colors, `black` (0) through `white` (7)
+    Ansi(u8),
+    /// The bright variant of a basic color, like `brightred`
+    Bright(u8),
+    /// A color from the 256-color palette
+    Ansi256(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    const NAMES: [&'static str; 8] = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+
+    fn parse(word: &str) -> Option<Self> {
+        if word == "normal" {
+            return Some(Self::Normal);
+        }
+        if word == "default" {
+            return Some(Self::Default);
+        }
+        if let Some(index) = Self::NAMES.iter().position(|name| *name == word) {
+            return Some(Self::Ansi(index as u8));
+        }
+        if let Some(index) = word
+            .strip_prefix("bright")
+            .and_then(|word| Self::NAMES.iter().position(|name| *name == word))
+        {
+            return Some(Self::Bright(index as u8));
+        }
+        if let Some(hex) = word.strip_prefix('#') {
+            if hex.len() != 6 || !hex.is_ascii() {
+                return None;
+            }
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+            return Some(Self::Rgb(channel(0)?, channel(2)?, channel(4)?));
+        }
+        match word.parse::<i16>().ok()? {
+            -1 => Some(Self::Normal),
+            value => u8::try_from(value).ok().map(Self::Ansi256),
+        }
+    }
+
+    fn code(self, background: bool) -> Option<String> {
+        let base = if background { 40 } else { 30 };
+        match self {
+            Self::Normal => None,
+            Self::Default => Some((base + 9).to_string()),
+            Self::Ansi(index) => Some((base + u32::from(index)).to_string()),
+            Self::Bright(index) => Some((base + 60 + u32::from(index)).to_string()),
+            Self::Ansi256(index) => Some(format!("{};5;{index}", base + 8)),
+            Self::Rgb(r, g, b) => Some(format!("{};2;{r};{g};{b}", base + 8)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorAttribute {
+    Bold,
+    Dim,
+    Italic,
+    Underline,
+    Blink,
+    Reverse,
+    Strike,
+}
+
+impl ColorAttribute {
+    fn parse(word: &str) -> Option<Self> {
+        match word {
+            "bold" => Some(Self::Bold),
+            "dim" => Some(Self::Dim),
+            "italic" => Some(Self::Italic),
+            "ul" => Some(Self::Underline),
+            "blink" => Some(Self::Blink),
+            "reverse" => Some(Self::Reverse),
+            "strike" => Some(Self::Strike),
+            _ => None,
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            Self::Bold => 1,
+            Self::Dim => 2,
+            Self::Italic => 3,
+            Self::Underline => 4,
+            Self::Blink => 5,
+            Self::Reverse => 7,
+            Self::Strike => 9,
+        }
+    }
+
+    fn negated_code(self) -> u8 {
+        match self {
+            // Both are turned off by "normal intensity"
+            Self::Bold | Self::Dim => 22,
+            attribute => 20 + attribute.code(),
+        }
+    }
+}
+
+/// The key for `name` in a subsection, like `branch.<branch>.merge`
+///
+/// Subsections are case-sensitive, unlike sections and key names.
+pub fn subsection_key(section: &str, subsection: &str, name: &str) -> String {
+    format!("{section}.{subsection}.{name}")
+}
+
+/// The value of `<section>.<url>.<name>` that best matches `url`, falling back to
+/// `<section>.<name>`, like `git` does for `http.<url>.*`
+///
+/// Among matching URLs, the one with the longest path wins, then one with a user name; ties go
+/// to the last one set.  See [`crate::auth::url_matches`] for how URLs match.
+pub fn get_url_matched(
+    config: &git2::Config,
+    section: &str,
+    url: &str,
+    name: &str,
+) -> Result<Option<String>, git2::Error> {
+    let section = section.to_ascii_lowercase();
+    let name = name.to_ascii_lowercase();
+    let Some(target) = crate::auth::Credential::from_url(url) else {
+        return get_string(config, &format!("{section}.{name}"));
+    };
+
+    let mut best: Option<((usize, bool), String)> = None;
+    let mut fallback = None;
+    let mut entries = config.entries(Some(&format!("^{section}\\..*{name}$")))?;
+    while let Some(entry) = entries.next() {
+        let entry = entry?;
+        let Some(entry_name) = entry.name() else {
+            continue;
+        };
+        let value = String::from_utf8_lossy(entry.value_bytes()).into_owned();
+        let Some(rest) = entry_name
+            .strip_prefix(&section)
+            .and_then(|rest| rest.strip_prefix('.'))
+        else {
+            continue;
+        };
+        if rest == name {
+            fallback = Some(value);
+            continue;
+        }
+        let Some(pattern) = rest
+            .strip_suffix(&name)
+            .and_then(|rest| rest.strip_suffix('.'))
+        else {
+            continue;
+        };
+        if !crate::auth::url::context_matches(pattern, &target) {
+            continue;
+        }
+        let Some(pattern) = crate::auth::Credential::from_url(pattern) else {
+            continue;
+        };
+        let specificity = (
+            pattern
+                .path
+                .as_deref()
+                .unwrap_or_default()
+                .trim_end_matches('/')
+                .len(),
+            pattern.username.is_some(),
+        );
+        if !matches!(&best, Some((best, _)) if specificity < *best) {
+            best = Some((specificity, value));
+        }
+    }
+    Ok(best.map(|(_, value)| value).or(fallback))
+}
+
+fn not_found_as_none<T>(result: Result<T, git2::Error>) -> Result<Option<T>, git2::Error> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn invalid(key: &str, kind: &str, value: &str) -> git2::Error {
+    git2::Error::new(
+        git2::ErrorCode::Invalid,
+        git2::ErrorClass::Config,
+        format!("invalid {kind} `{value}` for `{key}`"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_bool("Yes"), Some(true));
+        assert_eq!(parse_bool("2"), Some(true));
+        assert_eq!(parse_bool(""), Some(false));
+        assert_eq!(parse_bool("maybe"), None);
+
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("1k"), Some(1024));
+        assert_eq!(parse_size("1G"), Some(1 << 30));
+        assert_eq!(parse_size("1t"), None);
+
+        assert_eq!(
+            parse_color("bold red #00ff00").map(|color| color.to_ansi()),
+            Some("\x1b[1;31;48;2;0;255;0m".to_owned())
+        );
+        assert_eq!(
+            parse_color("reset brightblue nodim 208").map(|color| color.to_ansi()),
+            Some("\x1b[0;22;94;48;5;208m".to_owned())
+        );
+        assert_eq!(
+            parse_color("normal").map(|color| color.to_ansi()),
+            Some(String::new())
+        );
+        assert_eq!(parse_color("red blue green"), None);
+        assert_eq!(parse_color("sparkly"), None);
+    }
+
+    #[test]
+    fn test_config() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let config_path = git.repo_path.join(".git").join("config");
+        let mut content = std::fs::read_to_string(&config_path)?;
+        content.push_str(
+            "\
+[core]
+    flag
+    bigFileThreshold = 2m
+[remote \"origin\"]
+    fetch = +refs/heads/*:refs/remotes/origin/*
+    fetch = +refs/tags/*:refs/tags/*
+[http]
+    proxy = default
+[http \"https://example.com\"]
+    proxy = host
+[http \"https://example.com/org\"]
+    proxy = org
+[http \"https://*.example.com\"]
+    proxy = wildcard
+",
+        );
+        std::fs::write(&config_path, content)?;
+
+        let repo = git.get_repo()?;
+        let config = repo.config()?.snapshot()?;
+        assert_eq!(get_bool(&config, "core.flag")?, Some(true));
+        assert_eq!(get_bool(&config, "core.missing")?, None);
+        assert_eq!(get_size(&config, "core.bigFileThreshold")?, Some(2 << 20));
+        assert_eq!(
+            get_all(&config, &subsection_key("remote", "origin", "fetch"))?,
+            [
+                "+refs/heads/*:refs/remotes/origin/*",
+                "+refs/tags/*:refs/tags/*"
+            ]
+        );
+
+        let proxy = |url: &str| get_url_matched(&config, "http", url, "proxy");
+        assert_eq!(
+            proxy("https://example.com/org/repo")?.as_deref(),
+            Some("org")
+        );
+        assert_eq!(proxy("https://example.com/other")?.as_deref(), Some("host"));
+        assert_eq!(
+            proxy("https://git.example.com/")?.as_deref(),
+            Some("wildcard")
+        );
+        assert_eq!(proxy("https://elsewhere.com")?.as_deref(), Some("default"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+//! Stable textual dumps of repository state, for snapshot tests
+//!
+//! Each dump lists its items in a fixed order with one item per line, so two dumps of the same
+//! state compare equal and a change shows up as a readable diff.
+
+use std::fmt::Write as _;
+
+/// Every entry of `tree`, recursively, like `git ls-tree -r -t`
+///
+/// Entries are listed in tree order, directories before their contents, as
+/// `<mode> <kind> <id>\t<path>`.
+pub fn dump_tree(tree: &git2::Tree<'_>) -> Result<String, git2::Error> {
+    let mut dump = String::new();
+    tree.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+        let name = String::from_utf8_lossy(entry.name_bytes());
+        let kind = entry.kind().map(|kind| kind.str()).unwrap_or("unknown");
+        let (mode, id) = (entry.filemode(), entry.id());
+        let _ = writeln!(dump, "{mode:06o} {kind} {id}\t{root}{name}");
+        git2::TreeWalkResult::Ok
+    })?;
+    Ok(dump)
+}
+
+/// The headers and message of `commit`, like `git cat-file commit`
+pub fn dump_commit(commit: &git2::Commit<'_>) -> String {
+    let header = String::from_utf8_lossy(commit.raw_header_bytes());
+    let message = String::from_utf8_lossy(commit.message_raw_bytes());
+    format!("{}\n\n{message}", header.trim_end_matches('\n'))
+}
+
+/// `HEAD` and every ref, sorted by name, like `git show-ref --head`
+///
+/// Refs are listed as `<id>\t<name>`, or `ref: <target>\t<name>` for symbolic refs.
+pub fn dump_refs(repo: &git2::Repository) -> Result<String, git2::Error> {
+    let mut dump = String::new();
+    for reference in sorted_refs(repo)? {
+        let name = String::from_utf8_lossy(reference.name_bytes());
+        let target = match (reference.symbolic_target_bytes(), reference.target()) {
+            (Some(target), _) => format!("ref: {}", String::from_utf8_lossy(target)),
+            (None, Some(id)) => id.to_string(),
+            (None, None) => continue,
+        };
+        let _ = writeln!(dump, "{target}\t{name}");
+    }
+    Ok(dump)
+}
+
+/// The reflogs of `HEAD` and every ref, sorted by name and then newest entry first
+///
+/// Entries are listed as `<name>@{<n>} <old id> <new id> <message>`.  Committers and times
+/// are left out, as they vary between runs.
+pub fn dump_reflogs(repo: &git2::Repository) -> Result<String, git2::Error> {
+    let mut dump = String::new();
+    for reference in sorted_refs(repo)? {
+        let Some(name) = reference.name() else {
+            continue;
+        };
+        for (n, entry) in crate::refs::reflog(repo, name)?.iter().enumerate() {
+            let _ = writeln!(
+                dump,
+                "{name}@{{{n}}} {} {} {}",
+                entry.old_id, entry.new_id, entry.message
+            );
+        }
+    }
+    Ok(dump)
+}
+
+/// `HEAD`, when there is one, followed by every other ref in name order
+fn sorted_refs(repo: &git2::Repository) -> Result<Vec<git2::Reference<'_>>, git2::Error> {
+    let mut refs = repo.references()?.collect::<Result<Vec<_>, _>>()?;
+    refs.sort_by(|lhs, rhs| lhs.name_bytes().cmp(rhs.name_bytes()));
+    match repo.find_reference("HEAD") {
+        Ok(head) => refs.insert(0, head),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    Ok(refs)
+}
+
+#[cfg(test)]
+mod tests {
+    use snapbox::assert_data_eq;
+    use snapbox::str;
+
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_dumps() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.write_file("dir/nested", "nested")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "nested"])?;
+        git.run(&["branch", "side"])?;
+
+        let repo = git.get_repo()?;
+        let head = repo.head()?.peel_to_commit()?;
+        assert_data_eq!(
+            dump_tree(&head.tree()?)?,
+            str![[r#"
+040000 tree 4b5e1416a5c895693241bc6d9e5b99b604763dae	dir
+100644 blob bfe53d766e64d78f80050b73cd1c88095bc70abb	dir/nested.txt
+100644 blob 63af22885f8665a312ba8b83db722134f1f8290d	initial.txt
+
+"#]]
+        );
+        assert_data_eq!(
+            dump_commit(&head),
+            str![[r#"
+tree d4c8ef0315a7cedaacf04453d9bbedb04ba746c2
+parent f777ecc9b0db5ed372b2615695191a8a17f79f24
+author Testy McTestface <test@example.com> 1603974896 +0000
+committer Testy McTestface <test@example.com> 1603974896 +0000
+
+nested
+
+"#]]
+        );
+        assert_data_eq!(
+            dump_refs(&repo)?,
+            str![[r#"
+ref: refs/heads/master	HEAD
+1f3c47416962d40bb7d3cceda529c798ec5bca65	refs/heads/master
+1f3c47416962d40bb7d3cceda529c798ec5bca65	refs/heads/side
+
+"#]]
+        );
+        assert_data_eq!(
+            dump_reflogs(&repo)?,
+            str![[r#"
+HEAD@{0} f777ecc9b0db5ed372b2615695191a8a17f79f24 1f3c47416962d40bb7d3cceda529c798ec5bca65 commit: nested
+HEAD@{1} 0000000000000000000000000000000000000000 f777ecc9b0db5ed372b2615695191a8a17f79f24 commit (initial): create initial.txt
+refs/heads/master@{0} f777ecc9b0db5ed372b2615695191a8a17f79f24 1f3c47416962d40bb7d3cceda529c798ec5bca65 commit: nested
+refs/heads/master@{1} 0000000000000000000000000000000000000000 f777ecc9b0db5ed372b2615695191a8a17f79f24 commit (initial): create initial.txt
+refs/heads/side@{0} 0000000000000000000000000000000000000000 1f3c47416962d40bb7d3cceda529c798ec5bca65 branch: Created from master
+
+"#]]
+        );
+
+        Ok(())
+    }
+}
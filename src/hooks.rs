@@ -1,20 +1,35 @@
+/// Environment variable listing hooks to skip, separated by commas
+pub const SKIP_HOOKS_ENV: &str = "GIT2_EXT_SKIP_HOOKS";
+
 #[derive(Clone, Debug)]
 pub struct Hooks {
     root: std::path::PathBuf,
+    skip: Vec<String>,
+    stdio: HookStdio,
+    namespace: Option<crate::refs::Namespace>,
 }
 
 impl Hooks {
     pub fn new(hook_root: impl Into<std::path::PathBuf>) -> Self {
         Self {
             root: hook_root.into(),
+            skip: Vec::new(),
+            stdio: HookStdio::default(),
+            namespace: None,
         }
     }
 
+    /// Use the hooks directory `git` would for `repo`
+    ///
+    /// This is `core.hooksPath`, if set, and otherwise the `hooks` directory of the common
+    /// directory so linked worktrees share the hooks of the main repository.
     pub fn with_repo(repo: &git2::Repository) -> Result<Self, git2::Error> {
         let config = repo.config()?;
-        let root = config
-            .get_path("core.hooksPath")
-            .unwrap_or_else(|_| repo.path().join("hooks"));
+        let root = match crate::config::get_path(&config, "core.hooksPath")? {
+            // Relative paths are relative to where hooks are run
+            Some(root) => repo.workdir().unwrap_or_else(|| repo.path()).join(root),
+            None => crate::utils::common_dir(repo).join("hooks"),
+        };
         Ok(Self::new(root))
     }
 
@@ -22,23 +37,174 @@ impl Hooks {
         &self.root
     }
 
-    pub fn find_hook(&self, _repo: &git2::Repository, name: &str) -> Option<std::path::PathBuf> {
+    /// Don't run the hook called `name`
+    ///
+    /// This is for automation that wants to bypass slow local hooks (e.g. `pre-commit`
+    /// formatters) during bulk rewrites.  Hooks can also be skipped by listing them in
+    /// [`SKIP_HOOKS_ENV`].
+    pub fn skip(mut self, name: impl Into<String>) -> Self {
+        self.skip.push(name.into());
+        self
+    }
+
+    /// Where hook output should go
+    ///
+    /// Defaults to [`HookStdio::Stderr`], matching `git`.
+    pub fn stdio(mut self, stdio: HookStdio) -> Self {
+        self.stdio = stdio;
+        self
+    }
+
+    /// Run hooks as serving `namespace`, setting `GIT_NAMESPACE`
+    ///
+    /// See [`Hooks::receive`] for how ref names are translated.
+    pub fn namespace(mut self, namespace: crate::refs::Namespace) -> Self {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    /// Whether the hook called `name` will be skipped when run
+    pub fn is_skipped(&self, name: &str) -> bool {
+        if self.skip.iter().any(|s| s == name) {
+            return true;
+        }
+
+        std::env::var(SKIP_HOOKS_ENV)
+            .map(|skip| skip.split(',').map(str::trim).any(|s| s == name))
+            .unwrap_or(false)
+    }
+
+    /// Look up the hook called `name`, reporting why it can't be run
+    pub fn find_hook(&self, repo: &git2::Repository, name: &str) -> HookLookup {
+        let mut checked = Vec::new();
+        let mut not_executable = None;
+
         let mut hook_path = self.root().join(name);
         if is_executable(&hook_path) {
-            return Some(hook_path);
+            return HookLookup::Found(hook_path);
         }
+        if hook_path.is_file() {
+            not_executable.get_or_insert_with(|| hook_path.clone());
+        }
+        checked.push(hook_path.clone());
 
-        if !std::env::consts::EXE_SUFFIX.is_empty() {
-            hook_path.set_extension(std::env::consts::EXE_SUFFIX);
+        if !std::env::consts::EXE_EXTENSION.is_empty() {
+            hook_path.set_extension(std::env::consts::EXE_EXTENSION);
             if is_executable(&hook_path) {
-                return Some(hook_path);
+                return HookLookup::Found(hook_path);
+            }
+            if hook_path.is_file() {
+                not_executable.get_or_insert_with(|| hook_path.clone());
             }
+            checked.push(hook_path);
         }
 
-        // Technically, we should check `advice.ignoredHook` and warn users if the hook is present
-        // but not executable.  Supporting this in the future is why we accept `repo`.
+        if let Some(hook_path) = not_executable {
+            let advise = repo
+                .config()
+                .and_then(|c| c.get_bool("advice.ignoredHook"))
+                .unwrap_or(true);
+            if advise {
+                log::warn!(
+                    "The '{}' hook was ignored because it's not set as executable.",
+                    hook_path.display()
+                );
+            }
+            return HookLookup::NotExecutable(hook_path);
+        }
 
-        None
+        HookLookup::Missing(checked)
+    }
+
+    /// All hook files in [`Hooks::root`], ignoring `git`s `.sample` files
+    pub fn list_hooks(
+        &self,
+        repo: &git2::Repository,
+    ) -> Result<Vec<(String, HookLookup)>, std::io::Error> {
+        let entries = match std::fs::read_dir(self.root()) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        let mut names = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let path = entry.path();
+            if path.extension() == Some(std::ffi::OsStr::new("sample")) {
+                continue;
+            }
+            let name = if std::env::consts::EXE_SUFFIX.is_empty() {
+                path.file_name()
+            } else {
+                path.file_stem()
+            };
+            if let Some(name) = name.and_then(|n| n.to_str()) {
+                names.push(name.to_owned());
+            }
+        }
+        names.sort();
+        names.dedup();
+
+        Ok(names
+            .into_iter()
+            .map(|name| {
+                let lookup = self.find_hook(repo, &name);
+                (name, lookup)
+            })
+            .collect())
+    }
+
+    /// Install a hook that runs `command`, a shell snippet passed the hook's arguments
+    ///
+    /// Existing hooks are left alone, reporting [`std::io::ErrorKind::AlreadyExists`], unless
+    /// they were installed by this function or `force` is set.
+    pub fn install_hook(
+        &self,
+        name: &str,
+        command: &str,
+        force: bool,
+    ) -> Result<std::path::PathBuf, std::io::Error> {
+        let hook_path = self.root().join(name);
+        if hook_path.exists() && !force && !is_shim(&hook_path) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("`{name}` hook already exists at {}", hook_path.display()),
+            ));
+        }
+
+        // Even on Windows, `git` runs hooks through `sh` so a shebang script is portable
+        let shim = format!("#!/bin/sh\n{SHIM_MARKER}\n{command} \"$@\"\n");
+        std::fs::create_dir_all(self.root())?;
+        std::fs::write(&hook_path, shim)?;
+        set_executable(&hook_path)?;
+
+        Ok(hook_path)
+    }
+
+    /// Remove a hook installed by [`Hooks::install_hook`], reporting whether one was removed
+    ///
+    /// Hooks installed by other means are left alone unless `force` is set.
+    pub fn uninstall_hook(&self, name: &str, force: bool) -> Result<bool, std::io::Error> {
+        let hook_path = self.root().join(name);
+        if !hook_path.exists() {
+            return Ok(false);
+        }
+        if !force && !is_shim(&hook_path) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!(
+                    "`{name}` hook at {} was not installed by us",
+                    hook_path.display()
+                ),
+            ));
+        }
+
+        std::fs::remove_file(&hook_path)?;
+        Ok(true)
     }
 
     pub fn run_hook(
@@ -49,65 +215,136 @@ impl Hooks {
         stdin: Option<&[u8]>,
         env: &[(&str, &str)],
     ) -> Result<i32, std::io::Error> {
-        const SIGNAL_EXIT_CODE: i32 = 1;
-
-        let hook_path = if let Some(hook_path) = self.find_hook(repo, name) {
-            hook_path
-        } else {
-            return Ok(0);
-        };
-        let bin_name = hook_path
-            .file_name()
-            .expect("find_hook always returns a bin name")
-            .to_str()
-            .expect("find_hook always returns a utf-8 bin name");
+        let mut stdin = stdin;
+        self.run_hook_streaming(
+            repo,
+            name,
+            args,
+            stdin
+                .as_mut()
+                .map(|stdin| stdin as &mut (dyn std::io::Read + Send)),
+            env,
+        )
+    }
 
-        let path = {
-            let mut path_components: Vec<std::path::PathBuf> =
-                vec![std::fs::canonicalize(self.root())?];
-            if let Some(path) = std::env::var_os(std::ffi::OsStr::new("PATH")) {
-                path_components.extend(std::env::split_paths(&path));
-            }
-            std::env::join_paths(path_components)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?
-        };
+    /// Like [`Hooks::run_hook`] but without blocking an async task, see
+    /// [`crate::utils::spawn_blocking`]
+    #[cfg(feature = "tokio")]
+    pub fn run_hook_async(
+        &self,
+        repo: &git2::Repository,
+        name: &str,
+        args: &[&str],
+        stdin: Option<&[u8]>,
+        env: &[(&str, &str)],
+    ) -> crate::utils::Blocking<Result<i32, std::io::Error>> {
+        let name = name.to_owned();
+        let args = args.iter().map(|arg| (*arg).to_owned()).collect::<Vec<_>>();
+        let stdin = stdin.map(<[u8]>::to_vec);
+        let env = env
+            .iter()
+            .map(|(key, value)| ((*key).to_owned(), (*value).to_owned()))
+            .collect::<Vec<_>>();
+        let hooks = self.clone();
+        let path = repo.path().to_owned();
+        crate::utils::spawn_blocking(move || {
+            let repo = git2::Repository::open(path).map_err(std::io::Error::other)?;
+            let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+            let env = env
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect::<Vec<_>>();
+            hooks.run_hook(&repo, &name, &args, stdin.as_deref(), &env)
+        })
+    }
 
-        let sh_path = crate::utils::git_sh().ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::NotFound, "No `sh` for running hooks")
-        })?;
+    /// Run any of the hook runners without blocking an async task, see
+    /// [`crate::utils::spawn_blocking`]
+    ///
+    /// `run` is given its own handle on `repo`, like
+    /// `hooks.run_async(&repo, move |hooks, repo| hooks.run_commit_msg(repo, &message))`.
+    #[cfg(feature = "tokio")]
+    pub fn run_async<T, F>(
+        &self,
+        repo: &git2::Repository,
+        run: F,
+    ) -> crate::utils::Blocking<Result<T, git2::Error>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Hooks, &git2::Repository) -> T + Send + 'static,
+    {
+        let hooks = self.clone();
+        let path = repo.path().to_owned();
+        crate::utils::spawn_blocking(move || {
+            let repo = git2::Repository::open(path)?;
+            Ok(run(&hooks, &repo))
+        })
+    }
 
-        // From `githooks(5)`:
-        // > Before Git invokes a hook, it changes its working directory to either $GIT_DIR in a bare
-        // > repository or the root of the working tree in a non-bare repository. An exception are
-        // > hooks triggered during a push (pre-receive, update, post-receive, post-update,
-        // > push-to-checkout) which are always executed in $GIT_DIR.
-        let cwd = if PUSH_HOOKS.contains(&name) {
-            repo.path()
-        } else {
-            repo.workdir().unwrap_or_else(|| repo.path())
-        };
+    /// Run a hook, streaming `stdin` into it
+    ///
+    /// `stdin` is written from a separate thread while waiting on the hook so large inputs (e.g.
+    /// `pre-receive`, `post-rewrite`) don't need to be buffered up front and can't deadlock
+    /// against a hook that is busy writing its own output.
+    pub fn run_hook_streaming(
+        &self,
+        repo: &git2::Repository,
+        name: &str,
+        args: &[&str],
+        stdin: Option<&mut (dyn std::io::Read + Send)>,
+        env: &[(&str, &str)],
+    ) -> Result<i32, std::io::Error> {
+        self.run_hook_output(repo, name, args, stdin, env)
+            .map(|output| output.code)
+    }
 
-        let mut cmd = std::process::Command::new(sh_path);
-        cmd.arg("-c")
-            .arg(format!("{bin_name} \"$@\""))
-            .arg(bin_name) // "$@" expands "$1" "$2" "$3" ... but we also must specify $0.
-            .args(args)
-            .env("PATH", path)
-            .current_dir(cwd)
-            // Technically, git maps stdout to stderr when running hooks
-            .stdin(std::process::Stdio::piped());
+    /// Run a hook, reporting its output
+    ///
+    /// Output is only captured with [`HookStdio::Capture`]; otherwise it is left empty.
+    pub fn run_hook_output(
+        &self,
+        repo: &git2::Repository,
+        name: &str,
+        args: &[&str],
+        stdin: Option<&mut (dyn std::io::Read + Send)>,
+        env: &[(&str, &str)],
+    ) -> Result<HookOutput, std::io::Error> {
+        let mut invocation = self.invoke(name).args(args.iter().copied());
         for (key, value) in env.iter().copied() {
-            cmd.env(key, value);
+            invocation = invocation.env(key, value);
         }
-        let mut process = cmd.spawn()?;
         if let Some(stdin) = stdin {
-            use std::io::Write;
-
-            process.stdin.as_mut().unwrap().write_all(stdin)?;
+            invocation = invocation.stdin(stdin);
         }
-        let exit = process.wait()?;
+        invocation.run(repo)
+    }
 
-        Ok(exit.code().unwrap_or(SIGNAL_EXIT_CODE))
+    /// Prepare to run the hook called `name`
+    ///
+    /// ```no_run
+    /// # let repo = git2::Repository::open_from_env()?;
+    /// let hooks = git2_ext::hooks::Hooks::with_repo(&repo)?;
+    /// let output = hooks
+    ///     .invoke("pre-push")
+    ///     .arg("origin")
+    ///     .arg("https://example.com/repo.git")
+    ///     .stdin(&b""[..])
+    ///     .timeout(std::time::Duration::from_secs(60))
+    ///     .run(&repo)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn invoke(&self, name: impl Into<String>) -> HookInvocation<'_> {
+        HookInvocation {
+            hooks: self,
+            name: name.into(),
+            args: Vec::new(),
+            env: Vec::new(),
+            stdin: None,
+            cwd: None,
+            timeout: None,
+            cancel: None,
+            stdio: self.stdio,
+        }
     }
 
     /// Run `post-rewrite` hook as if called by `git rebase`
@@ -145,6 +382,245 @@ impl Hooks {
         }
     }
 
+    /// Run `pre-push` hook as if called by `git push`
+    ///
+    /// `remote_name` is the name of the remote, or its URL if it isn't named.
+    ///
+    /// The push should be aborted if the hook fails.
+    pub fn run_pre_push(
+        &self,
+        repo: &git2::Repository,
+        remote_name: &str,
+        url: &str,
+        updates: &[PushUpdate<'_>],
+    ) -> Result<(), std::io::Error> {
+        let name = "pre-push";
+        let stdin = pre_push_stdin(updates);
+        let output = self
+            .invoke(name)
+            .arg(remote_name)
+            .arg(url)
+            .stdin(stdin.as_bytes())
+            .run(repo)?;
+        if output.success() {
+            Ok(())
+        } else {
+            let code = output.code;
+            log::trace!("Hook `{name}` failed with code {code}");
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                format!("`{name}` hook failed with code {code}"),
+            ))
+        }
+    }
+
+    /// Simulate `git receive-pack` accepting `updates` (old, new, name)
+    ///
+    /// This runs
+    /// 1. `pre-receive` with all updates, rejecting all of them if it fails
+    /// 2. `update` for each ref, rejecting only that ref if it fails
+    /// 3. `apply` with the accepted updates, for the caller to actually update the refs
+    /// 4. `post-receive` and `post-update` with the accepted updates
+    ///
+    /// Like `git`, these run in `$GIT_DIR`.  `old` and `new` are zeroed out for created and
+    /// deleted refs, respectively.
+    ///
+    /// With a [`Hooks::namespace`], `updates` and the hooks use ref names as the client sees
+    /// them, while `apply` gets the full names to update.
+    pub fn receive(
+        &self,
+        repo: &git2::Repository,
+        updates: &[(git2::Oid, git2::Oid, &str)],
+        apply: impl FnOnce(&[(git2::Oid, git2::Oid, &str)]) -> Result<(), std::io::Error>,
+    ) -> Result<Vec<ReceiveStatus>, std::io::Error> {
+        let code = self.run_pre_receive(repo, updates)?;
+        if code != 0 {
+            return Ok(vec![ReceiveStatus::PreReceiveRejected(code); updates.len()]);
+        }
+
+        let mut statuses = Vec::with_capacity(updates.len());
+        let mut accepted = Vec::with_capacity(updates.len());
+        for update in updates.iter().copied() {
+            let code = self.run_update(repo, update)?;
+            if code == 0 {
+                statuses.push(ReceiveStatus::Accepted);
+                accepted.push(update);
+            } else {
+                statuses.push(ReceiveStatus::UpdateRejected(code));
+            }
+        }
+
+        if !accepted.is_empty() {
+            match &self.namespace {
+                Some(namespace) => {
+                    let names = accepted
+                        .iter()
+                        .map(|(_, _, name)| namespace.qualify(name))
+                        .collect::<Vec<_>>();
+                    let qualified = accepted
+                        .iter()
+                        .zip(names.iter())
+                        .map(|((old_oid, new_oid, _), name)| (*old_oid, *new_oid, name.as_str()))
+                        .collect::<Vec<_>>();
+                    apply(&qualified)?;
+                }
+                None => apply(&accepted)?,
+            }
+            self.run_post_receive(repo, &accepted);
+            self.run_post_update(repo, &accepted);
+        }
+
+        Ok(statuses)
+    }
+
+    /// Run `pre-receive` hook as if called by `git receive-pack`, returning its exit code
+    pub fn run_pre_receive(
+        &self,
+        repo: &git2::Repository,
+        updates: &[(git2::Oid, git2::Oid, &str)],
+    ) -> Result<i32, std::io::Error> {
+        let name = "pre-receive";
+        let stdin = receive_stdin(updates);
+        let output = self
+            .invoke(name)
+            .env("GIT_DIR", repo.path())
+            .stdin(stdin.as_bytes())
+            .run(repo)?;
+        Ok(output.code)
+    }
+
+    /// Run `update` hook as if called by `git receive-pack`, returning its exit code
+    pub fn run_update(
+        &self,
+        repo: &git2::Repository,
+        (old_oid, new_oid, ref_name): (git2::Oid, git2::Oid, &str),
+    ) -> Result<i32, std::io::Error> {
+        let name = "update";
+        let output = self
+            .invoke(name)
+            .arg(ref_name)
+            .arg(old_oid.to_string())
+            .arg(new_oid.to_string())
+            .env("GIT_DIR", repo.path())
+            .run(repo)?;
+        Ok(output.code)
+    }
+
+    /// Run `post-receive` hook as if called by `git receive-pack`
+    pub fn run_post_receive(
+        &self,
+        repo: &git2::Repository,
+        updates: &[(git2::Oid, git2::Oid, &str)],
+    ) {
+        let name = "post-receive";
+        let stdin = receive_stdin(updates);
+        match self
+            .invoke(name)
+            .env("GIT_DIR", repo.path())
+            .stdin(stdin.as_bytes())
+            .run(repo)
+        {
+            Ok(output) if output.success() => {}
+            Ok(output) => {
+                log::trace!("Hook `{name}` failed with code {}", output.code);
+            }
+            Err(err) => {
+                log::trace!("Hook `{name}` failed with {err}");
+            }
+        }
+    }
+
+    /// Run `post-update` hook as if called by `git receive-pack`
+    pub fn run_post_update(
+        &self,
+        repo: &git2::Repository,
+        updates: &[(git2::Oid, git2::Oid, &str)],
+    ) {
+        let name = "post-update";
+        match self
+            .invoke(name)
+            .args(updates.iter().map(|(_, _, ref_name)| *ref_name))
+            .env("GIT_DIR", repo.path())
+            .run(repo)
+        {
+            Ok(output) if output.success() => {}
+            Ok(output) => {
+                log::trace!("Hook `{name}` failed with code {}", output.code);
+            }
+            Err(err) => {
+                log::trace!("Hook `{name}` failed with {err}");
+            }
+        }
+    }
+
+    /// Run `commit-msg` hook as if called by `git commit`, returning the possibly edited message
+    ///
+    /// The message is passed through `$GIT_DIR/COMMIT_EDITMSG`.  The commit should be aborted if
+    /// the hook fails.
+    pub fn run_commit_msg(
+        &self,
+        repo: &git2::Repository,
+        message: &str,
+    ) -> Result<String, std::io::Error> {
+        let name = "commit-msg";
+        let message_path = repo.path().join("COMMIT_EDITMSG");
+        std::fs::write(&message_path, message)?;
+
+        let output = self.invoke(name).arg(&message_path).run(repo)?;
+        if output.success() {
+            std::fs::read_to_string(&message_path)
+        } else {
+            let code = output.code;
+            log::trace!("Hook `{name}` failed with code {code}");
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                format!("`{name}` hook failed with code {code}"),
+            ))
+        }
+    }
+
+    /// Run `post-commit` hook as if called by `git commit`
+    ///
+    /// The hook is purely a notification; its exit status doesn't affect the commit.
+    pub fn run_post_commit(&self, repo: &git2::Repository) {
+        let name = "post-commit";
+        match self.run_hook(repo, name, &[], None, &[]) {
+            Ok(0) => {}
+            Ok(code) => {
+                log::trace!("Hook `{name}` failed with code {code}");
+            }
+            Err(err) => {
+                log::trace!("Hook `{name}` failed with {err}");
+            }
+        }
+    }
+
+    /// Run `post-checkout` hook as if called by `git checkout`
+    ///
+    /// `branch_checkout` is whether a branch was checked out, rather than files.  The hook is
+    /// purely a notification; its exit status doesn't affect the checkout.
+    pub fn run_post_checkout(
+        &self,
+        repo: &git2::Repository,
+        old_head: git2::Oid,
+        new_head: git2::Oid,
+        branch_checkout: bool,
+    ) {
+        let name = "post-checkout";
+        let old_head = old_head.to_string();
+        let new_head = new_head.to_string();
+        let flag = if branch_checkout { "1" } else { "0" };
+        match self.run_hook(repo, name, &[&old_head, &new_head, flag], None, &[]) {
+            Ok(0) => {}
+            Ok(code) => {
+                log::trace!("Hook `{name}` failed with code {code}");
+            }
+            Err(err) => {
+                log::trace!("Hook `{name}` failed with {err}");
+            }
+        }
+    }
+
     /// Run `reference-transaction` hook to signal that all reference updates have been queued to the transaction.
     ///
     /// **`changed_refs` (old, new, name):**
@@ -162,6 +638,7 @@ impl Hooks {
             hook: self,
             repo,
             changed_refs,
+            finished: false,
         })
     }
 
@@ -266,37 +743,454 @@ impl Hooks {
     }
 }
 
+/// The result of looking up a hook, see [`Hooks::find_hook`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HookLookup {
+    /// An executable hook that will be run
+    Found(std::path::PathBuf),
+    /// A hook file that exists but is ignored because it isn't executable
+    NotExecutable(std::path::PathBuf),
+    /// No hook file exists at any of these paths
+    Missing(Vec<std::path::PathBuf>),
+}
+
+impl HookLookup {
+    /// The hook to run, if any
+    pub fn path(&self) -> Option<&std::path::Path> {
+        match self {
+            Self::Found(path) => Some(path),
+            Self::NotExecutable(_) | Self::Missing(_) => None,
+        }
+    }
+
+    pub fn into_path(self) -> Option<std::path::PathBuf> {
+        match self {
+            Self::Found(path) => Some(path),
+            Self::NotExecutable(_) | Self::Missing(_) => None,
+        }
+    }
+}
+
+/// A pending hook run, see [`Hooks::invoke`]
+pub struct HookInvocation<'a> {
+    hooks: &'a Hooks,
+    name: String,
+    args: Vec<std::ffi::OsString>,
+    env: Vec<(std::ffi::OsString, std::ffi::OsString)>,
+    stdin: Option<Box<dyn std::io::Read + Send + 'a>>,
+    cwd: Option<std::path::PathBuf>,
+    timeout: Option<std::time::Duration>,
+    cancel: Option<CancelHandle>,
+    stdio: HookStdio,
+}
+
+impl<'a> HookInvocation<'a> {
+    pub fn arg(mut self, arg: impl Into<std::ffi::OsString>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I>(mut self, args: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<std::ffi::OsString>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn env(
+        mut self,
+        key: impl Into<std::ffi::OsString>,
+        value: impl Into<std::ffi::OsString>,
+    ) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Stream `stdin` into the hook
+    ///
+    /// This is written from a separate thread while waiting on the hook so large inputs (e.g.
+    /// `pre-receive`, `post-rewrite`) don't need to be buffered up front and can't deadlock
+    /// against a hook that is busy writing its own output.
+    pub fn stdin(mut self, stdin: impl std::io::Read + Send + 'a) -> Self {
+        self.stdin = Some(Box::new(stdin));
+        self
+    }
+
+    /// Run the hook in `cwd` rather than where `git` would
+    pub fn cwd_override(mut self, cwd: impl Into<std::path::PathBuf>) -> Self {
+        self.cwd = Some(cwd.into());
+        self
+    }
+
+    /// Kill the hook if it runs longer than `timeout`, reporting [`std::io::ErrorKind::TimedOut`]
+    pub fn timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Kill the hook when `cancel` is triggered, reporting [`std::io::ErrorKind::Interrupted`]
+    ///
+    /// The processes the hook started are killed with it, on Unix.
+    pub fn cancel(mut self, cancel: CancelHandle) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Override [`Hooks::stdio`] for this run
+    pub fn stdio(mut self, stdio: HookStdio) -> Self {
+        self.stdio = stdio;
+        self
+    }
+
+    pub fn run(self, repo: &git2::Repository) -> Result<HookOutput, std::io::Error> {
+        const SIGNAL_EXIT_CODE: i32 = 1;
+
+        let Self {
+            hooks,
+            name,
+            args,
+            env,
+            stdin,
+            cwd,
+            timeout,
+            cancel,
+            stdio,
+        } = self;
+        let name = name.as_str();
+
+        if hooks.is_skipped(name) {
+            log::trace!("Skipping hook `{name}`");
+            return Ok(HookOutput::default());
+        }
+        if cancel.as_ref().map(|c| c.is_cancelled()).unwrap_or(false) {
+            return Err(cancelled_error(name));
+        }
+
+        let hook_path = if let HookLookup::Found(hook_path) = hooks.find_hook(repo, name) {
+            hook_path
+        } else {
+            return Ok(HookOutput::default());
+        };
+        let bin_name = hook_path
+            .file_name()
+            .expect("find_hook always returns a bin name")
+            .to_str()
+            .expect("find_hook always returns a utf-8 bin name");
+
+        let path = {
+            let mut path_components: Vec<std::path::PathBuf> =
+                vec![std::fs::canonicalize(hooks.root())?];
+            if let Some(path) = std::env::var_os(std::ffi::OsStr::new("PATH")) {
+                path_components.extend(std::env::split_paths(&path));
+            }
+            std::env::join_paths(path_components)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?
+        };
+
+        // From `githooks(5)`:
+        // > Before Git invokes a hook, it changes its working directory to either $GIT_DIR in a bare
+        // > repository or the root of the working tree in a non-bare repository. An exception are
+        // > hooks triggered during a push (pre-receive, update, post-receive, post-update,
+        // > push-to-checkout) which are always executed in $GIT_DIR.
+        let cwd = cwd.unwrap_or_else(|| {
+            if PUSH_HOOKS.contains(&name) {
+                repo.path().to_owned()
+            } else {
+                repo.workdir().unwrap_or_else(|| repo.path()).to_owned()
+            }
+        });
+
+        let mut cmd = crate::utils::ProcessCommand::shell(bin_name, args)?
+            .env("PATH", path)
+            .current_dir(cwd)
+            .timeout(timeout)
+            .cancel(cancel.clone());
+        if let Some(stdin) = stdin {
+            cmd = cmd.stdin(stdin);
+        }
+        let (stdout, stderr) = match stdio {
+            // git maps stdout to stderr when running hooks
+            HookStdio::Stderr => (
+                crate::utils::ProcessStdio::Stderr,
+                crate::utils::ProcessStdio::Inherit,
+            ),
+            HookStdio::Inherit => (
+                crate::utils::ProcessStdio::Inherit,
+                crate::utils::ProcessStdio::Inherit,
+            ),
+            HookStdio::Capture => (
+                crate::utils::ProcessStdio::Capture,
+                crate::utils::ProcessStdio::Capture,
+            ),
+            HookStdio::Null => (
+                crate::utils::ProcessStdio::Null,
+                crate::utils::ProcessStdio::Null,
+            ),
+        };
+        cmd = cmd.stdout(stdout).stderr(stderr);
+        if let Some(namespace) = &hooks.namespace {
+            cmd = cmd.env("GIT_NAMESPACE", namespace.name());
+        }
+        for (key, value) in env {
+            cmd = cmd.env(key, value);
+        }
+        let output = crate::utils::run_process(cmd).map_err(|err| {
+            if cancel.as_ref().map(|c| c.is_cancelled()).unwrap_or(false) {
+                cancelled_error(name)
+            } else if err.kind() == std::io::ErrorKind::TimedOut {
+                std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("`{name}` hook timed out"),
+                )
+            } else {
+                err
+            }
+        })?;
+
+        Ok(HookOutput {
+            code: output.code().unwrap_or(SIGNAL_EXIT_CODE),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+}
+
+/// Run the hook called `name` for each of `jobs` (hooks, repository path)
+///
+/// Up to `workers` hooks run at a time.  Triggering `cancel` kills the outstanding hooks and
+/// skips those not yet started, reporting [`std::io::ErrorKind::Interrupted`] for them.
+///
+/// Results are reported in the same order as `jobs`.
+pub fn run_concurrently(
+    jobs: &[(Hooks, std::path::PathBuf)],
+    name: &str,
+    args: &[&str],
+    stdin: Option<&[u8]>,
+    workers: usize,
+    cancel: &CancelHandle,
+) -> Vec<Result<HookOutput, std::io::Error>> {
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let results = std::sync::Mutex::new(
+        std::iter::repeat_with(|| None)
+            .take(jobs.len())
+            .collect::<Vec<_>>(),
+    );
+
+    std::thread::scope(|scope| {
+        for _ in 0..workers.clamp(1, jobs.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let Some((hooks, repo_path)) = jobs.get(index) else {
+                    break;
+                };
+                let result = git2::Repository::open(repo_path)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::NotFound, e))
+                    .and_then(|repo| {
+                        let mut invocation = hooks
+                            .invoke(name)
+                            .args(args.iter().copied())
+                            .cancel(cancel.clone());
+                        if let Some(stdin) = stdin {
+                            invocation = invocation.stdin(stdin);
+                        }
+                        invocation.run(&repo)
+                    });
+                results.lock().expect("workers don't panic")[index] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .expect("workers don't panic")
+        .into_iter()
+        .map(|result| result.unwrap_or_else(|| Err(cancelled_error(name))))
+        .collect()
+}
+
+/// Cancel in-progress hooks, see [`HookInvocation::cancel`]
+#[derive(Clone, Debug, Default)]
+pub struct CancelHandle(std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+impl CancelHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+/// Where a hook's output goes
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum HookStdio {
+    /// Send stdout to our stderr, like `git`, keeping our stdout machine-readable
+    #[default]
+    Stderr,
+    /// Share our stdout and stderr
+    Inherit,
+    /// Capture stdout and stderr into [`HookOutput`]
+    Capture,
+    /// Discard all output
+    Null,
+}
+
+/// The result of running a hook
+///
+/// A missing or skipped hook is reported as successful with no output.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HookOutput {
+    pub code: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl HookOutput {
+    pub fn success(&self) -> bool {
+        self.code == 0
+    }
+}
+
+/// A ref update to be pushed, see [`Hooks::run_pre_push`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PushUpdate<'a> {
+    /// The local ref and the object being pushed, `None` when deleting `remote_ref`
+    ///
+    /// Like `git`, this is what the ref points to, so an annotated tag's ID rather than its
+    /// commit's.
+    pub local: Option<(&'a str, git2::Oid)>,
+    /// The full name of the ref being updated on the remote
+    pub remote_ref: &'a str,
+    /// The object the remote has for `remote_ref`, `None` if it is being created
+    pub remote_oid: Option<git2::Oid>,
+}
+
+impl<'a> PushUpdate<'a> {
+    /// Push `local_ref` (or delete when `None`) to `remote_ref`
+    ///
+    /// What the remote has is taken from the remote-tracking ref for `remote_ref`, as mapped by
+    /// `remote`s fetch refspecs.
+    pub fn new(
+        repo: &git2::Repository,
+        remote: &git2::Remote<'_>,
+        local_ref: Option<&'a str>,
+        remote_ref: &'a str,
+    ) -> Result<Self, git2::Error> {
+        let local = local_ref
+            .map(|local_ref| -> Result<_, git2::Error> {
+                let local_oid = repo
+                    .refname_to_id(local_ref)
+                    .or_else(|_| repo.revparse_single(local_ref).map(|object| object.id()))?;
+                Ok((local_ref, local_oid))
+            })
+            .transpose()?;
+        let remote_oid = remote
+            .refspecs()
+            .filter(|refspec| refspec.direction() == git2::Direction::Fetch)
+            .find(|refspec| refspec.src_matches(remote_ref))
+            .and_then(|refspec| {
+                let tracking_ref = refspec.transform(remote_ref).ok()?;
+                repo.refname_to_id(tracking_ref.as_str()?).ok()
+            });
+        Ok(Self {
+            local,
+            remote_ref,
+            remote_oid,
+        })
+    }
+
+    /// The line `pre-push` expects on stdin for this update
+    ///
+    /// Deletes are reported with a local ref of `(delete)` and a zero ID, new refs with a zero
+    /// remote ID.
+    pub fn to_line(&self) -> String {
+        let (local_ref, local_oid) = self.local.unwrap_or(("(delete)", git2::Oid::zero()));
+        let remote_oid = self.remote_oid.unwrap_or_else(git2::Oid::zero);
+        format!("{local_ref} {local_oid} {} {remote_oid}\n", self.remote_ref)
+    }
+}
+
+/// The stdin `pre-push` expects for `updates`
+pub fn pre_push_stdin(updates: &[PushUpdate<'_>]) -> String {
+    updates.iter().map(PushUpdate::to_line).collect()
+}
+
+/// The outcome of [`Hooks::receive`] for a ref update
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReceiveStatus {
+    Accepted,
+    /// `pre-receive` failed with this code, rejecting all updates
+    PreReceiveRejected(i32),
+    /// `update` failed with this code for this ref
+    UpdateRejected(i32),
+}
+
+impl ReceiveStatus {
+    pub fn is_accepted(self) -> bool {
+        self == Self::Accepted
+    }
+}
+
 pub struct ReferenceTransaction<'t> {
     hook: &'t Hooks,
     repo: &'t git2::Repository,
     changed_refs: &'t [(git2::Oid, git2::Oid, &'t str)],
+    finished: bool,
 }
 
 impl ReferenceTransaction<'_> {
-    pub fn committed(self) {
-        let Self {
-            hook,
-            repo,
-            changed_refs,
-        } = self;
-        hook.run_reference_transaction_committed(repo, changed_refs);
+    pub fn committed(mut self) {
+        self.finished = true;
+        self.hook
+            .run_reference_transaction_committed(self.repo, self.changed_refs);
     }
 
-    pub fn aborted(self) {
-        let Self {
-            hook,
-            repo,
-            changed_refs,
-        } = self;
-        hook.run_reference_transaction_aborted(repo, changed_refs);
+    pub fn aborted(mut self) {
+        self.finished = true;
+        self.hook
+            .run_reference_transaction_aborted(self.repo, self.changed_refs);
     }
 }
 
 impl Drop for ReferenceTransaction<'_> {
     fn drop(&mut self) {
-        self.hook
-            .run_reference_transaction_aborted(self.repo, self.changed_refs);
+        if !self.finished {
+            self.hook
+                .run_reference_transaction_aborted(self.repo, self.changed_refs);
+        }
+    }
+}
+
+fn cancelled_error(name: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Interrupted,
+        format!("`{name}` hook was cancelled"),
+    )
+}
+
+fn receive_stdin(updates: &[(git2::Oid, git2::Oid, &str)]) -> String {
+    let mut stdin = String::new();
+    for (old_oid, new_oid, ref_name) in updates {
+        use std::fmt::Write;
+        writeln!(stdin, "{old_oid} {new_oid} {ref_name}").expect("Always writeable");
     }
+    stdin
+}
+
+const SHIM_MARKER: &str = "# Installed by git2-ext";
+
+fn is_shim(path: &std::path::Path) -> bool {
+    std::fs::read_to_string(path)
+        .map(|content| content.lines().nth(1) == Some(SHIM_MARKER))
+        .unwrap_or(false)
 }
 
 const PUSH_HOOKS: &[&str] = &[
@@ -323,3 +1217,538 @@ fn is_executable(path: &std::path::Path) -> bool {
 fn is_executable(path: &std::path::Path) -> bool {
     path.is_file()
 }
+
+#[cfg(unix)]
+fn set_executable(path: &std::path::Path) -> Result<(), std::io::Error> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = path.metadata()?.permissions();
+    permissions.set_mode(permissions.mode() | 0o111);
+    std::fs::set_permissions(path, permissions)
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &std::path::Path) -> Result<(), std::io::Error> {
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use std::io::Read as _;
+
+    use super::*;
+
+    use crate::testing::make_git;
+
+    fn write_hook(repo: &git2::Repository, name: &str, script: &str) -> eyre::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let hooks_dir = repo.path().join("hooks");
+        std::fs::create_dir_all(&hooks_dir)?;
+        let hook_path = hooks_dir.join(name);
+        std::fs::write(&hook_path, script)?;
+        std::fs::set_permissions(&hook_path, std::fs::Permissions::from_mode(0o755))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_hook_streaming_large_stdin() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        write_hook(&repo, "post-rewrite", "#!/bin/sh\nwc -c > stdin-size\n")?;
+
+        let len = 4 * 1024 * 1024;
+        let mut stdin = std::io::repeat(b'a').take(len);
+        let hooks = Hooks::with_repo(&repo)?;
+        let code = hooks.run_hook_streaming(&repo, "post-rewrite", &[], Some(&mut stdin), &[])?;
+        assert_eq!(code, 0);
+
+        let size = std::fs::read_to_string(git.repo_path.join("stdin-size"))?;
+        assert_eq!(size.trim(), len.to_string());
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "tokio")]
+    fn test_run_hook_async() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        write_hook(&repo, "post-rewrite", "#!/bin/sh\ncat > \"$1\"\nexit 3\n")?;
+        write_hook(&repo, "commit-msg", "#!/bin/sh\necho 'Hooked' >> \"$1\"\n")?;
+
+        let hooks = Hooks::with_repo(&repo)?;
+        let stdin = Some(&b"rewritten"[..]);
+        let code = crate::utils::block_on(async {
+            hooks
+                .run_hook_async(&repo, "post-rewrite", &["out"], stdin, &[])
+                .await
+        })?;
+        assert_eq!(code, 3);
+        let out = std::fs::read_to_string(git.repo_path.join("out"))?;
+        assert_eq!(out, "rewritten");
+
+        let message = crate::utils::block_on(async {
+            hooks
+                .run_async(&repo, |hooks, repo| hooks.run_commit_msg(repo, "Subject\n"))
+                .await
+        })??;
+        assert_eq!(message, "Subject\nHooked\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skip_hook() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        write_hook(&repo, "pre-commit", "#!/bin/sh\nexit 1\n")?;
+        write_hook(&repo, "post-commit", "#!/bin/sh\nexit 1\n")?;
+
+        let hooks = Hooks::with_repo(&repo)?.skip("pre-commit");
+        assert!(hooks.is_skipped("pre-commit"));
+        assert!(!hooks.is_skipped("post-commit"));
+        assert_eq!(hooks.run_hook(&repo, "pre-commit", &[], None, &[])?, 0);
+        assert_eq!(hooks.run_hook(&repo, "post-commit", &[], None, &[])?, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_capture_hook_output() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        write_hook(
+            &repo,
+            "pre-commit",
+            "#!/bin/sh\necho out\necho err >&2\nexit 3\n",
+        )?;
+
+        let hooks = Hooks::with_repo(&repo)?.stdio(HookStdio::Capture);
+        let output = hooks.run_hook_output(&repo, "pre-commit", &[], None, &[])?;
+        assert_eq!(
+            output,
+            HookOutput {
+                code: 3,
+                stdout: b"out\n".to_vec(),
+                stderr: b"err\n".to_vec(),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invoke_hook() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        write_hook(
+            &repo,
+            "pre-push",
+            "#!/bin/sh\necho \"$1 $2 $HOOK_VAR $(cat)\"\n",
+        )?;
+
+        let hooks = Hooks::with_repo(&repo)?;
+        let output = hooks
+            .invoke("pre-push")
+            .arg("origin")
+            .arg("url")
+            .env("HOOK_VAR", "var")
+            .stdin(&b"input"[..])
+            .stdio(HookStdio::Capture)
+            .run(&repo)?;
+        assert_eq!(output.code, 0);
+        assert_eq!(output.stdout, b"origin url var input\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invoke_hook_timeout() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        write_hook(&repo, "pre-commit", "#!/bin/sh\nexec sleep 10\n")?;
+
+        let hooks = Hooks::with_repo(&repo)?;
+        let err = hooks
+            .invoke("pre-commit")
+            .stdio(HookStdio::Null)
+            .timeout(std::time::Duration::from_millis(100))
+            .run(&repo)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invoke_hook_timeout_kills_children() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        // Without `exec`, `sleep` outlives `sh` and holds the captured pipes open
+        write_hook(&repo, "pre-commit", "#!/bin/sh\nsleep 30\necho done\n")?;
+
+        let hooks = Hooks::with_repo(&repo)?;
+        let start = std::time::Instant::now();
+        let err = hooks
+            .invoke("pre-commit")
+            .stdio(HookStdio::Capture)
+            .timeout(std::time::Duration::from_millis(100))
+            .run(&repo)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_hook() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        write_hook(&repo, "pre-commit", "#!/bin/sh\n")?;
+        let ignored = repo.path().join("hooks").join("post-commit");
+        std::fs::write(&ignored, "#!/bin/sh\n")?;
+
+        let hooks = Hooks::with_repo(&repo)?;
+        assert_eq!(
+            hooks.find_hook(&repo, "pre-commit"),
+            HookLookup::Found(repo.path().join("hooks").join("pre-commit"))
+        );
+        assert_eq!(
+            hooks.find_hook(&repo, "post-commit"),
+            HookLookup::NotExecutable(ignored)
+        );
+        assert_eq!(
+            hooks.find_hook(&repo, "pre-push"),
+            HookLookup::Missing(vec![repo.path().join("hooks").join("pre-push")])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_worktree() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.run(&["worktree", "add", "wt"])?;
+
+        let repo = git.get_repo()?;
+        let worktree = git2::Repository::open(git.repo_path.join("wt"))?;
+        let hooks = Hooks::with_repo(&worktree)?;
+        assert_eq!(
+            std::fs::canonicalize(hooks.root().parent().unwrap())?,
+            std::fs::canonicalize(repo.path())?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_bare_repo() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.run(&["init", "--bare", "bare.git"])?;
+
+        let repo = git2::Repository::open(git.repo_path.join("bare.git"))?;
+        let hooks = Hooks::with_repo(&repo)?;
+        assert_eq!(
+            std::fs::canonicalize(hooks.root())?,
+            std::fs::canonicalize(git.repo_path.join("bare.git").join("hooks"))?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_relative_hooks_path() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.run(&["config", "core.hooksPath", "githooks"])?;
+
+        let repo = git.get_repo()?;
+        let hooks = Hooks::with_repo(&repo)?;
+        assert_eq!(hooks.root(), repo.workdir().unwrap().join("githooks"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_commit_msg() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let hooks = Hooks::with_repo(&repo)?;
+
+        write_hook(
+            &repo,
+            "commit-msg",
+            "#!/bin/sh\necho 'Signed-off-by: Hook' >> \"$1\"\n",
+        )?;
+        let message = hooks.run_commit_msg(&repo, "Subject\n\n")?;
+        assert_eq!(message, "Subject\n\nSigned-off-by: Hook\n");
+
+        write_hook(&repo, "commit-msg", "#!/bin/sh\nexit 1\n")?;
+        let err = hooks.run_commit_msg(&repo, "Subject\n").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_receive() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.run(&["init", "--bare", "bare.git"])?;
+        let repo = git2::Repository::open(git.repo_path.join("bare.git"))?;
+        write_hook(&repo, "pre-receive", "#!/bin/sh\ncat > pre-receive.log\n")?;
+        write_hook(
+            &repo,
+            "update",
+            "#!/bin/sh\ntest \"$1\" != refs/heads/protected\n",
+        )?;
+        write_hook(&repo, "post-receive", "#!/bin/sh\ncat > post-receive.log\n")?;
+
+        let old_id = git2::Oid::zero();
+        let new_id = git2::Oid::from_str("63af22885f8665a312ba8b83db722134f1f8290d")?;
+        let updates = [
+            (old_id, new_id, "refs/heads/main"),
+            (old_id, new_id, "refs/heads/protected"),
+        ];
+        let hooks = Hooks::with_repo(&repo)?;
+        let mut applied = Vec::new();
+        let statuses = hooks.receive(&repo, &updates, |accepted| {
+            applied.extend(accepted.iter().map(|(_, _, name)| String::from(*name)));
+            Ok(())
+        })?;
+        assert_eq!(
+            statuses,
+            [ReceiveStatus::Accepted, ReceiveStatus::UpdateRejected(1)]
+        );
+        assert_eq!(applied, ["refs/heads/main"]);
+
+        let pre_receive = std::fs::read_to_string(repo.path().join("pre-receive.log"))?;
+        assert_eq!(pre_receive.lines().count(), 2);
+        let post_receive = std::fs::read_to_string(repo.path().join("post-receive.log"))?;
+        assert_eq!(post_receive, format!("{old_id} {new_id} refs/heads/main\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_receive_namespace() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.run(&["init", "--bare", "bare.git"])?;
+        let repo = git2::Repository::open(git.repo_path.join("bare.git"))?;
+        write_hook(
+            &repo,
+            "pre-receive",
+            "#!/bin/sh\necho \"$GIT_NAMESPACE\" > pre-receive.log\ncat >> pre-receive.log\n",
+        )?;
+
+        let old_id = git2::Oid::zero();
+        let new_id = git2::Oid::from_str("63af22885f8665a312ba8b83db722134f1f8290d")?;
+        let updates = [(old_id, new_id, "refs/heads/main")];
+        let hooks = Hooks::with_repo(&repo)?.namespace(crate::refs::Namespace::new("tenant")?);
+        let mut applied = Vec::new();
+        hooks.receive(&repo, &updates, |accepted| {
+            applied.extend(accepted.iter().map(|(_, _, name)| String::from(*name)));
+            Ok(())
+        })?;
+        assert_eq!(applied, ["refs/namespaces/tenant/refs/heads/main"]);
+
+        let pre_receive = std::fs::read_to_string(repo.path().join("pre-receive.log"))?;
+        assert_eq!(
+            pre_receive,
+            format!("tenant\n{old_id} {new_id} refs/heads/main\n")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_install_hook() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        write_hook(&repo, "pre-commit", "#!/bin/sh\n")?;
+
+        let hooks = Hooks::with_repo(&repo)?.stdio(HookStdio::Capture);
+        let err = hooks
+            .install_hook("pre-commit", "echo linted", false)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+
+        hooks.install_hook("commit-msg", "echo linted", false)?;
+        hooks.install_hook("commit-msg", "echo relinted", false)?;
+        let output = hooks.invoke("commit-msg").arg("msg").run(&repo)?;
+        assert_eq!(output.stdout, b"relinted msg\n");
+
+        let names = hooks
+            .list_hooks(&repo)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["commit-msg", "pre-commit"]);
+
+        assert!(hooks.uninstall_hook("commit-msg", false)?);
+        assert!(!hooks.uninstall_hook("commit-msg", false)?);
+        assert!(hooks.uninstall_hook("pre-commit", false).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_concurrently() -> eyre::Result<()> {
+        let mut gits = Vec::new();
+        let mut jobs = Vec::new();
+        for _ in 0..3 {
+            let git = make_git()?;
+            git.init_repo()?;
+            let repo = git.get_repo()?;
+            write_hook(&repo, "pre-commit", "#!/bin/sh\npwd\n")?;
+            let hooks = Hooks::with_repo(&repo)?.stdio(HookStdio::Capture);
+            jobs.push((hooks, git.repo_path.clone()));
+            gits.push(git);
+        }
+
+        let cancel = CancelHandle::new();
+        let results = run_concurrently(&jobs, "pre-commit", &[], None, 2, &cancel);
+        for ((_, repo_path), result) in jobs.iter().zip(results) {
+            let output = result?;
+            let cwd = String::from_utf8(output.stdout)?;
+            assert_eq!(
+                std::fs::canonicalize(cwd.trim())?,
+                std::fs::canonicalize(repo_path)?
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_concurrently_cancelled() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        write_hook(&repo, "pre-commit", "#!/bin/sh\nexec sleep 10\n")?;
+        let hooks = Hooks::with_repo(&repo)?.stdio(HookStdio::Null);
+        let jobs = vec![(hooks, git.repo_path.clone()); 3];
+
+        let cancel = CancelHandle::new();
+        let canceller = {
+            let cancel = cancel.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                cancel.cancel();
+            })
+        };
+        let results = run_concurrently(&jobs, "pre-commit", &[], None, 2, &cancel);
+        canceller.join().unwrap();
+        for result in results {
+            assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::Interrupted);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invoke_hook_cancel_kills_children() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        // Without `exec`, `sleep` outlives `sh` and holds the captured pipes open
+        write_hook(&repo, "pre-commit", "#!/bin/sh\nsleep 30\necho done\n")?;
+
+        let hooks = Hooks::with_repo(&repo)?;
+        let cancel = CancelHandle::new();
+        let canceller = {
+            let cancel = cancel.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                cancel.cancel();
+            })
+        };
+        let start = std::time::Instant::now();
+        let err = hooks
+            .invoke("pre-commit")
+            .stdio(HookStdio::Capture)
+            .cancel(cancel)
+            .run(&repo)
+            .unwrap_err();
+        canceller.join().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pre_push_stdin() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let head_id = repo.head()?.target().unwrap();
+        git.run(&[
+            "update-ref",
+            "refs/remotes/origin/master",
+            &head_id.to_string(),
+        ])?;
+        let remote = repo.remote("origin", "https://example.com/repo.git")?;
+        // Tags are pushed as-is, not peeled to their commit
+        git.run(&["tag", "-a", "v1", "-m", "Release"])?;
+        git.run(&["tag", "tree", "HEAD^{tree}"])?;
+        let tag_id = repo.refname_to_id("refs/tags/v1")?;
+        assert_ne!(tag_id, head_id);
+        let tree_id = repo.head()?.peel_to_tree()?.id();
+
+        let updates = [
+            PushUpdate::new(
+                &repo,
+                &remote,
+                Some("refs/heads/master"),
+                "refs/heads/master",
+            )?,
+            PushUpdate::new(&repo, &remote, Some("refs/heads/master"), "refs/heads/new")?,
+            PushUpdate::new(&repo, &remote, None, "refs/heads/master")?,
+            PushUpdate::new(&repo, &remote, Some("refs/tags/v1"), "refs/tags/v1")?,
+            PushUpdate::new(&repo, &remote, Some("refs/tags/tree"), "refs/tags/tree")?,
+        ];
+        let zero = git2::Oid::zero();
+        assert_eq!(
+            pre_push_stdin(&updates),
+            format!(
+                "refs/heads/master {head_id} refs/heads/master {head_id}
+refs/heads/master {head_id} refs/heads/new {zero}
+(delete) {zero} refs/heads/master {head_id}
+refs/tags/v1 {tag_id} refs/tags/v1 {zero}
+refs/tags/tree {tree_id} refs/tags/tree {zero}
+"
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_hook_ignoring_stdin() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        write_hook(&repo, "post-rewrite", "#!/bin/sh\nexit 0\n")?;
+
+        let stdin = vec![b'a'; 4 * 1024 * 1024];
+        let hooks = Hooks::with_repo(&repo)?;
+        let code = hooks.run_hook(&repo, "post-rewrite", &[], Some(&stdin), &[])?;
+        assert_eq!(code, 0);
+
+        Ok(())
+    }
+}
@@ -1,19 +1,26 @@
 //! Extensions for git2
 //!
 //! Goals:
-//! - Provide "good enough" implementations of essential or higher-level git2 logic, like cherry-pick, squash, hooks, authentication (not implemented yet), etc
+//! - Provide "good enough" implementations of essential or higher-level git2 logic, like cherry-pick, squash, hooks, authentication, etc
 //! - The above serves as examples for people needing to write their own implementations
 
 #![cfg_attr(docsrs, feature(doc_auto_cfg))]
 #![warn(clippy::print_stderr)]
 #![warn(clippy::print_stdout)]
 
+pub mod auth;
+pub mod backend;
+pub mod config;
+pub mod debug;
 pub mod hooks;
 pub mod ops;
+pub mod refs;
 pub mod tree;
 pub mod utils;
 
 pub(crate) mod bytes;
+#[cfg(feature = "serde")]
+pub(crate) mod serde_oid;
 
-#[cfg(test)]
-mod testing;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
@@ -0,0 +1,433 @@
+use std::collections::HashSet;
+
+/// Whether `ancestor` is reachable from `descendant`, counting a commit as its own ancestor
+///
+/// Like `git merge-base --is-ancestor`.  When both commits are in the commit-graph, the parents
+/// are read from it and the walk stops at commits older than `ancestor` by generation number,
+/// without loading any commits.  Otherwise this falls back to [`git2::Repository::merge_base`].
+///
+/// The commit-graph is read on every call, so for many queries, open the [`CommitGraph`] once
+/// and use [`is_ancestor_with`].
+pub fn is_ancestor(
+    repo: &git2::Repository,
+    ancestor: git2::Oid,
+    descendant: git2::Oid,
+) -> Result<bool, git2::Error> {
+    if ancestor == descendant {
+        return Ok(true);
+    }
+    let graph = CommitGraph::open(repo)?;
+    is_ancestor_with(repo, graph.as_ref(), ancestor, descendant)
+}
+
+/// [`is_ancestor`], reusing an already opened [`CommitGraph`]
+pub fn is_ancestor_with(
+    repo: &git2::Repository,
+    graph: Option<&CommitGraph>,
+    ancestor: git2::Oid,
+    descendant: git2::Oid,
+) -> Result<bool, git2::Error> {
+    if ancestor == descendant {
+        return Ok(true);
+    }
+    if let Some(reachable) = graph.and_then(|graph| graph.is_ancestor(ancestor, descendant)) {
+        return Ok(reachable);
+    }
+    match repo.merge_base(ancestor, descendant) {
+        Ok(base) => Ok(base == ancestor),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// The commit-graph `git` writes to `objects/info`, see gitformat-commit-graph(5)
+///
+/// Both a single `commit-graph` file and a chain of split graphs are read.  Commits written since
+/// the graph was last updated aren't in it, and lookups return `None` for them.
+#[derive(Clone, Debug)]
+pub struct CommitGraph {
+    /// Base graph first, positions continue from one layer to the next
+    layers: Vec<Layer>,
+}
+
+impl CommitGraph {
+    /// Read the repository's commit-graph
+    ///
+    /// `None` when there is none, `core.commitGraph` is off, or it can't be used, like a graph for
+    /// a different hash.  A corrupt graph is ignored rather than failing, as everything can be
+    /// answered without it.
+    pub fn open(repo: &git2::Repository) -> Result<Option<Self>, git2::Error> {
+        let config = repo.config()?;
+        if !crate::config::get_bool(&config, "core.commitGraph")?.unwrap_or(true) {
+            return Ok(None);
+        }
+
+        // Linked worktrees share the objects of the common directory
+        let info = crate::utils::common_dir(repo).join("objects").join("info");
+        let mut paths = Vec::new();
+        let single = info.join("commit-graph");
+        if single.exists() {
+            paths.push(single);
+        } else {
+            let graphs = info.join("commit-graphs");
+            let chain = match std::fs::read_to_string(graphs.join("commit-graph-chain")) {
+                Ok(chain) => chain,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(err) => return Err(os_error(&graphs, err)),
+            };
+            for hash in chain.lines().map(str::trim).filter(|hash| !hash.is_empty()) {
+                paths.push(graphs.join(format!("graph-{hash}.graph")));
+            }
+        }
+
+        let mut layers = Vec::with_capacity(paths.len());
+        let mut base = 0;
+        for path in paths {
+            let data = std::fs::read(&path).map_err(|err| os_error(&path, err))?;
+            let Some(layer) = Layer::parse(data, base) else {
+                log::debug!("Ignoring unusable commit-graph `{}`", path.display());
+                return Ok(None);
+            };
+            base += layer.count;
+            layers.push(layer);
+        }
+        if layers.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self { layers }))
+    }
+
+    /// Whether `id` is in the graph
+    pub fn contains(&self, id: git2::Oid) -> bool {
+        self.position(id).is_some()
+    }
+
+    /// The generation number of `id`, `None` when it isn't in the graph
+    ///
+    /// This is the topological level: `1` for a root commit, one more than its highest parent
+    /// otherwise.  A commit's ancestors all have lower generation numbers.  Graphs written by
+    /// very old `git` have none, reported as `0`.
+    pub fn generation(&self, id: git2::Oid) -> Option<u32> {
+        let position = self.position(id)?;
+        Some(self.level(position))
+    }
+
+    /// The parents of `id`, `None` when it isn't in the graph
+    pub fn parent_ids(&self, id: git2::Oid) -> Option<Vec<git2::Oid>> {
+        let position = self.position(id)?;
+        let mut parents = Vec::new();
+        self.parents(position, &mut parents)?;
+        parents
+            .into_iter()
+            .map(|parent| self.id_at(parent))
+            .collect()
+    }
+
+    /// Whether `ancestor` is reachable from `descendant`, see [`is_ancestor`]
+    ///
+    /// `None` when this can't be answered from the graph alone.
+    pub fn is_ancestor(&self, ancestor: git2::Oid, descendant: git2::Oid) -> Option<bool> {
+        let ancestor = self.position(ancestor)?;
+        let descendant = self.position(descendant)?;
+        let min_level = self.level(ancestor);
+        if min_level == 0 {
+            return None;
+        }
+
+        let mut seen = HashSet::new();
+        let mut pending = vec![descendant];
+        let mut parents = Vec::new();
+        while let Some(position) = pending.pop() {
+            if position == ancestor {
+                return Some(true);
+            }
+            let level = self.level(position);
+            if level == 0 {
+                return None;
+            }
+            // Nothing at or below `ancestor`'s level, besides itself, can reach it
+            if level <= min_level {
+                continue;
+            }
+            parents.clear();
+            self.parents(position, &mut parents)?;
+            for parent in parents.drain(..) {
+                if seen.insert(parent) {
+                    pending.push(parent);
+                }
+            }
+        }
+        Some(false)
+    }
+
+    fn position(&self, id: git2::Oid) -> Option<u32> {
+        self.layers
+            .iter()
+            .find_map(|layer| layer.lookup(id.as_bytes()))
+    }
+
+    fn layer(&self, position: u32) -> Option<&Layer> {
+        self.layers
+            .iter()
+            .find(|layer| layer.base <= position && position - layer.base < layer.count)
+    }
+
+    fn id_at(&self, position: u32) -> Option<git2::Oid> {
+        let layer = self.layer(position)?;
+        git2::Oid::from_bytes(layer.oid(position - layer.base)).ok()
+    }
+
+    fn level(&self, position: u32) -> u32 {
+        self.layer(position)
+            .map(|layer| layer.commit_data(position - layer.base).level())
+            .unwrap_or(0)
+    }
+
+    /// Append the positions of `position`'s parents, `None` if the graph is inconsistent
+    fn parents(&self, position: u32, parents: &mut Vec<u32>) -> Option<()> {
+        let layer = self.layer(position)?;
+        let data = layer.commit_data(position - layer.base);
+        if data.parent1 == NO_PARENT {
+            return Some(());
+        }
+        parents.push(data.parent1);
+        if data.parent2 == NO_PARENT {
+            return Some(());
+        }
+        if data.parent2 & EXTRA_EDGES != 0 {
+            // Octopus merges list the rest of their parents in the extra edges chunk
+            let mut index = (data.parent2 & !EXTRA_EDGES) as usize;
+            loop {
+                let edge = layer.extra_edge(index)?;
+                parents.push(edge & !LAST_EDGE);
+                if edge & LAST_EDGE != 0 {
+                    break;
+                }
+                index += 1;
+            }
+        } else {
+            parents.push(data.parent2);
+        }
+        Some(())
+    }
+}
+
+const SIGNATURE: &[u8] = b"CGPH";
+const HASH_LEN: usize = 20;
+const COMMIT_DATA_LEN: usize = HASH_LEN + 16;
+const NO_PARENT: u32 = 0x7000_0000;
+const EXTRA_EDGES: u32 = 0x8000_0000;
+const LAST_EDGE: u32 = 0x8000_0000;
+
+/// One commit-graph file
+#[derive(Clone)]
+struct Layer {
+    data: Vec<u8>,
+    /// Commits in the layers before this one
+    base: u32,
+    count: u32,
+    fanout: usize,
+    oid_lookup: usize,
+    commit_data: usize,
+    extra_edges: Option<std::ops::Range<usize>>,
+}
+
+impl std::fmt::Debug for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Layer")
+            .field("base", &self.base)
+            .field("count", &self.count)
+            .finish()
+    }
+}
+
+impl Layer {
+    fn parse(data: Vec<u8>, base: u32) -> Option<Self> {
+        if data.get(0..4)? != SIGNATURE || *data.get(4)? != 1 || *data.get(5)? != 1 {
+            return None;
+        }
+        let chunk_count = *data.get(6)? as usize;
+
+        let mut chunks = Vec::with_capacity(chunk_count + 1);
+        for i in 0..=chunk_count {
+            let entry = 8 + i * 12;
+            let id = data.get(entry..entry + 4)?;
+            let offset = u64::from_be_bytes(data.get(entry + 4..entry + 12)?.try_into().ok()?);
+            chunks.push((id, usize::try_from(offset).ok()?));
+        }
+        let chunk = |name: &[u8]| -> Option<std::ops::Range<usize>> {
+            let index = chunks.iter().position(|(id, _)| *id == name)?;
+            let start = chunks[index].1;
+            let end = chunks.get(index + 1)?.1;
+            (start <= end && end <= data.len()).then_some(start..end)
+        };
+
+        let fanout = chunk(b"OIDF")?;
+        let oid_lookup = chunk(b"OIDL")?;
+        let commit_data = chunk(b"CDAT")?;
+        let extra_edges = chunk(b"EDGE");
+        if fanout.len() != 256 * 4 {
+            return None;
+        }
+        let count = u32::from_be_bytes(data.get(fanout.end - 4..fanout.end)?.try_into().ok()?);
+        if oid_lookup.len() != count as usize * HASH_LEN
+            || commit_data.len() != count as usize * COMMIT_DATA_LEN
+        {
+            return None;
+        }
+        Some(Self {
+            base,
+            count,
+            fanout: fanout.start,
+            oid_lookup: oid_lookup.start,
+            commit_data: commit_data.start,
+            extra_edges,
+            data,
+        })
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        let bytes = self.data[offset..offset + 4]
+            .try_into()
+            .expect("range is 4 bytes");
+        u32::from_be_bytes(bytes)
+    }
+
+    fn oid(&self, index: u32) -> &[u8] {
+        let start = self.oid_lookup + index as usize * HASH_LEN;
+        &self.data[start..start + HASH_LEN]
+    }
+
+    /// The position of `id` across all layers
+    fn lookup(&self, id: &[u8]) -> Option<u32> {
+        let first = *id.first()? as usize;
+        let start = match first {
+            0 => 0,
+            _ => self.read_u32(self.fanout + (first - 1) * 4),
+        };
+        let end = self.read_u32(self.fanout + first * 4);
+        let (mut low, mut high) = (start, end.min(self.count));
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.oid(mid).cmp(id) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Some(self.base + mid),
+            }
+        }
+        None
+    }
+
+    fn commit_data(&self, index: u32) -> CommitData {
+        let start = self.commit_data + index as usize * COMMIT_DATA_LEN + HASH_LEN;
+        CommitData {
+            parent1: self.read_u32(start),
+            parent2: self.read_u32(start + 4),
+            level_and_time: self.read_u32(start + 8),
+        }
+    }
+
+    fn extra_edge(&self, index: usize) -> Option<u32> {
+        let edges = self.extra_edges.as_ref()?;
+        let start = edges.start + index * 4;
+        (start + 4 <= edges.end).then(|| self.read_u32(start))
+    }
+}
+
+struct CommitData {
+    parent1: u32,
+    parent2: u32,
+    /// The topological level in the top 30 bits, the rest are the top of the commit time
+    level_and_time: u32,
+}
+
+impl CommitData {
+    fn level(&self) -> u32 {
+        self.level_and_time >> 2
+    }
+}
+
+fn os_error(path: &std::path::Path, err: std::io::Error) -> git2::Error {
+    git2::Error::new(
+        git2::ErrorCode::GenericError,
+        git2::ErrorClass::Os,
+        format!("could not read `{}`: {}", path.display(), err),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_is_ancestor() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let base = repo.refname_to_id("refs/heads/master")?;
+        let first = git.commit_file("first", 1)?;
+        git.run(&["checkout", "-b", "side", &base.to_string()])?;
+        let side = git.commit_file("side", 2)?;
+        git.run(&["checkout", "-b", "other", &base.to_string()])?;
+        let other = git.commit_file("other", 3)?;
+        git.run(&["checkout", "master"])?;
+        git.run(&["merge", "--no-edit", "side", "other"])?;
+        let merge = repo.refname_to_id("refs/heads/master")?;
+        git.run(&["checkout", "-b", "unrelated", &first.to_string()])?;
+        let unrelated = git.commit_file("unrelated", 4)?;
+
+        let cases = [
+            (base, merge, true),
+            (side, merge, true),
+            (other, merge, true),
+            (merge, merge, true),
+            (merge, base, false),
+            (side, first, false),
+            (unrelated, merge, false),
+            (first, unrelated, true),
+        ];
+
+        assert!(CommitGraph::open(&repo)?.is_none());
+        for (ancestor, descendant, expected) in cases {
+            assert_eq!(
+                is_ancestor(&repo, ancestor, descendant)?,
+                expected,
+                "{ancestor} {descendant}"
+            );
+        }
+
+        git.run(&["commit-graph", "write", "--reachable"])?;
+        let graph = CommitGraph::open(&repo)?.unwrap();
+        assert_eq!(graph.generation(base), Some(1));
+        assert_eq!(graph.generation(merge), Some(3));
+        assert_eq!(graph.parent_ids(merge), Some(vec![first, side, other]));
+        for (ancestor, descendant, expected) in cases {
+            assert_eq!(
+                graph.is_ancestor(ancestor, descendant),
+                Some(expected),
+                "{ancestor} {descendant}"
+            );
+        }
+
+        // Commits made since the graph was written fall back to walking the objects
+        git.run(&["checkout", "master"])?;
+        let new = git.commit_file("new", 5)?;
+        assert!(!graph.contains(new));
+        assert_eq!(graph.is_ancestor(side, new), None);
+        assert!(is_ancestor_with(&repo, Some(&graph), side, new)?);
+
+        git.run(&["commit-graph", "write", "--reachable", "--split=no-merge"])?;
+        assert!(!repo.path().join("objects/info/commit-graph").exists());
+        let graph = CommitGraph::open(&repo)?.unwrap();
+        assert_eq!(graph.generation(new), Some(4));
+        assert_eq!(graph.is_ancestor(side, new), Some(true));
+        assert_eq!(graph.is_ancestor(unrelated, new), Some(false));
+
+        git.run(&["config", "core.commitGraph", "false"])?;
+        assert!(CommitGraph::open(&repo)?.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use std::borrow::Cow;
+
+/// The encoding new commit messages are written in, from `i18n.commitEncoding`
+///
+/// `None` for UTF-8, `git`s default, which commits don't record.
+pub fn commit_encoding(repo: &git2::Repository) -> Result<Option<String>, git2::Error> {
+    let config = repo.config()?;
+    let encoding = crate::config::get_string(&config, "i18n.commitEncoding")?;
+    Ok(encoding.filter(|encoding| !is_utf8(encoding)))
+}
+
+/// `commit`s message as text, decoded according to its `encoding` header
+///
+/// Like [`git2::Commit::message`], leading newlines are left out.  Bytes that aren't valid in
+/// the encoding, or all non-ASCII bytes for an encoding that isn't known, are replaced with
+/// U+FFFD.
+pub fn decode_message<'c>(commit: &'c git2::Commit<'_>) -> Cow<'c, str> {
+    let bytes = commit.message_bytes();
+    match commit.message_encoding().and_then(lookup) {
+        Some(encoding) => encoding.decode_without_bom_handling(bytes).0,
+        None => String::from_utf8_lossy(bytes),
+    }
+}
+
+/// A commit message in the encoding it'll be written in
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct EncodedMessage {
+    bytes: Vec<u8>,
+    /// The `encoding` header, `None` for UTF-8
+    encoding: Option<String>,
+}
+
+impl EncodedMessage {
+    /// `text` in `encoding`, falling back to UTF-8 for text it can't represent
+    pub(crate) fn encode(text: &str, encoding: Option<&str>) -> Self {
+        let encoded = encoding.and_then(|label| {
+            let encoding = lookup(label)?;
+            let (bytes, used, had_errors) = encoding.encode(text);
+            // Encodings without an encoder, like UTF-16, fall back to UTF-8
+            (used == encoding && !had_errors).then(|| Self {
+                bytes: bytes.into_owned(),
+                encoding: Some(label.to_owned()),
+            })
+        });
+        encoded.unwrap_or_else(|| {
+            if let Some(encoding) = encoding {
+                log::warn!(
+                    "`{encoding}` can't represent the commit message, writing UTF-8 instead"
+                );
+            }
+            Self {
+                bytes: text.as_bytes().to_owned(),
+                encoding: None,
+            }
+        })
+    }
+
+    /// `commit`s message, exactly as written
+    pub(crate) fn of(commit: &git2::Commit<'_>) -> Self {
+        Self {
+            bytes: commit.message_raw_bytes().to_owned(),
+            encoding: commit.message_encoding().map(str::to_owned),
+        }
+    }
+
+    /// `commit`s message converted to `encoding`, like `git` does when replaying commits
+    ///
+    /// When the message can't be converted, like for bytes not valid in the commit's own
+    /// encoding, it is passed through untouched, along with its `encoding` header.
+    pub(crate) fn reencode(commit: &git2::Commit<'_>, encoding: Option<&str>) -> Self {
+        let original = Self::of(commit);
+        let from = original.encoding.as_deref().filter(|from| !is_utf8(from));
+        let same = match (from, encoding) {
+            (None, None) => true,
+            (Some(from), Some(to)) => {
+                from.eq_ignore_ascii_case(to)
+                    || (lookup(from).is_some() && lookup(from) == lookup(to))
+            }
+            _ => false,
+        };
+        if same {
+            return original;
+        }
+
+        let text = match from {
+            Some(from) => lookup(from).and_then(|from| {
+                from.decode_without_bom_handling_and_without_replacement(&original.bytes)
+            }),
+            None => std::str::from_utf8(&original.bytes).ok().map(Cow::Borrowed),
+        };
+        let converted = text.and_then(|text| {
+            let converted = Self::encode(&text, encoding);
+            (converted.encoding.as_deref() == encoding).then_some(converted)
+        });
+        converted.unwrap_or(original)
+    }
+}
+
+/// Build the content of a commit object with `message`
+pub(crate) fn commit_buffer(
+    repo: &git2::Repository,
+    author: &git2::Signature<'_>,
+    committer: &git2::Signature<'_>,
+    message: &EncodedMessage,
+    tree: &git2::Tree<'_>,
+    parents: &[&git2::Commit<'_>],
+) -> Result<Vec<u8>, git2::Error> {
+    // `git2` only takes UTF-8 messages, so its buffer has an empty one for the message to be
+    // appended to, after the blank line ending the headers
+    let buffer = repo.commit_create_buffer(author, committer, "", tree, parents)?;
+    let headers = buffer.strip_suffix(b"\n").unwrap_or(&buffer);
+    let mut content = headers.to_vec();
+    if let Some(encoding) = &message.encoding {
+        content.extend_from_slice(format!("encoding {encoding}\n").as_bytes());
+    }
+    content.push(b'\n');
+    content.extend_from_slice(&message.bytes);
+    Ok(content)
+}
+
+/// Write a commit with `message`, optionally signed
+pub(crate) fn write_commit(
+    repo: &git2::Repository,
+    author: &git2::Signature<'_>,
+    committer: &git2::Signature<'_>,
+    message: &EncodedMessage,
+    tree: &git2::Tree<'_>,
+    parents: &[&git2::Commit<'_>],
+    sign: Option<&dyn super::Sign>,
+) -> Result<git2::Oid, git2::Error> {
+    let content = commit_buffer(repo, author, committer, message, tree, parents)?;
+    match sign {
+        Some(sign) => {
+            let content = std::str::from_utf8(&content).map_err(|_| {
+                git2::Error::new(
+                    git2::ErrorCode::Invalid,
+                    git2::ErrorClass::Object,
+                    "commit is not valid UTF-8 and can't be signed",
+                )
+            })?;
+            let signed = sign.sign(content)?;
+            repo.commit_signed(content, &signed, None)
+        }
+        None => repo.odb()?.write(git2::ObjectType::Commit, &content),
+    }
+}
+
+fn is_utf8(label: &str) -> bool {
+    label.eq_ignore_ascii_case("utf-8") || label.eq_ignore_ascii_case("utf8")
+}
+
+fn lookup(label: &str) -> Option<&'static encoding_rs::Encoding> {
+    encoding_rs::Encoding::for_label(label.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_commit_encoding() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let head_id = repo.refname_to_id("HEAD")?;
+        assert_eq!(commit_encoding(&repo)?, None);
+
+        // Written like `git` would with `i18n.commitEncoding=ISO-8859-1`
+        git.run(&["config", "i18n.commitEncoding", "ISO-8859-1"])?;
+        assert_eq!(commit_encoding(&repo)?.as_deref(), Some("ISO-8859-1"));
+        let head = repo.find_commit(head_id)?;
+        let message = EncodedMessage::encode("caf\u{e9}\n", Some("ISO-8859-1"));
+        let latin1_id = write_commit(
+            &repo,
+            &head.author(),
+            &head.committer(),
+            &message,
+            &head.tree()?,
+            &[&head],
+            None,
+        )?;
+        let latin1 = repo.find_commit(latin1_id)?;
+        assert_eq!(latin1.message_raw_bytes(), b"caf\xe9\n");
+        assert_eq!(latin1.message_encoding(), Some("ISO-8859-1"));
+        assert_eq!(decode_message(&latin1), "caf\u{e9}\n");
+        let (log, _) = git.run(&[
+            "log",
+            "-1",
+            "--encoding=UTF-8",
+            "--format=%s",
+            &latin1_id.to_string(),
+        ])?;
+        assert_eq!(log.trim_end(), "caf\u{e9}");
+
+        // New messages are written in the configured encoding, and carried ones left alone
+        let reworded =
+            repo.find_commit(crate::ops::reword(&repo, latin1_id, "na\u{ef}ve\n", None)?)?;
+        assert_eq!(reworded.message_raw_bytes(), b"na\xefve\n");
+        assert_eq!(reworded.message_encoding(), Some("ISO-8859-1"));
+        let squashed =
+            repo.find_commit(crate::ops::squash(&repo, reworded.id(), latin1_id, None)?)?;
+        assert_eq!(squashed.message_raw_bytes(), b"caf\xe9\n");
+
+        // Replaying converts into the configured encoding, or passes through what it can't
+        git.run(&["config", "i18n.commitEncoding", "UTF-8"])?;
+        assert_eq!(
+            EncodedMessage::reencode(&latin1, None),
+            EncodedMessage::encode("caf\u{e9}\n", None)
+        );
+        let message = EncodedMessage {
+            bytes: b"caf\xe9\n".to_vec(),
+            encoding: None,
+        };
+        let invalid_id = write_commit(
+            &repo,
+            &head.author(),
+            &head.committer(),
+            &message,
+            &head.tree()?,
+            &[&head],
+            None,
+        )?;
+        let invalid = repo.find_commit(invalid_id)?;
+        assert_eq!(
+            EncodedMessage::reencode(&invalid, Some("ISO-8859-1")),
+            EncodedMessage::of(&invalid)
+        );
+        assert_eq!(decode_message(&invalid), "caf\u{fffd}\n");
+
+        Ok(())
+    }
+}
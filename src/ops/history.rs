@@ -0,0 +1,280 @@
+/// A commit that changed lines being traced by [`line_history`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LineHistoryEntry {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_oid"))]
+    pub commit: git2::Oid,
+    /// The traced lines as of this commit, 1-based like `git log -L`
+    pub lines: std::ops::RangeInclusive<usize>,
+    /// The lines in the parent they came from, `None` when this commit introduced them all
+    pub parent_lines: Option<std::ops::RangeInclusive<usize>>,
+    /// The hunks of the commit's diff that touched the traced lines, in unified diff format
+    pub patch: String,
+}
+
+/// Trace `lines` of `path` back from `tip`, like `git log -L <start>,<end>:<path>`
+///
+/// Lines are 1-based and inclusive.  The range is followed as lines are added and removed
+/// above it.  Commits that don't change the file are skipped without diffing; at merges, a
+/// parent with the same file is followed, like `git log`s history simplification, otherwise
+/// the first parent is.  Renames aren't followed.
+pub fn line_history(
+    repo: &git2::Repository,
+    path: &std::path::Path,
+    lines: std::ops::RangeInclusive<usize>,
+    tip: git2::Oid,
+) -> Result<Vec<LineHistoryEntry>, git2::Error> {
+    let mut commit = repo.find_commit(tip)?;
+    let Some(mut blob_id) = blob_at(&commit, path)? else {
+        return Err(git2::Error::new(
+            git2::ErrorCode::NotFound,
+            git2::ErrorClass::Tree,
+            format!("`{}` does not exist in {}", path.display(), tip),
+        ));
+    };
+    let line_count = repo
+        .find_blob(blob_id)?
+        .content()
+        .split_inclusive(|b| *b == b'\n')
+        .count();
+    if *lines.start() == 0 || lines.start() > lines.end() || line_count < *lines.end() {
+        return Err(git2::Error::new(
+            git2::ErrorCode::Invalid,
+            git2::ErrorClass::Invalid,
+            format!(
+                "lines {}-{} are not in `{}`, which has {} lines",
+                lines.start(),
+                lines.end(),
+                path.display(),
+                line_count
+            ),
+        ));
+    }
+    // 0-based, exclusive end
+    let mut range = (*lines.start() - 1)..*lines.end();
+
+    let mut history = Vec::new();
+    loop {
+        let mut parent = None;
+        for candidate in commit.parents() {
+            let candidate_blob = blob_at(&candidate, path)?;
+            if candidate_blob == Some(blob_id) {
+                parent = Some((candidate, candidate_blob));
+                break;
+            }
+            if parent.is_none() {
+                parent = Some((candidate, candidate_blob));
+            }
+        }
+        let (parent, parent_blob) = match parent {
+            Some((parent, parent_blob)) => (Some(parent), parent_blob),
+            None => (None, None),
+        };
+        if parent_blob == Some(blob_id) {
+            commit = parent.expect("only parents have blobs");
+            continue;
+        }
+
+        let new = repo.find_blob(blob_id)?;
+        let old = parent_blob.map(|id| repo.find_blob(id)).transpose()?;
+        let old_content = old.as_ref().map(|blob| blob.content()).unwrap_or_default();
+        let hunks = hunks(old_content, new.content())?;
+        let touched = hunks.iter().any(|hunk| hunk.touches(&range));
+        let parent_range = match parent_blob {
+            Some(_) => map_to_old(&hunks, &range),
+            None => 0..0,
+        };
+
+        if touched {
+            history.push(LineHistoryEntry {
+                commit: commit.id(),
+                lines: (range.start + 1)..=range.end,
+                parent_lines: (!parent_range.is_empty())
+                    .then(|| (parent_range.start + 1)..=parent_range.end),
+                patch: render_patch(old_content, new.content(), path, &range)?,
+            });
+        }
+
+        match (parent, parent_blob) {
+            (Some(parent), Some(parent_blob)) if !parent_range.is_empty() => {
+                commit = parent;
+                blob_id = parent_blob;
+                range = parent_range;
+            }
+            _ => return Ok(history),
+        }
+    }
+}
+
+fn blob_at(
+    commit: &git2::Commit<'_>,
+    path: &std::path::Path,
+) -> Result<Option<git2::Oid>, git2::Error> {
+    match commit.tree()?.get_path(path) {
+        Ok(entry) if entry.kind() == Some(git2::ObjectType::Blob) => Ok(Some(entry.id())),
+        Ok(_) => Ok(None),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// A change between the parent and the commit, in 0-based lines with exclusive ends
+#[derive(Copy, Clone, Debug)]
+struct Hunk {
+    old_start: usize,
+    old_end: usize,
+    new_start: usize,
+    new_end: usize,
+}
+
+impl Hunk {
+    fn touches(&self, range: &std::ops::Range<usize>) -> bool {
+        if self.new_start == self.new_end {
+            // Lines were only removed, between two of the commit's lines
+            range.start < self.new_start && self.new_start < range.end
+        } else {
+            self.new_start < range.end && range.start < self.new_end
+        }
+    }
+
+    fn contains(&self, line: usize) -> bool {
+        self.new_start <= line && line < self.new_end
+    }
+}
+
+fn hunks(old: &[u8], new: &[u8]) -> Result<Vec<Hunk>, git2::Error> {
+    let mut options = git2::DiffOptions::new();
+    options.context_lines(0).interhunk_lines(0).force_text(true);
+    let patch = git2::Patch::from_buffers(old, None, new, None, Some(&mut options))?;
+    let mut hunks = Vec::with_capacity(patch.num_hunks());
+    for i in 0..patch.num_hunks() {
+        let (hunk, _) = patch.hunk(i)?;
+        // Line numbers are 1-based, except an empty range is the line it comes after
+        let range = |start: u32, len: u32| {
+            let start = start as usize;
+            let len = len as usize;
+            if len == 0 {
+                (start, start)
+            } else {
+                (start - 1, start - 1 + len)
+            }
+        };
+        let (old_start, old_end) = range(hunk.old_start(), hunk.old_lines());
+        let (new_start, new_end) = range(hunk.new_start(), hunk.new_lines());
+        hunks.push(Hunk {
+            old_start,
+            old_end,
+            new_start,
+            new_end,
+        });
+    }
+    Ok(hunks)
+}
+
+/// Where the commit's `range` came from in the parent
+///
+/// Changed lines at the edges widen the range to the whole change in the parent.
+fn map_to_old(hunks: &[Hunk], range: &std::ops::Range<usize>) -> std::ops::Range<usize> {
+    let map = |line: usize, end: bool| -> usize {
+        let mut shift = 0isize;
+        for hunk in hunks {
+            if hunk.contains(line) {
+                return if end { hunk.old_end } else { hunk.old_start };
+            }
+            if hunk.new_end <= line {
+                shift += (hunk.old_end - hunk.old_start) as isize
+                    - (hunk.new_end - hunk.new_start) as isize;
+            }
+        }
+        let mapped = line.saturating_add_signed(shift);
+        if end {
+            mapped + 1
+        } else {
+            mapped
+        }
+    };
+    let start = map(range.start, false);
+    let end = map(range.end - 1, true);
+    start..end.max(start)
+}
+
+/// The unified diff of the hunks touching `range`
+fn render_patch(
+    old: &[u8],
+    new: &[u8],
+    path: &std::path::Path,
+    range: &std::ops::Range<usize>,
+) -> Result<String, git2::Error> {
+    let mut options = git2::DiffOptions::new();
+    options.force_text(true);
+    let patch = git2::Patch::from_buffers(old, Some(path), new, Some(path), Some(&mut options))?;
+    let mut rendered = String::new();
+    for hunk_index in 0..patch.num_hunks() {
+        let (hunk, line_count) = patch.hunk(hunk_index)?;
+        let new_start = (hunk.new_start() as usize).saturating_sub(1);
+        let new_end = new_start + hunk.new_lines() as usize;
+        if new_end <= range.start || range.end <= new_start {
+            continue;
+        }
+        rendered.push_str(&String::from_utf8_lossy(hunk.header()));
+        for line_index in 0..line_count {
+            let line = patch.line_in_hunk(hunk_index, line_index)?;
+            if let origin @ (' ' | '+' | '-') = line.origin() {
+                rendered.push(origin);
+            }
+            rendered.push_str(&String::from_utf8_lossy(line.content()));
+        }
+    }
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_line_history() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.write_file("file", "a\nb\nc\n")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "add"])?;
+        git.write_file("file", "a\nB\nc\n")?;
+        git.run(&["commit", "-am", "change b"])?;
+        git.write_file("file", "new\nlines\na\nB\nc\n")?;
+        git.run(&["commit", "-am", "add lines above"])?;
+        git.write_file("other", "other")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "unrelated"])?;
+
+        let repo = git.get_repo()?;
+        let tip = repo.head()?.target().unwrap();
+        let id = |rev: &str| -> eyre::Result<git2::Oid> { Ok(repo.revparse_single(rev)?.id()) };
+
+        let history = line_history(&repo, std::path::Path::new("file.txt"), 4..=4, tip)?;
+        let summary = history
+            .iter()
+            .map(|entry| {
+                (
+                    entry.commit,
+                    entry.lines.clone(),
+                    entry.parent_lines.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (id("HEAD~2")?, 2..=2, Some(2..=2)),
+                (id("HEAD~3")?, 2..=2, None),
+            ]
+        );
+        assert_eq!(history[0].patch, "@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+
+        assert!(line_history(&repo, std::path::Path::new("file.txt"), 5..=6, tip).is_err());
+
+        Ok(())
+    }
+}
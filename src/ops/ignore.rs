@@ -0,0 +1,494 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// The pattern that decided a path is ignored, see [`is_ignored`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IgnoreMatch {
+    /// The file the pattern is from
+    ///
+    /// `.gitignore` files are relative to the working directory, like `git check-ignore -v`
+    /// reports them, while `info/exclude` and `core.excludesFile` are full paths.
+    pub source: PathBuf,
+    /// 1-based line of the pattern in `source`
+    pub line: usize,
+    /// The pattern as written
+    pub pattern: String,
+}
+
+/// Whether `path`, relative to the working directory, is ignored, like
+/// `git check-ignore --no-index -v`
+///
+/// Patterns are read from `.gitignore` files in the working directory, `info/exclude`, and
+/// `core.excludesFile`, in that order of precedence.  Within a file the last matching pattern
+/// wins, and a path inside an ignored directory is ignored by that directory's pattern.  Whether
+/// the path is tracked isn't considered.
+pub fn is_ignored(
+    repo: &git2::Repository,
+    path: &Path,
+) -> Result<Option<IgnoreMatch>, git2::Error> {
+    Ignores::new(repo)?.check(path)
+}
+
+/// Like [`is_ignored`] for many paths, reading each ignore file once
+pub fn check_ignore<P: AsRef<Path>>(
+    repo: &git2::Repository,
+    paths: impl IntoIterator<Item = P>,
+) -> Result<Vec<(PathBuf, Option<IgnoreMatch>)>, git2::Error> {
+    let mut ignores = Ignores::new(repo)?;
+    paths
+        .into_iter()
+        .map(|path| {
+            let path = path.as_ref();
+            Ok((path.to_owned(), ignores.check(path)?))
+        })
+        .collect()
+}
+
+struct Ignores {
+    workdir: Option<PathBuf>,
+    ignore_case: bool,
+    /// `info/exclude` then `core.excludesFile`
+    global: Vec<Vec<Pattern>>,
+    /// `.gitignore` patterns by the directory they are in
+    per_dir: HashMap<PathBuf, Vec<Pattern>>,
+}
+
+impl Ignores {
+    fn new(repo: &git2::Repository) -> Result<Self, git2::Error> {
+        let config = repo.config()?;
+        let ignore_case = config.get_bool("core.ignoreCase").unwrap_or(false);
+
+        let exclude = crate::utils::common_dir(repo).join("info").join("exclude");
+        let excludes_file = match crate::config::get_path(&config, "core.excludesFile")? {
+            Some(path) => Some(path),
+            None => default_excludes_file(),
+        };
+        let mut global = vec![read_patterns(&exclude, &exclude, Path::new(""))?];
+        if let Some(excludes_file) = excludes_file {
+            global.push(read_patterns(
+                &excludes_file,
+                &excludes_file,
+                Path::new(""),
+            )?);
+        }
+
+        Ok(Self {
+            workdir: repo.workdir().map(|workdir| workdir.to_owned()),
+            ignore_case,
+            global,
+            per_dir: HashMap::new(),
+        })
+    }
+
+    fn check(&mut self, path: &Path) -> Result<Option<IgnoreMatch>, git2::Error> {
+        // Like `git`, nothing inside an ignored directory can be re-included
+        let mut dir = PathBuf::new();
+        let components = path.components().collect::<Vec<_>>();
+        for (i, component) in components.iter().enumerate() {
+            let is_last = i + 1 == components.len();
+            let current = dir.join(component);
+            let is_dir = !is_last
+                || self
+                    .workdir
+                    .as_ref()
+                    .is_some_and(|workdir| workdir.join(&current).is_dir());
+            if let Some(found) = self.last_match(&dir, &current, is_dir)? {
+                if !found.negated {
+                    return Ok(Some(found.to_match()));
+                }
+            }
+            dir = current;
+        }
+        Ok(None)
+    }
+
+    /// The highest precedence pattern matching `path`, which is directly in `dir`
+    fn last_match(
+        &mut self,
+        dir: &Path,
+        path: &Path,
+        is_dir: bool,
+    ) -> Result<Option<&Pattern>, git2::Error> {
+        let path_bytes = crate::bytes::path2bytes_lossy(path).into_owned();
+        let ignore_case = self.ignore_case;
+
+        // Deeper `.gitignore` files take precedence
+        let mut dirs = dir.ancestors().map(Path::to_owned).collect::<Vec<_>>();
+        if dirs.last().is_some_and(|last| !last.as_os_str().is_empty()) {
+            dirs.push(PathBuf::new());
+        }
+        for dir in &dirs {
+            self.load_dir(dir)?;
+        }
+        for dir in &dirs {
+            let found = self.per_dir[dir]
+                .iter()
+                .rev()
+                .find(|pattern| pattern.matches(&path_bytes, is_dir, ignore_case));
+            if found.is_some() {
+                return Ok(found);
+            }
+        }
+        Ok(self.global.iter().find_map(|patterns| {
+            patterns
+                .iter()
+                .rev()
+                .find(|pattern| pattern.matches(&path_bytes, is_dir, ignore_case))
+        }))
+    }
+
+    fn load_dir(&mut self, dir: &Path) -> Result<(), git2::Error> {
+        if self.per_dir.contains_key(dir) {
+            return Ok(());
+        }
+        let patterns = match &self.workdir {
+            Some(workdir) => {
+                let source = dir.join(".gitignore");
+                read_patterns(&workdir.join(&source), &source, dir)?
+            }
+            None => Vec::new(),
+        };
+        self.per_dir.insert(dir.to_owned(), patterns);
+        Ok(())
+    }
+}
+
+fn default_excludes_file() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .or_else(|| std::env::var_os("USERPROFILE"))
+                .map(|home| PathBuf::from(home).join(".config"))
+        })?;
+    Some(config_home.join("git").join("ignore"))
+}
+
+/// Read the patterns in the file at `path`, reported as `source`, for paths under `base`
+fn read_patterns(path: &Path, source: &Path, base: &Path) -> Result<Vec<Pattern>, git2::Error> {
+    let content = match std::fs::read(path) {
+        Ok(content) => content,
+        // Including when part of the path is a file rather than a directory
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound || !path.exists() => {
+            return Ok(Vec::new());
+        }
+        Err(err) => {
+            return Err(git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Os,
+                format!("could not read `{}`: {}", path.display(), err),
+            ));
+        }
+    };
+    let base = crate::bytes::path2bytes_lossy(base).into_owned();
+    Ok(content
+        .split(|b| *b == b'\n')
+        .enumerate()
+        .filter_map(|(i, line)| Pattern::parse(line, source, i + 1, &base))
+        .collect())
+}
+
+#[derive(Clone, Debug)]
+struct Pattern {
+    glob: Vec<u8>,
+    negated: bool,
+    dir_only: bool,
+    /// Matched against the path relative to `base` rather than just the file name
+    anchored: bool,
+    base: Vec<u8>,
+    source: PathBuf,
+    line: usize,
+    text: String,
+}
+
+impl Pattern {
+    fn parse(line: &[u8], source: &Path, line_number: usize, base: &[u8]) -> Option<Self> {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let text = String::from_utf8_lossy(trim_trailing_spaces(line)).into_owned();
+        let mut glob = trim_trailing_spaces(line);
+        if glob.is_empty() || glob.starts_with(b"#") {
+            return None;
+        }
+        let negated = glob.starts_with(b"!");
+        if negated {
+            glob = &glob[1..];
+        }
+        let dir_only = glob.ends_with(b"/");
+        if dir_only {
+            glob = &glob[..glob.len() - 1];
+        }
+        let anchored = glob.contains(&b'/');
+        if let Some(rest) = glob.strip_prefix(b"/") {
+            glob = rest;
+        }
+        if glob.is_empty() {
+            return None;
+        }
+        Some(Self {
+            glob: glob.to_vec(),
+            negated,
+            dir_only,
+            anchored,
+            base: base.to_vec(),
+            source: source.to_owned(),
+            line: line_number,
+            text,
+        })
+    }
+
+    fn matches(&self, path: &[u8], is_dir: bool, ignore_case: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let relative = if self.base.is_empty() {
+            path
+        } else {
+            match path
+                .strip_prefix(self.base.as_slice())
+                .and_then(|rest| rest.strip_prefix(b"/"))
+            {
+                Some(relative) => relative,
+                None => return false,
+            }
+        };
+        if self.anchored {
+            wildmatch(&self.glob, relative, ignore_case)
+        } else {
+            let name = relative.rsplit(|b| *b == b'/').next().unwrap_or(relative);
+            wildmatch(&self.glob, name, ignore_case)
+        }
+    }
+
+    fn to_match(&self) -> IgnoreMatch {
+        IgnoreMatch {
+            source: self.source.clone(),
+            line: self.line,
+            pattern: self.text.clone(),
+        }
+    }
+}
+
+/// Trailing spaces are dropped unless escaped with a backslash
+fn trim_trailing_spaces(line: &[u8]) -> &[u8] {
+    let mut end = line.len();
+    while end > 0 && line[end - 1] == b' ' {
+        if end >= 2 && line[end - 2] == b'\\' {
+            break;
+        }
+        end -= 1;
+    }
+    &line[..end]
+}
+
+/// Match a gitignore glob, where `*` and `?` don't match `/` but `**` between slashes matches
+/// any number of directories
+fn wildmatch(pattern: &[u8], text: &[u8], ignore_case: bool) -> bool {
+    let eq = |a: u8, b: u8| a == b || (ignore_case && a.eq_ignore_ascii_case(&b));
+    let (mut p, mut t) = (0, 0);
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                let start = p;
+                while p < pattern.len() && pattern[p] == b'*' {
+                    p += 1;
+                }
+                let double = p - start >= 2
+                    && (start == 0 || pattern[start - 1] == b'/')
+                    && (p == pattern.len() || pattern[p] == b'/');
+                if double {
+                    if p == pattern.len() {
+                        return true;
+                    }
+                    // `**/` also matches no directories
+                    let rest = &pattern[p + 1..];
+                    return wildmatch(rest, &text[t..], ignore_case)
+                        || (t..text.len()).any(|i| {
+                            text[i] == b'/' && wildmatch(rest, &text[i + 1..], ignore_case)
+                        });
+                }
+                let rest = &pattern[p..];
+                for i in t..=text.len() {
+                    if wildmatch(rest, &text[i..], ignore_case) {
+                        return true;
+                    }
+                    if i < text.len() && text[i] == b'/' {
+                        break;
+                    }
+                }
+                return false;
+            }
+            b'?' => {
+                if t >= text.len() || text[t] == b'/' {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+            b'[' => {
+                let Some(&c) = text.get(t) else {
+                    return false;
+                };
+                match match_class(&pattern[p + 1..], c, ignore_case) {
+                    Some((true, len)) if c != b'/' => {
+                        p += 1 + len;
+                        t += 1;
+                    }
+                    Some(_) => return false,
+                    // An unterminated class is a literal `[`
+                    None => {
+                        if c != b'[' {
+                            return false;
+                        }
+                        p += 1;
+                        t += 1;
+                    }
+                }
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                if t >= text.len() || !eq(pattern[p + 1], text[t]) {
+                    return false;
+                }
+                p += 2;
+                t += 1;
+            }
+            c => {
+                if t >= text.len() || !eq(c, text[t]) {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+        }
+    }
+    t == text.len()
+}
+
+/// Whether `c` is in the class starting after a `[`, and how long the class is including `]`
+fn match_class(class: &[u8], c: u8, ignore_case: bool) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negated = matches!(class.first(), Some(b'!' | b'^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let &start = class.get(i)?;
+        if start == b']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        let start = if start == b'\\' {
+            i += 1;
+            *class.get(i)?
+        } else {
+            start
+        };
+        if class.get(i + 1) == Some(&b'-') && class.get(i + 2).is_some_and(|end| *end != b']') {
+            let end = class[i + 2];
+            let in_range = |c: u8| start <= c && c <= end;
+            matched |= in_range(c)
+                || (ignore_case
+                    && (in_range(c.to_ascii_lowercase()) || in_range(c.to_ascii_uppercase())));
+            i += 3;
+        } else {
+            matched |= start == c || (ignore_case && start.eq_ignore_ascii_case(&c));
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_wildmatch() {
+        assert!(wildmatch(b"*.o", b"main.o", false));
+        assert!(!wildmatch(b"*.o", b"dir/main.o", false));
+        assert!(wildmatch(b"**/build", b"a/b/build", false));
+        assert!(wildmatch(b"**/build", b"build", false));
+        assert!(wildmatch(b"a/**/b", b"a/b", false));
+        assert!(wildmatch(b"a/**/b", b"a/x/y/b", false));
+        assert!(wildmatch(b"logs/**", b"logs/x/y", false));
+        assert!(wildmatch(b"file[0-9]", b"file7", false));
+        assert!(!wildmatch(b"file[!0-9]", b"file7", false));
+        assert!(wildmatch(b"\\#hash", b"#hash", false));
+        assert!(wildmatch(b"README", b"readme", true));
+    }
+
+    #[test]
+    fn test_is_ignored() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        std::fs::write(
+            git.repo_path.join(".gitignore"),
+            "*.log\n!keep.log\n/build/\n",
+        )?;
+        std::fs::create_dir_all(git.repo_path.join("sub/build"))?;
+        std::fs::write(git.repo_path.join("sub/.gitignore"), "keep.log\n")?;
+        std::fs::write(git.repo_path.join(".git/info/exclude"), "# local\nsecret\n")?;
+        std::fs::create_dir_all(git.repo_path.join("build"))?;
+
+        let repo = git.get_repo()?;
+        let paths = [
+            "debug.log",
+            "keep.log",
+            "sub/keep.log",
+            "build/out/app",
+            "sub/build",
+            "secret",
+            "src/main.rs",
+        ];
+        let results = check_ignore(&repo, paths)?
+            .into_iter()
+            .map(|(path, found)| {
+                (
+                    path.display().to_string(),
+                    found.map(|found| (found.line, found.pattern)),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            [
+                ("debug.log".to_owned(), Some((1, "*.log".to_owned()))),
+                ("keep.log".to_owned(), None),
+                ("sub/keep.log".to_owned(), Some((1, "keep.log".to_owned()))),
+                ("build/out/app".to_owned(), Some((3, "/build/".to_owned()))),
+                ("sub/build".to_owned(), None),
+                ("secret".to_owned(), Some((2, "secret".to_owned()))),
+                ("src/main.rs".to_owned(), None),
+            ]
+        );
+
+        // Agrees with `git`, which also reports negated patterns but doesn't ignore for them
+        let mut args = vec!["check-ignore", "--no-index", "--verbose", "--non-matching"];
+        args.extend(paths);
+        let (stdout, _) = git.run(&args)?;
+        let expected = stdout
+            .lines()
+            .map(|line| {
+                let (source, path) = line.split_once('\t').expect("verbose output");
+                let mut source = source.splitn(3, ':');
+                let (_, line, pattern) = (source.next(), source.next(), source.next());
+                let found = line
+                    .zip(pattern)
+                    .filter(|(_, pattern)| !pattern.is_empty() && !pattern.starts_with('!'))
+                    .map(|(line, pattern)| (line.parse().unwrap(), pattern.to_owned()));
+                (path.to_owned(), found)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(results, expected);
+        assert_eq!(
+            is_ignored(&repo, Path::new("sub/keep.log"))?.map(|found| found.source),
+            Some(PathBuf::from("sub/.gitignore"))
+        );
+
+        Ok(())
+    }
+}
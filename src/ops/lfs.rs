@@ -0,0 +1,140 @@
+/// The content of a Git LFS pointer file, standing in for a file stored outside of `git`
+///
+/// See <https://github.com/git-lfs/git-lfs/blob/main/docs/spec.md>.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LfsPointer {
+    /// The SHA-256 of the file's content, in hex
+    pub oid: String,
+    /// The size of the file's content, in bytes
+    pub size: u64,
+}
+
+impl LfsPointer {
+    /// Parse a pointer file, `None` when `content` isn't one
+    ///
+    /// Following `git-lfs`, pointers are small UTF-8 files of sorted `key value` lines, starting
+    /// with the spec `version` and including the `oid` and `size`.
+    pub fn parse(content: &[u8]) -> Option<Self> {
+        // `git-lfs` doesn't look at anything larger as a pointer
+        const MAX_POINTER_SIZE: usize = 1024;
+        const VERSIONS: [&str; 2] = [
+            "https://git-lfs.github.com/spec/v1",
+            "https://hawser.github.com/spec/v1",
+        ];
+
+        if MAX_POINTER_SIZE <= content.len() {
+            return None;
+        }
+        let content = std::str::from_utf8(content).ok()?;
+        let mut lines = content.strip_suffix('\n')?.split('\n');
+        let version = lines.next()?.strip_prefix("version ")?;
+        if !VERSIONS.contains(&version) {
+            return None;
+        }
+
+        let mut oid = None;
+        let mut size = None;
+        let mut last_key = "";
+        for line in lines {
+            let (key, value) = line.split_once(' ')?;
+            let valid_key = key
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'.' || b == b'-');
+            if !valid_key || key <= last_key {
+                return None;
+            }
+            last_key = key;
+            match key {
+                "oid" => {
+                    let hex = value.strip_prefix("sha256:")?;
+                    let valid_hex = hex.len() == 64
+                        && hex
+                            .bytes()
+                            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+                    if !valid_hex {
+                        return None;
+                    }
+                    oid = Some(hex.to_owned());
+                }
+                "size" => size = Some(value.parse().ok()?),
+                _ => {}
+            }
+        }
+
+        Some(Self {
+            oid: oid?,
+            size: size?,
+        })
+    }
+}
+
+/// Whether `blob` is a Git LFS pointer, rather than real content, see [`LfsPointer::parse`]
+pub fn is_lfs_pointer(blob: &git2::Blob<'_>) -> bool {
+    LfsPointer::parse(blob.content()).is_some()
+}
+
+/// Whether `.gitattributes` have Git LFS store `path`, with `filter=lfs`
+///
+/// The blobs of such paths are expected to be pointer files, which [`is_lfs_pointer`] can
+/// confirm.
+pub fn is_lfs_tracked(
+    repo: &git2::Repository,
+    path: &std::path::Path,
+) -> Result<bool, git2::Error> {
+    let value = repo.get_attr_bytes(path, "filter", git2::AttrCheckFlags::FILE_THEN_INDEX)?;
+    Ok(value == Some(b"lfs".as_slice()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_lfs_pointer() -> eyre::Result<()> {
+        let oid = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+        let pointer =
+            format!("version https://git-lfs.github.com/spec/v1\noid sha256:{oid}\nsize 12345\n");
+        assert_eq!(
+            LfsPointer::parse(pointer.as_bytes()),
+            Some(LfsPointer {
+                oid: oid.to_owned(),
+                size: 12345,
+            })
+        );
+        let extended = format!(
+            "version https://git-lfs.github.com/spec/v1\next-0-foo sha256:{oid}\noid sha256:{oid}\nsize 1\n"
+        );
+        assert!(LfsPointer::parse(extended.as_bytes()).is_some());
+
+        // Missing or unsorted keys, and anything else, aren't pointers
+        let unsorted =
+            format!("version https://git-lfs.github.com/spec/v1\nsize 1\noid sha256:{oid}\n");
+        assert_eq!(LfsPointer::parse(unsorted.as_bytes()), None);
+        let no_size = format!("version https://git-lfs.github.com/spec/v1\noid sha256:{oid}\n");
+        assert_eq!(LfsPointer::parse(no_size.as_bytes()), None);
+        assert_eq!(LfsPointer::parse(pointer.trim_end().as_bytes()), None);
+        assert_eq!(LfsPointer::parse(b"version 1\n"), None);
+        assert_eq!(LfsPointer::parse(b""), None);
+
+        let git = make_git()?;
+        git.init_repo()?;
+        std::fs::write(
+            git.repo_path.join(".gitattributes"),
+            "*.bin filter=lfs diff=lfs merge=lfs -text\n",
+        )?;
+        let repo = git.get_repo()?;
+        assert!(is_lfs_tracked(
+            &repo,
+            std::path::Path::new("dir/large.bin")
+        )?);
+        assert!(!is_lfs_tracked(&repo, std::path::Path::new("small.txt"))?);
+        assert!(is_lfs_pointer(
+            &repo.find_blob(repo.blob(pointer.as_bytes())?)?
+        ));
+        assert!(!is_lfs_pointer(&repo.find_blob(repo.blob(b"content")?)?));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+/// Remembers merge bases and related ancestry queries across an operation
+///
+/// Interactive tools redraw by asking for the same pairs of commits over and over, like every
+/// branch against its upstream.  Commits never change, so answers, including that two commits
+/// have no merge base, stay valid for as long as the cache is kept.
+#[derive(Clone, Debug, Default)]
+pub struct MergeBaseCache {
+    /// Keyed by the pair in sorted order, as the merge base doesn't depend on it
+    merge_bases: HashMap<(git2::Oid, git2::Oid), Option<git2::Oid>>,
+    /// Keyed by `(ancestor, descendant)`
+    ancestry: HashMap<(git2::Oid, git2::Oid), bool>,
+    /// Keyed by `(local, upstream)`
+    ahead_behind: HashMap<(git2::Oid, git2::Oid), (usize, usize)>,
+    graph: Option<super::CommitGraph>,
+}
+
+impl MergeBaseCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A cache that answers ancestry queries from the repository's [`super::CommitGraph`], when
+    /// it has one
+    pub fn with_commit_graph(repo: &git2::Repository) -> Result<Self, git2::Error> {
+        Ok(Self {
+            graph: super::CommitGraph::open(repo)?,
+            ..Self::default()
+        })
+    }
+
+    /// The number of queries remembered
+    pub fn len(&self) -> usize {
+        self.merge_bases.len() + self.ancestry.len() + self.ahead_behind.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget everything, like when it has grown too large
+    pub fn clear(&mut self) {
+        self.merge_bases.clear();
+        self.ancestry.clear();
+        self.ahead_behind.clear();
+    }
+
+    /// The best common ancestor of `one` and `two`, `None` when they have no history in common
+    pub fn merge_base(
+        &mut self,
+        repo: &git2::Repository,
+        one: git2::Oid,
+        two: git2::Oid,
+    ) -> Result<Option<git2::Oid>, git2::Error> {
+        let key = if one <= two { (one, two) } else { (two, one) };
+        if let Some(hit) = self.merge_bases.get(&key) {
+            return Ok(*hit);
+        }
+        let base = match repo.merge_base(one, two) {
+            Ok(base) => Some(base),
+            Err(err) if err.code() == git2::ErrorCode::NotFound => None,
+            Err(err) => return Err(err),
+        };
+        self.merge_bases.insert(key, base);
+        Ok(base)
+    }
+
+    /// Whether `ancestor` is reachable from `descendant`, see [`super::is_ancestor`]
+    pub fn is_ancestor(
+        &mut self,
+        repo: &git2::Repository,
+        ancestor: git2::Oid,
+        descendant: git2::Oid,
+    ) -> Result<bool, git2::Error> {
+        if ancestor == descendant {
+            return Ok(true);
+        }
+        let key = (ancestor, descendant);
+        if let Some(hit) = self.ancestry.get(&key) {
+            return Ok(*hit);
+        }
+        let reachable = match self
+            .graph
+            .as_ref()
+            .and_then(|graph| graph.is_ancestor(ancestor, descendant))
+        {
+            Some(reachable) => reachable,
+            None => self.merge_base(repo, ancestor, descendant)? == Some(ancestor),
+        };
+        self.ancestry.insert(key, reachable);
+        Ok(reachable)
+    }
+
+    /// How many commits `local` has that `upstream` doesn't, and the other way around
+    pub fn ahead_behind(
+        &mut self,
+        repo: &git2::Repository,
+        local: git2::Oid,
+        upstream: git2::Oid,
+    ) -> Result<(usize, usize), git2::Error> {
+        let key = (local, upstream);
+        if let Some(hit) = self.ahead_behind.get(&key) {
+            return Ok(*hit);
+        }
+        let counts = repo.graph_ahead_behind(local, upstream)?;
+        self.ahead_behind.insert(key, counts);
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_merge_base_cache() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let base = repo.refname_to_id("refs/heads/master")?;
+        let ahead = git.commit_file("ahead", 1)?;
+        git.run(&["checkout", "-b", "side", &base.to_string()])?;
+        let side = git.commit_file("side", 2)?;
+        let side2 = git.commit_file("side2", 3)?;
+        git.run(&["checkout", "--orphan", "orphan"])?;
+        let orphan = git.commit_file("orphan", 4)?;
+
+        let mut cache = MergeBaseCache::new();
+        assert_eq!(cache.merge_base(&repo, ahead, side2)?, Some(base));
+        assert_eq!(cache.merge_base(&repo, side2, ahead)?, Some(base));
+        assert_eq!(cache.merge_base(&repo, ahead, orphan)?, None);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.is_ancestor(&repo, side, side2)?);
+        assert!(!cache.is_ancestor(&repo, side2, side)?);
+        assert!(!cache.is_ancestor(&repo, orphan, side)?);
+        assert_eq!(cache.ahead_behind(&repo, side2, ahead)?, (2, 1));
+
+        // Answers are reused without looking at the commits again
+        let len = cache.len();
+        let other = make_git()?;
+        other.init_repo()?;
+        let repo = other.get_repo()?;
+        repo.find_commit(side2).unwrap_err();
+        assert_eq!(cache.merge_base(&repo, side2, ahead)?, Some(base));
+        assert_eq!(cache.merge_base(&repo, orphan, ahead)?, None);
+        assert!(cache.is_ancestor(&repo, side, side2)?);
+        assert_eq!(cache.ahead_behind(&repo, side2, ahead)?, (2, 1));
+        assert_eq!(cache.len(), len);
+
+        cache.clear();
+        assert!(cache.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1740 @@
+//! Higher-level git operations
+//!
+//! These are closer to what you expect to see for porcelain commands, rather than just plumbing.
+//! They serve as both examples on how to use `git2` but also should be usable in some limited
+//! subset of cases.
+
+mod ancestry;
+mod encoding;
+mod history;
+mod ignore;
+mod lfs;
+mod merge_base;
+mod progress;
+mod remote;
+mod resign;
+mod speculate;
+mod worktree;
+
+pub use ancestry::is_ancestor;
+pub use ancestry::is_ancestor_with;
+pub use ancestry::CommitGraph;
+pub use encoding::commit_encoding;
+pub use encoding::decode_message;
+pub use history::line_history;
+pub use history::LineHistoryEntry;
+pub use ignore::check_ignore;
+pub use ignore::is_ignored;
+pub use ignore::IgnoreMatch;
+pub use lfs::is_lfs_pointer;
+pub use lfs::is_lfs_tracked;
+pub use lfs::LfsPointer;
+pub use merge_base::MergeBaseCache;
+pub use progress::Phase;
+pub use progress::Progress;
+pub use progress::ProgressUpdate;
+pub use remote::check_force_with_lease;
+pub use remote::clone;
+pub use remote::fetch;
+pub use remote::ls_remote;
+pub use remote::prune_remote;
+pub use remote::push;
+pub use remote::CloneOptions;
+pub use remote::FetchOptions;
+pub use remote::FetchProgress;
+pub use remote::FetchTags;
+pub use remote::FetchedRef;
+pub use remote::PushOptions;
+pub use remote::PushProgress;
+pub use remote::PushedRef;
+pub use remote::RemoteRef;
+pub use resign::resign_range;
+pub use speculate::speculate;
+pub use speculate::Speculation;
+pub use worktree::add_worktree;
+pub use worktree::prune_worktree;
+pub use worktree::worktrees;
+pub use worktree::AddWorktreeOptions;
+pub use worktree::PruneWorktreeOptions;
+pub use worktree::Worktree;
+
+use bstr::ByteSlice;
+use itertools::Itertools;
+
+use encoding::EncodedMessage;
+
+/// Lookup the commit ID for `HEAD`
+pub fn head_id(repo: &git2::Repository) -> Option<git2::Oid> {
+    repo.head().ok()?.resolve().ok()?.target()
+}
+
+/// Lookup the branch that HEAD points to
+pub fn head_branch(repo: &git2::Repository) -> Option<String> {
+    repo.head()
+        .ok()?
+        .resolve()
+        .ok()?
+        .shorthand()
+        .map(String::from)
+}
+
+/// Report if the working directory is dirty
+///
+/// When the status can't be read, like for a bare repository or a locked index, this errs on
+/// the side of caution and reports it as dirty; see [`try_is_dirty`] for the error.
+pub fn is_dirty(repo: &git2::Repository) -> bool {
+    try_is_dirty(repo).unwrap_or_else(|err| {
+        log::trace!("Could not read repository status: {err}");
+        true
+    })
+}
+
+/// Report if the working directory is dirty, failing when its status can't be read
+pub fn try_is_dirty(repo: &git2::Repository) -> Result<bool, git2::Error> {
+    if repo.state() != git2::RepositoryState::Clean {
+        log::trace!("Repository status is unclean: {:?}", repo.state());
+        return Ok(true);
+    }
+
+    let status = repo.statuses(Some(git2::StatusOptions::new().include_ignored(false)))?;
+    if status.is_empty() {
+        Ok(false)
+    } else {
+        log::trace!(
+            "Repository is dirty: {}",
+            status
+                .iter()
+                .filter_map(|s| s.path().map(|s| s.to_owned()))
+                .join(", ")
+        );
+        Ok(true)
+    }
+}
+
+/// Whether `repo` is missing history from a shallow clone or fetch
+pub fn is_shallow(repo: &git2::Repository) -> bool {
+    repo.is_shallow()
+}
+
+/// Commits whose parents were cut off by a shallow clone or fetch
+pub fn shallow_boundary(repo: &git2::Repository) -> Vec<git2::Oid> {
+    if !repo.is_shallow() {
+        return Vec::new();
+    }
+    let shallow =
+        std::fs::read_to_string(crate::utils::common_dir(repo).join("shallow")).unwrap_or_default();
+    shallow
+        .lines()
+        .filter_map(|line| git2::Oid::from_str(line.trim()).ok())
+        .collect()
+}
+
+/// Rewriting a commit at the shallow boundary can't see its real parents
+///
+/// Call this once per operation, with all of the commits it rewrites.
+fn warn_on_shallow_boundary(
+    repo: &git2::Repository,
+    op: &str,
+    ids: impl IntoIterator<Item = git2::Oid>,
+) {
+    // Most repositories aren't shallow, so don't bother looking for the boundary
+    if !repo.is_shallow() {
+        return;
+    }
+    let boundary = shallow_boundary(repo);
+    for id in ids {
+        if boundary.contains(&id) {
+            log::warn!("{op} of {id} at the shallow boundary; its parents are missing");
+        }
+    }
+}
+
+/// How [`get_changed_paths_for_commit`] treats merge commits
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ParentStrategy {
+    /// Only compare with the first parent, like `git log --first-parent`
+    #[default]
+    FirstParent,
+    /// Paths changed relative to any parent
+    Union,
+    /// Paths changed relative to every parent, like `git diff --cc`, so only paths the merge
+    /// had to reconcile
+    Combined,
+}
+
+/// The paths a commit changed
+///
+/// Root commits are compared with the empty tree, so every path is reported.  For merge
+/// commits, `strategy` decides which parents to compare with.
+pub fn get_changed_paths_for_commit(
+    repo: &git2::Repository,
+    commit: &git2::Commit<'_>,
+    strategy: ParentStrategy,
+) -> Result<std::collections::HashSet<std::path::PathBuf>, git2::Error> {
+    let tree = commit.tree()?;
+    if commit.parent_count() == 0 {
+        return crate::tree::get_changed_paths_between_trees(repo, None, Some(&tree));
+    }
+    let parent_count = match strategy {
+        ParentStrategy::FirstParent => 1,
+        ParentStrategy::Union | ParentStrategy::Combined => commit.parent_count(),
+    };
+
+    let mut paths: Option<std::collections::HashSet<std::path::PathBuf>> = None;
+    for parent in commit.parents().take(parent_count) {
+        let parent_tree = parent.tree()?;
+        let changed =
+            crate::tree::get_changed_paths_between_trees(repo, Some(&parent_tree), Some(&tree))?;
+        paths = Some(match (paths, strategy) {
+            (None, _) => changed,
+            (Some(mut paths), ParentStrategy::Union | ParentStrategy::FirstParent) => {
+                paths.extend(changed);
+                paths
+            }
+            (Some(mut paths), ParentStrategy::Combined) => {
+                paths.retain(|path| changed.contains(path));
+                paths
+            }
+        });
+    }
+    Ok(paths.unwrap_or_default())
+}
+
+/// Cherry pick a commit onto another without touching the working directory
+pub fn cherry_pick(
+    repo: &git2::Repository,
+    head_id: git2::Oid,
+    cherry_id: git2::Oid,
+    sign: Option<&dyn Sign>,
+) -> Result<git2::Oid, git2::Error> {
+    let options = CherryPickOptions {
+        sign,
+        ..CherryPickOptions::default()
+    };
+    cherry_pick_with(repo, head_id, cherry_id, options)
+}
+
+/// How to [`cherry_pick_with`]
+#[derive(Clone, Default)]
+pub struct CherryPickOptions<'a> {
+    sign: Option<&'a dyn Sign>,
+    merge: crate::tree::MergeOptions,
+    progress: Option<&'a dyn Progress>,
+}
+
+impl<'a> CherryPickOptions<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sign the new commit
+    pub fn sign(mut self, sign: &'a dyn Sign) -> Self {
+        self.sign = Some(sign);
+        self
+    }
+
+    /// How to merge the commit's changes, like favoring one side in conflicting hunks
+    pub fn merge_options(mut self, merge: crate::tree::MergeOptions) -> Self {
+        self.merge = merge;
+        self
+    }
+
+    /// Report each commit picked by [`cherry_pick_stack_with`]
+    pub fn progress(mut self, progress: &'a dyn Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+}
+
+/// Cherry pick a commit onto another without touching the working directory, see
+/// [`cherry_pick`]
+pub fn cherry_pick_with(
+    repo: &git2::Repository,
+    head_id: git2::Oid,
+    cherry_id: git2::Oid,
+    options: CherryPickOptions<'_>,
+) -> Result<git2::Oid, git2::Error> {
+    warn_on_shallow_boundary(repo, "cherry-pick", [head_id, cherry_id]);
+    let cherry_commit = repo.find_commit(cherry_id)?;
+    let base_id = match cherry_commit.parent_count() {
+        0 => cherry_id,
+        1 => cherry_commit.parent_id(0)?,
+        _ => cherry_commit
+            .parent_ids()
+            .find(|id| *id == head_id)
+            .map(Ok)
+            .unwrap_or_else(|| cherry_commit.parent_id(0))?,
+    };
+    if base_id == head_id {
+        // Already on top of the intended base
+        return Ok(cherry_id);
+    }
+
+    let head_commit = repo.find_commit(head_id)?;
+    if cherry_commit.parent_count() == 1 {
+        // Replaying one commit is a single merge, without the rebase machinery
+        let base_tree = repo.find_commit(base_id)?.tree()?;
+        return match pick_onto(repo, &head_commit, &cherry_commit, &base_tree, &options)? {
+            PickOutcome::Committed(new_id) => Ok(new_id),
+            PickOutcome::Conflicted(conflicted) => Err(conflicted.into_error()),
+        };
+    }
+
+    let base_ann_commit = repo.find_annotated_commit(base_id)?;
+    let head_ann_commit = repo.find_annotated_commit(head_id)?;
+    let cherry_ann_commit = repo.find_annotated_commit(cherry_id)?;
+    let mut rebase = repo.rebase(
+        Some(&cherry_ann_commit),
+        Some(&base_ann_commit),
+        Some(&head_ann_commit),
+        Some(
+            git2::RebaseOptions::new()
+                .inmemory(true)
+                .merge_options(options.merge.to_git2()),
+        ),
+    )?;
+
+    let mut tip_id = head_id;
+    while let Some(op) = rebase.next() {
+        op.inspect_err(|_err| {
+            let _ = rebase.abort();
+        })?;
+        let mut inmemory_index = rebase.inmemory_index()?;
+        crate::tree::run_merge_drivers(repo, &mut inmemory_index)?;
+        if inmemory_index.has_conflicts() {
+            return Err(conflicts_error("cherry-pick", &inmemory_index));
+        }
+
+        // Rather than `rebase.commit`, which writes a commit only for it to be rewritten to be
+        // signed, write the tree and commit it once
+        let tree_id = inmemory_index.write_tree_to(repo)?;
+        if tree_id == head_commit.tree_id() {
+            log::trace!("Skipping {}, already applied to {}", cherry_id, head_id);
+            return Ok(tip_id);
+        }
+        let tree = repo.find_tree(tree_id)?;
+        let committer = commit_signature(repo)?;
+        tip_id = replay_commit(
+            repo,
+            &committer,
+            &cherry_commit,
+            &tree,
+            &head_commit,
+            commit_encoding(repo)?.as_deref(),
+            options.sign,
+        )?;
+    }
+    rebase.finish(None)?;
+    Ok(tip_id)
+}
+
+/// Like [`cherry_pick`] but handing back the conflicted index rather than failing
+///
+/// Root and merge commits are replayed by merging their tree, against the empty tree or the
+/// parent `head_id` is, onto `head_id`s.
+pub fn try_cherry_pick(
+    repo: &git2::Repository,
+    head_id: git2::Oid,
+    cherry_id: git2::Oid,
+    options: CherryPickOptions<'_>,
+) -> Result<PickOutcome, git2::Error> {
+    warn_on_shallow_boundary(repo, "cherry-pick", [head_id, cherry_id]);
+    let cherry_commit = repo.find_commit(cherry_id)?;
+    let base_id = match cherry_commit.parent_count() {
+        0 => None,
+        1 => Some(cherry_commit.parent_id(0)?),
+        _ => Some(
+            cherry_commit
+                .parent_ids()
+                .find(|id| *id == head_id)
+                .map(Ok)
+                .unwrap_or_else(|| cherry_commit.parent_id(0))?,
+        ),
+    };
+    if base_id == Some(head_id) {
+        // Already on top of the intended base
+        return Ok(PickOutcome::Committed(cherry_id));
+    }
+
+    let head_commit = repo.find_commit(head_id)?;
+    let base_tree = match base_id {
+        Some(base_id) => repo.find_commit(base_id)?.tree()?,
+        None => repo.find_tree(repo.treebuilder(None)?.write()?)?,
+    };
+    pick_onto(repo, &head_commit, &cherry_commit, &base_tree, &options)
+}
+
+/// Replay the changes from `base_tree` to `cherry_commit` onto `head_commit`
+fn pick_onto(
+    repo: &git2::Repository,
+    head_commit: &git2::Commit<'_>,
+    cherry_commit: &git2::Commit<'_>,
+    base_tree: &git2::Tree<'_>,
+    options: &CherryPickOptions<'_>,
+) -> Result<PickOutcome, git2::Error> {
+    let index = crate::tree::merge_trees(
+        repo,
+        base_tree,
+        &head_commit.tree()?,
+        &cherry_commit.tree()?,
+        &options.merge,
+    )?;
+    let committer = replay_committer(&commit_signature(repo)?, cherry_commit)?;
+    let conflicted = Conflicted {
+        op: "cherry-pick",
+        index,
+        author: cherry_commit.author().to_owned(),
+        committer,
+        message: EncodedMessage::reencode(cherry_commit, commit_encoding(repo)?.as_deref()),
+        parent_ids: vec![head_commit.id()],
+        skip_unchanged: true,
+    };
+    if conflicted.index.has_conflicts() {
+        return Ok(PickOutcome::Conflicted(conflicted));
+    }
+    conflicted
+        .resume(repo, options.sign)
+        .map(PickOutcome::Committed)
+}
+
+/// The result of [`try_cherry_pick`] and [`try_squash`]
+pub enum PickOutcome {
+    /// The new commit
+    Committed(git2::Oid),
+    /// The merge stopped on conflicts, to be resolved and [`Conflicted::resume`]d
+    Conflicted(Conflicted),
+}
+
+/// An operation stopped on conflicts, holding what's needed to finish it
+///
+/// The in-memory [`git2::Index`] has the conflicting paths at stages 1 (ancestor), 2 (ours),
+/// and 3 (theirs).  Resolve them, like with [`git2::Index::remove_path`] and then
+/// [`git2::Index::add`]ing an entry for the resolved blob, then [`Conflicted::resume`] to
+/// commit.  Nothing is written to the working directory.
+pub struct Conflicted {
+    op: &'static str,
+    index: git2::Index,
+    author: git2::Signature<'static>,
+    committer: git2::Signature<'static>,
+    message: EncodedMessage,
+    parent_ids: Vec<git2::Oid>,
+    /// Drop the commit when it ends up not changing its parent, like a cherry pick already
+    /// applied
+    skip_unchanged: bool,
+}
+
+impl Conflicted {
+    pub fn index(&self) -> &git2::Index {
+        &self.index
+    }
+
+    pub fn index_mut(&mut self) -> &mut git2::Index {
+        &mut self.index
+    }
+
+    /// The commits the result will be committed on top of
+    pub fn parent_ids(&self) -> &[git2::Oid] {
+        &self.parent_ids
+    }
+
+    /// Commit the resolved index, failing if conflicts are left
+    ///
+    /// Returns the new commit, or for a cherry pick that ended up changing nothing, its parent.
+    pub fn resume(
+        mut self,
+        repo: &git2::Repository,
+        sign: Option<&dyn Sign>,
+    ) -> Result<git2::Oid, git2::Error> {
+        if self.index.has_conflicts() {
+            return Err(self.into_error());
+        }
+        let tree_id = self.index.write_tree_to(repo)?;
+        let parents = self
+            .parent_ids
+            .iter()
+            .map(|id| repo.find_commit(*id))
+            .collect::<Result<Vec<_>, _>>()?;
+        if let (true, [parent]) = (self.skip_unchanged, parents.as_slice()) {
+            if parent.tree_id() == tree_id {
+                log::trace!("Skipping {} as it changes nothing", self.op);
+                return Ok(parent.id());
+            }
+        }
+        let tree = repo.find_tree(tree_id)?;
+        let parents = parents.iter().collect::<Vec<_>>();
+        encoding::write_commit(
+            repo,
+            &self.author,
+            &self.committer,
+            &self.message,
+            &tree,
+            &parents,
+            sign,
+        )
+    }
+
+    /// The error [`cherry_pick`] or [`squash`] would have reported
+    pub fn into_error(self) -> git2::Error {
+        conflicts_error(self.op, &self.index)
+    }
+}
+
+/// Cherry pick a stack of commits onto `head_id` without touching the working directory
+///
+/// This gives the same commits as calling [`cherry_pick`] on each of `cherry_ids` in turn, but
+/// rather than setting up a rebase for every commit, each one's changes are merged straight into
+/// the tree built so far and only the new commits are written.
+///
+/// Returns the new tip after each of `cherry_ids`.  A commit already applied to the new base is
+/// dropped, leaving the tip where it was, and commits still on top of their intended base are
+/// reused as-is.
+pub fn cherry_pick_stack(
+    repo: &git2::Repository,
+    head_id: git2::Oid,
+    cherry_ids: &[git2::Oid],
+    sign: Option<&dyn Sign>,
+) -> Result<Vec<git2::Oid>, git2::Error> {
+    let options = CherryPickOptions {
+        sign,
+        ..CherryPickOptions::default()
+    };
+    cherry_pick_stack_with(repo, head_id, cherry_ids, options)
+}
+
+/// Cherry pick a stack of commits onto `head_id`, see [`cherry_pick_stack`]
+///
+/// The merge options apply to each commit and, unlike [`cherry_pick_with`], the stack is
+/// always replayed by merging trees.
+pub fn cherry_pick_stack_with(
+    repo: &git2::Repository,
+    head_id: git2::Oid,
+    cherry_ids: &[git2::Oid],
+    options: CherryPickOptions<'_>,
+) -> Result<Vec<git2::Oid>, git2::Error> {
+    let sign = options.sign;
+    let report = |completed: usize, cherry_id: git2::Oid| {
+        if let Some(progress) = options.progress {
+            let update = ProgressUpdate::new(Phase::CherryPick, completed, Some(cherry_ids.len()));
+            progress.report(&update.commit(cherry_id));
+        }
+    };
+    warn_on_shallow_boundary(
+        repo,
+        "cherry-pick",
+        std::iter::once(head_id).chain(cherry_ids.iter().copied()),
+    );
+    let committer = commit_signature(repo)?;
+    let encoding = commit_encoding(repo)?;
+    let mut tip_commit = repo.find_commit(head_id)?;
+    let mut tip_tree = tip_commit.tree()?;
+    let mut tips = Vec::with_capacity(cherry_ids.len());
+    for (i, cherry_id) in cherry_ids.iter().copied().enumerate() {
+        report(i, cherry_id);
+        let cherry_commit = repo.find_commit(cherry_id)?;
+        let base_id = match cherry_commit.parent_count() {
+            0 => None,
+            1 => Some(cherry_commit.parent_id(0)?),
+            _ => Some(
+                cherry_commit
+                    .parent_ids()
+                    .find(|id| *id == tip_commit.id())
+                    .map(Ok)
+                    .unwrap_or_else(|| cherry_commit.parent_id(0))?,
+            ),
+        };
+        if base_id == Some(tip_commit.id()) {
+            // Already on top of the intended base
+            tip_tree = cherry_commit.tree()?;
+            tip_commit = cherry_commit;
+            tips.push(cherry_id);
+            continue;
+        }
+
+        let base_tree = match base_id {
+            Some(base_id) => repo.find_commit(base_id)?.tree()?,
+            None => repo.find_tree(repo.treebuilder(None)?.write()?)?,
+        };
+        let cherry_tree = cherry_commit.tree()?;
+        let mut index =
+            crate::tree::merge_trees(repo, &base_tree, &tip_tree, &cherry_tree, &options.merge)?;
+        if index.has_conflicts() {
+            return Err(conflicts_error("cherry-pick", &index));
+        }
+        let tree_id = index.write_tree_to(repo)?;
+        if tree_id == tip_tree.id() {
+            log::trace!(
+                "Skipping {}, already applied to {}",
+                cherry_id,
+                tip_commit.id()
+            );
+            tips.push(tip_commit.id());
+            continue;
+        }
+        let tree = repo.find_tree(tree_id)?;
+        let new_id = replay_commit(
+            repo,
+            &committer,
+            &cherry_commit,
+            &tree,
+            &tip_commit,
+            encoding.as_deref(),
+            sign,
+        )?;
+        tip_commit = repo.find_commit(new_id)?;
+        tip_tree = tree;
+        tips.push(new_id);
+    }
+    if let Some(last_id) = cherry_ids.last() {
+        report(cherry_ids.len(), *last_id);
+    }
+    Ok(tips)
+}
+
+/// Commit `tree` as `cherry_commit` replayed onto `parent`
+fn replay_commit(
+    repo: &git2::Repository,
+    committer: &git2::Signature<'_>,
+    cherry_commit: &git2::Commit<'_>,
+    tree: &git2::Tree<'_>,
+    parent: &git2::Commit<'_>,
+    encoding: Option<&str>,
+    sign: Option<&dyn Sign>,
+) -> Result<git2::Oid, git2::Error> {
+    let sig = replay_committer(committer, cherry_commit)?;
+    let message = EncodedMessage::reencode(cherry_commit, encoding);
+    encoding::write_commit(
+        repo,
+        &cherry_commit.author(),
+        &sig,
+        &message,
+        tree,
+        &[parent],
+        sign,
+    )
+}
+
+/// `committer`, at `cherry_commit`s commit time
+fn replay_committer(
+    committer: &git2::Signature<'_>,
+    cherry_commit: &git2::Commit<'_>,
+) -> Result<git2::Signature<'static>, git2::Error> {
+    match (committer.name(), committer.email()) {
+        // For simple rebases, preserve the original commit time
+        (Some(name), Some(email)) => git2::Signature::new(name, email, &cherry_commit.time()),
+        _ => Ok(committer.to_owned()),
+    }
+}
+
+fn conflicts_error(op: &str, index: &git2::Index) -> git2::Error {
+    let conflicts = match index.conflicts() {
+        Ok(conflicts) => conflicts,
+        Err(err) => return err,
+    };
+    let conflicts = match conflicts.collect::<Result<Vec<_>, _>>() {
+        Ok(conflicts) => conflicts,
+        Err(err) => return err,
+    };
+    let conflicts = conflicts
+        .into_iter()
+        .map(|conflict| {
+            let our_path = conflict
+                .our
+                .as_ref()
+                .map(|c| crate::bytes::bytes2path_lossy(&c.path))
+                .or_else(|| {
+                    conflict
+                        .their
+                        .as_ref()
+                        .map(|c| crate::bytes::bytes2path_lossy(&c.path))
+                })
+                .or_else(|| {
+                    conflict
+                        .ancestor
+                        .as_ref()
+                        .map(|c| crate::bytes::bytes2path_lossy(&c.path))
+                })
+                .unwrap_or_else(|| std::path::Path::new("<unknown>").into());
+            format!("{}", our_path.display())
+        })
+        .join("\n  ");
+    git2::Error::new(
+        git2::ErrorCode::Unmerged,
+        git2::ErrorClass::Index,
+        format!("{op} conflicts:\n  {conflicts}\n"),
+    )
+}
+
+/// Squash `head_id` into `into_id` without touching the working directory
+///
+/// `into_id`'s author, committer, and message are preserved.
+pub fn squash(
+    repo: &git2::Repository,
+    head_id: git2::Oid,
+    into_id: git2::Oid,
+    sign: Option<&dyn Sign>,
+) -> Result<git2::Oid, git2::Error> {
+    let options = SquashOptions {
+        sign,
+        ..SquashOptions::default()
+    };
+    squash_with(repo, head_id, into_id, options)
+}
+
+/// How to [`squash_with`]
+#[derive(Clone, Default)]
+pub struct SquashOptions<'a> {
+    sign: Option<&'a dyn Sign>,
+    merge: crate::tree::MergeOptions,
+}
+
+impl<'a> SquashOptions<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sign the new commit
+    pub fn sign(mut self, sign: &'a dyn Sign) -> Self {
+        self.sign = Some(sign);
+        self
+    }
+
+    /// How to merge the squashed changes, like favoring one side in conflicting hunks
+    pub fn merge_options(mut self, merge: crate::tree::MergeOptions) -> Self {
+        self.merge = merge;
+        self
+    }
+}
+
+/// Squash `head_id` into `into_id` without touching the working directory, see [`squash`]
+pub fn squash_with(
+    repo: &git2::Repository,
+    head_id: git2::Oid,
+    into_id: git2::Oid,
+    options: SquashOptions<'_>,
+) -> Result<git2::Oid, git2::Error> {
+    match try_squash(repo, head_id, into_id, options)? {
+        PickOutcome::Committed(new_id) => Ok(new_id),
+        PickOutcome::Conflicted(conflicted) => Err(conflicted.into_error()),
+    }
+}
+
+/// Like [`squash`] but handing back the conflicted index rather than failing
+pub fn try_squash(
+    repo: &git2::Repository,
+    head_id: git2::Oid,
+    into_id: git2::Oid,
+    options: SquashOptions<'_>,
+) -> Result<PickOutcome, git2::Error> {
+    warn_on_shallow_boundary(repo, "squash", [head_id, into_id]);
+    // Based on https://www.pygit2.org/recipes/git-cherry-pick.html
+    let head_commit = repo.find_commit(head_id)?;
+    let head_tree = repo.find_tree(head_commit.tree_id())?;
+
+    let base_commit = if 0 < head_commit.parent_count() {
+        head_commit.parent(0)?
+    } else {
+        head_commit.clone()
+    };
+    let base_tree = repo.find_tree(base_commit.tree_id())?;
+
+    let into_commit = repo.find_commit(into_id)?;
+    let into_tree = repo.find_tree(into_commit.tree_id())?;
+
+    let parent_ids = if 0 < into_commit.parent_count() {
+        vec![into_commit.parent_id(0)?]
+    } else {
+        Vec::new()
+    };
+
+    let result_index =
+        crate::tree::merge_trees(repo, &base_tree, &into_tree, &head_tree, &options.merge)?;
+    let conflicted = Conflicted {
+        op: "squash",
+        index: result_index,
+        author: into_commit.author().to_owned(),
+        committer: into_commit.committer().to_owned(),
+        message: EncodedMessage::reencode(&into_commit, commit_encoding(repo)?.as_deref()),
+        parent_ids,
+        skip_unchanged: false,
+    };
+    if conflicted.index.has_conflicts() {
+        return Ok(PickOutcome::Conflicted(conflicted));
+    }
+    conflicted
+        .resume(repo, options.sign)
+        .map(PickOutcome::Committed)
+}
+
+/// Reword `head_id`s commit
+pub fn reword(
+    repo: &git2::Repository,
+    head_id: git2::Oid,
+    msg: &str,
+    sign: Option<&dyn Sign>,
+) -> Result<git2::Oid, git2::Error> {
+    reword_with(repo, head_id, msg, RewordOptions { sign })
+}
+
+/// How to [`reword_with`]
+#[derive(Clone, Default)]
+pub struct RewordOptions<'a> {
+    sign: Option<&'a dyn Sign>,
+}
+
+impl<'a> RewordOptions<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sign the new commit
+    pub fn sign(mut self, sign: &'a dyn Sign) -> Self {
+        self.sign = Some(sign);
+        self
+    }
+}
+
+/// Reword `head_id`s commit, see [`reword`]
+pub fn reword_with(
+    repo: &git2::Repository,
+    head_id: git2::Oid,
+    msg: &str,
+    options: RewordOptions<'_>,
+) -> Result<git2::Oid, git2::Error> {
+    warn_on_shallow_boundary(repo, "reword", [head_id]);
+    let old_commit = repo.find_commit(head_id)?;
+    let parents = old_commit.parents().collect::<Vec<_>>();
+    let parents = parents.iter().collect::<Vec<_>>();
+    let tree = repo.find_tree(old_commit.tree_id())?;
+    let new_id = commit(
+        repo,
+        &old_commit.author(),
+        &old_commit.committer(),
+        msg,
+        &tree,
+        &parents,
+        options.sign,
+    )?;
+    Ok(new_id)
+}
+
+/// Reword `head_id`s commit with a message from the user's editor
+///
+/// See [`crate::utils::edit_message`].  Like `git`, an empty message aborts the reword.
+pub fn reword_with_editor(
+    repo: &git2::Repository,
+    head_id: git2::Oid,
+    sign: Option<&dyn Sign>,
+) -> Result<git2::Oid, git2::Error> {
+    let old_commit = repo.find_commit(head_id)?;
+    let msg = crate::utils::edit_message(repo, &decode_message(&old_commit))?;
+    if msg.is_empty() {
+        return Err(git2::Error::new(
+            git2::ErrorCode::User,
+            git2::ErrorClass::Invalid,
+            "aborting reword due to empty message",
+        ));
+    }
+    reword(repo, head_id, &msg, sign)
+}
+
+/// Commit with signing support
+///
+/// Like `git`, `message` is written in the encoding set by `i18n.commitEncoding`, see
+/// [`commit_encoding`].  Rewrites, like [`cherry_pick`], convert the messages they carry over
+/// to it too, keeping the original bytes when a message can't be converted.
+pub fn commit(
+    repo: &git2::Repository,
+    author: &git2::Signature<'_>,
+    committer: &git2::Signature<'_>,
+    message: &str,
+    tree: &git2::Tree<'_>,
+    parents: &[&git2::Commit<'_>],
+    sign: Option<&dyn Sign>,
+) -> Result<git2::Oid, git2::Error> {
+    commit_with(
+        repo,
+        author,
+        committer,
+        message,
+        tree,
+        parents,
+        CommitOptions { sign },
+    )
+}
+
+/// How to [`commit_with`]
+#[derive(Clone, Default)]
+pub struct CommitOptions<'a> {
+    sign: Option<&'a dyn Sign>,
+}
+
+impl<'a> CommitOptions<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sign the commit
+    pub fn sign(mut self, sign: &'a dyn Sign) -> Self {
+        self.sign = Some(sign);
+        self
+    }
+}
+
+/// Create a commit, see [`commit`]
+pub fn commit_with(
+    repo: &git2::Repository,
+    author: &git2::Signature<'_>,
+    committer: &git2::Signature<'_>,
+    message: &str,
+    tree: &git2::Tree<'_>,
+    parents: &[&git2::Commit<'_>],
+    options: CommitOptions<'_>,
+) -> Result<git2::Oid, git2::Error> {
+    let message = EncodedMessage::encode(message, commit_encoding(repo)?.as_deref());
+    encoding::write_commit(
+        repo,
+        author,
+        committer,
+        &message,
+        tree,
+        parents,
+        options.sign,
+    )
+}
+
+/// Run history-rewriting ops, firing the hooks `git` would
+///
+/// - `commit-msg` is run on new messages, when enabled with [`HookedOps::verify`]
+/// - `post-commit` is run for each commit created
+/// - `reference-transaction` is run around each [`HookedOps::update_ref`]
+/// - `post-rewrite` is run for all recorded rewrites on [`HookedOps::finish`]
+pub struct HookedOps<'r> {
+    repo: &'r git2::Repository,
+    hooks: &'r crate::hooks::Hooks,
+    sign: Option<&'r dyn Sign>,
+    verify: bool,
+    progress: Option<&'r dyn Progress>,
+    rewritten: Vec<(git2::Oid, git2::Oid)>,
+}
+
+impl<'r> HookedOps<'r> {
+    pub fn new(repo: &'r git2::Repository, hooks: &'r crate::hooks::Hooks) -> Self {
+        Self {
+            repo,
+            hooks,
+            sign: None,
+            verify: false,
+            progress: None,
+            rewritten: Vec::new(),
+        }
+    }
+
+    /// Sign each commit created
+    pub fn sign(mut self, sign: &'r dyn Sign) -> Self {
+        self.sign = Some(sign);
+        self
+    }
+
+    /// Validate new messages with the `commit-msg` hook, like an interactive `git commit`
+    ///
+    /// This applies to [`HookedOps::reword`] and [`HookedOps::commit`].
+    pub fn verify(mut self, yes: bool) -> Self {
+        self.verify = yes;
+        self
+    }
+
+    /// Report each commit picked by [`HookedOps::cherry_pick_range`]
+    pub fn progress(mut self, progress: &'r dyn Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// The `(old, new)` commit IDs rewritten so far, in the order they were processed
+    pub fn rewritten(&self) -> &[(git2::Oid, git2::Oid)] {
+        &self.rewritten
+    }
+
+    /// Cherry pick a commit onto another, see [`cherry_pick`]
+    pub fn cherry_pick(
+        &mut self,
+        head_id: git2::Oid,
+        cherry_id: git2::Oid,
+    ) -> Result<git2::Oid, git2::Error> {
+        let new_id = cherry_pick(self.repo, head_id, cherry_id, self.sign)?;
+        if new_id != head_id && new_id != cherry_id {
+            self.hooks.run_post_commit(self.repo);
+            self.rewritten.push((cherry_id, new_id));
+        }
+        Ok(new_id)
+    }
+
+    /// Cherry pick a series of commits onto `head_id`, returning the new tip
+    ///
+    /// Commits already applied to the new base are dropped.  See [`cherry_pick_stack`].
+    pub fn cherry_pick_range(
+        &mut self,
+        head_id: git2::Oid,
+        cherry_ids: &[git2::Oid],
+    ) -> Result<git2::Oid, git2::Error> {
+        let options = CherryPickOptions {
+            sign: self.sign,
+            progress: self.progress,
+            ..CherryPickOptions::default()
+        };
+        let tips = cherry_pick_stack_with(self.repo, head_id, cherry_ids, options)?;
+        let mut tip_id = head_id;
+        for (cherry_id, new_id) in cherry_ids.iter().copied().zip(tips) {
+            if new_id != tip_id && new_id != cherry_id {
+                self.hooks.run_post_commit(self.repo);
+                self.rewritten.push((cherry_id, new_id));
+            }
+            tip_id = new_id;
+        }
+        Ok(tip_id)
+    }
+
+    /// Squash `head_id` into `into_id`, see [`squash`]
+    pub fn squash(
+        &mut self,
+        head_id: git2::Oid,
+        into_id: git2::Oid,
+    ) -> Result<git2::Oid, git2::Error> {
+        let new_id = squash(self.repo, head_id, into_id, self.sign)?;
+        self.hooks.run_post_commit(self.repo);
+        self.rewritten.push((into_id, new_id));
+        self.rewritten.push((head_id, new_id));
+        Ok(new_id)
+    }
+
+    /// Reword `head_id`s commit, see [`reword`]
+    pub fn reword(&mut self, head_id: git2::Oid, msg: &str) -> Result<git2::Oid, git2::Error> {
+        let msg = self.verify_message(msg)?;
+        let new_id = reword(self.repo, head_id, &msg, self.sign)?;
+        self.hooks.run_post_commit(self.repo);
+        self.rewritten.push((head_id, new_id));
+        Ok(new_id)
+    }
+
+    /// Create a commit, see [`commit`]
+    pub fn commit(
+        &mut self,
+        author: &git2::Signature<'_>,
+        committer: &git2::Signature<'_>,
+        message: &str,
+        tree: &git2::Tree<'_>,
+        parents: &[&git2::Commit<'_>],
+    ) -> Result<git2::Oid, git2::Error> {
+        let message = self.verify_message(message)?;
+        let new_id = commit(
+            self.repo, author, committer, &message, tree, parents, self.sign,
+        )?;
+        self.hooks.run_post_commit(self.repo);
+        Ok(new_id)
+    }
+
+    fn verify_message(&self, message: &str) -> Result<String, git2::Error> {
+        if !self.verify {
+            return Ok(message.to_owned());
+        }
+
+        self.hooks.run_commit_msg(self.repo, message).map_err(|e| {
+            git2::Error::new(
+                git2::ErrorCode::User,
+                git2::ErrorClass::Callback,
+                format!("commit message rejected: {e}"),
+            )
+        })
+    }
+
+    /// Point the reference `name` at `new_id`, running the `reference-transaction` hook
+    ///
+    /// The update is aborted if the `prepare` state of the hook fails.
+    pub fn update_ref(
+        &self,
+        name: &str,
+        new_id: git2::Oid,
+        reflog_msg: &str,
+    ) -> Result<(), git2::Error> {
+        let old_id = self
+            .repo
+            .refname_to_id(name)
+            .unwrap_or_else(|_| git2::Oid::zero());
+        crate::refs::ReferenceTransaction::new(self.repo)
+            .hooks(self.hooks)
+            .update(name, new_id, Some(old_id), reflog_msg)
+            .commit()
+    }
+
+    /// Run `post-rewrite` for all of the recorded rewrites
+    pub fn finish(self) {
+        if !self.rewritten.is_empty() {
+            self.hooks
+                .run_post_rewrite_rebase(self.repo, &self.rewritten);
+        }
+    }
+}
+
+/// For signing [commit]s
+///
+/// See <https://blog.hackeriet.no/signing-git-commits-in-rust/> for an example of what to do.
+pub trait Sign {
+    fn sign(&self, buffer: &str) -> Result<String, git2::Error>;
+}
+
+/// Sign `buffer` without blocking an async task, see [`crate::utils::spawn_blocking`]
+///
+/// Signing runs `gpg` or `ssh-keygen`, which may wait on the user for a passphrase.
+#[cfg(feature = "tokio")]
+pub fn sign_async(
+    sign: impl Sign + Send + 'static,
+    buffer: String,
+) -> crate::utils::Blocking<Result<String, git2::Error>> {
+    crate::utils::spawn_blocking(move || sign.sign(&buffer))
+}
+
+#[derive(Clone)]
+pub struct UserSign(UserSignInner);
+
+#[derive(Clone)]
+enum UserSignInner {
+    Gpg(GpgSign),
+    Ssh(SshSign),
+}
+
+impl UserSign {
+    pub fn from_config(
+        repo: &git2::Repository,
+        config: &git2::Config,
+    ) -> Result<Self, git2::Error> {
+        let format = config
+            .get_string("gpg.format")
+            .unwrap_or_else(|_| "openpgp".to_owned());
+        match format.as_str() {
+            "openpgp" => {
+                let program = config
+                    .get_string("gpg.openpgp.program")
+                    .or_else(|_| config.get_string("gpg.program"))
+                    .unwrap_or_else(|_| "gpg".to_owned());
+
+                let signing_key = config.get_string("user.signingkey").or_else(
+                    |_| -> Result<_, git2::Error> {
+                        let sig = commit_signature(repo)?;
+                        Ok(sig.to_string())
+                    },
+                )?;
+
+                Ok(UserSign(UserSignInner::Gpg(GpgSign::new(
+                    program,
+                    signing_key,
+                ))))
+            }
+            "x509" => {
+                let program = config
+                    .get_string("gpg.x509.program")
+                    .unwrap_or_else(|_| "gpgsm".to_owned());
+
+                let signing_key = config.get_string("user.signingkey").or_else(
+                    |_| -> Result<_, git2::Error> {
+                        let sig = commit_signature(repo)?;
+                        Ok(sig.to_string())
+                    },
+                )?;
+
+                Ok(UserSign(UserSignInner::Gpg(GpgSign::new(
+                    program,
+                    signing_key,
+                ))))
+            }
+            "ssh" => {
+                let program = config
+                    .get_string("gpg.ssh.program")
+                    .unwrap_or_else(|_| "ssh-keygen".to_owned());
+
+                let signing_key = config
+                    .get_string("user.signingkey")
+                    .map(Ok)
+                    .unwrap_or_else(|_| -> Result<_, git2::Error> {
+                        get_default_ssh_signing_key(config)?.map(Ok).unwrap_or_else(
+                            || -> Result<_, git2::Error> {
+                                let sig = commit_signature(repo)?;
+                                Ok(sig.to_string())
+                            },
+                        )
+                    })?;
+
+                Ok(UserSign(UserSignInner::Ssh(SshSign::new(
+                    program,
+                    signing_key,
+                ))))
+            }
+            _ => Err(git2::Error::new(
+                git2::ErrorCode::Invalid,
+                git2::ErrorClass::Config,
+                format!("invalid valid for gpg.format: {format}"),
+            )),
+        }
+    }
+}
+
+impl Sign for UserSign {
+    fn sign(&self, buffer: &str) -> Result<String, git2::Error> {
+        match &self.0 {
+            UserSignInner::Gpg(s) => s.sign(buffer),
+            UserSignInner::Ssh(s) => s.sign(buffer),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GpgSign {
+    program: String,
+    signing_key: String,
+}
+
+impl GpgSign {
+    pub fn new(program: String, signing_key: String) -> Self {
+        Self {
+            program,
+            signing_key,
+        }
+    }
+}
+
+impl Sign for GpgSign {
+    fn sign(&self, buffer: &str) -> Result<String, git2::Error> {
+        let output = pipe_command(
+            crate::utils::ProcessCommand::new(&self.program)
+                .arg("--status-fd=2")
+                .arg("-bsau")
+                .arg(&self.signing_key),
+            Some(buffer),
+        )
+        .map_err(|e| {
+            git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Os,
+                format!("{} failed to sign the data: {}", self.program, e),
+            )
+        })?;
+        if !output.status.success() {
+            return Err(git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Os,
+                format!("{} failed to sign the data", self.program),
+            ));
+        }
+        if output.stderr.find(b"\n[GNUPG:] SIG_CREATED ").is_none() {
+            return Err(git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Os,
+                format!("{} failed to sign the data", self.program),
+            ));
+        }
+
+        let sig = std::str::from_utf8(&output.stdout).map_err(|e| {
+            git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Os,
+                format!("{} failed to sign the data: {}", self.program, e),
+            )
+        })?;
+
+        // Strip CR from the line endings, in case we are on Windows.
+        let normalized = remove_cr_after(sig);
+
+        Ok(normalized)
+    }
+}
+
+#[derive(Clone)]
+pub struct SshSign {
+    program: String,
+    signing_key: String,
+}
+
+impl SshSign {
+    pub fn new(program: String, signing_key: String) -> Self {
+        Self {
+            program,
+            signing_key,
+        }
+    }
+}
+
+impl Sign for SshSign {
+    fn sign(&self, buffer: &str) -> Result<String, git2::Error> {
+        let mut literal_key_file = None;
+        let ssh_signing_key_file = if let Some(literal_key) = literal_key(&self.signing_key) {
+            let temp = tempfile::NamedTempFile::new().map_err(|e| {
+                git2::Error::new(
+                    git2::ErrorCode::GenericError,
+                    git2::ErrorClass::Os,
+                    format!("failed writing ssh signing key: {e}"),
+                )
+            })?;
+
+            std::fs::write(temp.path(), literal_key).map_err(|e| {
+                git2::Error::new(
+                    git2::ErrorCode::GenericError,
+                    git2::ErrorClass::Os,
+                    format!("failed writing ssh signing key: {e}"),
+                )
+            })?;
+            let path = temp.path().to_owned();
+            literal_key_file = Some(temp);
+            path
+        } else {
+            // We assume a file
+            crate::utils::expanduser(std::path::Path::new(&self.signing_key))
+        };
+
+        let buffer_file = tempfile::NamedTempFile::new().map_err(|e| {
+            git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Os,
+                format!("failed writing buffer: {e}"),
+            )
+        })?;
+        std::fs::write(buffer_file.path(), buffer).map_err(|e| {
+            git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Os,
+                format!("failed writing buffer: {e}"),
+            )
+        })?;
+
+        let output = pipe_command(
+            crate::utils::ProcessCommand::new(&self.program)
+                .arg("-Y")
+                .arg("sign")
+                .arg("-n")
+                .arg("git")
+                .arg("-f")
+                .arg(&ssh_signing_key_file)
+                .arg(buffer_file.path()),
+            Some(buffer),
+        )
+        .map_err(|e| {
+            git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Os,
+                format!("{} failed to sign the data: {}", self.program, e),
+            )
+        })?;
+        if !output.status.success() {
+            if output.stderr.find("usage:").is_some() {
+                return Err(git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Os,
+                "ssh-keygen -Y sign is needed for ssh signing (available in openssh version 8.2p1+)"
+            ));
+            } else {
+                return Err(git2::Error::new(
+                    git2::ErrorCode::GenericError,
+                    git2::ErrorClass::Os,
+                    format!(
+                        "{} failed to sign the data: {}",
+                        self.program,
+                        String::from_utf8_lossy(&output.stderr)
+                    ),
+                ));
+            }
+        }
+
+        let mut ssh_signature_filename = buffer_file.path().as_os_str().to_owned();
+        ssh_signature_filename.push(".sig");
+        let ssh_signature_filename = std::path::PathBuf::from(ssh_signature_filename);
+        let sig = std::fs::read_to_string(&ssh_signature_filename).map_err(|e| {
+            git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Os,
+                format!(
+                    "failed reading ssh signing data buffer from {}: {}",
+                    ssh_signature_filename.display(),
+                    e
+                ),
+            )
+        })?;
+        // Strip CR from the line endings, in case we are on Windows.
+        let normalized = remove_cr_after(&sig);
+
+        buffer_file.close().map_err(|e| {
+            git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Os,
+                format!("failed writing buffer: {e}"),
+            )
+        })?;
+        if let Some(literal_key_file) = literal_key_file {
+            literal_key_file.close().map_err(|e| {
+                git2::Error::new(
+                    git2::ErrorCode::GenericError,
+                    git2::ErrorClass::Os,
+                    format!("failed writing ssh signing key: {e}"),
+                )
+            })?;
+        }
+
+        Ok(normalized)
+    }
+}
+
+fn pipe_command(
+    cmd: crate::utils::ProcessCommand<'_>,
+    stdin: Option<&str>,
+) -> Result<crate::utils::ProcessOutput, std::io::Error> {
+    let cmd = match stdin {
+        Some(stdin) => cmd.stdin(stdin.as_bytes()),
+        None => cmd,
+    };
+    crate::utils::run_process(cmd)
+}
+
+fn remove_cr_after(sig: &str) -> String {
+    let mut normalized = String::new();
+    for line in sig.lines() {
+        normalized.push_str(line);
+        normalized.push('\n');
+    }
+    normalized
+}
+
+fn literal_key(signing_key: &str) -> Option<&str> {
+    if let Some(literal) = signing_key.strip_prefix("key::") {
+        Some(literal)
+    } else if signing_key.starts_with("ssh-") {
+        Some(signing_key)
+    } else {
+        None
+    }
+}
+
+// Returns the first public key from an ssh-agent to use for signing
+fn get_default_ssh_signing_key(config: &git2::Config) -> Result<Option<String>, git2::Error> {
+    let ssh_default_key_command = config
+        .get_string("gpg.ssh.defaultKeyCommand")
+        .map_err(|_| {
+            git2::Error::new(
+                git2::ErrorCode::Invalid,
+                git2::ErrorClass::Config,
+                "either user.signingkey or gpg.ssh.defaultKeyCommand needs to be configured",
+            )
+        })?;
+    let ssh_default_key_args = shlex::split(&ssh_default_key_command).ok_or_else(|| {
+        git2::Error::new(
+            git2::ErrorCode::Invalid,
+            git2::ErrorClass::Config,
+            format!("malformed gpg.ssh.defaultKeyCommand: {ssh_default_key_command}"),
+        )
+    })?;
+    if ssh_default_key_args.is_empty() {
+        return Err(git2::Error::new(
+            git2::ErrorCode::Invalid,
+            git2::ErrorClass::Config,
+            format!("malformed gpg.ssh.defaultKeyCommand: {ssh_default_key_command}"),
+        ));
+    }
+
+    let Ok(output) = pipe_command(
+        crate::utils::ProcessCommand::new(&ssh_default_key_args[0])
+            .args(&ssh_default_key_args[1..]),
+        None,
+    ) else {
+        return Ok(None);
+    };
+
+    let Ok(keys) = std::str::from_utf8(&output.stdout) else {
+        return Ok(None);
+    };
+    let Some((default_key, _)) = keys.split_once('\n') else {
+        return Ok(None);
+    };
+    // We only use `is_literal_ssh_key` here to check validity
+    // The prefix will be stripped when the key is used
+    if literal_key(default_key).is_none() {
+        return Ok(None);
+    }
+
+    Ok(Some(default_key.to_owned()))
+}
+
+#[doc(hidden)]
+#[deprecated(
+    since = "0.4.3",
+    note = "Replaced with `commit_signature`, `author_signature`"
+)]
+pub fn signature(repo: &git2::Repository) -> Result<git2::Signature<'_>, git2::Error> {
+    commit_signature(repo)
+}
+
+/// Lookup the configured committer's signature
+///
+/// See [`resolve_identities`].
+pub fn commit_signature(repo: &git2::Repository) -> Result<git2::Signature<'_>, git2::Error> {
+    let config = repo.config()?;
+    resolve_identity(&config, &env_var, "committer", crate::utils::date::now()?)
+}
+
+/// Lookup the configured author's signature
+///
+/// See [`resolve_identities`].
+pub fn author_signature(repo: &git2::Repository) -> Result<git2::Signature<'_>, git2::Error> {
+    let config = repo.config()?;
+    resolve_identity(&config, &env_var, "author", crate::utils::date::now()?)
+}
+
+/// The author and committer for a new commit, see [`resolve_identities`]
+#[derive(Clone, PartialEq, Eq)]
+pub struct Identities {
+    pub author: git2::Signature<'static>,
+    pub committer: git2::Signature<'static>,
+}
+
+/// Resolve who is making a commit, and when, like `git commit`
+///
+/// For each of the author and committer, in order of precedence:
+/// - Name: `GIT_AUTHOR_NAME`, `author.name`, then `user.name`
+/// - Email: `GIT_AUTHOR_EMAIL`, `author.email`, `user.email`, then `EMAIL`
+/// - Date: `GIT_AUTHOR_DATE`, then now
+///
+/// With `GIT_COMMITTER_*` and `committer.*` for the committer.
+pub fn resolve_identities(repo: &git2::Repository) -> Result<Identities, git2::Error> {
+    let config = repo.config()?;
+    resolve_identities_with(&config, &env_var)
+}
+
+fn resolve_identities_with(
+    config: &git2::Config,
+    env: &dyn Fn(&str) -> Result<Option<String>, git2::Error>,
+) -> Result<Identities, git2::Error> {
+    // Both get the same "now", like `git`
+    let now = crate::utils::date::now()?;
+    Ok(Identities {
+        author: resolve_identity(config, env, "author", now)?,
+        committer: resolve_identity(config, env, "committer", now)?,
+    })
+}
+
+fn resolve_identity(
+    config: &git2::Config,
+    env: &dyn Fn(&str) -> Result<Option<String>, git2::Error>,
+    role: &str,
+    now: git2::Time,
+) -> Result<git2::Signature<'static>, git2::Error> {
+    let prefix = format!("GIT_{}", role.to_ascii_uppercase());
+    let name = read_signature_field(
+        config,
+        env,
+        &format!("{prefix}_NAME"),
+        &format!("{role}.name"),
+        "user.name",
+    )?;
+    let email = match read_signature_field(
+        config,
+        env,
+        &format!("{prefix}_EMAIL"),
+        &format!("{role}.email"),
+        "user.email",
+    ) {
+        Ok(email) => email,
+        Err(err) if err.code() == git2::ErrorCode::NotFound => env("EMAIL")?.ok_or(err)?,
+        Err(err) => return Err(err),
+    };
+    let date_var = format!("{prefix}_DATE");
+    let when = match env(&date_var)?.filter(|date| !date.is_empty()) {
+        Some(date) => crate::utils::date::parse_strict_date(&date).ok_or_else(|| {
+            git2::Error::new(
+                git2::ErrorCode::Invalid,
+                git2::ErrorClass::Invalid,
+                format!("`{date_var}` has an invalid date: {date}"),
+            )
+        })?,
+        None => now,
+    };
+
+    git2::Signature::new(&name, &email, &when)
+}
+
+fn read_signature_field(
+    config: &git2::Config,
+    env: &dyn Fn(&str) -> Result<Option<String>, git2::Error>,
+    env_var: &str,
+    specialized_key: &str,
+    general_key: &str,
+) -> Result<String, git2::Error> {
+    env(env_var)?
+        .map(Ok)
+        .or_else(|| config.get_string(specialized_key).ok().map(Ok))
+        .unwrap_or_else(|| config.get_string(general_key))
+}
+
+fn env_var(name: &str) -> Result<Option<String>, git2::Error> {
+    std::env::var_os(name)
+        .map(|os| {
+            os.into_string().map_err(|os| {
+                git2::Error::new(
+                    git2::ErrorCode::Unmerged,
+                    git2::ErrorClass::Invalid,
+                    format!("`{}` is not valid UTF-8: {}", name, os.to_string_lossy()),
+                )
+            })
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_resolve_identities() -> eyre::Result<()> {
+        let mut config = git2::Config::new()?;
+        let dir = tempfile::tempdir()?;
+        config.add_file(&dir.path().join("config"), git2::ConfigLevel::Local, false)?;
+        config.set_str("user.name", "User")?;
+        config.set_str("user.email", "user@example.com")?;
+        config.set_str("committer.name", "Committer")?;
+
+        let env = |name: &str| -> Result<Option<String>, git2::Error> {
+            Ok(match name {
+                "GIT_AUTHOR_EMAIL" => Some("author@example.com".to_owned()),
+                "GIT_AUTHOR_DATE" => Some("1112911993 +0200".to_owned()),
+                _ => None,
+            })
+        };
+        let identities = resolve_identities_with(&config, &env)?;
+        assert_eq!(identities.author.name(), Some("User"));
+        assert_eq!(identities.author.email(), Some("author@example.com"));
+        assert_eq!(identities.author.when(), git2::Time::new(1112911993, 120));
+        assert_eq!(identities.committer.name(), Some("Committer"));
+        assert_eq!(identities.committer.email(), Some("user@example.com"));
+
+        let env = |name: &str| -> Result<Option<String>, git2::Error> {
+            Ok((name == "GIT_COMMITTER_DATE").then(|| "next tuesday".to_owned()))
+        };
+        assert!(resolve_identities_with(&config, &env).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_is_dirty() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        git.commit_file("file", 1)?;
+        assert!(!try_is_dirty(&repo)?);
+        git.write_file("file", "changed")?;
+        assert!(try_is_dirty(&repo)?);
+
+        let bare = git2::Repository::init_bare(tempfile::tempdir()?.path())?;
+        try_is_dirty(&bare).unwrap_err();
+        assert!(is_dirty(&bare));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_cherry_pick_conflicted() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.write_file("file", "base")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "base"])?;
+        git.write_file("file", "head")?;
+        git.run(&["commit", "-am", "head"])?;
+        git.run(&["checkout", "-q", "-b", "cherry", "HEAD~"])?;
+        git.write_file("file", "cherry")?;
+        git.run(&["commit", "-am", "cherry"])?;
+
+        let repo = git.get_repo()?;
+        let head_id = repo.revparse_single("master")?.id();
+        let cherry_id = repo.revparse_single("cherry")?.id();
+        let PickOutcome::Conflicted(mut conflicted) =
+            try_cherry_pick(&repo, head_id, cherry_id, CherryPickOptions::new())?
+        else {
+            panic!("expected conflicts");
+        };
+        assert_eq!(conflicted.parent_ids(), [head_id]);
+        let conflict = conflicted.index().conflicts()?.next().unwrap()?;
+        assert_eq!(conflict.our.as_ref().unwrap().path, b"file.txt");
+        assert!(cherry_pick(&repo, head_id, cherry_id, None).is_err());
+        let theirs = crate::tree::MergeOptions::new().file_favor(git2::FileFavor::Theirs);
+        let options = CherryPickOptions::new().merge_options(theirs);
+        let favored_id = cherry_pick_with(&repo, head_id, cherry_id, options)?;
+        let favored_tree = repo.find_commit(favored_id)?.tree()?;
+        assert_eq!(favored_tree.id(), repo.find_commit(cherry_id)?.tree_id());
+
+        let index = conflicted.index_mut();
+        let mut entry = conflict.our.unwrap();
+        // Back to stage 0
+        entry.flags &= !0x3000;
+        index.remove_path("file.txt".as_ref())?;
+        entry.id = repo.blob(b"resolved")?;
+        entry.file_size = 8;
+        index.add(&entry)?;
+        let new_id = conflicted.resume(&repo, None)?;
+
+        let new_commit = repo.find_commit(new_id)?;
+        assert_eq!(new_commit.parent_ids().collect::<Vec<_>>(), [head_id]);
+        assert_eq!(new_commit.message(), Some("cherry\n"));
+        let blob = new_commit
+            .tree()?
+            .get_path("file.txt".as_ref())?
+            .to_object(&repo)?;
+        assert_eq!(blob.as_blob().unwrap().content(), b"resolved");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_changed_paths_for_commit() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.write_file("shared", "base")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "base"])?;
+        git.run(&["checkout", "-q", "-b", "side"])?;
+        git.write_file("side", "side")?;
+        git.write_file("shared", "side")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "side"])?;
+        git.run(&["checkout", "-q", "master"])?;
+        git.write_file("main", "main")?;
+        git.write_file("shared", "main")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "main"])?;
+        git.run(&["merge", "-q", "--no-commit", "side"]).ok();
+        git.write_file("shared", "merged")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-q", "--no-edit"])?;
+
+        let repo = git.get_repo()?;
+        let paths = |rev: &str, strategy| -> eyre::Result<Vec<String>> {
+            let commit = repo.revparse_single(rev)?.peel_to_commit()?;
+            let paths = get_changed_paths_for_commit(&repo, &commit, strategy)?;
+            Ok(paths
+                .into_iter()
+                .map(|path| path.display().to_string())
+                .sorted()
+                .collect())
+        };
+        assert_eq!(
+            paths("HEAD", ParentStrategy::FirstParent)?,
+            ["shared.txt", "side.txt"]
+        );
+        assert_eq!(
+            paths("HEAD", ParentStrategy::Union)?,
+            ["main.txt", "shared.txt", "side.txt"]
+        );
+        assert_eq!(paths("HEAD", ParentStrategy::Combined)?, ["shared.txt"]);
+        assert_eq!(
+            paths("HEAD~3", ParentStrategy::FirstParent)?,
+            ["initial.txt"]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+/// What a long-running operation is doing, see [`Progress`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Phase {
+    /// Replaying commits, like with [`super::cherry_pick_stack`]
+    CherryPick,
+    /// Re-signing commits, see [`super::resign_range`]
+    Resign,
+    /// Receiving objects from a remote
+    Receiving,
+    /// Indexing the objects received from a remote
+    Indexing,
+    /// Sending objects to a remote
+    Sending,
+}
+
+/// How far along an operation is
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ProgressUpdate<'a> {
+    pub phase: Phase,
+    pub completed: usize,
+    /// `None` when the amount of work isn't known up front
+    pub total: Option<usize>,
+    /// The commit being worked on, or the last one once everything is completed
+    pub commit: Option<git2::Oid>,
+    /// The path being worked on, for phases that work through paths
+    pub path: Option<&'a std::path::Path>,
+}
+
+impl ProgressUpdate<'_> {
+    pub fn new(phase: Phase, completed: usize, total: Option<usize>) -> Self {
+        Self {
+            phase,
+            completed,
+            total,
+            commit: None,
+            path: None,
+        }
+    }
+
+    pub fn commit(mut self, id: git2::Oid) -> Self {
+        self.commit = Some(id);
+        self
+    }
+}
+
+/// Reports how far along a long-running operation is, like to draw a progress bar
+///
+/// Updates are reported through `&self`, so one reporter can be shared by the options of
+/// several operations; use interior mutability to keep state between updates.  Closures taking
+/// a [`ProgressUpdate`] are reporters.
+pub trait Progress {
+    fn report(&self, update: &ProgressUpdate<'_>);
+}
+
+impl<F: Fn(&ProgressUpdate<'_>)> Progress for F {
+    fn report(&self, update: &ProgressUpdate<'_>) {
+        self(update);
+    }
+}
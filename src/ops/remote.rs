@@ -0,0 +1,1758 @@
+use crate::auth::AuthConfig;
+use crate::auth::CredentialApproval;
+use crate::auth::HttpHeaders;
+use crate::hooks::Hooks;
+use crate::hooks::PushUpdate;
+
+/// How to [`push`]
+pub struct PushOptions<'a> {
+    refspecs: Vec<String>,
+    force_with_lease: bool,
+    leases: Vec<(String, Option<git2::Oid>)>,
+    hooks: Option<&'a Hooks>,
+    auth: AuthConfig,
+    progress: Option<Box<dyn FnMut(PushProgress) + 'a>>,
+}
+
+impl<'a> PushOptions<'a> {
+    pub fn new() -> Self {
+        Self {
+            refspecs: Vec::new(),
+            force_with_lease: false,
+            leases: Vec::new(),
+            hooks: None,
+            auth: AuthConfig::new(),
+            progress: None,
+        }
+    }
+
+    /// Push `refspec` (`[+]<src>[:<dst>]`)
+    ///
+    /// When no refspecs are given, `remote.<name>.push` is used, falling back to `push.default`.
+    pub fn refspec(mut self, refspec: impl Into<String>) -> Self {
+        self.refspecs.push(refspec.into());
+        self
+    }
+
+    /// Force push, unless a remote ref moved since it was last fetched (`--force-with-lease`)
+    ///
+    /// The remote's refs are expected to match their remote-tracking refs.
+    pub fn force_with_lease(mut self, yes: bool) -> Self {
+        self.force_with_lease = yes;
+        self
+    }
+
+    /// Force push `remote_ref`, unless the remote has something other than `expected`
+    /// (`--force-with-lease=<ref>:<expect>`)
+    ///
+    /// `None` expects `remote_ref` to not exist yet.
+    pub fn force_with_lease_ref(
+        mut self,
+        remote_ref: impl Into<String>,
+        expected: Option<git2::Oid>,
+    ) -> Self {
+        self.leases.push((remote_ref.into(), expected));
+        self
+    }
+
+    /// Run the `pre-push` hook, aborting the push if it fails
+    pub fn hooks(mut self, hooks: &'a Hooks) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    /// How to authenticate, see [`crate::auth::callbacks`]
+    pub fn auth(mut self, auth: AuthConfig) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Report how much of the pack has been sent
+    pub fn progress(mut self, progress: impl FnMut(PushProgress) + 'a) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Report how much of the pack has been sent to `progress`, as [`super::Phase::Sending`]
+    pub fn progress_reporter(self, progress: &'a dyn super::Progress) -> Self {
+        self.progress(move |transfer: PushProgress| {
+            let total = Some(transfer.total_objects);
+            let update = super::ProgressUpdate::new(super::Phase::Sending, transfer.objects, total);
+            progress.report(&update);
+        })
+    }
+}
+
+impl Default for PushOptions<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Transfer progress for [`push`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PushProgress {
+    pub objects: usize,
+    pub total_objects: usize,
+    pub bytes: usize,
+}
+
+/// The outcome of pushing a ref
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PushedRef {
+    /// The local ref pushed, `None` when deleting `remote_ref`
+    pub local_ref: Option<String>,
+    pub remote_ref: String,
+    /// What the remote had for `remote_ref`, as last fetched
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_oid::option"))]
+    pub old_id: Option<git2::Oid>,
+    /// What `remote_ref` was updated to, `None` when deleted
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_oid::option"))]
+    pub new_id: Option<git2::Oid>,
+    /// Why the remote rejected the update
+    pub rejection: Option<String>,
+}
+
+impl PushedRef {
+    pub fn is_accepted(&self) -> bool {
+        self.rejection.is_none()
+    }
+}
+
+/// Push to `remote` (a name or URL), like `git push`
+///
+/// This
+/// 1. Resolves the refspecs to push, see [`PushOptions::refspec`]
+/// 2. Connects, authenticating with [`crate::auth::callbacks`] and `http.extraHeader`s
+/// 3. Runs the `pre-push` hook, if requested, with what the remote says its refs point at
+/// 4. Pushes over the same connection
+///
+/// Refs the remote rejects are reported in the result rather than failing the push.  A broken
+/// lease (see [`PushOptions::force_with_lease`]) fails the whole push with
+/// [`git2::ErrorCode::Modified`] before anything is sent.
+pub fn push(
+    repo: &git2::Repository,
+    remote: &str,
+    options: PushOptions<'_>,
+) -> Result<Vec<PushedRef>, git2::Error> {
+    let PushOptions {
+        refspecs,
+        force_with_lease,
+        leases,
+        hooks,
+        auth,
+        progress,
+    } = options;
+
+    let mut remote = find_remote(repo, remote)?;
+    let remote_name = remote
+        .name()
+        .or_else(|| remote.url())
+        .unwrap_or_default()
+        .to_owned();
+    let url = remote
+        .pushurl()
+        .or_else(|| remote.url())
+        .unwrap_or_default()
+        .to_owned();
+
+    let refspecs = if refspecs.is_empty() {
+        default_push_refspecs(repo, &remote)?
+    } else {
+        refspecs
+    };
+    let refspecs = refspecs
+        .iter()
+        .map(|refspec| resolve_push_refspec(repo, &remote, refspec))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    let updates = refspecs
+        .iter()
+        .map(|refspec| PushUpdate::new(repo, &remote, refspec.src.as_deref(), &refspec.dst))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut expected_ids = std::collections::HashMap::new();
+    for (refspec, update) in refspecs.iter().zip(updates.iter()) {
+        let lease = leases
+            .iter()
+            .find(|(remote_ref, _)| qualify_dst(remote_ref, "refs/heads/") == refspec.dst)
+            .map(|(_, expected)| *expected);
+        let expected = match lease {
+            Some(expected) => expected,
+            None if force_with_lease => update.remote_oid,
+            None => continue,
+        };
+        expected_ids.insert(refspec.dst.clone(), expected);
+    }
+
+    let statuses = std::cell::RefCell::new(Vec::new());
+    // What the remote advertised for each ref being pushed, `None` when it doesn't have it
+    let advertised = std::cell::RefCell::new(std::collections::HashMap::new());
+    let aborted = std::cell::RefCell::new(None);
+    let (mut callbacks, approval) = crate::auth::callbacks_with_approval(repo, &url, &auth)?;
+    // Called once connected, with the remote's refs, and before anything is sent
+    callbacks.push_negotiation(|remote_updates| {
+        let mut advertised = advertised.borrow_mut();
+        for remote_update in remote_updates {
+            let Some(name) = remote_update.dst_refname() else {
+                continue;
+            };
+            let actual = Some(remote_update.src()).filter(|id| !id.is_zero());
+            advertised.insert(name.to_owned(), actual);
+            let Some(expected) = expected_ids.get(name) else {
+                continue;
+            };
+            if let Err(err) = crate::refs::check_lease(name, actual, *expected) {
+                let message = err.message().to_owned();
+                *aborted.borrow_mut() = Some(err);
+                return Err(git2::Error::from_str(&message));
+            }
+        }
+
+        if let Some(hooks) = hooks {
+            // Like `git`, the hook is told what the remote has rather than what we last fetched
+            let updates = updates
+                .iter()
+                .map(|update| PushUpdate {
+                    remote_oid: advertised.get(update.remote_ref).copied().flatten(),
+                    ..*update
+                })
+                .collect::<Vec<_>>();
+            if let Err(err) = hooks.run_pre_push(repo, &remote_name, &url, &updates) {
+                let err = git2::Error::new(
+                    git2::ErrorCode::User,
+                    git2::ErrorClass::Callback,
+                    format!("push rejected: {err}"),
+                );
+                let message = err.message().to_owned();
+                *aborted.borrow_mut() = Some(err);
+                return Err(git2::Error::from_str(&message));
+            }
+        }
+        Ok(())
+    });
+    callbacks.push_update_reference(|name, status| {
+        statuses
+            .borrow_mut()
+            .push((name.to_owned(), status.map(ToOwned::to_owned)));
+        Ok(())
+    });
+    if let Some(mut progress) = progress {
+        callbacks.push_transfer_progress(move |objects, total_objects, bytes| {
+            progress(PushProgress {
+                objects,
+                total_objects,
+                bytes,
+            });
+        });
+    }
+    let mut push_options = git2::PushOptions::new();
+    push_options.remote_callbacks(callbacks);
+    HttpHeaders::from_config(&repo.config()?, &url)?.apply_push(&mut push_options);
+
+    let specs = refspecs
+        .iter()
+        .map(|refspec| {
+            // A lease is checked in `push_negotiation`, so the update itself is forced
+            let force = refspec.force || expected_ids.contains_key(&refspec.dst);
+            PushRefspec {
+                force,
+                ..refspec.clone()
+            }
+            .to_spec()
+        })
+        .collect::<Vec<_>>();
+    let result = remote.push(&specs, Some(&mut push_options));
+    drop(push_options);
+    if let Some(err) = aborted.into_inner() {
+        return Err(err);
+    }
+    result?;
+    approve_credential(&approval);
+
+    let statuses = statuses.into_inner();
+    let advertised = advertised.into_inner();
+    let pushed = refspecs
+        .iter()
+        .zip(updates.iter())
+        .map(|(refspec, update)| {
+            let rejection = statuses
+                .iter()
+                .find(|(name, _)| *name == refspec.dst)
+                .and_then(|(_, status)| status.clone());
+            PushedRef {
+                local_ref: refspec.src.clone(),
+                remote_ref: refspec.dst.clone(),
+                old_id: advertised
+                    .get(&refspec.dst)
+                    .copied()
+                    .unwrap_or(update.remote_oid),
+                new_id: update.local.map(|(_, id)| id),
+                rejection,
+            }
+        })
+        .collect();
+    Ok(pushed)
+}
+
+/// Check that `branch`'s remote-tracking branch is still at `expected_remote_oid`
+///
+/// This is the lease behind `git push --force-with-lease`: `expected_remote_oid` is what was
+/// last observed for the remote branch (`None` if it didn't exist) and force pushing over it is
+/// only safe if nothing moved it since.  Fails with [`git2::ErrorCode::Modified`] otherwise.
+pub fn check_force_with_lease(
+    repo: &git2::Repository,
+    branch: &str,
+    expected_remote_oid: Option<git2::Oid>,
+) -> Result<(), git2::Error> {
+    let local_ref = qualify_dst(branch, "refs/heads/");
+    let upstream = repo.branch_upstream_name(&local_ref)?;
+    let upstream = upstream.as_str().ok_or_else(|| {
+        git2::Error::new(
+            git2::ErrorCode::Invalid,
+            git2::ErrorClass::Reference,
+            format!("upstream of `{branch}` is not UTF-8"),
+        )
+    })?;
+    let actual = match repo.refname_to_id(upstream) {
+        Ok(id) => Some(id),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => None,
+        Err(err) => return Err(err),
+    };
+    crate::refs::check_lease(upstream, actual, expected_remote_oid)
+}
+
+/// How to [`fetch`]
+pub struct FetchOptions<'a> {
+    refspecs: Vec<String>,
+    prune: Option<bool>,
+    tags: Option<FetchTags>,
+    depth: i32,
+    auth: AuthConfig,
+    progress: Option<Box<dyn FnMut(FetchProgress) + 'a>>,
+}
+
+impl<'a> FetchOptions<'a> {
+    pub fn new() -> Self {
+        Self {
+            refspecs: Vec::new(),
+            prune: None,
+            tags: None,
+            depth: 0,
+            auth: AuthConfig::new(),
+            progress: None,
+        }
+    }
+
+    /// Fetch `refspec` instead of `remote.<name>.fetch`
+    pub fn refspec(mut self, refspec: impl Into<String>) -> Self {
+        self.refspecs.push(refspec.into());
+        self
+    }
+
+    /// Delete remote-tracking refs the remote no longer has
+    ///
+    /// Defaults to `remote.<name>.prune`, then `fetch.prune`.
+    pub fn prune(mut self, yes: bool) -> Self {
+        self.prune = Some(yes);
+        self
+    }
+
+    /// Which tags to fetch
+    ///
+    /// Defaults to `remote.<name>.tagOpt`, then [`FetchTags::Auto`].
+    pub fn tags(mut self, tags: FetchTags) -> Self {
+        self.tags = Some(tags);
+        self
+    }
+
+    /// Only fetch the last `depth` commits of history from each tip (`--depth`)
+    ///
+    /// `0` fetches all history that is missing.  Partial clone filters (`--filter`) and
+    /// `--deepen` aren't supported by libgit2.
+    pub fn depth(mut self, depth: u32) -> Self {
+        // `i32::MAX` is libgit2's request to unshallow
+        self.depth = i32::try_from(depth).unwrap_or(i32::MAX - 1);
+        self
+    }
+
+    /// Fetch all history a shallow repository is missing (`--unshallow`)
+    pub fn unshallow(mut self) -> Self {
+        self.depth = i32::MAX;
+        self
+    }
+
+    /// How to authenticate, see [`crate::auth::callbacks`]
+    pub fn auth(mut self, auth: AuthConfig) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Report how much of the pack has been received
+    pub fn progress(mut self, progress: impl FnMut(FetchProgress) + 'a) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Report how much of the pack has been received to `progress`, see
+    /// [`FetchProgress::report_to`]
+    pub fn progress_reporter(self, progress: &'a dyn super::Progress) -> Self {
+        self.progress(move |transfer: FetchProgress| transfer.report_to(progress))
+    }
+}
+
+impl Default for FetchOptions<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Which tags [`fetch`] downloads
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FetchTags {
+    /// Tags pointing at fetched commits
+    Auto,
+    /// All tags (`--tags`)
+    All,
+    /// No tags (`--no-tags`)
+    None,
+}
+
+impl FetchTags {
+    fn from_tag_opt(tag_opt: &str) -> Option<Self> {
+        match tag_opt {
+            "--tags" => Some(Self::All),
+            "--no-tags" => Some(Self::None),
+            _ => None,
+        }
+    }
+
+    fn to_autotag(self) -> git2::AutotagOption {
+        match self {
+            Self::Auto => git2::AutotagOption::Auto,
+            Self::All => git2::AutotagOption::All,
+            Self::None => git2::AutotagOption::None,
+        }
+    }
+}
+
+/// Transfer progress for [`fetch`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FetchProgress {
+    pub received_objects: usize,
+    pub indexed_objects: usize,
+    pub total_objects: usize,
+    pub received_bytes: usize,
+}
+
+impl FetchProgress {
+    /// Report to `progress` as [`super::Phase::Receiving`] until all objects are received, then as
+    /// [`super::Phase::Indexing`]
+    pub fn report_to(&self, progress: &dyn super::Progress) {
+        let total = Some(self.total_objects);
+        let update = if self.received_objects < self.total_objects {
+            super::ProgressUpdate::new(super::Phase::Receiving, self.received_objects, total)
+        } else {
+            super::ProgressUpdate::new(super::Phase::Indexing, self.indexed_objects, total)
+        };
+        progress.report(&update);
+    }
+}
+
+/// A ref changed by [`fetch`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FetchedRef {
+    pub name: String,
+    /// `None` when the ref was created
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_oid::option"))]
+    pub old_id: Option<git2::Oid>,
+    /// `None` when the ref was pruned
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_oid::option"))]
+    pub new_id: Option<git2::Oid>,
+}
+
+/// Fetch from `remote` (a name or URL), like `git fetch`
+///
+/// Remote-tracking refs and `FETCH_HEAD` are updated, authenticating with
+/// [`crate::auth::callbacks`] and `http.extraHeader`s.
+pub fn fetch(
+    repo: &git2::Repository,
+    remote: &str,
+    options: FetchOptions<'_>,
+) -> Result<Vec<FetchedRef>, git2::Error> {
+    let mut remote = find_remote(repo, remote)?;
+    fetch_remote(repo, &mut remote, options)
+}
+
+/// [`fetch`] from `remote`, reusing its connection if it is connected
+fn fetch_remote(
+    repo: &git2::Repository,
+    remote: &mut git2::Remote<'_>,
+    options: FetchOptions<'_>,
+) -> Result<Vec<FetchedRef>, git2::Error> {
+    let FetchOptions {
+        refspecs,
+        prune,
+        tags,
+        depth,
+        auth,
+        progress,
+    } = options;
+
+    let url = remote.url().unwrap_or_default().to_owned();
+    let config = repo.config()?;
+
+    let remote_config = |key: &str| -> Option<String> {
+        let name = remote.name()?;
+        config.get_string(&format!("remote.{name}.{key}")).ok()
+    };
+    let prune = prune
+        .or_else(|| remote_config("prune").and_then(|v| git2::Config::parse_bool(v).ok()))
+        .or_else(|| config.get_bool("fetch.prune").ok())
+        .unwrap_or(false);
+    let tags = tags
+        .or_else(|| remote_config("tagOpt").and_then(|v| FetchTags::from_tag_opt(&v)))
+        .unwrap_or(FetchTags::Auto);
+
+    let updated = std::cell::RefCell::new(Vec::new());
+    let (mut callbacks, approval) = crate::auth::callbacks_with_approval(repo, &url, &auth)?;
+    callbacks.update_tips(|name, old_id, new_id| {
+        updated.borrow_mut().push(FetchedRef {
+            name: name.to_owned(),
+            old_id: Some(old_id).filter(|id| !id.is_zero()),
+            new_id: Some(new_id).filter(|id| !id.is_zero()),
+        });
+        true
+    });
+    if let Some(mut progress) = progress {
+        callbacks.transfer_progress(move |stats| {
+            progress(FetchProgress {
+                received_objects: stats.received_objects(),
+                indexed_objects: stats.indexed_objects(),
+                total_objects: stats.total_objects(),
+                received_bytes: stats.received_bytes(),
+            });
+            true
+        });
+    }
+    let mut fetch_options = git2::FetchOptions::new();
+    fetch_options
+        .remote_callbacks(callbacks)
+        .prune(if prune {
+            git2::FetchPrune::On
+        } else {
+            git2::FetchPrune::Off
+        })
+        .download_tags(tags.to_autotag())
+        .depth(depth)
+        .update_fetchhead(true);
+    HttpHeaders::from_config(&config, &url)?.apply_fetch(&mut fetch_options);
+
+    remote.fetch(&refspecs, Some(&mut fetch_options), None)?;
+    drop(fetch_options);
+    approve_credential(&approval);
+
+    Ok(updated.into_inner())
+}
+
+/// A ref advertised by a remote, see [`ls_remote`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RemoteRef {
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_oid"))]
+    pub id: git2::Oid,
+    /// What the ref points to, if it is symbolic (like `HEAD`)
+    pub symref_target: Option<String>,
+}
+
+/// List the refs `remote` (a name or URL) has, like `git ls-remote`
+///
+/// When `patterns` are given, only refs matching one of them are listed.  Like `git ls-remote`,
+/// a pattern matches the end of a ref name at a `/` boundary, so `main` matches
+/// `refs/heads/main`, and may contain `*` / `?` wildcards.
+pub fn ls_remote(
+    repo: &git2::Repository,
+    remote: &str,
+    patterns: &[&str],
+    auth: &AuthConfig,
+) -> Result<Vec<RemoteRef>, git2::Error> {
+    let mut remote = find_remote(repo, remote)?;
+    let url = remote.url().unwrap_or_default().to_owned();
+    let (callbacks, approval) = crate::auth::callbacks_with_approval(repo, &url, auth)?;
+    let connection = remote.connect_auth(git2::Direction::Fetch, Some(callbacks), None)?;
+    approve_credential(&approval);
+    let refs = connection
+        .list()?
+        .iter()
+        .filter(|head| patterns.is_empty() || patterns.iter().any(|p| ref_matches(p, head.name())))
+        .map(|head| RemoteRef {
+            name: head.name().to_owned(),
+            id: head.oid(),
+            symref_target: head.symref_target().map(ToOwned::to_owned),
+        })
+        .collect();
+    Ok(refs)
+}
+
+/// Delete remote-tracking refs for branches `remote` no longer has, like `git remote prune`
+///
+/// The refs are deleted in one [`crate::refs::ReferenceTransaction`], running the
+/// `reference-transaction` hook.  Like `git`, the reflogs of pruned refs are deleted with them.
+///
+/// What the remote still has is listed with [`ls_remote`], authenticating with `auth`.
+///
+/// Returns the names of the pruned refs.
+pub fn prune_remote(
+    repo: &git2::Repository,
+    remote_name: &str,
+    auth: &AuthConfig,
+) -> Result<Vec<String>, git2::Error> {
+    let remote = repo.find_remote(remote_name)?;
+    let advertised: std::collections::HashSet<_> = ls_remote(repo, remote_name, &[], auth)?
+        .into_iter()
+        .map(|r| r.name)
+        .collect();
+
+    let fetch_refspecs: Vec<_> = remote
+        .refspecs()
+        .filter(|refspec| refspec.direction() == git2::Direction::Fetch)
+        .collect();
+    let mut stale = Vec::new();
+    for reference in repo.references()? {
+        let reference = reference?;
+        // Symbolic refs, like `refs/remotes/origin/HEAD`, are left alone like `git` does
+        let (Some(name), Some(id)) = (reference.name(), reference.target()) else {
+            continue;
+        };
+        let is_stale = fetch_refspecs
+            .iter()
+            .filter(|refspec| refspec.dst_matches(name))
+            .filter_map(|refspec| refspec.rtransform(name).ok())
+            .filter_map(|remote_ref| remote_ref.as_str().map(ToOwned::to_owned))
+            .any(|remote_ref| !advertised.contains(&remote_ref));
+        if is_stale {
+            stale.push((name.to_owned(), id));
+        }
+    }
+    if stale.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let hooks = Hooks::with_repo(repo)?;
+    stale
+        .iter()
+        .fold(
+            crate::refs::ReferenceTransaction::new(repo).hooks(&hooks),
+            |transaction, (name, id)| transaction.delete(name.as_str(), Some(*id)),
+        )
+        .commit()?;
+
+    Ok(stale.into_iter().map(|(name, _)| name).collect())
+}
+
+/// How to [`clone`]
+pub struct CloneOptions<'a> {
+    bare: bool,
+    branch: Option<String>,
+    single_branch: bool,
+    depth: u32,
+    checkout: bool,
+    hooks: Option<&'a Hooks>,
+    auth: AuthConfig,
+    progress: Option<Box<dyn FnMut(FetchProgress) + 'a>>,
+}
+
+impl<'a> CloneOptions<'a> {
+    pub fn new() -> Self {
+        Self {
+            bare: false,
+            branch: None,
+            single_branch: false,
+            depth: 0,
+            checkout: true,
+            hooks: None,
+            auth: AuthConfig::new(),
+            progress: None,
+        }
+    }
+
+    /// Create a bare repository (`--bare`), with the remote's branches as local branches
+    pub fn bare(mut self, yes: bool) -> Self {
+        self.bare = yes;
+        self
+    }
+
+    /// Point `HEAD` at `branch` rather than the remote's `HEAD`
+    pub fn branch(mut self, branch: impl Into<String>) -> Self {
+        self.branch = Some(branch.into());
+        self
+    }
+
+    /// Only fetch the branch `HEAD` will point at (`--single-branch`)
+    pub fn single_branch(mut self, yes: bool) -> Self {
+        self.single_branch = yes;
+        self
+    }
+
+    /// Only fetch the last `depth` commits of history (`--depth`)
+    ///
+    /// `0` fetches all history.
+    pub fn depth(mut self, depth: u32) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Check out `HEAD` into the working tree, running `post-checkout`
+    ///
+    /// Defaults to `true`; ignored for bare repositories.
+    pub fn checkout(mut self, yes: bool) -> Self {
+        self.checkout = yes;
+        self
+    }
+
+    /// Run hooks from `hooks` rather than the new repository's hooks directory
+    pub fn hooks(mut self, hooks: &'a Hooks) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    /// How to authenticate, see [`crate::auth::callbacks`]
+    pub fn auth(mut self, auth: AuthConfig) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Report how much of the pack has been received
+    pub fn progress(mut self, progress: impl FnMut(FetchProgress) + 'a) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Report how much of the pack has been received to `progress`, see
+    /// [`FetchProgress::report_to`]
+    pub fn progress_reporter(self, progress: &'a dyn super::Progress) -> Self {
+        self.progress(move |transfer: FetchProgress| transfer.report_to(progress))
+    }
+}
+
+impl Default for CloneOptions<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Clone `url` into `path` as `origin`, like `git clone`
+///
+/// Besides fetching, this sets up what `git clone` does: `origin`'s fetch refspec, the local
+/// branch for `HEAD` tracking its remote-tracking branch, `refs/remotes/origin/HEAD`, and
+/// checking out the working tree.
+pub fn clone(
+    url: &str,
+    path: &std::path::Path,
+    options: CloneOptions<'_>,
+) -> Result<git2::Repository, git2::Error> {
+    let CloneOptions {
+        bare,
+        branch,
+        single_branch,
+        depth,
+        checkout,
+        hooks,
+        auth,
+        progress,
+    } = options;
+
+    let mut init_options = git2::RepositoryInitOptions::new();
+    init_options.bare(bare).no_reinit(true).mkpath(true);
+    let repo = git2::Repository::init_opts(path, &init_options)?;
+
+    if bare {
+        // Like `git clone --bare`, a bare repository has no fetch refspec configured
+        repo.config()?.set_str("remote.origin.url", url)?;
+    } else {
+        repo.remote("origin", url)?;
+    }
+    let mut remote = repo.find_remote("origin")?;
+
+    // Look at what the remote has over the connection used for fetching, so it only
+    // authenticates once
+    let (callbacks, approval) = crate::auth::callbacks_with_approval(&repo, url, &auth)?;
+    let mut connection = remote.connect_auth(git2::Direction::Fetch, Some(callbacks), None)?;
+    let head_branch = match branch {
+        Some(branch) => {
+            let name = format!("refs/heads/{branch}");
+            if !connection.list()?.iter().any(|head| head.name() == name) {
+                return Err(git2::Error::new(
+                    git2::ErrorCode::NotFound,
+                    git2::ErrorClass::Reference,
+                    format!("remote branch {branch} not found in upstream origin"),
+                ));
+            }
+            Some(branch)
+        }
+        None => match connection.default_branch() {
+            Ok(target) => target
+                .as_str()
+                .and_then(|target| target.strip_prefix("refs/heads/"))
+                .map(ToOwned::to_owned),
+            Err(err) if err.code() == git2::ErrorCode::NotFound => None,
+            Err(err) => return Err(err),
+        },
+    };
+
+    let src = match (&head_branch, single_branch) {
+        (Some(branch), true) => format!("refs/heads/{branch}"),
+        _ => "refs/heads/*".to_owned(),
+    };
+    let tracking_prefix = if bare {
+        "refs/heads"
+    } else {
+        "refs/remotes/origin"
+    };
+    let refspec = format!(
+        "+{src}:{tracking_prefix}{}",
+        src.strip_prefix("refs/heads").unwrap_or_default()
+    );
+    if !bare {
+        repo.config()?.set_str("remote.origin.fetch", &refspec)?;
+    }
+    let mut fetch_options = FetchOptions::new().depth(depth).auth(auth).refspec(refspec);
+    if let Some(progress) = progress {
+        fetch_options = fetch_options.progress(progress);
+    }
+    fetch_remote(&repo, connection.remote(), fetch_options)?;
+    drop(connection);
+    drop(remote);
+    approve_credential(&approval);
+
+    let Some(head_branch) = head_branch else {
+        // An empty remote, so leave `HEAD` unborn
+        return Ok(repo);
+    };
+    let local_ref = format!("refs/heads/{head_branch}");
+    if !bare {
+        let tracking_ref = format!("refs/remotes/origin/{head_branch}");
+        let id = repo.refname_to_id(&tracking_ref)?;
+        let message = format!("clone: from {url}");
+        repo.reference(&local_ref, id, true, &message)?;
+        repo.reference_symbolic("refs/remotes/origin/HEAD", &tracking_ref, true, &message)?;
+        let mut config = repo.config()?;
+        config.set_str(&format!("branch.{head_branch}.remote"), "origin")?;
+        config.set_str(&format!("branch.{head_branch}.merge"), &local_ref)?;
+    }
+    repo.set_head(&local_ref)?;
+
+    if !bare && checkout {
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        checkout.force();
+        repo.checkout_head(Some(&mut checkout))?;
+        let new_head = repo.refname_to_id("HEAD")?;
+        let repo_hooks;
+        let hooks = match hooks {
+            Some(hooks) => hooks,
+            None => {
+                repo_hooks = Hooks::with_repo(&repo)?;
+                &repo_hooks
+            }
+        };
+        hooks.run_post_checkout(&repo, git2::Oid::zero(), new_head, true);
+    }
+
+    Ok(repo)
+}
+
+/// Have the credential helpers remember the credential the remote accepted, like `git`
+fn approve_credential(approval: &CredentialApproval) {
+    if let Err(err) = approval.approve() {
+        log::warn!("Failed to store credential: {err}");
+    }
+}
+
+fn ref_matches(pattern: &str, name: &str) -> bool {
+    let mut tail = Some(name);
+    while let Some(candidate) = tail {
+        if crate::utils::wildcard_match(pattern, candidate, false) {
+            return true;
+        }
+        tail = candidate.split_once('/').map(|(_, rest)| rest);
+    }
+    false
+}
+
+pub(crate) fn find_remote<'r>(
+    repo: &'r git2::Repository,
+    remote: &str,
+) -> Result<git2::Remote<'r>, git2::Error> {
+    match repo.find_remote(remote) {
+        Ok(remote) => Ok(remote),
+        // Not a valid remote name, so try it as a URL
+        Err(_) if remote.contains([':', '/']) => repo.remote_anonymous(remote),
+        Err(err) => Err(err),
+    }
+}
+
+/// A fully-qualified push refspec
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct PushRefspec {
+    force: bool,
+    /// `None` when deleting `dst`
+    src: Option<String>,
+    dst: String,
+}
+
+impl PushRefspec {
+    fn to_spec(&self) -> String {
+        let force = if self.force { "+" } else { "" };
+        let src = self.src.as_deref().unwrap_or_default();
+        format!("{force}{src}:{}", self.dst)
+    }
+}
+
+fn resolve_push_refspec(
+    repo: &git2::Repository,
+    remote: &git2::Remote<'_>,
+    refspec: &str,
+) -> Result<Vec<PushRefspec>, git2::Error> {
+    let (force, spec) = match refspec.strip_prefix('+') {
+        Some(spec) => (true, spec),
+        None => (false, refspec),
+    };
+    if spec == ":" {
+        return matching_refspecs(repo, remote, force);
+    }
+    let (src, dst) = match spec.split_once(':') {
+        Some((src, dst)) => (src, Some(dst)),
+        None => (spec, None),
+    };
+
+    if src.contains('*') {
+        let dst = dst.unwrap_or(src);
+        let (src_prefix, src_suffix) = src.split_once('*').expect("checked for `*`");
+        let (dst_prefix, dst_suffix) = dst
+            .split_once('*')
+            .ok_or_else(|| invalid_refspec(refspec, "destination must also have a `*`"))?;
+        let mut resolved = Vec::new();
+        for reference in repo.references_glob(src)? {
+            let reference = reference?;
+            let Some(name) = reference.name() else {
+                continue;
+            };
+            let Some(matched) = name
+                .strip_prefix(src_prefix)
+                .and_then(|n| n.strip_suffix(src_suffix))
+            else {
+                continue;
+            };
+            resolved.push(PushRefspec {
+                force,
+                src: Some(name.to_owned()),
+                dst: format!("{dst_prefix}{matched}{dst_suffix}"),
+            });
+        }
+        return Ok(resolved);
+    }
+
+    if src.is_empty() {
+        let dst = dst
+            .filter(|dst| !dst.is_empty())
+            .ok_or_else(|| invalid_refspec(refspec, "nothing to push"))?;
+        let dst = qualify_dst(dst, "refs/heads/");
+        return Ok(vec![PushRefspec {
+            force,
+            src: None,
+            dst,
+        }]);
+    }
+
+    let src_ref = repo.resolve_reference_from_short_name(src)?.resolve()?;
+    let src = src_ref
+        .name()
+        .ok_or_else(|| invalid_refspec(refspec, "source is not UTF-8"))?
+        .to_owned();
+    let dst = match dst.filter(|dst| !dst.is_empty()) {
+        Some(dst) => {
+            let namespace = if src.starts_with("refs/tags/") {
+                "refs/tags/"
+            } else {
+                "refs/heads/"
+            };
+            qualify_dst(dst, namespace)
+        }
+        None => src.clone(),
+    };
+    Ok(vec![PushRefspec {
+        force,
+        src: Some(src),
+        dst,
+    }])
+}
+
+fn qualify_dst(dst: &str, namespace: &str) -> String {
+    if dst.starts_with("refs/") {
+        dst.to_owned()
+    } else {
+        format!("{namespace}{dst}")
+    }
+}
+
+/// Local branches that the remote also has, per its remote-tracking branches
+fn matching_refspecs(
+    repo: &git2::Repository,
+    remote: &git2::Remote<'_>,
+    force: bool,
+) -> Result<Vec<PushRefspec>, git2::Error> {
+    let mut resolved = Vec::new();
+    for branch in repo.branches(Some(git2::BranchType::Local))? {
+        let (branch, _) = branch?;
+        let Some(name) = branch.get().name() else {
+            continue;
+        };
+        let tracked = remote
+            .refspecs()
+            .filter(|refspec| refspec.direction() == git2::Direction::Fetch)
+            .filter(|refspec| refspec.src_matches(name))
+            .filter_map(|refspec| refspec.transform(name).ok())
+            .filter_map(|tracking| tracking.as_str().map(ToOwned::to_owned))
+            .any(|tracking| repo.find_reference(&tracking).is_ok());
+        if tracked {
+            resolved.push(PushRefspec {
+                force,
+                src: Some(name.to_owned()),
+                dst: name.to_owned(),
+            });
+        }
+    }
+    Ok(resolved)
+}
+
+/// The refspecs `git push <remote>` would use without any refspecs
+fn default_push_refspecs(
+    repo: &git2::Repository,
+    remote: &git2::Remote<'_>,
+) -> Result<Vec<String>, git2::Error> {
+    let configured = remote.push_refspecs()?;
+    let configured: Vec<_> = configured.iter().flatten().map(ToOwned::to_owned).collect();
+    if !configured.is_empty() {
+        return Ok(configured);
+    }
+
+    let config = repo.config()?;
+    let head = repo.head()?;
+    let branch = if head.is_branch() {
+        head.shorthand()
+    } else {
+        None
+    };
+    let refspec = crate::refs::push_default_refspec(&config, branch, remote.name())?;
+    Ok(vec![refspec])
+}
+
+fn invalid_refspec(refspec: &str, reason: &str) -> git2::Error {
+    git2::Error::new(
+        git2::ErrorCode::InvalidSpec,
+        git2::ErrorClass::Invalid,
+        format!("invalid refspec `{refspec}`: {reason}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_fetch() -> eyre::Result<()> {
+        let upstream = make_git()?;
+        upstream.init_repo()?;
+        upstream.run(&["branch", "feature"])?;
+        upstream.run(&["tag", "v1"])?;
+        let upstream_path = upstream.repo_path.to_str().unwrap().to_owned();
+        let upstream_id = upstream.get_repo()?.head()?.target();
+
+        let git = make_git()?;
+        git.run(&["init"])?;
+        git.run(&["remote", "add", "origin", &upstream_path])?;
+        let repo = git.get_repo()?;
+
+        let mut reported = FetchProgress::default();
+        let mut fetched = fetch(
+            &repo,
+            "origin",
+            FetchOptions::new()
+                .tags(FetchTags::All)
+                .progress(|progress| reported = progress),
+        )?;
+        fetched.sort_by(|a, b| a.name.cmp(&b.name));
+        let names: Vec<_> = fetched.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "refs/remotes/origin/feature",
+                "refs/remotes/origin/master",
+                "refs/tags/v1"
+            ]
+        );
+        assert!(fetched
+            .iter()
+            .all(|f| f.old_id.is_none() && f.new_id == upstream_id));
+        assert_ne!(reported.total_objects, 0);
+        let fetch_head = std::fs::read_to_string(repo.path().join("FETCH_HEAD"))?;
+        assert!(fetch_head.contains("branch 'master' of"), "{fetch_head}");
+
+        upstream.run(&["branch", "-D", "feature"])?;
+        git.run(&["config", "remote.origin.prune", "true"])?;
+        let fetched = fetch(&repo, "origin", FetchOptions::new())?;
+        assert_eq!(
+            fetched,
+            [FetchedRef {
+                name: "refs/remotes/origin/feature".to_owned(),
+                old_id: upstream_id,
+                new_id: None,
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ls_remote() -> eyre::Result<()> {
+        let upstream = make_git()?;
+        upstream.init_repo()?;
+        upstream.run(&["branch", "feature/one"])?;
+        upstream.run(&["tag", "v1"])?;
+        let upstream_path = upstream.repo_path.to_str().unwrap().to_owned();
+        let upstream_id = upstream.get_repo()?.head()?.target().unwrap();
+
+        let git = make_git()?;
+        git.run(&["init"])?;
+        let repo = git.get_repo()?;
+
+        let refs = ls_remote(&repo, &upstream_path, &[], &AuthConfig::new())?;
+        let names: Vec<_> = refs.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "HEAD",
+                "refs/heads/feature/one",
+                "refs/heads/master",
+                "refs/tags/v1"
+            ]
+        );
+        assert_eq!(refs[0].symref_target.as_deref(), Some("refs/heads/master"));
+        assert!(refs.iter().all(|r| r.id == upstream_id));
+
+        let refs = ls_remote(&repo, &upstream_path, &["one", "v*"], &AuthConfig::new())?;
+        let names: Vec<_> = refs.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["refs/heads/feature/one", "refs/tags/v1"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_remote() -> eyre::Result<()> {
+        let upstream = make_git()?;
+        upstream.init_repo()?;
+        upstream.run(&["branch", "stale"])?;
+        upstream.run(&["branch", "kept"])?;
+        let upstream_path = upstream.repo_path.to_str().unwrap().to_owned();
+
+        let git = make_git()?;
+        git.init_repo()?;
+        git.run(&["remote", "add", "origin", &upstream_path])?;
+        git.run(&["fetch", "origin"])?;
+        git.run(&["remote", "set-head", "origin", "master"])?;
+        upstream.run(&["branch", "-D", "stale"])?;
+
+        let repo = git.get_repo()?;
+        let auth = AuthConfig::new().ssh_agent(false).env_credentials(false);
+        assert_eq!(
+            prune_remote(&repo, "origin", &auth)?,
+            ["refs/remotes/origin/stale"]
+        );
+        assert!(repo.find_reference("refs/remotes/origin/stale").is_err());
+        assert!(repo.find_reference("refs/remotes/origin/kept").is_ok());
+        assert!(repo.find_reference("refs/remotes/origin/HEAD").is_ok());
+        assert!(prune_remote(&repo, "origin", &auth)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_prune_remote_transaction() -> eyre::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let upstream = make_git()?;
+        upstream.init_repo()?;
+        upstream.run(&["branch", "one"])?;
+        upstream.run(&["branch", "two"])?;
+        let upstream_path = upstream.repo_path.to_str().unwrap().to_owned();
+
+        let git = make_git()?;
+        git.init_repo()?;
+        git.run(&["remote", "add", "origin", &upstream_path])?;
+        git.run(&["fetch", "origin"])?;
+        upstream.run(&["branch", "-D", "one", "two"])?;
+        let repo = git.get_repo()?;
+        let hook_path = repo.path().join("hooks").join("reference-transaction");
+        std::fs::create_dir_all(hook_path.parent().unwrap())?;
+        let log = git.repo_path.join("transaction.log");
+        std::fs::write(
+            &hook_path,
+            format!(
+                "#!/bin/sh\necho $1 $(wc -l) >> '{}'\n[ -e reject ] && exit 1\nexit 0\n",
+                log.display()
+            ),
+        )?;
+        std::fs::set_permissions(&hook_path, std::fs::Permissions::from_mode(0o755))?;
+        let auth = AuthConfig::new().ssh_agent(false).env_credentials(false);
+
+        // Rejected as a whole
+        std::fs::write(git.repo_path.join("reject"), "")?;
+        assert!(prune_remote(&repo, "origin", &auth).is_err());
+        assert!(repo.find_reference("refs/remotes/origin/one").is_ok());
+        assert!(repo.find_reference("refs/remotes/origin/two").is_ok());
+        assert_eq!(std::fs::read_to_string(&log)?, "prepare 2\naborted 2\n");
+
+        std::fs::remove_file(git.repo_path.join("reject"))?;
+        std::fs::remove_file(&log)?;
+        assert_eq!(
+            prune_remote(&repo, "origin", &auth)?,
+            ["refs/remotes/origin/one", "refs/remotes/origin/two"]
+        );
+        assert_eq!(std::fs::read_to_string(&log)?, "prepare 2\ncommitted 2\n");
+        assert!(!repo.path().join("logs/refs/remotes/origin/one").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_clone() -> eyre::Result<()> {
+        let upstream = make_git()?;
+        upstream.init_repo()?;
+        upstream.run(&["branch", "other"])?;
+        let upstream_repo = upstream.get_repo()?;
+        let head_id = upstream_repo.head()?.target().unwrap();
+        let upstream_path = upstream.repo_path.to_str().unwrap().to_owned();
+
+        let dir = tempfile::tempdir()?;
+        let repo = clone(
+            &upstream_path,
+            &dir.path().join("clone"),
+            CloneOptions::new(),
+        )?;
+        assert_eq!(repo.head()?.name(), Some("refs/heads/master"));
+        assert_eq!(repo.head()?.target(), Some(head_id));
+        assert!(repo.find_reference("refs/remotes/origin/other").is_ok());
+        assert_eq!(
+            repo.find_reference("refs/remotes/origin/HEAD")?
+                .symbolic_target(),
+            Some("refs/remotes/origin/master")
+        );
+        let config = repo.config()?;
+        assert_eq!(config.get_string("branch.master.remote")?, "origin");
+        assert!(repo.statuses(None)?.is_empty());
+
+        let repo = clone(
+            &upstream_path,
+            &dir.path().join("bare"),
+            CloneOptions::new()
+                .bare(true)
+                .branch("other")
+                .single_branch(true),
+        )?;
+        assert!(repo.is_bare());
+        assert_eq!(repo.head()?.name(), Some("refs/heads/other"));
+        assert!(repo.find_reference("refs/heads/master").is_err());
+        assert_eq!(repo.find_remote("origin")?.refspecs().count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shallow() -> eyre::Result<()> {
+        let upstream = make_git()?;
+        upstream.init_repo()?;
+        upstream.commit_file("second", 2)?;
+        let upstream_url = format!("file://{}", upstream.repo_path.display());
+
+        let git = make_git()?;
+        git.run(&["clone", "--depth", "1", &upstream_url, "."])?;
+        let repo = git.get_repo()?;
+        let head_id = repo.head()?.target().unwrap();
+        assert!(crate::ops::is_shallow(&repo));
+        assert_eq!(crate::ops::shallow_boundary(&repo), [head_id]);
+
+        fetch(&repo, "origin", FetchOptions::new().unshallow())?;
+        assert!(!crate::ops::is_shallow(&repo));
+        assert!(crate::ops::shallow_boundary(&repo).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_force_with_lease() -> eyre::Result<()> {
+        let upstream = make_git()?;
+        upstream.run(&["init", "--bare"])?;
+        let upstream_path = upstream.repo_path.to_str().unwrap().to_owned();
+
+        let git = make_git()?;
+        git.init_repo()?;
+        git.run(&["remote", "add", "origin", &upstream_path])?;
+        git.run(&["push", "--set-upstream", "origin", "master"])?;
+        let repo = git.get_repo()?;
+        let fetched_id = repo.refname_to_id("refs/remotes/origin/master")?;
+        git.run(&["commit", "--amend", "-m", "rewritten"])?;
+        let rewritten_id = repo.head()?.target().unwrap();
+
+        // Someone else pushed since we last fetched
+        let upstream_repo = upstream.get_repo()?;
+        let fetched_commit = upstream_repo.find_commit(fetched_id)?;
+        let sig = fetched_commit.author();
+        let upstream_id = upstream_repo.commit(
+            Some("refs/heads/master"),
+            &sig,
+            &sig,
+            "other",
+            &fetched_commit.tree()?,
+            &[&fetched_commit],
+        )?;
+        check_force_with_lease(&repo, "master", Some(fetched_id))?;
+        let err = push(
+            &repo,
+            "origin",
+            PushOptions::new().refspec("master").force_with_lease(true),
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::Modified);
+        assert_eq!(upstream.get_repo()?.head()?.target(), Some(upstream_id));
+
+        fetch(&repo, "origin", FetchOptions::new())?;
+        let err = check_force_with_lease(&repo, "master", Some(fetched_id)).unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::Modified);
+        let pushed = push(
+            &repo,
+            "origin",
+            PushOptions::new()
+                .refspec("master")
+                .force_with_lease_ref("master", Some(upstream_id)),
+        )?;
+        assert!(pushed[0].is_accepted());
+        assert_eq!(upstream.get_repo()?.head()?.target(), Some(rewritten_id));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_push() -> eyre::Result<()> {
+        let upstream = make_git()?;
+        upstream.run(&["init", "--bare"])?;
+        let upstream_path = upstream.repo_path.to_str().unwrap().to_owned();
+
+        let git = make_git()?;
+        git.init_repo()?;
+        git.run(&["remote", "add", "origin", &upstream_path])?;
+        git.run(&["branch", "feature"])?;
+
+        let repo = git.get_repo()?;
+        let mut reported = PushProgress::default();
+        let pushed = push(
+            &repo,
+            "origin",
+            PushOptions::new()
+                .auth(AuthConfig::new().ssh_agent(false).env_credentials(false))
+                .progress(|progress| reported = progress),
+        )?;
+        let head_id = repo.head()?.target();
+        assert_eq!(
+            pushed,
+            [PushedRef {
+                local_ref: Some("refs/heads/master".to_owned()),
+                remote_ref: "refs/heads/master".to_owned(),
+                old_id: None,
+                new_id: head_id,
+                rejection: None,
+            }]
+        );
+        assert_ne!(reported.total_objects, 0);
+        assert_eq!(
+            repo.refname_to_id("refs/remotes/origin/master").ok(),
+            head_id
+        );
+
+        git.run(&["config", "push.default", "matching"])?;
+        git.run(&["push", "origin", "feature"])?;
+        git.commit_file("second", 1)?;
+        git.run(&["branch", "-f", "feature"])?;
+        let pushed = push(&repo, "origin", PushOptions::new())?;
+        let names: Vec<_> = pushed.iter().map(|p| p.remote_ref.as_str()).collect();
+        assert_eq!(names, ["refs/heads/feature", "refs/heads/master"]);
+        assert!(pushed.iter().all(PushedRef::is_accepted));
+
+        let pushed = push(
+            &repo,
+            &upstream_path,
+            PushOptions::new().refspec(":feature"),
+        )?;
+        assert_eq!(pushed[0].local_ref, None);
+        assert!(upstream
+            .get_repo()?
+            .find_reference("refs/heads/feature")
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_push_pre_push_rejects() -> eyre::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let upstream = make_git()?;
+        upstream.run(&["init", "--bare"])?;
+        let upstream_path = upstream.repo_path.to_str().unwrap().to_owned();
+
+        let git = make_git()?;
+        git.init_repo()?;
+        git.run(&["remote", "add", "origin", &upstream_path])?;
+        let repo = git.get_repo()?;
+        let hook_path = repo.path().join("hooks").join("pre-push");
+        std::fs::create_dir_all(hook_path.parent().unwrap())?;
+        std::fs::write(&hook_path, "#!/bin/sh\ncat > pre-push-stdin\nexit 1\n")?;
+        std::fs::set_permissions(&hook_path, std::fs::Permissions::from_mode(0o755))?;
+
+        let hooks = Hooks::with_repo(&repo)?;
+        let err = push(
+            &repo,
+            "origin",
+            PushOptions::new().hooks(&hooks).refspec("master:main"),
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::User);
+        let stdin = std::fs::read_to_string(git.repo_path.join("pre-push-stdin"))?;
+        let head_id = repo.head()?.target().unwrap();
+        assert_eq!(
+            stdin,
+            format!(
+                "refs/heads/master {head_id} refs/heads/main {}\n",
+                git2::Oid::zero()
+            )
+        );
+        assert!(upstream
+            .get_repo()?
+            .find_reference("refs/heads/main")
+            .is_err());
+
+        // The hook is told what the remote has, even without a remote-tracking ref for it
+        push(&repo, "origin", PushOptions::new().refspec("master:main"))?;
+        git.run(&["update-ref", "-d", "refs/remotes/origin/main"])?;
+        let new_head = git.commit_file("file", 1)?;
+        push(
+            &repo,
+            "origin",
+            PushOptions::new().hooks(&hooks).refspec("master:main"),
+        )
+        .unwrap_err();
+        let stdin = std::fs::read_to_string(git.repo_path.join("pre-push-stdin"))?;
+        assert_eq!(
+            stdin,
+            format!("refs/heads/master {new_head} refs/heads/main {head_id}\n")
+        );
+        assert_eq!(
+            upstream.get_repo()?.refname_to_id("refs/heads/main")?,
+            head_id
+        );
+
+        Ok(())
+    }
+
+    /// Serve the repositories under `root` with `git http-backend`, requiring basic auth as
+    /// `user:password`
+    #[cfg(unix)]
+    fn serve_http(root: &std::path::Path, user: &str, password: &str) -> eyre::Result<String> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let root = root.to_owned();
+        let user = user.to_owned();
+        let authorization = format!("Basic {}", base64(format!("{user}:{password}").as_bytes()));
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(err) = serve_request(stream, &root, &user, &authorization) {
+                    log::debug!("HTTP request failed: {err}");
+                }
+            }
+        });
+        Ok(url)
+    }
+
+    #[cfg(unix)]
+    fn serve_request(
+        mut stream: std::net::TcpStream,
+        root: &std::path::Path,
+        user: &str,
+        authorization: &str,
+    ) -> std::io::Result<()> {
+        use std::io::BufRead as _;
+        use std::io::Read as _;
+        use std::io::Write as _;
+
+        let mut reader = std::io::BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut request_line = request_line.split_whitespace();
+        let method = request_line.next().unwrap_or_default().to_owned();
+        let target = request_line.next().unwrap_or_default().to_owned();
+        let mut headers = std::collections::HashMap::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            let Some((name, value)) = line.split_once(':') else {
+                break;
+            };
+            headers.insert(name.to_ascii_lowercase(), value.trim().to_owned());
+        }
+        let header = |name: &str| headers.get(name).map(String::as_str).unwrap_or_default();
+
+        let mut body = Vec::new();
+        if header("transfer-encoding") == "chunked" {
+            loop {
+                let mut size = String::new();
+                reader.read_line(&mut size)?;
+                let size = usize::from_str_radix(size.trim(), 16)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                let mut chunk = vec![0; size + 2];
+                reader.read_exact(&mut chunk)?;
+                if size == 0 {
+                    break;
+                }
+                body.extend_from_slice(&chunk[..size]);
+            }
+        } else {
+            body.resize(header("content-length").parse().unwrap_or(0), 0);
+            reader.read_exact(&mut body)?;
+        }
+
+        if header("authorization") != authorization {
+            return stream.write_all(
+                b"HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"test\"\r\n\
+                  Content-Length: 0\r\nConnection: close\r\n\r\n",
+            );
+        }
+        let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+        let mut child = std::process::Command::new("git")
+            .arg("http-backend")
+            .env("GIT_PROJECT_ROOT", root)
+            .env("GIT_HTTP_EXPORT_ALL", "1")
+            .env("REMOTE_USER", user)
+            .env("REQUEST_METHOD", &method)
+            .env("PATH_INFO", path)
+            .env("QUERY_STRING", query)
+            .env("CONTENT_TYPE", header("content-type"))
+            .env("CONTENT_LENGTH", body.len().to_string())
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        let writer = std::thread::spawn(move || stdin.write_all(&body));
+        let output = child.wait_with_output()?;
+        writer.join().unwrap()?;
+
+        let split = output
+            .stdout
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .unwrap_or(output.stdout.len());
+        let cgi_headers = String::from_utf8_lossy(&output.stdout[..split]);
+        let content = output.stdout.get(split + 4..).unwrap_or_default();
+        let mut status = "200 OK".to_owned();
+        let mut response = String::new();
+        for line in cgi_headers.lines() {
+            match line.strip_prefix("Status: ") {
+                Some(cgi_status) => status = cgi_status.to_owned(),
+                None => response.push_str(&format!("{line}\r\n")),
+            }
+        }
+        let response = format!(
+            "HTTP/1.1 {status}\r\n{response}Content-Length: {}\r\nConnection: close\r\n\r\n",
+            content.len()
+        );
+        stream.write_all(response.as_bytes())?;
+        stream.write_all(content)
+    }
+
+    #[cfg(unix)]
+    fn base64(data: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut encoded = String::new();
+        for chunk in data.chunks(3) {
+            let bytes = [
+                chunk[0],
+                *chunk.get(1).unwrap_or(&0),
+                *chunk.get(2).unwrap_or(&0),
+            ];
+            let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+                } else {
+                    encoded.push('=');
+                }
+            }
+        }
+        encoded
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_push_stores_accepted_credential() -> eyre::Result<()> {
+        let upstream = make_git()?;
+        upstream.run(&["init", "--bare"])?;
+        let root = upstream.repo_path.parent().unwrap();
+        let name = upstream.repo_path.file_name().unwrap().to_str().unwrap();
+        let url = format!("{}/{name}", serve_http(root, "user", "secret")?);
+
+        let git = make_git()?;
+        git.init_repo()?;
+        let log = git.repo_path.join("helper.log");
+        git.run(&[
+            "config",
+            "credential.helper",
+            &format!(
+                "!f() {{ echo $1 >> '{}'; cat >/dev/null; [ $1 = get ] && echo username=user && echo password=secret; true; }}; f",
+                log.display()
+            ),
+        ])?;
+        let repo = git.get_repo()?;
+        let auth = AuthConfig::new()
+            .ssh_agent(false)
+            .env_credentials(false)
+            .max_attempts(2);
+        let pushed = push(&repo, &url, PushOptions::new().refspec("master").auth(auth))?;
+        assert!(pushed.iter().all(PushedRef::is_accepted));
+        assert_eq!(std::fs::read_to_string(&log)?, "get\nstore\n");
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_push_erases_rejected_credential() -> eyre::Result<()> {
+        struct Password;
+
+        impl crate::auth::Prompter for Password {
+            fn username(&self, _url: &str) -> Option<String> {
+                None
+            }
+
+            fn password(&self, _url: &str, _username: &str) -> Option<String> {
+                Some("secret".to_owned())
+            }
+        }
+
+        let upstream = make_git()?;
+        upstream.run(&["init", "--bare"])?;
+        let root = upstream.repo_path.parent().unwrap();
+        let name = upstream.repo_path.file_name().unwrap().to_str().unwrap();
+        let url = format!("{}/{name}", serve_http(root, "user", "secret")?);
+
+        let git = make_git()?;
+        git.init_repo()?;
+        let log = git.repo_path.join("helper.log");
+        git.run(&[
+            "config",
+            "credential.helper",
+            &format!(
+                "!f() {{ echo $1 >> '{0}'; [ $1 = get ] || grep ^password >> '{0}'; cat >/dev/null; [ $1 = get ] && echo username=user && echo password=stale; true; }}; f",
+                log.display()
+            ),
+        ])?;
+        let repo = git.get_repo()?;
+        let cache = crate::auth::CredentialCache::default();
+        let auth = AuthConfig::new()
+            .ssh_agent(false)
+            .env_credentials(false)
+            .cache(cache.clone())
+            .prompter(Password);
+        push(&repo, &url, PushOptions::new().refspec("master").auth(auth))?;
+        // The stale password is erased each time it is rejected, before prompting
+        assert_eq!(
+            std::fs::read_to_string(&log)?,
+            "get\nerase\npassword=stale\nget\nerase\npassword=stale\nstore\npassword=secret\n"
+        );
+        let context = crate::auth::Credential::from_url(&url).unwrap();
+        let context = crate::auth::Credential {
+            path: None,
+            ..context
+        };
+        let cached = cache.lookup(&context).unwrap();
+        assert_eq!(cached.password.as_deref(), Some("secret"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_clone_authenticates_once() -> eyre::Result<()> {
+        #[derive(Clone, Default)]
+        struct Recording(std::sync::Arc<std::sync::Mutex<Vec<&'static str>>>);
+
+        impl crate::auth::CredentialHelper for Recording {
+            fn get(
+                &self,
+                cred: &crate::auth::Credential,
+            ) -> Result<crate::auth::Credential, std::io::Error> {
+                self.0.lock().unwrap().push("get");
+                Ok(crate::auth::Credential {
+                    username: Some("user".to_owned()),
+                    password: Some("secret".to_owned()),
+                    ..cred.clone()
+                })
+            }
+
+            fn store(&self, _cred: &crate::auth::Credential) -> Result<(), std::io::Error> {
+                self.0.lock().unwrap().push("store");
+                Ok(())
+            }
+
+            fn erase(&self, _cred: &crate::auth::Credential) -> Result<(), std::io::Error> {
+                self.0.lock().unwrap().push("erase");
+                Ok(())
+            }
+        }
+
+        let upstream = make_git()?;
+        upstream.init_repo()?;
+        upstream.run(&["branch", "other"])?;
+        upstream.run(&["clone", "--bare", ".", "upstream.git"])?;
+        let head_id = upstream.get_repo()?.head()?.target().unwrap();
+        let url = format!(
+            "{}/upstream.git",
+            serve_http(&upstream.repo_path, "user", "secret")?
+        );
+
+        let git = make_git()?;
+        let helper = Recording::default();
+        let auth = AuthConfig::new()
+            .ssh_agent(false)
+            .credential_helpers(false)
+            .env_credentials(false)
+            .credential_helper(helper.clone());
+        let repo = clone(
+            &url,
+            &git.repo_path.join("clone"),
+            CloneOptions::new().auth(auth),
+        )?;
+        assert_eq!(repo.head()?.target(), Some(head_id));
+        assert!(repo.find_reference("refs/remotes/origin/other").is_ok());
+        assert_eq!(*helper.0.lock().unwrap(), ["get", "store"]);
+
+        Ok(())
+    }
+}
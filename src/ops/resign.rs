@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use super::Phase;
+use super::Progress;
+use super::ProgressUpdate;
+use super::Sign;
+
+/// Re-sign the commits reachable from `tips` but not from `base`, returning the `(old, new)` ids
+///
+/// Commits keep their tree, authors, and message; only their signature and, for descendants
+/// of re-signed commits, their parents change.  Rewrites are listed with parents before their
+/// children.
+///
+/// Signing is the slow part, calling out to `gpg` or `ssh-keygen` for each commit, so commits
+/// that don't depend on each other, like those on sibling branches, are signed on up to
+/// `parallelism` threads at once, defaulting to the available parallelism.  A commit's content
+/// includes its parents' new ids, so it still waits for them: a linear stack is signed one
+/// commit at a time.
+///
+/// `progress` is told of each commit once it has been re-signed.
+pub fn resign_range(
+    repo: &git2::Repository,
+    base: git2::Oid,
+    tips: &[git2::Oid],
+    sign: &(dyn Sign + Sync),
+    parallelism: Option<NonZeroUsize>,
+    progress: Option<&dyn Progress>,
+) -> Result<Vec<(git2::Oid, git2::Oid)>, git2::Error> {
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    for tip in tips {
+        walk.push(*tip)?;
+    }
+    walk.hide(base)?;
+
+    // Commits at the same depth, counting only the commits being re-signed, can't depend on
+    // each other
+    let mut depths = HashMap::new();
+    let mut levels: Vec<Vec<git2::Commit<'_>>> = Vec::new();
+    for id in walk {
+        let commit = repo.find_commit(id?)?;
+        let depth = commit
+            .parent_ids()
+            .filter_map(|parent_id| depths.get(&parent_id))
+            .map(|depth| depth + 1)
+            .max()
+            .unwrap_or(0);
+        depths.insert(commit.id(), depth);
+        if levels.len() <= depth {
+            levels.resize_with(depth + 1, Vec::new);
+        }
+        levels[depth].push(commit);
+    }
+
+    let threads = parallelism
+        .or_else(|| std::thread::available_parallelism().ok())
+        .map(NonZeroUsize::get)
+        .unwrap_or(1);
+    let mut new_ids = HashMap::new();
+    let mut rewritten = Vec::with_capacity(depths.len());
+    for level in levels {
+        let mut buffers = Vec::with_capacity(level.len());
+        for commit in &level {
+            let parents = commit
+                .parent_ids()
+                .map(|parent_id| repo.find_commit(*new_ids.get(&parent_id).unwrap_or(&parent_id)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let parents = parents.iter().collect::<Vec<_>>();
+            // The message is kept byte for byte, along with its `encoding` header
+            let buffer = super::encoding::commit_buffer(
+                repo,
+                &commit.author(),
+                &commit.committer(),
+                &super::encoding::EncodedMessage::of(commit),
+                &commit.tree()?,
+                &parents,
+            )?;
+            let buffer = std::str::from_utf8(&buffer).map_err(|_| {
+                git2::Error::new(
+                    git2::ErrorCode::Invalid,
+                    git2::ErrorClass::Object,
+                    format!("{} is not valid UTF-8 and can't be signed", commit.id()),
+                )
+            })?;
+            buffers.push(buffer.to_owned());
+        }
+
+        let signatures = sign_all(&buffers, sign, threads)?;
+        for ((commit, buffer), signature) in level.iter().zip(&buffers).zip(&signatures) {
+            let new_id = repo.commit_signed(buffer, signature, None)?;
+            new_ids.insert(commit.id(), new_id);
+            rewritten.push((commit.id(), new_id));
+            if let Some(progress) = progress {
+                let update =
+                    ProgressUpdate::new(Phase::Resign, rewritten.len(), Some(depths.len()));
+                progress.report(&update.commit(commit.id()));
+            }
+        }
+    }
+    Ok(rewritten)
+}
+
+/// Sign each of `buffers`, on up to `threads` threads
+fn sign_all(
+    buffers: &[String],
+    sign: &(dyn Sign + Sync),
+    threads: usize,
+) -> Result<Vec<String>, git2::Error> {
+    let threads = threads.min(buffers.len());
+    if threads <= 1 {
+        return buffers.iter().map(|buffer| sign.sign(buffer)).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let mut signatures = Vec::with_capacity(buffers.len());
+    signatures.resize_with(buffers.len(), || None);
+    std::thread::scope(|scope| {
+        let workers = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut signed = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(buffer) = buffers.get(index) else {
+                            break;
+                        };
+                        let result = sign.sign(buffer);
+                        if result.is_err() {
+                            // Let the other threads stop too; everything before `index` was
+                            // already taken, so this is the first error reported
+                            next.store(buffers.len(), Ordering::Relaxed);
+                        }
+                        signed.push((index, result));
+                    }
+                    signed
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            let signed = worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            for (index, result) in signed {
+                signatures[index] = Some(result);
+            }
+        }
+    });
+    signatures
+        .into_iter()
+        .map(|signature| {
+            signature.unwrap_or_else(|| {
+                Err(git2::Error::new(
+                    git2::ErrorCode::User,
+                    git2::ErrorClass::Callback,
+                    "signing was stopped",
+                ))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    /// Signs with a fixed signature, tracking how many signatures were in flight at once
+    #[derive(Default)]
+    struct SlowSign {
+        active: AtomicUsize,
+        max_active: AtomicUsize,
+    }
+
+    impl Sign for SlowSign {
+        fn sign(&self, buffer: &str) -> Result<String, git2::Error> {
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_active.fetch_max(active, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            Ok(format!("signature of {} bytes", buffer.len()))
+        }
+    }
+
+    #[test]
+    fn test_resign_range() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let base = repo.refname_to_id("refs/heads/master")?;
+        let first = git.commit_file("first", 1)?;
+        let second = git.commit_file("second", 2)?;
+        git.run(&["checkout", "-b", "sibling", &base.to_string()])?;
+        let sibling = git.commit_file("sibling", 3)?;
+
+        let sign = SlowSign::default();
+        let reported = std::cell::RefCell::new(Vec::new());
+        let progress = |update: &ProgressUpdate<'_>| {
+            reported.borrow_mut().push((update.completed, update.total));
+        };
+        let rewritten = resign_range(
+            &repo,
+            base,
+            &[second, sibling],
+            &sign,
+            NonZeroUsize::new(4),
+            Some(&progress),
+        )?;
+        assert_eq!(sign.max_active.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            reported.into_inner(),
+            [(1, Some(3)), (2, Some(3)), (3, Some(3))]
+        );
+
+        let old_ids = rewritten.iter().map(|(old, _)| *old).collect::<Vec<_>>();
+        assert_eq!(old_ids.len(), 3);
+        assert!(old_ids.contains(&sibling));
+        let position = |id| old_ids.iter().position(|old| *old == id);
+        assert!(position(first) < position(second));
+
+        let new = |old| rewritten.iter().find(|(id, _)| *id == old).unwrap().1;
+        let new_second = repo.find_commit(new(second))?;
+        assert_eq!(new_second.parent_id(0)?, new(first));
+        assert_eq!(new_second.tree_id(), repo.find_commit(second)?.tree_id());
+        assert_eq!(new_second.message(), repo.find_commit(second)?.message());
+        assert_eq!(repo.find_commit(new(first))?.parent_id(0)?, base);
+        for (_, new_id) in &rewritten {
+            let (signature, _) = repo.extract_signature(new_id, None)?;
+            assert!(signature.as_str().unwrap().starts_with("signature of "));
+        }
+
+        // A linear stack can't overlap
+        let sign = SlowSign::default();
+        resign_range(&repo, base, &[second], &sign, None, None)?;
+        assert_eq!(sign.max_active.load(Ordering::SeqCst), 1);
+
+        Ok(())
+    }
+}
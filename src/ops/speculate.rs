@@ -0,0 +1,115 @@
+use std::io::Write as _;
+
+/// What [`speculate`] does with the objects written once the operation is over
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Speculation {
+    /// Keep the objects if the operation succeeds
+    Commit,
+    /// Always drop the objects, like for a dry run
+    Preview,
+}
+
+/// Run `op` with the objects it writes held in memory
+///
+/// `op` is given its own handle on `repo` whose new objects, like the trees and commits of a
+/// history rewrite, go into an in-memory pack.  Only when `op` succeeds with
+/// [`Speculation::Commit`] are they written to `repo`'s object database, as a single pack;
+/// otherwise they're dropped, rather than left behind as loose objects for `git gc`.
+///
+/// Until then, the new objects can only be read through the handle `op` is given.  Refs must
+/// not be pointed at them from within `op`, as they'd dangle if the objects are dropped.
+pub fn speculate<T>(
+    repo: &git2::Repository,
+    mode: Speculation,
+    op: impl FnOnce(&git2::Repository) -> Result<T, git2::Error>,
+) -> Result<T, git2::Error> {
+    // A mempack can't be detached, so it goes on a throwaway handle rather than `repo`s
+    let scratch = git2::Repository::open(repo.path())?;
+    let odb = scratch.odb()?;
+    let mempack = odb.add_new_mempack_backend(MEMPACK_PRIORITY)?;
+
+    let result = op(&scratch);
+    if let (Ok(_), Speculation::Commit) = (&result, mode) {
+        let mut pack = git2::Buf::new();
+        mempack.dump(&scratch, &mut pack)?;
+        if pack_object_count(&pack) != 0 {
+            let odb = repo.odb()?;
+            let mut writer = odb.packwriter()?;
+            writer.write_all(&pack).map_err(|err| {
+                git2::Error::new(
+                    git2::ErrorCode::GenericError,
+                    git2::ErrorClass::Odb,
+                    format!("could not write pack: {err}"),
+                )
+            })?;
+            writer.commit()?;
+            odb.refresh()?;
+        }
+    }
+    mempack.reset()?;
+    result
+}
+
+/// Ahead of the on-disk backends, so new objects land in it
+const MEMPACK_PRIORITY: i32 = 1000;
+
+/// The number of objects in a pack, from its header
+fn pack_object_count(pack: &[u8]) -> u32 {
+    pack.get(8..12)
+        .and_then(|count| count.try_into().ok())
+        .map(u32::from_be_bytes)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_speculate() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let head_id = git.commit_file("file", 1)?;
+        let pack_dir = repo.path().join("objects").join("pack");
+        let packs = || -> eyre::Result<usize> {
+            Ok(std::fs::read_dir(&pack_dir)?
+                .filter_map(Result::ok)
+                .filter(|entry| entry.path().extension() == Some("pack".as_ref()))
+                .count())
+        };
+
+        let previewed = speculate(&repo, Speculation::Preview, |scratch| {
+            let new_id = crate::ops::reword(scratch, head_id, "previewed", None)?;
+            scratch.find_commit(new_id)?;
+            Ok(new_id)
+        })?;
+        repo.find_commit(previewed).unwrap_err();
+
+        let err = speculate(&repo, Speculation::Commit, |scratch| -> Result<(), _> {
+            let blob_id = scratch.blob(b"failed")?;
+            Err(git2::Error::from_str(&blob_id.to_string()))
+        })
+        .unwrap_err();
+        let blob_id = git2::Oid::from_str(err.message())?;
+        repo.find_blob(blob_id).unwrap_err();
+        assert_eq!(packs()?, 0);
+
+        let committed = speculate(&repo, Speculation::Commit, |scratch| {
+            crate::ops::reword(scratch, head_id, "committed", None)
+        })?;
+        assert_eq!(repo.find_commit(committed)?.message(), Some("committed"));
+        assert_eq!(packs()?, 1);
+        git.run(&["cat-file", "-e", &committed.to_string()])?;
+
+        // Nothing new to write
+        speculate(&repo, Speculation::Commit, |scratch| {
+            crate::ops::reword(scratch, head_id, "committed", None)
+        })?;
+        assert_eq!(packs()?, 1);
+
+        Ok(())
+    }
+}
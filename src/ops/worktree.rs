@@ -0,0 +1,240 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+/// A linked worktree, see [`worktrees`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Worktree {
+    /// The name of its administrative directory, `$GIT_COMMON_DIR/worktrees/<name>`
+    pub name: String,
+    /// Its working directory
+    pub path: PathBuf,
+    /// The commit checked out, `None` for an unborn branch
+    pub head_id: Option<git2::Oid>,
+    /// The branch checked out, like `refs/heads/main`, `None` when detached
+    pub head_branch: Option<String>,
+    /// Why it is locked against pruning, empty when no reason was given
+    pub locked: Option<String>,
+    /// Whether its working directory is gone, so [`prune_worktree`] can clean it up
+    pub prunable: bool,
+}
+
+/// The linked worktrees of `repo`, sorted by name, like `git worktree list`
+///
+/// `repo` can be the main repository or any of its worktrees; the main worktree isn't listed.
+pub fn worktrees(repo: &git2::Repository) -> Result<Vec<Worktree>, git2::Error> {
+    let mut names = repo
+        .worktrees()?
+        .iter()
+        .flatten()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| read_worktree(repo, &name))
+        .collect()
+}
+
+/// How to [`add_worktree`]
+#[derive(Clone, Debug, Default)]
+pub struct AddWorktreeOptions {
+    branch: Option<String>,
+    lock: bool,
+}
+
+impl AddWorktreeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check out the existing local branch `branch`
+    ///
+    /// Defaults to creating a branch named after the worktree at `HEAD`, like
+    /// `git worktree add <path>`.
+    pub fn branch(mut self, branch: impl Into<String>) -> Self {
+        self.branch = Some(branch.into());
+        self
+    }
+
+    /// Lock the new worktree against pruning (`--lock`)
+    pub fn lock(mut self, yes: bool) -> Self {
+        self.lock = yes;
+        self
+    }
+}
+
+/// Create a worktree called `name` at `path`, checking it out
+pub fn add_worktree(
+    repo: &git2::Repository,
+    name: &str,
+    path: &Path,
+    options: AddWorktreeOptions,
+) -> Result<Worktree, git2::Error> {
+    let reference = options
+        .branch
+        .as_deref()
+        .map(|branch| repo.find_branch(branch, git2::BranchType::Local))
+        .transpose()?
+        .map(git2::Branch::into_reference);
+    let mut add_options = git2::WorktreeAddOptions::new();
+    add_options.lock(options.lock);
+    add_options.reference(reference.as_ref());
+    repo.worktree(name, path, Some(&add_options))?;
+    read_worktree(repo, name)
+}
+
+/// How to [`prune_worktree`]
+#[derive(Clone, Debug, Default)]
+pub struct PruneWorktreeOptions {
+    valid: bool,
+    locked: bool,
+    working_tree: bool,
+}
+
+impl PruneWorktreeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prune the worktree even though its working directory still exists
+    pub fn valid(mut self, yes: bool) -> Self {
+        self.valid = yes;
+        self
+    }
+
+    /// Prune the worktree even though it is locked
+    pub fn locked(mut self, yes: bool) -> Self {
+        self.locked = yes;
+        self
+    }
+
+    /// Delete the working directory too
+    pub fn working_tree(mut self, yes: bool) -> Self {
+        self.working_tree = yes;
+        self
+    }
+}
+
+/// Remove the administrative files of the worktree called `name`, like `git worktree prune`
+///
+/// By default, only worktrees whose working directory is gone and that aren't locked are
+/// pruned, failing otherwise.
+pub fn prune_worktree(
+    repo: &git2::Repository,
+    name: &str,
+    options: PruneWorktreeOptions,
+) -> Result<(), git2::Error> {
+    let worktree = repo.find_worktree(name)?;
+    let mut prune_options = git2::WorktreePruneOptions::new();
+    prune_options
+        .valid(options.valid)
+        .locked(options.locked)
+        .working_tree(options.working_tree);
+    if !worktree.is_prunable(Some(&mut prune_options))? {
+        return Err(git2::Error::new(
+            git2::ErrorCode::Locked,
+            git2::ErrorClass::Worktree,
+            format!("worktree `{name}` is still valid or locked"),
+        ));
+    }
+    worktree.prune(Some(&mut prune_options))
+}
+
+fn read_worktree(repo: &git2::Repository, name: &str) -> Result<Worktree, git2::Error> {
+    let worktree = repo.find_worktree(name)?;
+    let locked = match worktree.is_locked()? {
+        git2::WorktreeLockStatus::Unlocked => None,
+        git2::WorktreeLockStatus::Locked(reason) => {
+            Some(reason.unwrap_or_default().trim_end().to_owned())
+        }
+    };
+    let prunable = worktree.validate().is_err();
+
+    // `HEAD` is read from the administrative directory, which is there even when the working
+    // directory is gone
+    let head_path = crate::utils::common_dir(repo)
+        .join("worktrees")
+        .join(name)
+        .join("HEAD");
+    let head = std::fs::read_to_string(&head_path).map_err(|err| {
+        git2::Error::new(
+            git2::ErrorCode::NotFound,
+            git2::ErrorClass::Worktree,
+            format!("could not read {}: {err}", head_path.display()),
+        )
+    })?;
+    let head = head.trim_end();
+    let (head_id, head_branch) = match head.strip_prefix("ref: ") {
+        Some(branch) => (repo.refname_to_id(branch).ok(), Some(branch.to_owned())),
+        None => (git2::Oid::from_str(head).ok(), None),
+    };
+
+    Ok(Worktree {
+        name: name.to_owned(),
+        path: worktree.path().to_owned(),
+        head_id,
+        head_branch,
+        locked,
+        prunable,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_worktrees() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.run(&["branch", "feature"])?;
+        git.run(&["commit-graph", "write", "--reachable"])?;
+        let repo = git.get_repo()?;
+        let head_id = repo.refname_to_id("HEAD")?;
+
+        let wt = add_worktree(
+            &repo,
+            "wt",
+            &git.repo_path.join("wt"),
+            AddWorktreeOptions::new(),
+        )?;
+        assert_eq!(wt.head_branch.as_deref(), Some("refs/heads/wt"));
+        assert_eq!(wt.head_id, Some(head_id));
+        assert_eq!((wt.locked.as_deref(), wt.prunable), (None, false));
+        let locked = add_worktree(
+            &repo,
+            "locked",
+            &git.repo_path.join("locked"),
+            AddWorktreeOptions::new().branch("feature").lock(true),
+        )?;
+        assert_eq!(locked.head_branch.as_deref(), Some("refs/heads/feature"));
+        assert_eq!(locked.locked.as_deref(), Some(""));
+        assert_eq!(worktrees(&repo)?, [locked.clone(), wt.clone()]);
+
+        // Worktree handles see the shared state, but their own working directory
+        let wt_repo = git2::Repository::open(&wt.path)?;
+        assert_eq!(worktrees(&wt_repo)?, [locked, wt.clone()]);
+        assert!(crate::ops::CommitGraph::open(&wt_repo)?.is_some());
+        std::fs::write(wt.path.join("initial.txt"), "changed")?;
+        assert!(crate::ops::try_is_dirty(&wt_repo)?);
+        assert!(!crate::ops::try_is_dirty(&repo)?);
+
+        // Only worktrees whose working directory is gone are pruned by default
+        assert!(prune_worktree(&repo, "wt", PruneWorktreeOptions::new()).is_err());
+        std::fs::remove_dir_all(&wt.path)?;
+        assert!(worktrees(&repo)?[1].prunable);
+        prune_worktree(&repo, "wt", PruneWorktreeOptions::new())?;
+        assert!(prune_worktree(&repo, "locked", PruneWorktreeOptions::new()).is_err());
+        let force = PruneWorktreeOptions::new()
+            .valid(true)
+            .locked(true)
+            .working_tree(true);
+        prune_worktree(&repo, "locked", force)?;
+        assert_eq!(worktrees(&repo)?, []);
+        assert!(!git.repo_path.join("locked").exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+
+/// Local branches touched by a set of commits, see [`branches_containing`]
+///
+/// Branches are identified by their short name, alongside their tip.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AffectedBranches {
+    /// Branches whose tip is one of the commits
+    pub inside: Vec<(String, git2::Oid)>,
+    /// Branches whose tip descends from one of the commits, without being one of them
+    pub descendants: Vec<(String, git2::Oid)>,
+}
+
+impl AffectedBranches {
+    pub fn is_empty(&self) -> bool {
+        self.inside.is_empty() && self.descendants.is_empty()
+    }
+}
+
+/// Find the local branches that need to move when `commits` are rewritten
+///
+/// This takes one walk from the branch tips down to the parents of `commits`, rather than checking
+/// every branch against every commit.  Bounding that walk checks the parents' ancestry with a
+/// [`crate::ops::MergeBaseCache`], which uses the commit-graph when present.
+pub fn branches_containing(
+    repo: &git2::Repository,
+    commits: &[git2::Oid],
+) -> Result<AffectedBranches, git2::Error> {
+    let mut cache = crate::ops::MergeBaseCache::with_commit_graph(repo)?;
+    branches_containing_cached(repo, commits, &mut cache)
+}
+
+/// Like [`branches_containing`] but reusing ancestry answers in `cache`
+pub fn branches_containing_cached(
+    repo: &git2::Repository,
+    commits: &[git2::Oid],
+    cache: &mut crate::ops::MergeBaseCache,
+) -> Result<AffectedBranches, git2::Error> {
+    let commits = commits.iter().copied().collect::<HashSet<_>>();
+    let mut boundary = Vec::new();
+    for id in &commits {
+        let commit = repo.find_commit(*id)?;
+        for parent_id in commit.parent_ids() {
+            if !commits.contains(&parent_id) && !boundary.contains(&parent_id) {
+                boundary.push(parent_id);
+            }
+        }
+    }
+    // A parent may itself descend from one of the commits when they aren't a contiguous range,
+    // and hiding it would hide its descendants too
+    let mut hidden = Vec::with_capacity(boundary.len());
+    for parent_id in boundary {
+        let mut inner = false;
+        for id in &commits {
+            if cache.is_ancestor(repo, *id, parent_id)? {
+                inner = true;
+                break;
+            }
+        }
+        if !inner {
+            hidden.push(parent_id);
+        }
+    }
+    classify(repo, &commits, &hidden)
+}
+
+/// Find the local branches that need to move when the commits in `base..head` are rewritten
+///
+/// See [`branches_containing`].
+pub fn branches_pointing_into(
+    repo: &git2::Repository,
+    base: git2::Oid,
+    head: git2::Oid,
+) -> Result<AffectedBranches, git2::Error> {
+    let mut walk = repo.revwalk()?;
+    walk.push(head)?;
+    walk.hide(base)?;
+    let commits = walk.collect::<Result<HashSet<_>, _>>()?;
+    classify(repo, &commits, &[base])
+}
+
+/// Classify branches, walking only what isn't reachable from `hidden`
+///
+/// Nothing reachable from `hidden` may descend from `commits`.
+fn classify(
+    repo: &git2::Repository,
+    commits: &HashSet<git2::Oid>,
+    hidden: &[git2::Oid],
+) -> Result<AffectedBranches, git2::Error> {
+    let mut affected = AffectedBranches::default();
+    if commits.is_empty() {
+        return Ok(affected);
+    }
+
+    let mut branches = Vec::new();
+    for branch in repo.branches(Some(git2::BranchType::Local))? {
+        let (branch, _) = branch?;
+        let Some(tip) = branch.get().target() else {
+            continue;
+        };
+        let Some(name) = branch.name()? else {
+            log::trace!("Skipping branch with non-UTF-8 name pointing at {tip}");
+            continue;
+        };
+        branches.push((name.to_owned(), tip));
+    }
+
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    for (_, tip) in &branches {
+        walk.push(*tip)?;
+    }
+    for id in hidden {
+        walk.hide(*id)?;
+    }
+    // Parents come first, so a commit's ancestry is settled by the time it is seen
+    let mut descends = HashSet::new();
+    for id in walk {
+        let id = id?;
+        let commit = repo.find_commit(id)?;
+        if commits.contains(&id)
+            || commit
+                .parent_ids()
+                .any(|parent_id| descends.contains(&parent_id))
+        {
+            descends.insert(id);
+        }
+    }
+
+    for (name, tip) in branches {
+        if commits.contains(&tip) {
+            affected.inside.push((name, tip));
+        } else if descends.contains(&tip) {
+            affected.descendants.push((name, tip));
+        }
+    }
+    Ok(affected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_branches_containing() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let base = repo.refname_to_id("refs/heads/master")?;
+        let first = git.commit_file("first", 1)?;
+        git.run(&["branch", "mid"])?;
+        let second = git.commit_file("second", 2)?;
+        git.run(&["checkout", "-b", "child"])?;
+        let child = git.commit_file("child", 3)?;
+        git.run(&["checkout", "-b", "unrelated", &base.to_string()])?;
+        let unrelated = git.commit_file("unrelated", 4)?;
+
+        let expected = AffectedBranches {
+            inside: vec![("master".to_owned(), second), ("mid".to_owned(), first)],
+            descendants: vec![("child".to_owned(), child)],
+        };
+        assert_eq!(branches_containing(&repo, &[first, second])?, expected);
+        assert_eq!(branches_pointing_into(&repo, base, second)?, expected);
+
+        // `second`'s parent descends from `base`, so it must not bound the walk
+        let affected = branches_containing(&repo, &[base, second])?;
+        assert_eq!(
+            affected.inside,
+            [("master".to_owned(), second)],
+            "{affected:?}"
+        );
+        assert_eq!(
+            affected.descendants,
+            [
+                ("child".to_owned(), child),
+                ("mid".to_owned(), first),
+                ("unrelated".to_owned(), unrelated)
+            ]
+        );
+
+        assert!(branches_containing(&repo, &[unrelated])?
+            .descendants
+            .is_empty());
+        assert!(branches_containing(&repo, &[])?.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+/// What [`update_branch`] may do to an existing branch
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UpdateMode {
+    /// Only move an existing branch to a descendant of where it is, like `git merge --ff-only`
+    FfOnly,
+    /// Create the branch or move it anywhere, like `git branch --force`
+    Force,
+    /// Only create the branch, like `git branch`
+    New,
+}
+
+/// Point the local branch `name` at `new_id`, writing `reflog_msg` to its reflog
+///
+/// `name` may be short (`main`) or full (`refs/heads/main`).  The branch's position is checked
+/// against `mode` and then updated with a [`super::ReferenceTransaction`] expecting that
+/// position, so the update fails rather than clobbering the branch if something else moves it in
+/// the meantime.
+///
+/// When moving many branches, open the [`crate::ops::CommitGraph`] once and use
+/// [`update_branch_with`].
+pub fn update_branch(
+    repo: &git2::Repository,
+    name: &str,
+    new_id: git2::Oid,
+    mode: UpdateMode,
+    reflog_msg: &str,
+) -> Result<(), git2::Error> {
+    update_branch_with(repo, None, name, new_id, mode, reflog_msg)
+}
+
+/// [`update_branch`], checking fast-forwards with an already opened
+/// [`crate::ops::CommitGraph`]
+pub fn update_branch_with(
+    repo: &git2::Repository,
+    graph: Option<&crate::ops::CommitGraph>,
+    name: &str,
+    new_id: git2::Oid,
+    mode: UpdateMode,
+    reflog_msg: &str,
+) -> Result<(), git2::Error> {
+    let short_name = name.strip_prefix("refs/heads/").unwrap_or(name);
+    if !git2::Branch::name_is_valid(short_name)? {
+        return Err(git2::Error::new(
+            git2::ErrorCode::InvalidSpec,
+            git2::ErrorClass::Reference,
+            format!("`{short_name}` is not a valid branch name"),
+        ));
+    }
+    let refname = format!("refs/heads/{short_name}");
+    // Like `git`, branches only ever point at commits
+    repo.find_commit(new_id)?;
+
+    let old_id = match repo.refname_to_id(&refname) {
+        Ok(id) => Some(id),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => None,
+        Err(err) => return Err(err),
+    };
+    match (mode, old_id) {
+        (UpdateMode::New, Some(_)) => {
+            return Err(git2::Error::new(
+                git2::ErrorCode::Exists,
+                git2::ErrorClass::Reference,
+                format!("a branch named `{short_name}` already exists"),
+            ));
+        }
+        (UpdateMode::FfOnly, None) => {
+            return Err(git2::Error::new(
+                git2::ErrorCode::NotFound,
+                git2::ErrorClass::Reference,
+                format!("no branch named `{short_name}`"),
+            ));
+        }
+        (UpdateMode::FfOnly, Some(old_id))
+            if !crate::ops::is_ancestor_with(repo, graph, old_id, new_id)? =>
+        {
+            return Err(git2::Error::new(
+                git2::ErrorCode::NotFastForward,
+                git2::ErrorClass::Reference,
+                format!("cannot fast-forward `{short_name}` from {old_id} to {new_id}"),
+            ));
+        }
+        _ => {}
+    }
+
+    super::ReferenceTransaction::new(repo)
+        .update(
+            refname,
+            new_id,
+            Some(old_id.unwrap_or_else(git2::Oid::zero)),
+            reflog_msg,
+        )
+        .commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_update_branch() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let base = repo.refname_to_id("refs/heads/master")?;
+        let head = git.commit_file("file", 1)?;
+
+        update_branch(&repo, "topic", base, UpdateMode::New, "branch: Created")?;
+        let err = update_branch(&repo, "topic", head, UpdateMode::New, "").unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::Exists);
+
+        update_branch(&repo, "refs/heads/topic", head, UpdateMode::FfOnly, "ff")?;
+        assert_eq!(repo.refname_to_id("refs/heads/topic")?, head);
+        let err = update_branch(&repo, "topic", base, UpdateMode::FfOnly, "").unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::NotFastForward);
+        let err = update_branch(&repo, "missing", head, UpdateMode::FfOnly, "").unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::NotFound);
+
+        update_branch(&repo, "topic", base, UpdateMode::Force, "reset")?;
+        assert_eq!(repo.refname_to_id("refs/heads/topic")?, base);
+        let reflog = repo.reflog("refs/heads/topic")?;
+        let messages = reflog
+            .iter()
+            .map(|entry| entry.message().unwrap_or_default().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, ["reset", "ff", "branch: Created"]);
+
+        let err = update_branch(&repo, "bad name", base, UpdateMode::Force, "").unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::InvalidSpec);
+
+        git.run(&["commit-graph", "write", "--reachable"])?;
+        let graph = crate::ops::CommitGraph::open(&repo)?;
+        assert!(graph.is_some());
+        let err = update_branch_with(
+            &repo,
+            graph.as_ref(),
+            "master",
+            base,
+            UpdateMode::FfOnly,
+            "",
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::NotFastForward);
+        update_branch_with(
+            &repo,
+            graph.as_ref(),
+            "topic",
+            head,
+            UpdateMode::FfOnly,
+            "ff",
+        )?;
+        assert_eq!(repo.refname_to_id("refs/heads/topic")?, head);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+/// Point `refname` at `new`, but only if it is still at `expected_old`
+///
+/// This is a compare-and-swap, protecting against other tools moving the ref since it was read.
+/// `expected_old` of `None` expects the ref to not exist yet.  When the ref has moved, this fails
+/// with [`git2::ErrorCode::Modified`], the same "lease broken" error as
+/// [`crate::ops::check_force_with_lease`].
+pub fn cas_update(
+    repo: &git2::Repository,
+    refname: &str,
+    expected_old: Option<git2::Oid>,
+    new: git2::Oid,
+    reflog_msg: &str,
+) -> Result<(), git2::Error> {
+    let actual = match repo.refname_to_id(refname) {
+        Ok(id) => Some(id),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => None,
+        Err(err) => return Err(err),
+    };
+    check_lease(refname, actual, expected_old)?;
+    // The transaction checks again once the ref is locked, in case it moved since
+    super::ReferenceTransaction::new(repo)
+        .update(
+            refname,
+            new,
+            Some(expected_old.unwrap_or_else(git2::Oid::zero)),
+            reflog_msg,
+        )
+        .commit()
+}
+
+/// Fail with [`git2::ErrorCode::Modified`] unless `name` is at `expected`
+pub(crate) fn check_lease(
+    name: &str,
+    actual: Option<git2::Oid>,
+    expected: Option<git2::Oid>,
+) -> Result<(), git2::Error> {
+    if actual == expected {
+        return Ok(());
+    }
+    let describe = |id: Option<git2::Oid>| match id {
+        Some(id) => id.to_string(),
+        None => "nothing".to_owned(),
+    };
+    Err(git2::Error::new(
+        git2::ErrorCode::Modified,
+        git2::ErrorClass::Reference,
+        format!(
+            "stale info: `{name}` is at {} but {} was expected",
+            describe(actual),
+            describe(expected)
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_cas_update() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let base = repo.refname_to_id("refs/heads/master")?;
+        let head = git.commit_file("file", 1)?;
+
+        cas_update(&repo, "refs/heads/topic", None, base, "create")?;
+        let err = cas_update(&repo, "refs/heads/topic", None, head, "create").unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::Modified);
+
+        cas_update(&repo, "refs/heads/topic", Some(base), head, "move")?;
+        let err = cas_update(&repo, "refs/heads/topic", Some(base), base, "move").unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::Modified);
+        assert!(err.message().contains("stale info"), "{err}");
+        assert_eq!(repo.refname_to_id("refs/heads/topic")?, head);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+/// Point `HEAD` directly at `id`, like `git checkout --detach` without touching the worktree
+///
+/// Only `HEAD`'s reflog gets `reflog_msg`, the branch it was attached to is left alone.  This works
+/// from an unborn branch too.
+pub fn detach_head(
+    repo: &git2::Repository,
+    id: git2::Oid,
+    reflog_msg: &str,
+) -> Result<(), git2::Error> {
+    repo.find_commit(id)?;
+    repo.reference("HEAD", id, true, reflog_msg)?;
+    Ok(())
+}
+
+/// Point `HEAD` at the local branch `name`, like `git checkout` without touching the worktree
+///
+/// `name` may be short (`main`) or full (`refs/heads/main`).  When the branch doesn't exist,
+/// `HEAD` is left on it as an unborn branch, like `git checkout --orphan`.  `HEAD`'s reflog gets
+/// `reflog_msg` unless the branch is unborn, as `git` leaves no entry without a commit to record.
+pub fn attach_head(
+    repo: &git2::Repository,
+    name: &str,
+    reflog_msg: &str,
+) -> Result<(), git2::Error> {
+    let short_name = name.strip_prefix("refs/heads/").unwrap_or(name);
+    if !git2::Branch::name_is_valid(short_name)? {
+        return Err(git2::Error::new(
+            git2::ErrorCode::InvalidSpec,
+            git2::ErrorClass::Reference,
+            format!("`{short_name}` is not a valid branch name"),
+        ));
+    }
+    let refname = format!("refs/heads/{short_name}");
+    repo.reference_symbolic("HEAD", &refname, true, reflog_msg)?;
+    Ok(())
+}
+
+/// Switch `HEAD` to the local branch `name` without a checkout
+///
+/// This is for after rewriting history, when the branch's commit has the same tree as `HEAD`'s:
+/// the worktree and index are already right for it, including anything staged.  It fails, leaving
+/// `HEAD` alone, when the trees differ or either side has no commit.  `HEAD`'s reflog records the
+/// switch like `git checkout` does.
+pub fn point_head_at_branch(repo: &git2::Repository, name: &str) -> Result<(), git2::Error> {
+    let short_name = name.strip_prefix("refs/heads/").unwrap_or(name);
+    let branch = repo.find_branch(short_name, git2::BranchType::Local)?;
+    let branch_tree_id = branch.get().peel_to_commit()?.tree_id();
+    let head = repo.head()?;
+    let head_tree_id = head.peel_to_commit()?.tree_id();
+    if branch_tree_id != head_tree_id {
+        return Err(git2::Error::new(
+            git2::ErrorCode::Conflict,
+            git2::ErrorClass::Checkout,
+            format!("`{short_name}` has a different tree than `HEAD`, it needs a checkout"),
+        ));
+    }
+
+    let from = match head.shorthand() {
+        Some(from) if head.is_branch() => from.to_owned(),
+        _ => head
+            .target()
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| "HEAD".to_owned()),
+    };
+    attach_head(
+        repo,
+        short_name,
+        &format!("checkout: moving from {from} to {short_name}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    fn head_reflog(repo: &git2::Repository) -> eyre::Result<Vec<(git2::Oid, git2::Oid, String)>> {
+        let reflog = repo.reflog("HEAD")?;
+        Ok(reflog
+            .iter()
+            .map(|entry| {
+                (
+                    entry.id_old(),
+                    entry.id_new(),
+                    entry.message().unwrap_or_default().to_owned(),
+                )
+            })
+            .collect())
+    }
+
+    #[test]
+    fn test_detach_attach_head() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let base = repo.refname_to_id("refs/heads/master")?;
+        let head = git.commit_file("file", 1)?;
+        let before = head_reflog(&repo)?.len();
+
+        detach_head(&repo, base, "detach")?;
+        assert!(repo.head_detached()?);
+        assert_eq!(repo.head()?.target(), Some(base));
+        assert_eq!(repo.refname_to_id("refs/heads/master")?, head);
+
+        attach_head(&repo, "master", "attach")?;
+        assert_eq!(repo.head()?.name(), Some("refs/heads/master"));
+
+        let reflog = head_reflog(&repo)?;
+        assert_eq!(reflog.len(), before + 2, "{reflog:?}");
+        assert_eq!(reflog[0], (base, head, "attach".to_owned()));
+        assert_eq!(reflog[1], (head, base, "detach".to_owned()));
+
+        attach_head(&repo, "refs/heads/orphan", "orphan")?;
+        assert_eq!(
+            repo.find_reference("HEAD")?.symbolic_target(),
+            Some("refs/heads/orphan")
+        );
+        assert_eq!(head_reflog(&repo)?.len(), before + 2);
+        detach_head(&repo, base, "detach from unborn")?;
+        assert_eq!(repo.head()?.target(), Some(base));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_point_head_at_branch() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let base = repo.refname_to_id("refs/heads/master")?;
+        git.commit_file("file", 1)?;
+        // Same tree, different commit
+        git.run(&["checkout", "-b", "rewritten"])?;
+        git.run(&["commit", "--amend", "-m", "reworded"])?;
+        git.run(&["checkout", "master"])?;
+        git.run(&["branch", "old", &base.to_string()])?;
+        git.write_file("staged", "content")?;
+        git.run(&["add", "."])?;
+
+        let err = point_head_at_branch(&repo, "old").unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::Conflict);
+        assert_eq!(repo.head()?.name(), Some("refs/heads/master"));
+
+        point_head_at_branch(&repo, "rewritten")?;
+        assert_eq!(repo.head()?.name(), Some("refs/heads/rewritten"));
+        let status = repo.statuses(None)?;
+        let changes = status
+            .iter()
+            .map(|entry| (entry.path().unwrap_or_default().to_owned(), entry.status()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            [("staged.txt".to_owned(), git2::Status::INDEX_NEW)]
+        );
+        let reflog = repo.reflog("HEAD")?;
+        assert_eq!(
+            reflog
+                .get(0)
+                .and_then(|entry| entry.message().map(str::to_owned)),
+            Some("checkout: moving from master to rewritten".to_owned())
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,19 @@
+//! Reading and updating references the way `git` does
+
+mod affected;
+mod branch;
+mod cas;
+mod head;
+mod namespace;
+mod reflog;
+mod transaction;
+mod upstream;
+
+pub use affected::*;
+pub use branch::*;
+pub use cas::*;
+pub use head::*;
+pub use namespace::*;
+pub use reflog::*;
+pub use transaction::*;
+pub use upstream::*;
@@ -0,0 +1,225 @@
+/// A ref namespace, like `GIT_NAMESPACE`, for serving several repositories from one
+///
+/// Refs in the namespace `foo/bar` are stored under `refs/namespaces/foo/refs/namespaces/bar/`
+/// and appear without that prefix to clients.  See gitnamespaces(7).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Namespace {
+    name: String,
+    prefix: String,
+}
+
+impl Namespace {
+    /// Parse a namespace like `git` does `GIT_NAMESPACE`, with `/` separating nested namespaces
+    pub fn new(name: &str) -> Result<Self, git2::Error> {
+        let components = name
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .collect::<Vec<_>>();
+        let prefix = components
+            .iter()
+            .map(|component| format!("refs/namespaces/{component}/"))
+            .collect::<String>();
+        if components.is_empty() || !git2::Reference::is_valid_name(&format!("{prefix}HEAD")) {
+            return Err(git2::Error::new(
+                git2::ErrorCode::InvalidSpec,
+                git2::ErrorClass::Reference,
+                format!("`{name}` is not a valid namespace"),
+            ));
+        }
+        Ok(Self {
+            name: components.join("/"),
+            prefix,
+        })
+    }
+
+    /// The namespace from `GIT_NAMESPACE`, if set
+    pub fn from_env() -> Result<Option<Self>, git2::Error> {
+        match std::env::var("GIT_NAMESPACE") {
+            Ok(name) if !name.is_empty() => Self::new(&name).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// The namespace as given to `GIT_NAMESPACE`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// What the full names of refs in the namespace start with
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// The full name of `name` within the namespace, e.g. `refs/heads/main` or `HEAD`
+    pub fn qualify(&self, name: &str) -> String {
+        format!("{}{name}", self.prefix)
+    }
+
+    /// The name of `full_name` as seen within the namespace, `None` if it is outside of it
+    pub fn strip<'n>(&self, full_name: &'n str) -> Option<&'n str> {
+        full_name.strip_prefix(&self.prefix)
+    }
+}
+
+/// Refs hidden from clients by `transfer.hideRefs`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HiddenRefs {
+    patterns: Vec<String>,
+}
+
+impl HiddenRefs {
+    /// Read `transfer.hideRefs` and `<section>.hideRefs`, where `section` is `receive` or
+    /// `uploadpack`
+    pub fn from_config(config: &git2::Config, section: &str) -> Result<Self, git2::Error> {
+        let mut patterns = crate::config::get_all(config, "transfer.hideRefs")?;
+        patterns.extend(crate::config::get_all(
+            config,
+            &format!("{section}.hideRefs"),
+        )?);
+        let patterns = patterns
+            .into_iter()
+            .map(|pattern| pattern.trim_end_matches('/').to_owned())
+            .collect();
+        Ok(Self { patterns })
+    }
+
+    /// Whether the ref called `name` within a namespace, and `full_name` outside of it, is hidden
+    ///
+    /// Patterns are prefixes of `name`, or of `full_name` when starting with `^`, and are negated
+    /// by a leading `!`.  The last matching pattern wins.
+    pub fn is_hidden(&self, name: &str, full_name: &str) -> bool {
+        for pattern in self.patterns.iter().rev() {
+            let (negated, pattern) = match pattern.strip_prefix('!') {
+                Some(pattern) => (true, pattern),
+                None => (false, pattern.as_str()),
+            };
+            let (subject, pattern) = match pattern.strip_prefix('^') {
+                Some(pattern) => (full_name, pattern),
+                None => (name, pattern),
+            };
+            let matched = subject
+                .strip_prefix(pattern)
+                .map(|rest| rest.is_empty() || rest.starts_with('/'))
+                .unwrap_or(false);
+            if matched {
+                return !negated;
+            }
+        }
+        false
+    }
+}
+
+/// The refs a server would advertise to clients, by their name as the client sees them
+///
+/// Only refs within `namespace`, when given, are included, and those [`HiddenRefs`] hides are
+/// dropped.  Symbolic refs are reported by what they point to.
+pub fn advertised_refs(
+    repo: &git2::Repository,
+    namespace: Option<&Namespace>,
+    hidden: &HiddenRefs,
+) -> Result<Vec<(String, git2::Oid)>, git2::Error> {
+    let mut advertised = Vec::new();
+    for reference in repo.references()? {
+        let reference = reference?;
+        let Some(full_name) = reference.name() else {
+            continue;
+        };
+        let name = match namespace {
+            Some(namespace) => match namespace.strip(full_name) {
+                Some(name) => name,
+                None => continue,
+            },
+            None => full_name,
+        };
+        if hidden.is_hidden(name, full_name) {
+            continue;
+        }
+        let Some(id) = reference
+            .resolve()
+            .ok()
+            .and_then(|resolved| resolved.target())
+        else {
+            continue;
+        };
+        advertised.push((name.to_owned(), id));
+    }
+    advertised.sort();
+    Ok(advertised)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_namespace() -> eyre::Result<()> {
+        let namespace = Namespace::new("/foo//bar/")?;
+        assert_eq!(namespace.name(), "foo/bar");
+        assert_eq!(
+            namespace.qualify("refs/heads/main"),
+            "refs/namespaces/foo/refs/namespaces/bar/refs/heads/main"
+        );
+        assert_eq!(
+            namespace.strip("refs/namespaces/foo/refs/namespaces/bar/HEAD"),
+            Some("HEAD")
+        );
+        assert_eq!(namespace.strip("refs/namespaces/foo/HEAD"), None);
+        Namespace::new("").unwrap_err();
+        Namespace::new("a..b").unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_advertised_refs() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let head = repo.refname_to_id("refs/heads/master")?;
+        let namespace = Namespace::new("tenant")?;
+        crate::refs::ReferenceTransaction::new(&repo)
+            .namespace(&namespace)
+            .update("refs/heads/main", head, None, "push")
+            .update("refs/heads/wip/draft", head, None, "push")
+            .update("refs/pull/1/head", head, None, "push")
+            .commit()?;
+        assert_eq!(
+            repo.refname_to_id("refs/namespaces/tenant/refs/heads/main")?,
+            head
+        );
+
+        git.run(&["config", "transfer.hideRefs", "refs/pull"])?;
+        git.run(&["config", "--add", "uploadpack.hideRefs", "refs/heads/wip"])?;
+        git.run(&[
+            "config",
+            "--add",
+            "uploadpack.hideRefs",
+            "!refs/heads/wip/draft",
+        ])?;
+        git.run(&[
+            "config",
+            "--add",
+            "uploadpack.hideRefs",
+            "^refs/namespaces/tenant/refs/heads/main",
+        ])?;
+        let hidden = HiddenRefs::from_config(&repo.config()?.snapshot()?, "uploadpack")?;
+        assert_eq!(
+            advertised_refs(&repo, Some(&namespace), &hidden)?,
+            [("refs/heads/wip/draft".to_owned(), head)]
+        );
+        let hidden = HiddenRefs::from_config(&repo.config()?.snapshot()?, "receive")?;
+        assert_eq!(
+            advertised_refs(&repo, None, &hidden)?,
+            [
+                ("refs/heads/master".to_owned(), head),
+                (namespace.qualify("refs/heads/main"), head),
+                (namespace.qualify("refs/heads/wip/draft"), head),
+                (namespace.qualify("refs/pull/1/head"), head),
+            ]
+        );
+
+        Ok(())
+    }
+}
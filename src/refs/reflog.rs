@@ -0,0 +1,231 @@
+/// An entry in a ref's reflog
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReflogEntry {
+    /// What the ref pointed at before, zero when it was created
+    pub old_id: git2::Oid,
+    /// What the ref pointed at after
+    pub new_id: git2::Oid,
+    pub committer_name: String,
+    pub committer_email: String,
+    pub time: git2::Time,
+    pub message: String,
+}
+
+/// Read the reflog of `refname`, newest entry first
+///
+/// A ref without a reflog has no entries.
+pub fn reflog(repo: &git2::Repository, refname: &str) -> Result<Vec<ReflogEntry>, git2::Error> {
+    let reflog = repo.reflog(refname)?;
+    let entries = reflog
+        .iter()
+        .map(|entry| {
+            let committer = entry.committer();
+            ReflogEntry {
+                old_id: entry.id_old(),
+                new_id: entry.id_new(),
+                committer_name: String::from_utf8_lossy(committer.name_bytes()).into_owned(),
+                committer_email: String::from_utf8_lossy(committer.email_bytes()).into_owned(),
+                time: committer.when(),
+                message: entry
+                    .message_bytes()
+                    .map(|message| String::from_utf8_lossy(message).into_owned())
+                    .unwrap_or_default(),
+            }
+        })
+        .collect();
+    Ok(entries)
+}
+
+/// Where `refname` was `n` moves ago, like `<refname>@{<n>}`
+///
+/// `n` of `0` is the latest value recorded in the reflog.  `None` when the reflog doesn't go
+/// back that far.
+pub fn reflog_nth(
+    repo: &git2::Repository,
+    refname: &str,
+    n: usize,
+) -> Result<Option<git2::Oid>, git2::Error> {
+    let entries = reflog(repo, refname)?;
+    let id = match entries.get(n) {
+        Some(entry) => Some(entry.new_id),
+        // Like `git`, the oldest entry also records where the ref was before it
+        None if n == entries.len() => entries.last().map(|entry| entry.old_id),
+        None => None,
+    };
+    Ok(id.filter(|id| !id.is_zero()))
+}
+
+/// Where `refname` was at `time`, like `<refname>@{<date>}`
+///
+/// Dates come from [`crate::utils::parse_date`].  When the reflog starts after `time`, this is
+/// where the ref was before its oldest entry, as `git` does.  `None` when the ref didn't exist
+/// yet or there is no reflog.
+pub fn reflog_at(
+    repo: &git2::Repository,
+    refname: &str,
+    time: git2::Time,
+) -> Result<Option<git2::Oid>, git2::Error> {
+    let entries = reflog(repo, refname)?;
+    let id = match entries
+        .iter()
+        .find(|entry| entry.time.seconds() <= time.seconds())
+    {
+        Some(entry) => Some(entry.new_id),
+        None => entries.last().map(|entry| entry.old_id),
+    };
+    Ok(id.filter(|id| !id.is_zero()))
+}
+
+/// The pseudo-refs `git` commands leave in `$GIT_DIR` to record what they are doing
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PseudoRef {
+    /// Where `HEAD` was before a command that moves it drastically, like `git reset` or `git rebase`
+    OrigHead,
+    /// The commits being merged into `HEAD`
+    MergeHead,
+    /// The commit being cherry-picked
+    CherryPickHead,
+    /// The commit being reverted
+    RevertHead,
+}
+
+impl PseudoRef {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::OrigHead => "ORIG_HEAD",
+            Self::MergeHead => "MERGE_HEAD",
+            Self::CherryPickHead => "CHERRY_PICK_HEAD",
+            Self::RevertHead => "REVERT_HEAD",
+        }
+    }
+}
+
+/// Read the commits recorded in `pseudo_ref`, empty if it isn't set
+pub fn read_pseudo_ref(
+    repo: &git2::Repository,
+    pseudo_ref: PseudoRef,
+) -> Result<Vec<git2::Oid>, git2::Error> {
+    let path = repo.path().join(pseudo_ref.name());
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(os_error(&path, err)),
+    };
+    content
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(git2::Oid::from_str)
+        .collect()
+}
+
+/// Record `ids` in `pseudo_ref`
+///
+/// Only [`PseudoRef::MergeHead`] holds more than one commit, for octopus merges.
+pub fn write_pseudo_ref(
+    repo: &git2::Repository,
+    pseudo_ref: PseudoRef,
+    ids: &[git2::Oid],
+) -> Result<(), git2::Error> {
+    if ids.is_empty() || (ids.len() > 1 && pseudo_ref != PseudoRef::MergeHead) {
+        return Err(git2::Error::new(
+            git2::ErrorCode::Invalid,
+            git2::ErrorClass::Reference,
+            format!("`{}` can't record {} commits", pseudo_ref.name(), ids.len()),
+        ));
+    }
+    let content = ids.iter().map(|id| format!("{id}\n")).collect::<String>();
+    let path = repo.path().join(pseudo_ref.name());
+    std::fs::write(&path, content).map_err(|err| os_error(&path, err))
+}
+
+/// Remove `pseudo_ref`, if set
+pub fn clear_pseudo_ref(repo: &git2::Repository, pseudo_ref: PseudoRef) -> Result<(), git2::Error> {
+    let path = repo.path().join(pseudo_ref.name());
+    match std::fs::remove_file(&path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(os_error(&path, err)),
+    }
+}
+
+/// Point `ORIG_HEAD` at the current `HEAD`, as `git` does before rewriting it
+///
+/// Returns the recorded commit, `None` when `HEAD` is unborn and `ORIG_HEAD` is left alone.
+pub fn save_orig_head(repo: &git2::Repository) -> Result<Option<git2::Oid>, git2::Error> {
+    let head_id = match repo.head() {
+        Ok(head) => head.peel_to_commit()?.id(),
+        Err(err) if err.code() == git2::ErrorCode::UnbornBranch => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    write_pseudo_ref(repo, PseudoRef::OrigHead, &[head_id])?;
+    Ok(Some(head_id))
+}
+
+fn os_error(path: &std::path::Path, err: std::io::Error) -> git2::Error {
+    git2::Error::new(
+        git2::ErrorCode::GenericError,
+        git2::ErrorClass::Os,
+        format!("could not access `{}`: {}", path.display(), err),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_reflog() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let first = repo.refname_to_id("refs/heads/master")?;
+        let second = git.commit_file("second", 1)?;
+        let third = git.commit_file("third", 2)?;
+
+        let entries = reflog(&repo, "refs/heads/master")?;
+        assert_eq!(entries.len(), 3, "{entries:?}");
+        assert_eq!((entries[0].old_id, entries[0].new_id), (second, third));
+        assert!(entries[0].message.starts_with("commit: "), "{entries:?}");
+        assert!(entries[2].old_id.is_zero());
+
+        assert_eq!(reflog_nth(&repo, "refs/heads/master", 0)?, Some(third));
+        assert_eq!(reflog_nth(&repo, "refs/heads/master", 2)?, Some(first));
+        assert_eq!(reflog_nth(&repo, "refs/heads/master", 3)?, None);
+
+        let at = |seconds: i64| reflog_at(&repo, "refs/heads/master", git2::Time::new(seconds, 0));
+        assert_eq!(at(entries[1].time.seconds())?, Some(second));
+        assert_eq!(at(entries[0].time.seconds() + 1)?, Some(third));
+        assert_eq!(at(0)?, None);
+
+        assert!(reflog(&repo, "refs/heads/missing")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pseudo_ref() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let head = repo.refname_to_id("HEAD")?;
+        let other = git.commit_file("other", 1)?;
+
+        assert_eq!(save_orig_head(&repo)?, Some(other));
+        assert_eq!(repo.revparse_single("ORIG_HEAD")?.id(), other);
+
+        write_pseudo_ref(&repo, PseudoRef::MergeHead, &[head, other])?;
+        assert_eq!(read_pseudo_ref(&repo, PseudoRef::MergeHead)?, [head, other]);
+        write_pseudo_ref(&repo, PseudoRef::CherryPickHead, &[head, other]).unwrap_err();
+        write_pseudo_ref(&repo, PseudoRef::CherryPickHead, &[head])?;
+        assert_eq!(repo.state(), git2::RepositoryState::Merge);
+
+        clear_pseudo_ref(&repo, PseudoRef::MergeHead)?;
+        clear_pseudo_ref(&repo, PseudoRef::MergeHead)?;
+        assert!(read_pseudo_ref(&repo, PseudoRef::MergeHead)?.is_empty());
+        assert_eq!(repo.state(), git2::RepositoryState::CherryPick);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,357 @@
+/// Update several references at once, like `git update-ref --stdin`
+///
+/// Updates are queued and then applied all-or-nothing by [`ReferenceTransaction::commit`]: every
+/// reference is locked and checked against its expected value, the `reference-transaction` hook
+/// gets a chance to reject the updates, and only then are they written.  On failure, the locks
+/// are released with nothing changed.
+///
+/// An expected old value of `None` updates the reference regardless of its current value, while
+/// [`git2::Oid::zero`] requires that it doesn't exist yet.
+///
+/// ```no_run
+/// let repo = git2::Repository::open(".").unwrap();
+/// let hooks = git2_ext::hooks::Hooks::with_repo(&repo).unwrap();
+/// let main = repo.refname_to_id("refs/heads/main").unwrap();
+/// let topic = repo.refname_to_id("refs/heads/topic").unwrap();
+/// git2_ext::refs::ReferenceTransaction::new(&repo)
+///     .hooks(&hooks)
+///     .update("refs/heads/main", topic, Some(main), "merge topic: Fast-forward")
+///     .delete("refs/heads/topic", Some(topic))
+///     .commit()
+///     .unwrap();
+/// ```
+pub struct ReferenceTransaction<'r> {
+    repo: &'r git2::Repository,
+    hooks: Option<&'r crate::hooks::Hooks>,
+    namespace: Option<&'r super::Namespace>,
+    updates: Vec<RefUpdate>,
+}
+
+/// One reference change, see [`update_many`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RefUpdate {
+    /// The full name of the reference
+    pub name: String,
+    /// What the reference must point at, see [`ReferenceTransaction`]
+    pub expected_old: Option<git2::Oid>,
+    /// What to point the reference at, `None` to delete it
+    pub new: Option<git2::Oid>,
+    /// The reflog message, unused when deleting
+    pub reflog_msg: String,
+}
+
+impl<'r> ReferenceTransaction<'r> {
+    pub fn new(repo: &'r git2::Repository) -> Self {
+        Self {
+            repo,
+            hooks: None,
+            namespace: None,
+            updates: Vec::new(),
+        }
+    }
+
+    /// Run the `reference-transaction` hook from `hooks`
+    pub fn hooks(mut self, hooks: &'r crate::hooks::Hooks) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    /// Treat ref names as being within `namespace`
+    ///
+    /// The `reference-transaction` hook sees the full names.
+    pub fn namespace(mut self, namespace: &'r super::Namespace) -> Self {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    /// Point the reference `name` at `new`, creating it if needed
+    pub fn update(
+        mut self,
+        name: impl Into<String>,
+        new: git2::Oid,
+        expected_old: Option<git2::Oid>,
+        reflog_msg: impl Into<String>,
+    ) -> Self {
+        self.updates.push(RefUpdate {
+            name: name.into(),
+            expected_old,
+            new: Some(new),
+            reflog_msg: reflog_msg.into(),
+        });
+        self
+    }
+
+    /// Delete the reference `name`, along with its reflog
+    ///
+    /// [`ReferenceTransaction::commit`] fails if the reference doesn't exist.
+    pub fn delete(mut self, name: impl Into<String>, expected_old: Option<git2::Oid>) -> Self {
+        self.updates.push(RefUpdate {
+            name: name.into(),
+            expected_old,
+            new: None,
+            reflog_msg: String::new(),
+        });
+        self
+    }
+
+    /// Queue `update`
+    pub fn push(mut self, update: RefUpdate) -> Self {
+        self.updates.push(update);
+        self
+    }
+
+    /// Apply all of the queued updates, or none of them
+    ///
+    /// The `aborted` state of the hook is run if it was `prepare`d but the updates couldn't be
+    /// applied.
+    pub fn commit(mut self) -> Result<(), git2::Error> {
+        if self.updates.is_empty() {
+            return Ok(());
+        }
+        if let Some(namespace) = self.namespace {
+            for update in &mut self.updates {
+                update.name = namespace.qualify(&update.name);
+            }
+        }
+
+        let mut transaction = self.repo.transaction()?;
+        let mut changed_refs = Vec::with_capacity(self.updates.len());
+        for (i, update) in self.updates.iter().enumerate() {
+            if self.updates[..i]
+                .iter()
+                .any(|other| other.name == update.name)
+            {
+                return Err(git2::Error::new(
+                    git2::ErrorCode::Invalid,
+                    git2::ErrorClass::Reference,
+                    format!("multiple updates for ref `{}` not allowed", update.name),
+                ));
+            }
+            transaction.lock_ref(&update.name)?;
+            let current = match self.repo.refname_to_id(&update.name) {
+                Ok(id) => Some(id),
+                Err(err) if err.code() == git2::ErrorCode::NotFound => None,
+                Err(err) => return Err(err),
+            };
+            verify(update, current)?;
+            let old = update.expected_old.unwrap_or_else(git2::Oid::zero);
+            let new = update.new.unwrap_or_else(git2::Oid::zero);
+            changed_refs.push((old, new, update.name.as_str()));
+        }
+
+        if let Some(hooks) = self.hooks {
+            if let Err(err) = hooks.run_reference_transaction_prepare(self.repo, &changed_refs) {
+                hooks.run_reference_transaction_aborted(self.repo, &changed_refs);
+                return Err(git2::Error::new(
+                    git2::ErrorCode::User,
+                    git2::ErrorClass::Reference,
+                    format!("reference transaction rejected: {err}"),
+                ));
+            }
+        }
+
+        let result = self
+            .updates
+            .iter()
+            .try_for_each(|update| match update.new {
+                Some(new) => transaction.set_target(&update.name, new, None, &update.reflog_msg),
+                None => transaction.remove(&update.name),
+            })
+            .and_then(|()| transaction.commit());
+        if result.is_ok() {
+            // The refs are gone already, so a leftover reflog isn't worth failing over
+            for update in self.updates.iter().filter(|update| update.new.is_none()) {
+                if let Err(err) = self.repo.reflog_delete(&update.name) {
+                    log::debug!("Failed to delete reflog of `{}`: {err}", update.name);
+                }
+            }
+        }
+        if let Some(hooks) = self.hooks {
+            match result {
+                Ok(()) => hooks.run_reference_transaction_committed(self.repo, &changed_refs),
+                Err(_) => hooks.run_reference_transaction_aborted(self.repo, &changed_refs),
+            }
+        }
+        result
+    }
+}
+
+/// Apply all of `updates` or none of them, like `git update-ref --stdin`
+///
+/// This is a [`ReferenceTransaction`] running the `reference-transaction` hook from
+/// [`crate::hooks::Hooks::with_repo`].
+pub fn update_many(repo: &git2::Repository, updates: &[RefUpdate]) -> Result<(), git2::Error> {
+    let hooks = crate::hooks::Hooks::with_repo(repo)?;
+    updates
+        .iter()
+        .cloned()
+        .fold(
+            ReferenceTransaction::new(repo).hooks(&hooks),
+            ReferenceTransaction::push,
+        )
+        .commit()
+}
+
+fn verify(update: &RefUpdate, current: Option<git2::Oid>) -> Result<(), git2::Error> {
+    let mismatch = match (update.expected_old, current) {
+        (None, None) if update.new.is_none() => "it does not exist".to_owned(),
+        (None, _) => return Ok(()),
+        (Some(expected), None) if expected.is_zero() => return Ok(()),
+        (Some(expected), Some(current)) if expected == current => return Ok(()),
+        (Some(expected), None) => format!("it does not exist, expected {expected}"),
+        (Some(expected), Some(current)) if expected.is_zero() => {
+            format!("it already exists at {current}")
+        }
+        (Some(expected), Some(current)) => format!("it is at {current}, expected {expected}"),
+    };
+    Err(git2::Error::new(
+        git2::ErrorCode::Modified,
+        git2::ErrorClass::Reference,
+        format!("cannot lock ref `{}`: {mismatch}", update.name),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_transaction() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let base = repo.refname_to_id("refs/heads/master")?;
+        let head = git.commit_file("file", 1)?;
+
+        ReferenceTransaction::new(&repo)
+            .update("refs/heads/topic", base, Some(git2::Oid::zero()), "create")
+            .update("refs/heads/master", base, Some(head), "reset")
+            .commit()?;
+        assert_eq!(repo.refname_to_id("refs/heads/topic")?, base);
+        assert_eq!(repo.refname_to_id("refs/heads/master")?, base);
+        let reflog = repo.reflog("refs/heads/master")?;
+        assert_eq!(
+            reflog
+                .get(0)
+                .and_then(|entry| entry.message().map(str::to_owned))
+                .as_deref(),
+            Some("reset")
+        );
+
+        // A stale expectation leaves every ref alone
+        let err = ReferenceTransaction::new(&repo)
+            .update("refs/heads/master", head, Some(base), "restore")
+            .delete("refs/heads/topic", Some(head))
+            .commit()
+            .unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::Modified);
+        assert_eq!(repo.refname_to_id("refs/heads/master")?, base);
+        assert_eq!(repo.refname_to_id("refs/heads/topic")?, base);
+
+        assert!(repo.path().join("logs/refs/heads/topic").exists());
+        ReferenceTransaction::new(&repo)
+            .delete("refs/heads/topic", Some(base))
+            .commit()?;
+        assert!(repo.find_reference("refs/heads/topic").is_err());
+        assert!(!repo.path().join("logs/refs/heads/topic").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_many() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let base = repo.refname_to_id("refs/heads/master")?;
+        let head = git.commit_file("file", 1)?;
+        git.run(&["branch", "topic", &base.to_string()])?;
+
+        let update = |name: &str, expected_old, new| RefUpdate {
+            name: name.to_owned(),
+            expected_old,
+            new,
+            reflog_msg: "restack".to_owned(),
+        };
+        update_many(
+            &repo,
+            &[
+                update("refs/heads/topic", Some(base), Some(head)),
+                update("refs/heads/master", Some(head), None),
+                update("refs/heads/new", Some(git2::Oid::zero()), Some(base)),
+            ],
+        )?;
+        assert_eq!(repo.refname_to_id("refs/heads/topic")?, head);
+        assert!(repo.find_reference("refs/heads/master").is_err());
+        assert_eq!(repo.refname_to_id("refs/heads/new")?, base);
+
+        let err = update_many(
+            &repo,
+            &[
+                update("refs/heads/topic", Some(head), Some(base)),
+                update("refs/heads/topic", Some(head), Some(base)),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::Invalid);
+        assert_eq!(repo.refname_to_id("refs/heads/topic")?, head);
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_transaction_hook() -> eyre::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let head = repo.refname_to_id("refs/heads/master")?;
+        let hook_path = repo.path().join("hooks").join("reference-transaction");
+        std::fs::create_dir_all(hook_path.parent().unwrap())?;
+        std::fs::write(
+            &hook_path,
+            "#!/bin/sh\n\
+             echo \"$1\" >> states\n\
+             cat >> states\n\
+             test \"$1\" != prepare || ! test -e reject\n",
+        )?;
+        std::fs::set_permissions(&hook_path, std::fs::Permissions::from_mode(0o755))?;
+        let hooks = crate::hooks::Hooks::with_repo(&repo)?;
+        let states_path = git.repo_path.join("states");
+
+        ReferenceTransaction::new(&repo)
+            .hooks(&hooks)
+            .update("refs/heads/topic", head, None, "create")
+            .commit()?;
+        let zero = git2::Oid::zero();
+        assert_eq!(
+            std::fs::read_to_string(&states_path)?,
+            format!(
+                "prepare\n{zero} {head} refs/heads/topic\n\
+                 committed\n{zero} {head} refs/heads/topic\n"
+            )
+        );
+
+        std::fs::remove_file(&states_path)?;
+        std::fs::write(git.repo_path.join("reject"), "")?;
+        ReferenceTransaction::new(&repo)
+            .hooks(&hooks)
+            .delete("refs/heads/topic", Some(head))
+            .commit()
+            .unwrap_err();
+        assert_eq!(
+            std::fs::read_to_string(&states_path)?,
+            format!(
+                "prepare\n{head} {zero} refs/heads/topic\n\
+                 aborted\n{head} {zero} refs/heads/topic\n"
+            )
+        );
+        assert_eq!(repo.refname_to_id("refs/heads/topic")?, head);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,269 @@
+/// A branch's upstream, from `branch.<name>.remote` and `branch.<name>.merge`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Upstream {
+    /// The remote, or `.` for a local branch
+    pub remote: String,
+    /// The full name of the upstream branch on `remote`
+    pub merge: String,
+    /// The local ref following `merge`, per the remote's fetch refspecs
+    pub tracking: Option<String>,
+}
+
+/// Look up the upstream of the local branch `name`, if it has one
+///
+/// `name` may be short (`main`) or full (`refs/heads/main`).
+pub fn upstream(repo: &git2::Repository, name: &str) -> Result<Option<Upstream>, git2::Error> {
+    let short_name = name.strip_prefix("refs/heads/").unwrap_or(name);
+    let config = repo.config()?;
+    let remote = crate::config::get_string(&config, &format!("branch.{short_name}.remote"))?;
+    let merge = crate::config::get_string(&config, &format!("branch.{short_name}.merge"))?;
+    let (Some(remote), Some(merge)) = (remote, merge) else {
+        return Ok(None);
+    };
+
+    let tracking = if remote == "." {
+        Some(merge.clone())
+    } else {
+        match repo.find_remote(&remote) {
+            Ok(found) => found
+                .refspecs()
+                .filter(|refspec| refspec.direction() == git2::Direction::Fetch)
+                .filter(|refspec| refspec.src_matches(&merge))
+                .filter_map(|refspec| refspec.transform(&merge).ok())
+                .find_map(|tracking| tracking.as_str().map(ToOwned::to_owned)),
+            Err(err) if err.code() == git2::ErrorCode::NotFound => None,
+            Err(err) => return Err(err),
+        }
+    };
+    Ok(Some(Upstream {
+        remote,
+        merge,
+        tracking,
+    }))
+}
+
+/// Set the upstream of the local branch `name`, like `git branch --set-upstream-to`
+///
+/// `upstream` is a remote-tracking branch (`origin/main`) or a local one, `None` to unset it.
+pub fn set_upstream(
+    repo: &git2::Repository,
+    name: &str,
+    upstream: Option<&str>,
+) -> Result<(), git2::Error> {
+    let short_name = name.strip_prefix("refs/heads/").unwrap_or(name);
+    let mut branch = repo.find_branch(short_name, git2::BranchType::Local)?;
+    branch.set_upstream(upstream)
+}
+
+/// Where `git push` would send a branch, see [`push_target`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PushTarget {
+    /// The remote's name
+    pub remote: String,
+    /// The refspec pushed, `:` for `push.default=matching`
+    pub refspec: String,
+}
+
+/// Resolve where `git push` would send the local branch `name` when it is checked out
+///
+/// The remote is `branch.<name>.pushRemote`, `remote.pushDefault`, `branch.<name>.remote`, or
+/// `origin`, in that order.  The remote's `push` refspecs take precedence over `push.default`.
+///
+/// Unlike `git`, `push.default=simple` pushes a branch without an upstream to the same name, as
+/// [`crate::ops::push`] does.
+pub fn push_target(repo: &git2::Repository, name: &str) -> Result<PushTarget, git2::Error> {
+    let short_name = name.strip_prefix("refs/heads/").unwrap_or(name);
+    let refname = format!("refs/heads/{short_name}");
+    let config = repo.config()?;
+    let remote =
+        match crate::config::get_string(&config, &format!("branch.{short_name}.pushRemote"))? {
+            Some(remote) => remote,
+            None => match crate::config::get_string(&config, "remote.pushDefault")? {
+                Some(remote) => remote,
+                None => crate::config::get_string(&config, &format!("branch.{short_name}.remote"))?
+                    .unwrap_or_else(|| "origin".to_owned()),
+            },
+        };
+
+    let configured = match repo.find_remote(&remote) {
+        Ok(found) => {
+            if found.push_refspecs()?.is_empty() {
+                None
+            } else {
+                let refspec = found
+                    .refspecs()
+                    .filter(|refspec| refspec.direction() == git2::Direction::Push)
+                    .filter(|refspec| refspec.src_matches(&refname))
+                    .find_map(|refspec| {
+                        let dst = refspec.transform(&refname).ok()?;
+                        let force = if refspec.is_force() { "+" } else { "" };
+                        Some(format!("{force}{refname}:{}", dst.as_str()?))
+                    })
+                    .ok_or_else(|| {
+                        push_default_error(&format!(
+                            "no `remote.{remote}.push` refspec matches `{short_name}`"
+                        ))
+                    })?;
+                Some(refspec)
+            }
+        }
+        Err(err) if err.code() == git2::ErrorCode::NotFound => None,
+        Err(err) => return Err(err),
+    };
+    let refspec = match configured {
+        Some(refspec) => refspec,
+        None => push_default_refspec(&config, Some(short_name), Some(&remote))?,
+    };
+    Ok(PushTarget { remote, refspec })
+}
+
+/// The refspec `push.default` gives for pushing `branch` to `remote`
+///
+/// `branch` is the short name of the checked out branch, `None` when `HEAD` is detached.
+pub(crate) fn push_default_refspec(
+    config: &git2::Config,
+    branch: Option<&str>,
+    remote: Option<&str>,
+) -> Result<String, git2::Error> {
+    let mode =
+        crate::config::get_string(config, "push.default")?.unwrap_or_else(|| "simple".to_owned());
+    if mode == "matching" {
+        return Ok(":".to_owned());
+    }
+    if mode == "nothing" {
+        return Err(push_default_error(
+            "`push.default` is `nothing` and no refspecs were given",
+        ));
+    }
+
+    let Some(branch) = branch else {
+        return Err(push_default_error("HEAD is detached"));
+    };
+    let head_name = format!("refs/heads/{branch}");
+    let upstream_remote = crate::config::get_string(config, &format!("branch.{branch}.remote"))?;
+    let upstream_merge = crate::config::get_string(config, &format!("branch.{branch}.merge"))?;
+    let is_upstream_remote = upstream_remote.is_some() && upstream_remote.as_deref() == remote;
+
+    match mode.as_str() {
+        "current" => Ok(format!("{head_name}:{head_name}")),
+        "upstream" | "tracking" => match (is_upstream_remote, upstream_merge) {
+            (true, Some(merge)) => Ok(format!("{head_name}:{merge}")),
+            _ => Err(push_default_error(&format!(
+                "`{branch}` has no upstream branch on this remote"
+            ))),
+        },
+        "simple" => {
+            if is_upstream_remote {
+                if let Some(merge) = upstream_merge {
+                    if merge != head_name {
+                        return Err(push_default_error(&format!(
+                            "the upstream of `{branch}` (`{merge}`) has a different name"
+                        )));
+                    }
+                }
+            }
+            Ok(format!("{head_name}:{head_name}"))
+        }
+        _ => Err(push_default_error(&format!(
+            "unsupported `push.default` of `{mode}`"
+        ))),
+    }
+}
+
+fn push_default_error(reason: &str) -> git2::Error {
+    git2::Error::new(
+        git2::ErrorCode::Invalid,
+        git2::ErrorClass::Config,
+        format!("nothing to push: {reason}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_upstream() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        git.run(&["branch", "topic"])?;
+        git.run(&["update-ref", "refs/remotes/origin/master", "master"])?;
+        git.run(&["remote", "add", "origin", "https://example.com/repo.git"])?;
+
+        assert_eq!(upstream(&repo, "master")?, None);
+        set_upstream(&repo, "master", Some("origin/master"))?;
+        assert_eq!(
+            upstream(&repo, "refs/heads/master")?,
+            Some(Upstream {
+                remote: "origin".to_owned(),
+                merge: "refs/heads/master".to_owned(),
+                tracking: Some("refs/remotes/origin/master".to_owned()),
+            })
+        );
+        set_upstream(&repo, "topic", Some("master"))?;
+        assert_eq!(
+            upstream(&repo, "topic")?.and_then(|upstream| upstream.tracking),
+            Some("refs/heads/master".to_owned())
+        );
+        set_upstream(&repo, "topic", None)?;
+        assert_eq!(upstream(&repo, "topic")?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_target() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        git.run(&["remote", "add", "origin", "https://example.com/origin.git"])?;
+        git.run(&["remote", "add", "fork", "https://example.com/fork.git"])?;
+        git.run(&["config", "branch.master.remote", "origin"])?;
+        git.run(&["config", "branch.master.merge", "refs/heads/main"])?;
+
+        let target = |name: &str| -> Result<(String, String), git2::Error> {
+            let target = push_target(&repo, name)?;
+            Ok((target.remote, target.refspec))
+        };
+        let err = target("master").unwrap_err();
+        assert!(err.message().contains("different name"), "{err}");
+        git.run(&["config", "push.default", "upstream"])?;
+        assert_eq!(
+            target("master")?,
+            (
+                "origin".to_owned(),
+                "refs/heads/master:refs/heads/main".to_owned()
+            )
+        );
+
+        // A triangular workflow can't push to the upstream
+        git.run(&["config", "remote.pushDefault", "fork"])?;
+        target("master").unwrap_err();
+        git.run(&["config", "push.default", "current"])?;
+        assert_eq!(
+            target("master")?,
+            (
+                "fork".to_owned(),
+                "refs/heads/master:refs/heads/master".to_owned()
+            )
+        );
+        git.run(&["config", "branch.master.pushRemote", "origin"])?;
+        git.run(&[
+            "config",
+            "remote.origin.push",
+            "+refs/heads/*:refs/heads/user/*",
+        ])?;
+        assert_eq!(
+            target("master")?,
+            (
+                "origin".to_owned(),
+                "+refs/heads/master:refs/heads/user/master".to_owned()
+            )
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+//! Serialize [`git2::Oid`]s as hex strings, for `#[serde(with = "crate::serde_oid")]`
+
+pub(crate) fn serialize<S: serde::Serializer>(
+    id: &git2::Oid,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(id)
+}
+
+pub(crate) fn deserialize<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<git2::Oid, D::Error> {
+    let hex = <String as serde::Deserialize>::deserialize(deserializer)?;
+    git2::Oid::from_str(&hex).map_err(serde::de::Error::custom)
+}
+
+/// For `Option<git2::Oid>`, with `None` as a missing value
+pub(crate) mod option {
+    pub(crate) fn serialize<S: serde::Serializer>(
+        id: &Option<git2::Oid>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match id {
+            Some(id) => serializer.collect_str(id),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<git2::Oid>, D::Error> {
+        let hex = <Option<String> as serde::Deserialize>::deserialize(deserializer)?;
+        hex.map(|hex| git2::Oid::from_str(&hex).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::de::value::Error;
+    use serde::de::IntoDeserializer as _;
+
+    #[test]
+    fn test_deserialize() {
+        let hex = "0123456789abcdef0123456789abcdef01234567";
+        let id = deserialize(hex.into_deserializer()).map_err(|err: Error| err);
+        assert_eq!(id.unwrap(), git2::Oid::from_str(hex).unwrap());
+        let err = deserialize("not hex".into_deserializer()).map_err(|err: Error| err);
+        assert!(err.is_err());
+    }
+}
@@ -1,7 +1,8 @@
 //! Testing utilities.
 //!
 //! This is inside `src` rather than `tests` since we use this code in some unit
-//! tests.
+//! tests.  Enable the `testing` feature to use it in your own tests, like
+//! `git2-ext = { version = "*", features = ["testing"] }` under `[dev-dependencies]`.
 
 use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsString;
@@ -21,23 +22,23 @@ const DUMMY_DATE: &str = "Wed 29 Oct 12:34:56 2020 PDT";
 
 /// Wrapper around the Git executable, for testing.
 #[derive(Clone, Debug)]
-pub(crate) struct Git {
+pub struct Git {
     /// The path to the repository on disk. The directory itself must exist,
     /// although it might not have a `.git` folder in it. (Use `Git::init_repo`
     /// to initialize it.)
-    pub(crate) repo_path: PathBuf,
+    pub repo_path: PathBuf,
 
     /// The path to the Git executable on disk. This is important since we test
     /// against multiple Git versions.
-    pub(crate) path_to_git: PathBuf,
+    pub path_to_git: PathBuf,
 }
 
 /// Options for `Git::init_repo_with_options`.
 #[derive(Debug)]
-pub(crate) struct GitInitOptions {
+pub struct GitInitOptions {
     /// If `true`, then `init_repo_with_options` makes an initial commit with
     /// some content.
-    pub(crate) make_initial_commit: bool,
+    pub make_initial_commit: bool,
 }
 
 impl Default for GitInitOptions {
@@ -50,12 +51,12 @@ impl Default for GitInitOptions {
 
 /// Path to the `git` executable on disk to be executed.
 #[derive(Clone)]
-pub(crate) struct GitRunInfo {
+pub struct GitRunInfo {
     /// The path to the Git executable on disk.
-    pub(crate) path_to_git: PathBuf,
+    pub path_to_git: PathBuf,
 
     /// The working directory that the Git executable should be run in.
-    pub(crate) working_directory: PathBuf,
+    pub working_directory: PathBuf,
 }
 
 impl std::fmt::Debug for GitRunInfo {
@@ -70,24 +71,24 @@ impl std::fmt::Debug for GitRunInfo {
 
 /// Options for `Git::run_with_options`.
 #[derive(Debug, Default)]
-pub(crate) struct GitRunOptions {
+pub struct GitRunOptions {
     /// The timestamp of the command. Mostly useful for `git commit`. This should
     /// be a number like 0, 1, 2, 3...
-    pub(crate) time: isize,
+    pub time: isize,
 
     /// The exit code that `Git` should return.
-    pub(crate) expected_exit_code: i32,
+    pub expected_exit_code: i32,
 
     /// The input to write to the child process's stdin.
-    pub(crate) input: Option<String>,
+    pub input: Option<String>,
 
     /// Additional environment variables to start the process with.
-    pub(crate) env: HashMap<String, String>,
+    pub env: HashMap<String, String>,
 }
 
 /// The parsed version of Git.
 #[derive(Debug, PartialEq, PartialOrd, Eq)]
-pub(crate) struct GitVersion(pub(crate) isize, pub(crate) isize, pub(crate) isize);
+pub struct GitVersion(pub isize, pub isize, pub isize);
 
 impl std::str::FromStr for GitVersion {
     type Err = eyre::Error;
@@ -116,7 +117,7 @@ impl std::str::FromStr for GitVersion {
 
 impl Git {
     /// Constructor.
-    pub(crate) fn new(git_run_info: GitRunInfo, repo_path: PathBuf) -> Self {
+    pub fn new(git_run_info: GitRunInfo, repo_path: PathBuf) -> Self {
         let GitRunInfo {
             path_to_git,
             // We pass the repo directory when calling `run`.
@@ -129,7 +130,7 @@ impl Git {
     }
 
     /// Replace dynamic strings in the output, for testing purposes.
-    pub(crate) fn preprocess_output(&self, stdout: String) -> eyre::Result<String> {
+    pub fn preprocess_output(&self, stdout: String) -> eyre::Result<String> {
         let path_to_git = self
             .path_to_git
             .to_str()
@@ -166,7 +167,7 @@ impl Git {
     }
 
     /// Get the environment variables needed to run git in the test environment.
-    pub(crate) fn get_base_env(&self, time: isize) -> Vec<(OsString, OsString)> {
+    pub fn get_base_env(&self, time: isize) -> Vec<(OsString, OsString)> {
         // Required for determinism, as these values will be baked into the commit
         // hash.
         let date: OsString = format!("{DUMMY_DATE} -{time:0>2}").into();
@@ -279,7 +280,7 @@ stderr:
     }
 
     /// Run a Git command.
-    pub(crate) fn run_with_options<S: AsRef<str> + std::fmt::Debug>(
+    pub fn run_with_options<S: AsRef<str> + std::fmt::Debug>(
         &self,
         args: &[S],
         options: &GitRunOptions,
@@ -291,7 +292,7 @@ stderr:
     }
 
     /// Run a Git command.
-    pub(crate) fn run<S: AsRef<str> + std::fmt::Debug>(
+    pub fn run<S: AsRef<str> + std::fmt::Debug>(
         &self,
         args: &[S],
     ) -> eyre::Result<(String, String)> {
@@ -300,7 +301,7 @@ stderr:
 
     /// Set up a Git repo in the directory and initialize git to work
     /// with it.
-    pub(crate) fn init_repo_with_options(&self, options: &GitInitOptions) -> eyre::Result<()> {
+    pub fn init_repo_with_options(&self, options: &GitInitOptions) -> eyre::Result<()> {
         self.run(&["init"])?;
         self.run(&["config", "user.name", DUMMY_NAME])?;
         self.run(&["config", "user.email", DUMMY_EMAIL])?;
@@ -322,12 +323,12 @@ stderr:
 
     /// Set up a Git repo in the directory and initialize git to work
     /// with it.
-    pub(crate) fn init_repo(&self) -> eyre::Result<()> {
+    pub fn init_repo(&self) -> eyre::Result<()> {
         self.init_repo_with_options(&Default::default())
     }
 
     /// Write the provided contents to the provided file in the repository root.
-    pub(crate) fn write_file(&self, name: &str, contents: &str) -> eyre::Result<()> {
+    pub fn write_file(&self, name: &str, contents: &str) -> eyre::Result<()> {
         let path = PathBuf::from(name);
         if let Some(dir) = path.parent() {
             std::fs::create_dir_all(self.repo_path.join(dir))?;
@@ -339,7 +340,7 @@ stderr:
 
     /// Commit a file with default contents. The `time` argument is used to set
     /// the commit timestamp, which is factored into the commit hash.
-    pub(crate) fn commit_file_with_contents(
+    pub fn commit_file_with_contents(
         &self,
         name: &str,
         time: isize,
@@ -365,19 +366,216 @@ stderr:
 
     /// Commit a file with default contents. The `time` argument is used to set
     /// the commit timestamp, which is factored into the commit hash.
-    pub(crate) fn commit_file(&self, name: &str, time: isize) -> eyre::Result<git2::Oid> {
+    pub fn commit_file(&self, name: &str, time: isize) -> eyre::Result<git2::Oid> {
         self.commit_file_with_contents(name, time, &format!("{name} contents\n"))
     }
 
+    /// Get the version of the Git executable.
+    pub fn get_version(&self) -> eyre::Result<GitVersion> {
+        let (version_str, _stderr) = self.run(&["version"])?;
+        version_str.parse()
+    }
+
     /// Get a `Repo` object for this repository.
-    pub(crate) fn get_repo(&self) -> eyre::Result<git2::Repository> {
+    pub fn get_repo(&self) -> eyre::Result<git2::Repository> {
         let repo = git2::Repository::open(&self.repo_path)?;
         Ok(repo)
     }
 }
 
+/// Build a graph of commits directly through `git2`, without running `git`.
+///
+/// Commits are named by their message and get deterministic authors and times, so their
+/// IDs are the same from run to run:
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let dir = tempfile::tempdir()?;
+/// # let repo = git2::Repository::init_bare(dir.path())?;
+/// let ids = git2_ext::testing::DagBuilder::new(&repo)
+///     .commit("A")
+///     .branch("feature")
+///     .commit_with_files("B", [("src/lib.rs", "fn b() {}\n")])
+///     .checkout("main")
+///     .commit("C")
+///     .merge("D", "feature")
+///     .build()?;
+/// assert_eq!(repo.find_commit(ids["D"])?.parent_id(1)?, ids["B"]);
+/// assert_eq!(repo.find_commit(ids["C"])?.parent_id(0)?, ids["A"]);
+/// assert_eq!(repo.refname_to_id("refs/heads/feature")?, ids["B"]);
+/// assert_eq!(repo.head()?.target(), Some(ids["D"]));
+/// # Ok(())
+/// # }
+/// ```
+pub struct DagBuilder<'r> {
+    repo: &'r git2::Repository,
+    /// The ref new commits go on.
+    branch: String,
+    ids: HashMap<String, git2::Oid>,
+    time: i64,
+    error: Option<git2::Error>,
+}
+
+impl<'r> DagBuilder<'r> {
+    /// Start on `main`, adding to it if it already exists.
+    pub fn new(repo: &'r git2::Repository) -> Self {
+        DagBuilder {
+            repo,
+            branch: "refs/heads/main".to_owned(),
+            ids: HashMap::new(),
+            time: 0,
+            error: None,
+        }
+    }
+
+    /// Commit a file named `<name>.txt` on the current branch.
+    pub fn commit(self, name: &str) -> Self {
+        let file = format!("{name}.txt");
+        let contents = format!("{name} contents\n");
+        self.commit_with_files(name, [(file.as_str(), contents.as_str())])
+    }
+
+    /// Commit `files`, as `(path, contents)`, on the current branch.
+    pub fn commit_with_files<'f>(
+        mut self,
+        name: &str,
+        files: impl IntoIterator<Item = (&'f str, &'f str)>,
+    ) -> Self {
+        let result = self.try_commit(name, files);
+        self.record(name, result)
+    }
+
+    /// Create a merge commit of `other`, a named commit or branch, into the current branch.
+    pub fn merge(mut self, name: &str, other: &str) -> Self {
+        let result = self.try_merge(name, other);
+        self.record(name, result)
+    }
+
+    /// Create the branch `name` at the current commit and switch to it.
+    pub fn branch(mut self, name: &str) -> Self {
+        if self.error.is_none() {
+            let branch = format!("refs/heads/{name}");
+            if let Some(tip) = self.tip() {
+                if let Err(err) = self.repo.reference(&branch, tip, false, "dag: branch") {
+                    self.error = Some(err);
+                }
+            }
+            self.branch = branch;
+        }
+        self
+    }
+
+    /// Switch to the existing branch `name`.
+    pub fn checkout(mut self, name: &str) -> Self {
+        self.branch = format!("refs/heads/{name}");
+        self
+    }
+
+    /// The named commits, or the first error hit while building them.
+    ///
+    /// `HEAD` is left pointing at the current branch.
+    pub fn build(self) -> Result<HashMap<String, git2::Oid>, git2::Error> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.repo.set_head(&self.branch)?;
+        Ok(self.ids)
+    }
+
+    fn tip(&self) -> Option<git2::Oid> {
+        self.repo.refname_to_id(&self.branch).ok()
+    }
+
+    fn record(mut self, name: &str, result: Result<git2::Oid, git2::Error>) -> Self {
+        match result {
+            Ok(id) => {
+                self.ids.insert(name.to_owned(), id);
+            }
+            Err(err) => {
+                self.error.get_or_insert(err);
+            }
+        }
+        self
+    }
+
+    fn try_commit<'f>(
+        &mut self,
+        name: &str,
+        files: impl IntoIterator<Item = (&'f str, &'f str)>,
+    ) -> Result<git2::Oid, git2::Error> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        let parent = self.tip().map(|id| self.repo.find_commit(id)).transpose()?;
+        let mut index = git2::Index::new()?;
+        if let Some(parent) = &parent {
+            index.read_tree(&parent.tree()?)?;
+        }
+        for (path, contents) in files {
+            let entry = git2::IndexEntry {
+                ctime: git2::IndexTime::new(0, 0),
+                mtime: git2::IndexTime::new(0, 0),
+                dev: 0,
+                ino: 0,
+                mode: git2::FileMode::Blob.into(),
+                uid: 0,
+                gid: 0,
+                file_size: contents.len() as u32,
+                id: self.repo.blob(contents.as_bytes())?,
+                flags: 0,
+                flags_extended: 0,
+                path: path.as_bytes().to_vec(),
+            };
+            index.add(&entry)?;
+        }
+        let tree = self.repo.find_tree(index.write_tree_to(self.repo)?)?;
+        self.write_commit(name, &tree, parent.iter().collect::<Vec<_>>().as_slice())
+    }
+
+    fn try_merge(&mut self, name: &str, other: &str) -> Result<git2::Oid, git2::Error> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        let other_id = match self.ids.get(other) {
+            Some(id) => *id,
+            None => self.repo.refname_to_id(&format!("refs/heads/{other}"))?,
+        };
+        let ours = self
+            .repo
+            .find_commit(self.repo.refname_to_id(&self.branch)?)?;
+        let theirs = self.repo.find_commit(other_id)?;
+        let mut index = self.repo.merge_commits(&ours, &theirs, None)?;
+        if index.has_conflicts() {
+            return Err(git2::Error::new(
+                git2::ErrorCode::Conflict,
+                git2::ErrorClass::Merge,
+                format!("merging {other} into {name} conflicts"),
+            ));
+        }
+        let tree = self.repo.find_tree(index.write_tree_to(self.repo)?)?;
+        self.write_commit(name, &tree, &[&ours, &theirs])
+    }
+
+    fn write_commit(
+        &mut self,
+        message: &str,
+        tree: &git2::Tree<'_>,
+        parents: &[&git2::Commit<'_>],
+    ) -> Result<git2::Oid, git2::Error> {
+        // Fixed times, a minute apart, keep the IDs deterministic.
+        self.time += 1;
+        let time = git2::Time::new(1_604_000_096 + self.time * 60, -7 * 60);
+        let signature = git2::Signature::new(DUMMY_NAME, DUMMY_EMAIL, &time)?;
+        let id = self
+            .repo
+            .commit(None, &signature, &signature, message, tree, parents)?;
+        self.repo.reference(&self.branch, id, true, "dag: commit")?;
+        Ok(id)
+    }
+}
+
 /// Wrapper around a `Git` instance which cleans up the repository once dropped.
-pub(crate) struct GitWrapper {
+pub struct GitWrapper {
     #[allow(dead_code)]
     repo_dir: TempDir,
     git: Git,
@@ -392,7 +590,7 @@ impl Deref for GitWrapper {
 }
 
 /// Create a temporary directory for testing and a `Git` instance to use with it.
-pub(crate) fn make_git() -> eyre::Result<GitWrapper> {
+pub fn make_git() -> eyre::Result<GitWrapper> {
     let repo_dir = TempDir::new()?;
     let path_to_git = get_path_to_git()?;
     let git_run_info = GitRunInfo {
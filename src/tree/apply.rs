@@ -0,0 +1,367 @@
+use super::TreeUpdate;
+
+/// How to [`apply_patch`]
+#[derive(Clone, Debug)]
+pub struct ApplyOptions {
+    reject: bool,
+    max_offset: usize,
+}
+
+impl ApplyOptions {
+    pub fn new() -> Self {
+        Self {
+            reject: false,
+            max_offset: usize::MAX,
+        }
+    }
+
+    /// Apply the hunks that can be applied, leaving out the rest, like `git apply --reject`
+    ///
+    /// Defaults to `false`, where nothing is applied unless every hunk applies.
+    pub fn reject(mut self, yes: bool) -> Self {
+        self.reject = yes;
+        self
+    }
+
+    /// How many lines away from where the patch says a hunk should be to look for it
+    ///
+    /// Defaults to looking through the whole file, nearest first.
+    pub fn max_offset(mut self, lines: usize) -> Self {
+        self.max_offset = lines;
+        self
+    }
+}
+
+impl Default for ApplyOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The result of [`apply_patch`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AppliedPatch {
+    /// The patched tree, `None` when hunks were rejected without [`ApplyOptions::reject`]
+    pub tree_id: Option<git2::Oid>,
+    pub rejected: Vec<RejectedHunk>,
+}
+
+/// A hunk [`apply_patch`] couldn't find in the tree
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RejectedHunk {
+    pub path: std::path::PathBuf,
+    /// Index of the hunk within the file's patch
+    pub index: usize,
+    /// The `@@ ... @@` line of the hunk
+    pub header: String,
+    pub reason: String,
+}
+
+/// Apply a unified diff to `tree`, like `git apply --cached` but without an index
+///
+/// Hunks only apply where their context and removed lines match exactly, though they may have
+/// moved since the patch was made.  Binary patches aren't supported and are rejected.
+pub fn apply_patch<'r>(
+    repo: &'r git2::Repository,
+    tree: &git2::Tree<'r>,
+    patch: &[u8],
+    options: &ApplyOptions,
+) -> Result<AppliedPatch, git2::Error> {
+    let diff = git2::Diff::from_buffer(patch)?;
+    let mut updates: Vec<(std::path::PathBuf, Option<TreeUpdate>)> = Vec::new();
+    let mut rejected = Vec::new();
+    for delta_index in 0..diff.deltas().len() {
+        let Some(patch) = git2::Patch::from_diff(&diff, delta_index)? else {
+            continue;
+        };
+        let delta = patch.delta();
+        let old_path = delta.old_file().path().map(|path| path.to_owned());
+        let new_path = delta.new_file().path().map(|path| path.to_owned());
+        let path = new_path
+            .clone()
+            .or_else(|| old_path.clone())
+            .unwrap_or_default();
+        let reject_file = |rejected: &mut Vec<RejectedHunk>, reason: &str| {
+            rejected.push(RejectedHunk {
+                path: path.clone(),
+                index: 0,
+                header: String::new(),
+                reason: reason.to_owned(),
+            });
+        };
+        if delta.flags().is_binary() {
+            reject_file(&mut rejected, "binary patches are not supported");
+            continue;
+        }
+
+        let old_entry = match (delta.status(), &old_path) {
+            (git2::Delta::Added, _) | (_, None) => None,
+            (_, Some(old_path)) => match tree.get_path(old_path) {
+                Ok(entry) => Some(entry),
+                Err(err) if err.code() == git2::ErrorCode::NotFound => {
+                    reject_file(&mut rejected, "does not exist in the tree");
+                    continue;
+                }
+                Err(err) => return Err(err),
+            },
+        };
+        if delta.status() == git2::Delta::Added {
+            if let Some(new_path) = &new_path {
+                if tree.get_path(new_path).is_ok() {
+                    reject_file(&mut rejected, "already exists in the tree");
+                    continue;
+                }
+            }
+        }
+
+        let old_blob = old_entry
+            .as_ref()
+            .map(|entry| repo.find_blob(entry.id()))
+            .transpose()?;
+        let old_content = old_blob
+            .as_ref()
+            .map(|blob| blob.content())
+            .unwrap_or_default();
+        let Some(new_content) = apply_hunks(&patch, &path, old_content, options, &mut rejected)?
+        else {
+            continue;
+        };
+
+        if delta.status() == git2::Delta::Deleted {
+            if !new_content.is_empty() {
+                reject_file(&mut rejected, "deleted file still has content");
+                continue;
+            }
+            updates.push((path, None));
+            continue;
+        }
+        if let (Some(old_path), Some(new_path)) = (&old_path, &new_path) {
+            if old_path != new_path {
+                updates.push((old_path.clone(), None));
+            }
+        }
+        let new_mode = i32::from(delta.new_file().mode());
+        let mode = if super::normalize_mode(new_mode).is_some() {
+            new_mode
+        } else {
+            old_entry
+                .as_ref()
+                .map(|entry| entry.filemode())
+                .unwrap_or(git2::FileMode::Blob.into())
+        };
+        let id = repo.blob(&new_content)?;
+        updates.push((path, Some(TreeUpdate { id, mode })));
+    }
+
+    if !rejected.is_empty() && !options.reject {
+        return Ok(AppliedPatch {
+            tree_id: None,
+            rejected,
+        });
+    }
+    let tree_id = super::rebuild_tree_from_updates(
+        repo,
+        Some(tree),
+        updates
+            .iter()
+            .map(|(path, update)| (path.as_path(), *update)),
+    )?;
+    Ok(AppliedPatch {
+        tree_id: Some(tree_id),
+        rejected,
+    })
+}
+
+/// Apply a file's hunks to its content
+///
+/// Returns `None` when a hunk was rejected without [`ApplyOptions::reject`].
+fn apply_hunks(
+    patch: &git2::Patch<'_>,
+    path: &std::path::Path,
+    content: &[u8],
+    options: &ApplyOptions,
+    rejected: &mut Vec<RejectedHunk>,
+) -> Result<Option<Vec<u8>>, git2::Error> {
+    let source: Vec<&[u8]> = content.split_inclusive(|b| *b == b'\n').collect();
+    let mut output = Vec::with_capacity(content.len());
+    let mut pos = 0;
+    let mut any_rejected = false;
+    for hunk_index in 0..patch.num_hunks() {
+        let (hunk, line_count) = patch.hunk(hunk_index)?;
+        let mut old_lines = Vec::new();
+        let mut new_lines = Vec::new();
+        for line_index in 0..line_count {
+            let line = patch.line_in_hunk(hunk_index, line_index)?;
+            match line.origin_value() {
+                git2::DiffLineType::Context => {
+                    old_lines.push(line.content().to_vec());
+                    new_lines.push(line.content().to_vec());
+                }
+                git2::DiffLineType::Deletion => old_lines.push(line.content().to_vec()),
+                git2::DiffLineType::Addition => new_lines.push(line.content().to_vec()),
+                _ => {}
+            }
+        }
+
+        // Line numbers are 1-based, except an empty range is the line it comes after
+        let expected = if hunk.old_lines() == 0 {
+            hunk.old_start() as usize
+        } else {
+            (hunk.old_start() as usize).saturating_sub(1)
+        };
+        match find_hunk(&source, pos, expected, &old_lines, options.max_offset) {
+            Some(start) => {
+                output.extend(source[pos..start].concat());
+                output.extend(new_lines.concat());
+                pos = start + old_lines.len();
+            }
+            None => {
+                any_rejected = true;
+                rejected.push(RejectedHunk {
+                    path: path.to_owned(),
+                    index: hunk_index,
+                    header: String::from_utf8_lossy(hunk.header()).trim_end().to_owned(),
+                    reason: "does not match the tree".to_owned(),
+                });
+            }
+        }
+    }
+    if any_rejected && !options.reject {
+        return Ok(None);
+    }
+    output.extend(source[pos..].concat());
+    Ok(Some(output))
+}
+
+/// Where `lines` are in `source`, at or after `pos`, searching outward from `expected`
+fn find_hunk(
+    source: &[&[u8]],
+    pos: usize,
+    expected: usize,
+    lines: &[Vec<u8>],
+    max_offset: usize,
+) -> Option<usize> {
+    let matches_at = |start: usize| {
+        pos <= start
+            && start + lines.len() <= source.len()
+            && lines
+                .iter()
+                .zip(&source[start..])
+                .all(|(line, source_line)| line.as_slice() == *source_line)
+    };
+    let max_offset = max_offset.min(source.len());
+    (0..=max_offset).find_map(|offset| {
+        let after = expected
+            .checked_add(offset)
+            .filter(|start| matches_at(*start));
+        let before = expected
+            .checked_sub(offset)
+            .filter(|start| matches_at(*start));
+        after.or(before)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools as _;
+
+    use super::*;
+
+    use crate::testing::make_git;
+    use crate::tree::from_entries;
+    use crate::tree::Content;
+
+    #[test]
+    fn test_apply_patch() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+
+        let repo = git.get_repo()?;
+        let tree_id = from_entries(
+            &repo,
+            [
+                ("moved", Content::file("new first\none\ntwo\nthree\n")),
+                ("gone", Content::file("gone\n")),
+                ("rejected", Content::file("something else\n")),
+            ],
+        )?;
+        let tree = repo.find_tree(tree_id)?;
+        let patch = b"\
+diff --git a/moved b/moved
+--- a/moved
++++ b/moved
+@@ -1,3 +1,3 @@
+ one
+-two
++TWO
+ three
+diff --git a/gone b/gone
+deleted file mode 100644
+--- a/gone
++++ /dev/null
+@@ -1 +0,0 @@
+-gone
+diff --git a/added b/added
+new file mode 100755
+--- /dev/null
++++ b/added
+@@ -0,0 +1 @@
++#!/bin/sh
+diff --git a/rejected b/rejected
+--- a/rejected
++++ b/rejected
+@@ -1 +1 @@
+-expected
++replaced
+";
+
+        let applied = apply_patch(&repo, &tree, patch, &ApplyOptions::new())?;
+        assert_eq!(applied.tree_id, None);
+        assert_eq!(
+            applied.rejected,
+            [RejectedHunk {
+                path: "rejected".into(),
+                index: 0,
+                header: "@@ -1 +1 @@".to_owned(),
+                reason: "does not match the tree".to_owned(),
+            }]
+        );
+
+        let applied = apply_patch(&repo, &tree, patch, &ApplyOptions::new().reject(true))?;
+        assert_eq!(applied.rejected.len(), 1);
+        let patched = repo.find_tree(applied.tree_id.expect("rejects are allowed"))?;
+        let files = crate::tree::walk(&repo, &patched)
+            .map_ok(|(path, info)| -> Result<_, git2::Error> {
+                let content = repo.find_blob(info.id)?.content().to_vec();
+                Ok((
+                    path.display().to_string(),
+                    format!("{:o}", info.mode),
+                    String::from_utf8_lossy(&content).into_owned(),
+                ))
+            })
+            .flatten_ok()
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            files,
+            [
+                (
+                    "added".to_owned(),
+                    "100755".to_owned(),
+                    "#!/bin/sh\n".to_owned()
+                ),
+                (
+                    "moved".to_owned(),
+                    "100644".to_owned(),
+                    "new first\none\nTWO\nthree\n".to_owned()
+                ),
+                (
+                    "rejected".to_owned(),
+                    "100644".to_owned(),
+                    "something else\n".to_owned()
+                ),
+            ]
+        );
+
+        Ok(())
+    }
+}
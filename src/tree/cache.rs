@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+
+use super::ChangedEntry;
+
+/// Remembers changes between pairs of subtrees, see [`get_changed_entries_between_trees_cached`]
+///
+/// Diffing many related trees, like every commit in a log, compares the same pairs of
+/// subtrees over and over.  Reusing one cache across those diffs skips re-walking them.
+#[derive(Clone, Debug, Default)]
+pub struct TreeDiffCache {
+    /// Changes keyed by `(lhs, rhs)` tree ids, with the zero id for a missing tree, and paths
+    /// relative to the subtrees
+    entries: HashMap<(git2::Oid, git2::Oid), Vec<ChangedEntry>>,
+}
+
+impl TreeDiffCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of subtree pairs remembered
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Forget everything, like when it has grown too large
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Like [`super::get_changed_entries_between_trees`] but reusing subtree results in `cache`
+pub fn get_changed_entries_between_trees_cached(
+    repo: &git2::Repository,
+    lhs: Option<&git2::Tree<'_>>,
+    rhs: Option<&git2::Tree<'_>>,
+    cache: &mut TreeDiffCache,
+) -> Result<Vec<ChangedEntry>, git2::Error> {
+    let root_key = key(lhs, rhs);
+    if let Some(hit) = cache.entries.get(&root_key) {
+        let mut acc = hit.clone();
+        acc.sort_by(|a, b| a.path.cmp(&b.path));
+        return Ok(acc);
+    }
+
+    // Subtrees are walked with an explicit stack, like the uncached diff, with each frame
+    // collecting its changes so they can be cached once all of its children are done
+    let mut trees = super::lookup::TreeLookup::new(repo);
+    let mut stack = vec![Frame::new(
+        &mut trees,
+        std::path::PathBuf::new(),
+        root_key,
+        lhs,
+        rhs,
+    )?];
+    let mut name = Vec::new();
+    loop {
+        let top = stack.last_mut().expect("stack is never left empty");
+        if let Some((lhs, rhs)) = top.children.pop(&mut name) {
+            let name = crate::bytes::bytes2path_lossy(&name).into_owned();
+            let child_key = key(lhs.as_ref(), rhs.as_ref());
+            match cache.entries.get(&child_key) {
+                Some(hit) => {
+                    top.acc
+                        .extend(hit.iter().map(|entry| prefixed(entry.clone(), &name)));
+                }
+                None => {
+                    let child =
+                        Frame::new(&mut trees, name, child_key, lhs.as_ref(), rhs.as_ref())?;
+                    stack.push(child);
+                }
+            }
+            continue;
+        }
+
+        let frame = stack.pop().expect("stack is never left empty");
+        cache.entries.insert(frame.key, frame.acc.clone());
+        match stack.last_mut() {
+            Some(parent) => {
+                parent.acc.extend(
+                    frame
+                        .acc
+                        .into_iter()
+                        .map(|entry| prefixed(entry, &frame.prefix)),
+                );
+            }
+            None => {
+                let mut acc = frame.acc;
+                acc.sort_by(|a, b| a.path.cmp(&b.path));
+                return Ok(acc);
+            }
+        }
+    }
+}
+
+/// A pair of subtrees being diffed
+struct Frame<'r> {
+    /// Where the subtrees are, relative to the parent frame
+    prefix: std::path::PathBuf,
+    key: (git2::Oid, git2::Oid),
+    /// Changes found so far, relative to the subtrees
+    acc: Vec<ChangedEntry>,
+    /// Changed subtrees left to diff
+    children: super::PendingTrees<git2::Tree<'r>>,
+}
+
+impl<'r> Frame<'r> {
+    fn new(
+        trees: &mut super::lookup::TreeLookup<'r>,
+        prefix: std::path::PathBuf,
+        key: (git2::Oid, git2::Oid),
+        lhs: Option<&git2::Tree<'_>>,
+        rhs: Option<&git2::Tree<'_>>,
+    ) -> Result<Self, git2::Error> {
+        // Reattach the trees to `repo`s lifetime
+        let lhs = lhs.map(|tree| trees.find_tree(tree.id())).transpose()?;
+        let rhs = rhs.map(|tree| trees.find_tree(tree.id())).transpose()?;
+        let mut acc = Vec::new();
+        let mut children = super::PendingTrees::default();
+        let mut visit = |entry| {
+            acc.push(entry);
+            std::ops::ControlFlow::Continue(())
+        };
+        super::get_changed_paths_between_subtrees(
+            trees,
+            &mut super::Changes::new(&mut visit),
+            &mut children,
+            &mut Vec::new(),
+            None,
+            lhs.as_ref(),
+            rhs.as_ref(),
+        )?;
+        Ok(Self {
+            prefix,
+            key,
+            acc,
+            children,
+        })
+    }
+}
+
+fn key(lhs: Option<&git2::Tree<'_>>, rhs: Option<&git2::Tree<'_>>) -> (git2::Oid, git2::Oid) {
+    let id =
+        |tree: Option<&git2::Tree<'_>>| tree.map(|tree| tree.id()).unwrap_or(git2::Oid::zero());
+    (id(lhs), id(rhs))
+}
+
+fn prefixed(mut entry: ChangedEntry, prefix: &std::path::Path) -> ChangedEntry {
+    entry.path = prefix.join(&entry.path);
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+    use crate::tree::get_changed_entries_between_trees;
+
+    #[test]
+    fn test_tree_diff_cache() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.write_file("shared/a", "a")?;
+        git.write_file("shared/nested/b", "b")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "base"])?;
+        git.write_file("shared/a", "changed")?;
+        git.write_file("shared/nested/b", "changed")?;
+        git.write_file("top", "top")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "change"])?;
+        git.write_file("other", "other")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "other"])?;
+
+        let repo = git.get_repo()?;
+        let tree = |name: &str| repo.revparse_single(name)?.peel_to_tree();
+        let (base, change, other) = (tree("HEAD~2")?, tree("HEAD~1")?, tree("HEAD")?);
+
+        let mut cache = TreeDiffCache::new();
+        let first = get_changed_entries_between_trees_cached(
+            &repo,
+            Some(&base),
+            Some(&change),
+            &mut cache,
+        )?;
+        assert_eq!(
+            first,
+            get_changed_entries_between_trees(&repo, Some(&base), Some(&change))?
+        );
+        let cached = cache.len();
+        assert_eq!(cached, 3);
+
+        // `shared` didn't change since, so its diff is reused
+        let second =
+            get_changed_entries_between_trees_cached(&repo, Some(&base), Some(&other), &mut cache)?;
+        assert_eq!(
+            second,
+            get_changed_entries_between_trees(&repo, Some(&base), Some(&other))?
+        );
+        assert_eq!(cache.len(), cached + 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+use super::TreeUpdate;
+
+/// Edit a tree one path at a time
+///
+/// Edits are applied in order when [`TreeEditor::write`] is called, using
+/// [`super::rebuild_tree_from_updates`].
+///
+/// ```no_run
+/// let repo = git2::Repository::open(".").unwrap();
+/// let tree = repo.head().unwrap().peel_to_tree().unwrap();
+/// let tree_id = git2_ext::tree::TreeEditor::new(&repo, Some(&tree))
+///     .upsert_blob("VERSION", b"1.0.0\n")
+///     .move_entry("docs", "documentation")
+///     .remove("CHANGELOG.md")
+///     .write()
+///     .unwrap();
+/// ```
+pub struct TreeEditor<'r> {
+    repo: &'r git2::Repository,
+    base: Option<git2::Tree<'r>>,
+    edits: Vec<Edit>,
+}
+
+enum Edit {
+    Upsert(PathBuf, TreeUpdate),
+    UpsertBlob(PathBuf, Vec<u8>),
+    Remove(PathBuf),
+    Move(PathBuf, PathBuf),
+}
+
+impl<'r> TreeEditor<'r> {
+    /// Edit `base_tree`, or an empty tree
+    pub fn new(repo: &'r git2::Repository, base_tree: Option<&git2::Tree<'r>>) -> Self {
+        Self {
+            repo,
+            base: base_tree.cloned(),
+            edits: Vec::new(),
+        }
+    }
+
+    /// Point `path` at an existing object, creating parent directories as needed
+    pub fn upsert(mut self, path: impl Into<PathBuf>, id: git2::Oid, mode: i32) -> Self {
+        self.edits
+            .push(Edit::Upsert(path.into(), TreeUpdate { id, mode }));
+        self
+    }
+
+    /// Point the submodule at `path` at `commit`
+    ///
+    /// The commit belongs to the submodule's repository, so it isn't checked.  Nothing else can
+    /// be edited inside the submodule; [`TreeEditor::write`] fails if that's tried.
+    pub fn set_submodule(self, path: impl Into<PathBuf>, commit: git2::Oid) -> Self {
+        self.upsert(path, commit, git2::FileMode::Commit.into())
+    }
+
+    /// Write `content` as a regular file at `path`
+    pub fn upsert_blob(mut self, path: impl Into<PathBuf>, content: impl Into<Vec<u8>>) -> Self {
+        self.edits
+            .push(Edit::UpsertBlob(path.into(), content.into()));
+        self
+    }
+
+    /// Remove the file or directory at `path`, if present
+    ///
+    /// Directories left empty are removed.
+    pub fn remove(mut self, path: impl Into<PathBuf>) -> Self {
+        self.edits.push(Edit::Remove(path.into()));
+        self
+    }
+
+    /// Move the file or directory at `from` to `to`, replacing what is at `to`
+    ///
+    /// [`TreeEditor::write`] fails if nothing is at `from`.
+    pub fn move_entry(mut self, from: impl Into<PathBuf>, to: impl Into<PathBuf>) -> Self {
+        self.edits.push(Edit::Move(from.into(), to.into()));
+        self
+    }
+
+    /// Apply the edits, returning the new tree
+    pub fn write(self) -> Result<git2::Oid, git2::Error> {
+        let Self { repo, base, edits } = self;
+        let mut updates: HashMap<PathBuf, Option<TreeUpdate>> = HashMap::new();
+        for edit in edits {
+            match edit {
+                Edit::Upsert(path, update) => {
+                    clear_under(&mut updates, &path);
+                    updates.insert(path, Some(update));
+                }
+                Edit::UpsertBlob(path, content) => {
+                    let id = repo.blob(&content)?;
+                    clear_under(&mut updates, &path);
+                    updates.insert(
+                        path,
+                        Some(TreeUpdate {
+                            id,
+                            mode: git2::FileMode::Blob.into(),
+                        }),
+                    );
+                }
+                Edit::Remove(path) => {
+                    clear_under(&mut updates, &path);
+                    updates.insert(path, None);
+                }
+                Edit::Move(from, to) => {
+                    let base_entry = match updates.get(&from) {
+                        Some(Some(update)) => Some(*update),
+                        Some(None) => None,
+                        None => base_entry(base.as_ref(), &from)?,
+                    };
+                    let moved: Vec<_> = updates
+                        .iter()
+                        .filter(|(path, _)| path.starts_with(&from) && **path != from)
+                        .map(|(path, update)| {
+                            let rest = path.strip_prefix(&from).expect("filtered by prefix");
+                            (to.join(rest), *update)
+                        })
+                        .collect();
+                    if base_entry.is_none() && moved.is_empty() {
+                        return Err(git2::Error::new(
+                            git2::ErrorCode::NotFound,
+                            git2::ErrorClass::Tree,
+                            format!("nothing to move at `{}`", from.display()),
+                        ));
+                    }
+
+                    clear_under(&mut updates, &from);
+                    updates.insert(from, None);
+                    clear_under(&mut updates, &to);
+                    updates.insert(to, base_entry);
+                    updates.extend(moved);
+                }
+            }
+        }
+
+        super::rebuild_tree_from_updates(
+            repo,
+            base.as_ref(),
+            updates
+                .iter()
+                .map(|(path, update)| (path.as_path(), *update)),
+        )
+    }
+}
+
+fn base_entry(
+    base: Option<&git2::Tree<'_>>,
+    path: &Path,
+) -> Result<Option<TreeUpdate>, git2::Error> {
+    let Some(base) = base else {
+        return Ok(None);
+    };
+    match base.get_path(path) {
+        Ok(entry) => Ok(Some(TreeUpdate {
+            id: entry.id(),
+            mode: entry.filemode_raw(),
+        })),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Forget earlier edits inside `dir`, as it is being replaced
+fn clear_under(updates: &mut HashMap<PathBuf, Option<TreeUpdate>>, dir: &Path) {
+    updates.retain(|path, _| path == dir || !path.starts_with(dir));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_tree_editor() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.write_file("docs/guide", "guide")?;
+        git.write_file("old", "old")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "commit"])?;
+
+        let repo = git.get_repo()?;
+        let head_tree = repo.head()?.peel_to_tree()?;
+        let initial = head_tree.get_path(Path::new("initial.txt"))?.id();
+        let tree_id = TreeEditor::new(&repo, Some(&head_tree))
+            .upsert_blob("docs/new.txt", "new")
+            .move_entry("docs", "documentation")
+            .upsert("copy.txt", initial, git2::FileMode::BlobExecutable.into())
+            .remove("old.txt")
+            .write()?;
+
+        let tree = repo.find_tree(tree_id)?;
+        let paths = super::super::walk(&repo, &tree)
+            .map(|item| item.map(|(path, info)| format!("{} {:o}", path.display(), info.mode)))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            paths,
+            [
+                "copy.txt 100755",
+                "documentation 40000",
+                "documentation/guide.txt 100644",
+                "documentation/new.txt 100644",
+                "initial.txt 100644",
+            ]
+        );
+
+        let err = TreeEditor::new(&repo, Some(&head_tree))
+            .move_entry("missing", "elsewhere")
+            .write()
+            .unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::NotFound);
+
+        Ok(())
+    }
+}
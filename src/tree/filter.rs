@@ -0,0 +1,533 @@
+use super::filter_process::FilterProcess;
+use super::filter_process::FilterResponse;
+
+/// Convert working tree content for `path` into what `git add` would store
+///
+/// Following `.gitattributes` and config, in the same order as `git`:
+/// - The `filter` attribute's driver cleans the content, with `filter.<driver>.process` or
+///   `filter.<driver>.clean`
+/// - Line endings are normalized to LF for `text`, `text=auto` (when the content isn't
+///   binary), `eol`, or, without attributes, `core.autocrlf` of `true` or `input`
+/// - `$Id: ...$` keywords are collapsed to `$Id$` for `ident`
+///
+/// To convert many files, use [`FilterDrivers`] to not restart long-running filter processes.
+pub fn clean(
+    repo: &git2::Repository,
+    path: &std::path::Path,
+    content: &[u8],
+) -> Result<Vec<u8>, git2::Error> {
+    FilterDrivers::new().clean(repo, path, content)
+}
+
+/// Convert blob content for `path` into what `git checkout` would write, undoing [`clean`]
+///
+/// Following `.gitattributes` and config, in the same order as `git`:
+/// - `$Id$` keywords are expanded to `$Id: <blob id> $` for `ident`
+/// - Line endings are converted to CRLF for text with `eol=crlf`, or `core.autocrlf=true`, or
+///   `core.eol=crlf`
+/// - The `filter` attribute's driver smudges the content, with `filter.<driver>.process` or
+///   `filter.<driver>.smudge`
+///
+/// To convert many files, use [`FilterDrivers`] to not restart long-running filter processes.
+pub fn smudge(
+    repo: &git2::Repository,
+    path: &std::path::Path,
+    content: &[u8],
+) -> Result<Vec<u8>, git2::Error> {
+    FilterDrivers::new().smudge(repo, path, content)
+}
+
+/// Write `content` as a blob like `git add` would, see [`clean`]
+pub fn write_blob(
+    repo: &git2::Repository,
+    path: &std::path::Path,
+    content: &[u8],
+) -> Result<git2::Oid, git2::Error> {
+    FilterDrivers::new().write_blob(repo, path, content)
+}
+
+/// Read blob `id` as `git checkout` would write it to `path`, see [`smudge`]
+pub fn read_blob(
+    repo: &git2::Repository,
+    path: &std::path::Path,
+    id: git2::Oid,
+) -> Result<Vec<u8>, git2::Error> {
+    FilterDrivers::new().read_blob(repo, path, id)
+}
+
+/// Converts content like [`clean`] and [`smudge`], keeping filter processes running across files
+///
+/// `filter.<driver>.process` commands, like `git lfs filter-process`, are started on first use
+/// and exit when this is dropped.  Use one per repository.
+#[derive(Default)]
+pub struct FilterDrivers {
+    /// Started processes by driver, `None` once the process aborted
+    processes: std::collections::HashMap<String, Option<FilterProcess>>,
+}
+
+impl FilterDrivers {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// See [`clean`]
+    pub fn clean(
+        &mut self,
+        repo: &git2::Repository,
+        path: &std::path::Path,
+        content: &[u8],
+    ) -> Result<Vec<u8>, git2::Error> {
+        let mut content = content.to_vec();
+
+        if let Some(driver) = attr_string(repo, path, "filter")? {
+            content = self.run_driver(repo, path, &driver, "clean", content)?;
+        }
+
+        if normalizes_eol(repo, path, &content)? {
+            content = crlf_to_lf(&content);
+        }
+
+        if attr(repo, path, "ident")? == AttrValue::True {
+            content = replace_ident(&content, b"$Id$");
+        }
+
+        Ok(content)
+    }
+
+    /// See [`smudge`]
+    pub fn smudge(
+        &mut self,
+        repo: &git2::Repository,
+        path: &std::path::Path,
+        content: &[u8],
+    ) -> Result<Vec<u8>, git2::Error> {
+        let mut content = content.to_vec();
+
+        if attr(repo, path, "ident")? == AttrValue::True {
+            let id = git2::Oid::hash_object(git2::ObjectType::Blob, &content)?;
+            content = replace_ident(&content, format!("$Id: {id} $").as_bytes());
+        }
+
+        if converts_to_crlf(repo, path, &content)? {
+            content = lf_to_crlf(&content);
+        }
+
+        if let Some(driver) = attr_string(repo, path, "filter")? {
+            content = self.run_driver(repo, path, &driver, "smudge", content)?;
+        }
+
+        Ok(content)
+    }
+
+    /// See [`write_blob`]
+    pub fn write_blob(
+        &mut self,
+        repo: &git2::Repository,
+        path: &std::path::Path,
+        content: &[u8],
+    ) -> Result<git2::Oid, git2::Error> {
+        let content = self.clean(repo, path, content)?;
+        repo.blob(&content)
+    }
+
+    /// See [`read_blob`]
+    pub fn read_blob(
+        &mut self,
+        repo: &git2::Repository,
+        path: &std::path::Path,
+        id: git2::Oid,
+    ) -> Result<Vec<u8>, git2::Error> {
+        let blob = repo.find_blob(id)?;
+        self.smudge(repo, path, blob.content())
+    }
+
+    /// Run `driver` in `direction`, `clean` or `smudge`
+    fn run_driver(
+        &mut self,
+        repo: &git2::Repository,
+        path: &std::path::Path,
+        driver: &str,
+        direction: &str,
+        content: Vec<u8>,
+    ) -> Result<Vec<u8>, git2::Error> {
+        let config = repo.config()?;
+        let required = crate::config::get_bool(&config, &format!("filter.{driver}.required"))?
+            .unwrap_or(false);
+        // Like `git`, a process takes the place of the single-file commands
+        let filtered =
+            match crate::config::get_string(&config, &format!("filter.{driver}.process"))? {
+                Some(command) => {
+                    self.run_process(repo, path, driver, &command, direction, &content)
+                }
+                None => {
+                    match crate::config::get_string(
+                        &config,
+                        &format!("filter.{driver}.{direction}"),
+                    )? {
+                        Some(command) => {
+                            run_filter_command(repo, &command, path, &content).map(Some)
+                        }
+                        None => Ok(None),
+                    }
+                }
+            };
+
+        match filtered {
+            Ok(Some(filtered)) => Ok(filtered),
+            Ok(None) if required => Err(git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Filter,
+                format!(
+                    "required {direction} filter `{driver}` isn't available for `{}`",
+                    path.display()
+                ),
+            )),
+            Ok(None) => Ok(content),
+            Err(err) if required => Err(git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Filter,
+                format!(
+                    "{direction} filter `{driver}` failed for `{}`: {err}",
+                    path.display()
+                ),
+            )),
+            Err(err) => {
+                // Like `git`, an optional filter failing leaves the content as-is
+                log::warn!(
+                    "{direction} filter `{driver}` failed for `{}`: {err}",
+                    path.display()
+                );
+                Ok(content)
+            }
+        }
+    }
+
+    /// Filter `content` through the process of `driver`, `None` when it doesn't do `direction`
+    fn run_process(
+        &mut self,
+        repo: &git2::Repository,
+        path: &std::path::Path,
+        driver: &str,
+        command: &str,
+        direction: &str,
+        content: &[u8],
+    ) -> Result<Option<Vec<u8>>, std::io::Error> {
+        if !self.processes.contains_key(driver) {
+            let process = FilterProcess::start(repo, command)?;
+            self.processes.insert(driver.to_owned(), Some(process));
+        }
+        let Some(Some(process)) = self.processes.get_mut(driver) else {
+            return Ok(None);
+        };
+        if !process.supports(direction) {
+            return Ok(None);
+        }
+
+        match process.filter(direction, path, content) {
+            Ok(FilterResponse::Filtered(filtered)) => Ok(Some(filtered)),
+            Ok(FilterResponse::Error) => Err(std::io::Error::other("filter process failed")),
+            Ok(FilterResponse::Abort) => {
+                self.processes.insert(driver.to_owned(), None);
+                Err(std::io::Error::other("filter process aborted"))
+            }
+            Err(err) => {
+                // Start over with the next file
+                self.processes.remove(driver);
+                Err(err)
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for FilterDrivers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FilterDrivers")
+            .field("processes", &self.processes.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum AttrValue {
+    True,
+    False,
+    Unspecified,
+    Value(String),
+}
+
+fn attr(
+    repo: &git2::Repository,
+    path: &std::path::Path,
+    name: &str,
+) -> Result<AttrValue, git2::Error> {
+    let value = repo.get_attr_bytes(path, name, git2::AttrCheckFlags::FILE_THEN_INDEX)?;
+    Ok(match git2::AttrValue::from_bytes(value) {
+        git2::AttrValue::True => AttrValue::True,
+        git2::AttrValue::False => AttrValue::False,
+        git2::AttrValue::Unspecified => AttrValue::Unspecified,
+        git2::AttrValue::String(value) => AttrValue::Value(value.to_owned()),
+        git2::AttrValue::Bytes(value) => AttrValue::Value(String::from_utf8_lossy(value).into()),
+    })
+}
+
+fn attr_string(
+    repo: &git2::Repository,
+    path: &std::path::Path,
+    name: &str,
+) -> Result<Option<String>, git2::Error> {
+    match attr(repo, path, name)? {
+        AttrValue::Value(value) => Ok(Some(value)),
+        _ => Ok(None),
+    }
+}
+
+fn normalizes_eol(
+    repo: &git2::Repository,
+    path: &std::path::Path,
+    content: &[u8],
+) -> Result<bool, git2::Error> {
+    let auto = match attr(repo, path, "text")? {
+        AttrValue::False => return Ok(false),
+        AttrValue::True => return Ok(true),
+        AttrValue::Value(value) if value == "auto" => true,
+        _ => {
+            if attr_string(repo, path, "eol")?.is_some() {
+                return Ok(true);
+            }
+            let autocrlf = repo
+                .config()?
+                .get_string("core.autocrlf")
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            matches!(autocrlf.as_str(), "true" | "input" | "yes" | "on" | "1")
+        }
+    };
+    // Like `git`, only convert what is safely text: no binary content and no lone CRs
+    let lone_cr = content
+        .iter()
+        .enumerate()
+        .any(|(i, b)| *b == b'\r' && content.get(i + 1) != Some(&b'\n'));
+    Ok(auto && !super::stats::is_binary(content) && !lone_cr)
+}
+
+fn crlf_to_lf(content: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(content.len());
+    for (i, b) in content.iter().enumerate() {
+        if *b == b'\r' && content.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        converted.push(*b);
+    }
+    converted
+}
+
+fn converts_to_crlf(
+    repo: &git2::Repository,
+    path: &std::path::Path,
+    content: &[u8],
+) -> Result<bool, git2::Error> {
+    let config = repo.config()?;
+    let autocrlf = config
+        .get_string("core.autocrlf")
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let autocrlf_true = matches!(autocrlf.as_str(), "true" | "yes" | "on" | "1");
+    let eol = attr_string(repo, path, "eol")?;
+    let auto = match attr(repo, path, "text")? {
+        AttrValue::False => return Ok(false),
+        AttrValue::True => false,
+        AttrValue::Value(value) if value == "auto" => true,
+        _ if eol.is_some() => false,
+        _ if autocrlf_true => true,
+        _ => return Ok(false),
+    };
+    let crlf = match eol.as_deref() {
+        Some(eol) => eol == "crlf",
+        None if autocrlf_true => true,
+        None if autocrlf == "input" => false,
+        None => match config
+            .get_string("core.eol")
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "crlf" => true,
+            "lf" => false,
+            _ => cfg!(windows),
+        },
+    };
+    // Like `git`, leave alone what may not be text: binary content or content with CRs
+    Ok(crlf && !(auto && (super::stats::is_binary(content) || content.contains(&b'\r'))))
+}
+
+fn lf_to_crlf(content: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(content.len());
+    for (i, b) in content.iter().enumerate() {
+        if *b == b'\n' && (i == 0 || content[i - 1] != b'\r') {
+            converted.push(b'\r');
+        }
+        converted.push(*b);
+    }
+    converted
+}
+
+/// Replace `$Id$` and `$Id: ...$` keywords with `keyword`
+fn replace_ident(content: &[u8], keyword: &[u8]) -> Vec<u8> {
+    const KEYWORD: &[u8] = b"$Id";
+    let mut replaced = Vec::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.windows(KEYWORD.len()).position(|w| w == KEYWORD) {
+        let after = &rest[start + KEYWORD.len()..];
+        // The keyword ends on the same line
+        let end = match after.first() {
+            Some(b'$') => Some(0),
+            Some(b':') => after
+                .iter()
+                .position(|b| *b == b'$' || *b == b'\n')
+                .filter(|end| after[*end] == b'$'),
+            _ => None,
+        };
+        match end {
+            Some(end) => {
+                replaced.extend_from_slice(&rest[..start]);
+                replaced.extend_from_slice(keyword);
+                rest = &after[end + 1..];
+            }
+            None => {
+                replaced.extend_from_slice(&rest[..start + KEYWORD.len()]);
+                rest = after;
+            }
+        }
+    }
+    replaced.extend_from_slice(rest);
+    replaced
+}
+
+/// Run a filter command through the shell, like `git` does, with `%f` as the path
+pub(crate) fn run_filter_command(
+    repo: &git2::Repository,
+    command: &str,
+    path: &std::path::Path,
+    content: &[u8],
+) -> Result<Vec<u8>, std::io::Error> {
+    // The path is passed as `$1` rather than quoted into the command
+    let mut cmd = crate::utils::ProcessCommand::new(crate::utils::git_sh().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "No `sh` for running filter drivers",
+        )
+    })?)
+    .arg("-c")
+    .arg(command.replace("%f", "\"$1\""))
+    .arg(command)
+    .arg(path)
+    .stdin(content)
+    .stderr(crate::utils::ProcessStdio::Inherit);
+    if let Some(workdir) = repo.workdir() {
+        cmd = cmd.current_dir(workdir);
+    }
+    let output = crate::utils::run_process(cmd)?;
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "exited with {}",
+            output.status
+        )));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_clean() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        std::fs::write(
+            git.repo_path.join(".gitattributes"),
+            "*.txt text\n*.bin -text\n*.c ident\n*.up filter=upper\n",
+        )?;
+        git.run(&["config", "filter.upper.clean", "tr a-z A-Z"])?;
+
+        let repo = git.get_repo()?;
+        let clean = |path: &str, content: &[u8]| -> Result<Vec<u8>, git2::Error> {
+            clean(&repo, std::path::Path::new(path), content)
+        };
+        assert_eq!(clean("a.txt", b"one\r\ntwo\r\n")?, b"one\ntwo\n");
+        assert_eq!(clean("a.bin", b"one\r\ntwo\r\n")?, b"one\r\ntwo\r\n");
+        assert_eq!(
+            clean("a.c", b"/* $Id: 1234 $ */\n$Id: unterminated\n")?,
+            b"/* $Id$ */\n$Id: unterminated\n"
+        );
+        #[cfg(unix)]
+        assert_eq!(clean("a.up", b"shout\n")?, b"SHOUT\n");
+
+        // Matches what `git add` stores
+        std::fs::write(git.repo_path.join("crlf.txt"), b"one\r\ntwo\r\n")?;
+        git.run(&["add", "crlf.txt"])?;
+        let staged = git
+            .get_repo()?
+            .index()?
+            .get_path(std::path::Path::new("crlf.txt"), 0)
+            .expect("staged")
+            .id;
+        assert_eq!(
+            write_blob(&repo, std::path::Path::new("crlf.txt"), b"one\r\ntwo\r\n")?,
+            staged
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_smudge() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        std::fs::write(
+            git.repo_path.join(".gitattributes"),
+            "*.txt text eol=crlf\n*.c ident\n*.up filter=upper\n*.req filter=missing\n*.proc filter=broken\n",
+        )?;
+        git.run(&["config", "filter.upper.smudge", "tr A-Z a-z"])?;
+        git.run(&["config", "filter.missing.required", "true"])?;
+        git.run(&["config", "filter.broken.process", "exit 1"])?;
+
+        let repo = git.get_repo()?;
+        let smudge = |path: &str, content: &[u8]| -> Result<Vec<u8>, git2::Error> {
+            smudge(&repo, std::path::Path::new(path), content)
+        };
+        assert_eq!(smudge("a.txt", b"one\ntwo\r\n")?, b"one\r\ntwo\r\n");
+        let id = git2::Oid::hash_object(git2::ObjectType::Blob, b"$Id$\n")?;
+        let expanded = smudge("a.c", b"$Id$\n")?;
+        assert_eq!(expanded, format!("$Id: {id} $\n").as_bytes());
+        assert_eq!(
+            clean(&repo, std::path::Path::new("a.c"), &expanded)?,
+            b"$Id$\n"
+        );
+        #[cfg(unix)]
+        assert_eq!(smudge("a.up", b"WHISPER\n")?, b"whisper\n");
+        assert!(smudge("a.req", b"content").is_err());
+        #[cfg(unix)]
+        assert_eq!(smudge("a.proc", b"content")?, b"content");
+
+        // Matches what `git checkout` writes
+        std::fs::write(git.repo_path.join("crlf.txt"), b"one\r\ntwo\r\n")?;
+        git.run(&["add", "crlf.txt"])?;
+        let staged = git
+            .get_repo()?
+            .index()?
+            .get_path(std::path::Path::new("crlf.txt"), 0)
+            .expect("staged")
+            .id;
+        std::fs::remove_file(git.repo_path.join("crlf.txt"))?;
+        git.run(&["checkout", "--", "crlf.txt"])?;
+        assert_eq!(
+            read_blob(&repo, std::path::Path::new("crlf.txt"), staged)?,
+            std::fs::read(git.repo_path.join("crlf.txt"))?
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+//! The long-running filter protocol of `filter.<driver>.process`
+//!
+//! See `gitattributes(5)` and `gitprotocol-common(5)` for pkt-lines.
+
+use std::io::Read;
+use std::io::Write;
+
+use crate::utils::ChildProcess;
+
+/// The most data a pkt-line carries, after its 4 byte length
+const MAX_PACKET_DATA: usize = 65516;
+
+/// What a filter process did with one file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum FilterResponse {
+    Filtered(Vec<u8>),
+    /// The filter failed on this file
+    Error,
+    /// The filter gave up, for this and all later files
+    Abort,
+}
+
+/// A running `filter.<driver>.process`, kept around across files
+pub(crate) struct FilterProcess {
+    child: Option<Box<dyn ChildProcess>>,
+    capabilities: Vec<String>,
+}
+
+impl FilterProcess {
+    /// Start `command` in the working directory of `repo`
+    pub(crate) fn start(repo: &git2::Repository, command: &str) -> Result<Self, std::io::Error> {
+        let mut cmd = crate::utils::ProcessCommand::shell(command, std::iter::empty::<&str>())?
+            .stderr(crate::utils::ProcessStdio::Inherit);
+        if let Some(workdir) = repo.workdir() {
+            cmd = cmd.current_dir(workdir);
+        }
+        Self::handshake(crate::utils::spawn_process(cmd)?)
+    }
+
+    /// Agree on the protocol version and capabilities with a started process
+    pub(crate) fn handshake(mut child: Box<dyn ChildProcess>) -> Result<Self, std::io::Error> {
+        write_list(child.stdin(), &["git-filter-client", "version=2"])?;
+        let welcome = read_list(child.stdout())?;
+        if welcome.first().map(String::as_str) != Some("git-filter-server") {
+            return Err(protocol_error("expected `git-filter-server`"));
+        }
+        if !welcome.iter().any(|line| line == "version=2") {
+            return Err(protocol_error("expected `version=2`"));
+        }
+
+        write_list(child.stdin(), &["capability=clean", "capability=smudge"])?;
+        let capabilities = read_list(child.stdout())?
+            .into_iter()
+            .filter_map(|line| line.strip_prefix("capability=").map(str::to_owned))
+            .collect();
+
+        Ok(Self {
+            child: Some(child),
+            capabilities,
+        })
+    }
+
+    /// Whether the process handles `command`, like `clean` or `smudge`
+    pub(crate) fn supports(&self, command: &str) -> bool {
+        self.capabilities.iter().any(|c| c == command)
+    }
+
+    /// Have the process run `command` on the content of `path`
+    pub(crate) fn filter(
+        &mut self,
+        command: &str,
+        path: &std::path::Path,
+        content: &[u8],
+    ) -> Result<FilterResponse, std::io::Error> {
+        let child = self
+            .child
+            .as_mut()
+            .ok_or_else(|| protocol_error("filter process already exited"))?;
+        let pathname = String::from_utf8_lossy(&crate::bytes::path2bytes_lossy(path)).into_owned();
+        write_list(
+            child.stdin(),
+            &[
+                format!("command={command}").as_str(),
+                format!("pathname={pathname}").as_str(),
+            ],
+        )?;
+        for chunk in content.chunks(MAX_PACKET_DATA) {
+            write_packet(child.stdin(), chunk)?;
+        }
+        write_flush(child.stdin())?;
+        child.stdin().flush()?;
+
+        match read_status(child.stdout())? {
+            Some(FilterResponse::Filtered(_)) | None => {}
+            Some(response) => return Ok(response),
+        }
+        let mut filtered = Vec::new();
+        while let Some(packet) = read_packet(child.stdout())? {
+            filtered.extend_from_slice(&packet);
+        }
+        // The status may be updated after the content, an empty list keeping it as-is
+        match read_status(child.stdout())? {
+            Some(FilterResponse::Filtered(_)) | None => Ok(FilterResponse::Filtered(filtered)),
+            Some(response) => Ok(response),
+        }
+    }
+}
+
+impl Drop for FilterProcess {
+    fn drop(&mut self) {
+        // Closing stdin tells the process to exit
+        if let Some(child) = self.child.take() {
+            if let Err(err) = child.wait() {
+                log::debug!("filter process failed to exit: {err}");
+            }
+        }
+    }
+}
+
+fn read_status(reader: &mut dyn Read) -> Result<Option<FilterResponse>, std::io::Error> {
+    let mut response = None;
+    for line in read_list(reader)? {
+        response = match line.strip_prefix("status=") {
+            Some("success") => Some(FilterResponse::Filtered(Vec::new())),
+            Some("error") => Some(FilterResponse::Error),
+            Some("abort") => Some(FilterResponse::Abort),
+            Some(status) => return Err(protocol_error(&format!("unknown status `{status}`"))),
+            None => response,
+        };
+    }
+    Ok(response)
+}
+
+fn write_packet(writer: &mut dyn Write, data: &[u8]) -> Result<(), std::io::Error> {
+    let mut packet = format!("{:04x}", data.len() + 4).into_bytes();
+    packet.extend_from_slice(data);
+    writer.write_all(&packet)
+}
+
+fn write_flush(writer: &mut dyn Write) -> Result<(), std::io::Error> {
+    writer.write_all(b"0000")
+}
+
+fn write_list(writer: &mut dyn Write, lines: &[&str]) -> Result<(), std::io::Error> {
+    for line in lines {
+        write_packet(writer, format!("{line}\n").as_bytes())?;
+    }
+    write_flush(writer)?;
+    writer.flush()
+}
+
+/// The data of the next pkt-line, `None` for a flush
+fn read_packet(reader: &mut dyn Read) -> Result<Option<Vec<u8>>, std::io::Error> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = std::str::from_utf8(&len)
+        .ok()
+        .and_then(|len| usize::from_str_radix(len, 16).ok())
+        .ok_or_else(|| protocol_error("invalid pkt-line length"))?;
+    match len {
+        0 => Ok(None),
+        1..=4 => Err(protocol_error("invalid pkt-line length")),
+        _ => {
+            let mut data = vec![0; len - 4];
+            reader.read_exact(&mut data)?;
+            Ok(Some(data))
+        }
+    }
+}
+
+/// Text packets up to the next flush, without their trailing newlines
+fn read_list(reader: &mut dyn Read) -> Result<Vec<String>, std::io::Error> {
+    let mut lines = Vec::new();
+    while let Some(packet) = read_packet(reader)? {
+        let line = String::from_utf8_lossy(&packet);
+        lines.push(line.strip_suffix('\n').unwrap_or(&line).to_owned());
+    }
+    Ok(lines)
+}
+
+fn protocol_error(message: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("filter process protocol: {message}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A filter process that upper-cases content, answering requests as they are read
+    #[derive(Default)]
+    struct UpperFilter {
+        input: Vec<u8>,
+        output: std::io::Cursor<Vec<u8>>,
+        requests: usize,
+    }
+
+    impl UpperFilter {
+        fn respond(&mut self) -> Result<(), std::io::Error> {
+            let mut input = std::io::Cursor::new(std::mem::take(&mut self.input));
+            let mut output = Vec::new();
+            let request = read_list(&mut input)?;
+            match self.requests {
+                0 => {
+                    assert_eq!(request, ["git-filter-client", "version=2"]);
+                    write_list(&mut output, &["git-filter-server", "version=2"])?;
+                }
+                1 => write_list(&mut output, &["capability=clean"])?,
+                _ => {
+                    let mut content = Vec::new();
+                    while let Some(packet) = read_packet(&mut input)? {
+                        content.extend_from_slice(&packet);
+                    }
+                    if request.iter().any(|line| line == "pathname=error.txt") {
+                        write_list(&mut output, &["status=error"])?;
+                    } else {
+                        write_list(&mut output, &["status=success"])?;
+                        for chunk in content.to_ascii_uppercase().chunks(MAX_PACKET_DATA) {
+                            write_packet(&mut output, chunk)?;
+                        }
+                        write_flush(&mut output)?;
+                        write_list(&mut output, &[])?;
+                    }
+                }
+            }
+            self.requests += 1;
+            self.output = std::io::Cursor::new(output);
+            Ok(())
+        }
+    }
+
+    impl Read for UpperFilter {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.output.position() == self.output.get_ref().len() as u64 {
+                self.respond()?;
+            }
+            self.output.read(buf)
+        }
+    }
+
+    impl ChildProcess for UpperFilter {
+        fn stdin(&mut self) -> &mut dyn Write {
+            &mut self.input
+        }
+
+        fn stdout(&mut self) -> &mut dyn Read {
+            self
+        }
+
+        fn wait(self: Box<Self>) -> Result<crate::utils::ProcessStatus, std::io::Error> {
+            Ok(crate::utils::ProcessStatus::exited(0))
+        }
+    }
+
+    #[test]
+    fn test_filter_process() -> eyre::Result<()> {
+        let mut process = FilterProcess::handshake(Box::<UpperFilter>::default())?;
+        assert!(process.supports("clean"));
+        assert!(!process.supports("smudge"));
+
+        let path = std::path::Path::new("dir/a.txt");
+        assert_eq!(
+            process.filter("clean", path, b"shout\n")?,
+            FilterResponse::Filtered(b"SHOUT\n".to_vec())
+        );
+        let large = vec![b'a'; MAX_PACKET_DATA * 2 + 1];
+        assert_eq!(
+            process.filter("clean", path, &large)?,
+            FilterResponse::Filtered(large.to_ascii_uppercase())
+        );
+        assert_eq!(
+            process.filter("clean", std::path::Path::new("error.txt"), b"")?,
+            FilterResponse::Error
+        );
+
+        Ok(())
+    }
+}
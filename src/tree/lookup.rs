@@ -0,0 +1,79 @@
+/// A small least-recently-used cache of parsed trees, for one diff
+///
+/// Copied directories make the same subtree show up over and over, and `libgit2`'s own object
+/// cache only keeps small trees by default.  This makes sure each is looked up and parsed once.
+pub(crate) struct TreeLookup<'r> {
+    repo: &'r git2::Repository,
+    /// Most recently used last
+    trees: Vec<git2::Tree<'r>>,
+    capacity: usize,
+}
+
+impl<'r> TreeLookup<'r> {
+    /// Enough for the directories being worked on, while the linear search stays cheap next to
+    /// parsing a tree
+    const CAPACITY: usize = 64;
+
+    pub(crate) fn new(repo: &'r git2::Repository) -> Self {
+        Self::with_capacity(repo, Self::CAPACITY)
+    }
+
+    fn with_capacity(repo: &'r git2::Repository, capacity: usize) -> Self {
+        Self {
+            repo,
+            trees: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub(crate) fn find_tree(&mut self, id: git2::Oid) -> Result<git2::Tree<'r>, git2::Error> {
+        if let Some(index) = self.trees.iter().position(|tree| tree.id() == id) {
+            let tree = self.trees.remove(index);
+            self.trees.push(tree.clone());
+            return Ok(tree);
+        }
+        let tree = self.repo.find_tree(id)?;
+        if self.trees.len() == self.capacity {
+            self.trees.remove(0);
+        }
+        self.trees.push(tree.clone());
+        Ok(tree)
+    }
+
+    #[cfg(test)]
+    fn cached(&self) -> Vec<git2::Oid> {
+        self.trees.iter().map(|tree| tree.id()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_tree_lookup() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let empty = repo.treebuilder(None)?.write()?;
+        let a = crate::tree::TreeEditor::new(&repo, None)
+            .upsert_blob("a", "a")
+            .write()?;
+        let b = crate::tree::TreeEditor::new(&repo, None)
+            .upsert_blob("b", "b")
+            .write()?;
+
+        let mut lookup = TreeLookup::with_capacity(&repo, 2);
+        assert_eq!(lookup.find_tree(a)?.id(), a);
+        assert_eq!(lookup.find_tree(b)?.id(), b);
+        assert_eq!(lookup.find_tree(a)?.id(), a);
+        assert_eq!(lookup.cached(), [b, a]);
+        lookup.find_tree(empty)?;
+        assert_eq!(lookup.cached(), [a, empty]);
+        lookup.find_tree(git2::Oid::zero()).unwrap_err();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,638 @@
+/// How to [`merge`]
+#[derive(Clone, Debug)]
+pub struct MergeOptions {
+    find_renames: bool,
+    rename_threshold: u32,
+    file_favor: git2::FileFavor,
+}
+
+impl MergeOptions {
+    pub fn new() -> Self {
+        Self {
+            find_renames: true,
+            rename_threshold: 50,
+            file_favor: git2::FileFavor::Normal,
+        }
+    }
+
+    /// Detect renames so changes follow renamed files
+    ///
+    /// Defaults to `true`.
+    pub fn find_renames(mut self, yes: bool) -> Self {
+        self.find_renames = yes;
+        self
+    }
+
+    /// How similar, in percent, files must be to be considered renames
+    ///
+    /// Defaults to `50`, like `git`.
+    pub fn rename_threshold(mut self, threshold: u32) -> Self {
+        self.rename_threshold = threshold;
+        self
+    }
+
+    /// Resolve conflicting hunks in favor of one side (`-X ours` / `-X theirs`)
+    pub fn file_favor(mut self, favor: git2::FileFavor) -> Self {
+        self.file_favor = favor;
+        self
+    }
+
+    pub(crate) fn to_git2(&self) -> git2::MergeOptions {
+        let mut options = git2::MergeOptions::new();
+        options
+            .find_renames(self.find_renames)
+            .rename_threshold(self.rename_threshold)
+            .file_favor(self.file_favor);
+        options
+    }
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The result of [`merge`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TreeMerge {
+    /// The merged tree
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_oid"))]
+    Clean(git2::Oid),
+    Conflicted(Vec<TreeConflict>),
+}
+
+/// A path that couldn't be merged, with what each side has for it
+///
+/// A side is `None` when it doesn't have the path, like when one side deleted a file the other
+/// modified.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TreeConflict {
+    pub ancestor: Option<ConflictEntry>,
+    pub ours: Option<ConflictEntry>,
+    pub theirs: Option<ConflictEntry>,
+}
+
+impl TreeConflict {
+    /// The path of the conflict, preferring ours when a side renamed it
+    pub fn path(&self) -> &std::path::Path {
+        [&self.ours, &self.theirs, &self.ancestor]
+            .into_iter()
+            .flatten()
+            .map(|entry| entry.path.as_path())
+            .next()
+            .expect("a conflict has at least one side")
+    }
+}
+
+/// One side of a [`TreeConflict`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConflictEntry {
+    pub path: std::path::PathBuf,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_oid"))]
+    pub id: git2::Oid,
+    pub mode: i32,
+}
+
+impl ConflictEntry {
+    fn new(entry: &git2::IndexEntry) -> Self {
+        Self {
+            path: crate::bytes::bytes2path_lossy(&entry.path).into_owned(),
+            id: entry.id,
+            mode: entry.mode as i32,
+        }
+    }
+}
+
+/// Three-way merge `ours` and `theirs`, like `git merge-tree`
+///
+/// Rather than failing on conflicts, the conflicting paths are reported with each side's
+/// entries.  See [`render_conflict`] for turning a conflict into a file with conflict markers.
+///
+/// Files are merged with the built-in driver their `merge` attribute names: `text`, `binary`, or
+/// `union`.  Unlike `git`, a `merge.<driver>.driver` command from config is only run on the
+/// paths the built-in merge left conflicted, not on every path both sides changed.
+pub fn merge(
+    repo: &git2::Repository,
+    ancestor: &git2::Tree<'_>,
+    ours: &git2::Tree<'_>,
+    theirs: &git2::Tree<'_>,
+    options: &MergeOptions,
+) -> Result<TreeMerge, git2::Error> {
+    let mut index = merge_trees(repo, ancestor, ours, theirs, options)?;
+    if !index.has_conflicts() {
+        let tree_id = index.write_tree_to(repo)?;
+        return Ok(TreeMerge::Clean(tree_id));
+    }
+
+    let mut conflicts = Vec::new();
+    for conflict in index.conflicts()? {
+        let conflict = conflict?;
+        conflicts.push(TreeConflict {
+            ancestor: conflict.ancestor.as_ref().map(ConflictEntry::new),
+            ours: conflict.our.as_ref().map(ConflictEntry::new),
+            theirs: conflict.their.as_ref().map(ConflictEntry::new),
+        });
+    }
+    Ok(TreeMerge::Conflicted(conflicts))
+}
+
+/// [`git2::Repository::merge_trees`], then resolving what conflicts with custom merge drivers
+///
+/// `libgit2` only knows the built-in drivers and merges other files as text, so only the files it
+/// leaves conflicted get their `merge.<driver>.driver` command run.
+pub(crate) fn merge_trees(
+    repo: &git2::Repository,
+    ancestor: &git2::Tree<'_>,
+    ours: &git2::Tree<'_>,
+    theirs: &git2::Tree<'_>,
+    options: &MergeOptions,
+) -> Result<git2::Index, git2::Error> {
+    let mut index = repo.merge_trees(ancestor, ours, theirs, Some(&options.to_git2()))?;
+    run_merge_drivers(repo, &mut index)?;
+    Ok(index)
+}
+
+/// Resolve the conflicts of `index` that custom merge drivers can, see [`merge_trees`]
+pub(crate) fn run_merge_drivers(
+    repo: &git2::Repository,
+    index: &mut git2::Index,
+) -> Result<(), git2::Error> {
+    if !index.has_conflicts() {
+        return Ok(());
+    }
+
+    let conflicts = index.conflicts()?.collect::<Result<Vec<_>, _>>()?;
+    for conflict in conflicts {
+        // Only content conflicts go to drivers, not deletes or renames
+        let (Some(ours), Some(theirs)) = (conflict.our, conflict.their) else {
+            continue;
+        };
+        if ours.path != theirs.path {
+            continue;
+        }
+        let path = crate::bytes::bytes2path_lossy(&ours.path).into_owned();
+        let Some(command) = merge_driver(repo, &path)? else {
+            continue;
+        };
+        let ancestor = conflict.ancestor;
+        let Some(merged) =
+            run_merge_driver(repo, &command, &path, ancestor.as_ref(), &ours, &theirs)?
+        else {
+            continue;
+        };
+
+        let mut entry = ours;
+        // Back to stage 0
+        entry.flags &= !0x3000;
+        if ancestor.map(|ancestor| ancestor.mode) == Some(entry.mode) {
+            entry.mode = theirs.mode;
+        }
+        entry.id = repo.blob(&merged)?;
+        entry.file_size = merged.len() as u32;
+        index.remove_path(&path)?;
+        index.add(&entry)?;
+    }
+    Ok(())
+}
+
+/// Render `conflict` as a file with diff3-style conflict markers
+///
+/// Hunks only one side changed are merged, like `git merge-file --diff3`, so only the
+/// conflicting hunks get markers.  Markers are as long as the path's `conflict-marker-size`
+/// attribute, `7` by default.  A side without the path is treated as empty.
+pub fn render_conflict(
+    repo: &git2::Repository,
+    conflict: &TreeConflict,
+    ours_label: &str,
+    theirs_label: &str,
+) -> Result<Vec<u8>, git2::Error> {
+    let load = |entry: &Option<ConflictEntry>| -> Result<Vec<u8>, git2::Error> {
+        match entry {
+            Some(entry) => Ok(repo.find_blob(entry.id)?.content().to_vec()),
+            None => Ok(Vec::new()),
+        }
+    };
+    let marker_size = conflict_marker_size(repo, conflict.path())?;
+    let base = load(&conflict.ancestor)?;
+    let ours = load(&conflict.ours)?;
+    let theirs = load(&conflict.theirs)?;
+
+    let base_lines = lines(&base);
+    let ours_lines = lines(&ours);
+    let theirs_lines = lines(&theirs);
+    let ours_hunks = hunks(&base, &ours)?;
+    let theirs_hunks = hunks(&base, &theirs)?;
+
+    let mut output = Vec::new();
+    let mut base_pos = 0;
+    // How far each side's lines are shifted from the base, before the current hunk
+    let mut ours_delta = 0isize;
+    let mut theirs_delta = 0isize;
+    let (mut o, mut t) = (0, 0);
+    while o < ours_hunks.len() || t < theirs_hunks.len() {
+        // Gather a group of overlapping hunks from both sides
+        let mut start = usize::MAX;
+        let mut end = 0;
+        let (group_o, group_t) = (o, t);
+        loop {
+            let next_o = ours_hunks
+                .get(o)
+                .filter(|h| start == usize::MAX || h.overlaps(start, end));
+            let next_t = theirs_hunks
+                .get(t)
+                .filter(|h| start == usize::MAX || h.overlaps(start, end));
+            let hunk = match (next_o, next_t) {
+                (Some(ho), Some(ht)) if ho.base_start <= ht.base_start => {
+                    o += 1;
+                    ho
+                }
+                (Some(_), Some(ht)) => {
+                    t += 1;
+                    ht
+                }
+                (Some(ho), None) => {
+                    o += 1;
+                    ho
+                }
+                (None, Some(ht)) => {
+                    t += 1;
+                    ht
+                }
+                (None, None) => break,
+            };
+            start = start.min(hunk.base_start);
+            end = end.max(hunk.base_end);
+        }
+
+        output.extend(base_lines[base_pos..start].concat());
+        base_pos = end;
+        let side_range = |hunks: &[Hunk], delta: &mut isize| {
+            let side_start = start.saturating_add_signed(*delta);
+            for hunk in hunks {
+                *delta += hunk.side_len() as isize - hunk.base_len() as isize;
+            }
+            let side_end = end.saturating_add_signed(*delta);
+            side_start..side_end
+        };
+        let ours_range = side_range(&ours_hunks[group_o..o], &mut ours_delta);
+        let theirs_range = side_range(&theirs_hunks[group_t..t], &mut theirs_delta);
+        let ours_text = ours_lines[ours_range].concat();
+        let theirs_text = theirs_lines[theirs_range].concat();
+
+        if group_t == t {
+            output.extend(ours_text);
+        } else if group_o == o || ours_text == theirs_text {
+            output.extend(theirs_text);
+        } else {
+            let base_text = base_lines[start..end].concat();
+            push_marker(&mut output, b'<', marker_size, ours_label);
+            output.extend(ours_text);
+            push_marker(&mut output, b'|', marker_size, "base");
+            output.extend(base_text);
+            push_marker(&mut output, b'=', marker_size, "");
+            output.extend(theirs_text);
+            push_marker(&mut output, b'>', marker_size, theirs_label);
+        }
+    }
+    output.extend(base_lines[base_pos..].concat());
+
+    Ok(output)
+}
+
+/// The `merge.<driver>.driver` command for `path`, when its `merge` attribute names one
+fn merge_driver(
+    repo: &git2::Repository,
+    path: &std::path::Path,
+) -> Result<Option<String>, git2::Error> {
+    let value = repo.get_attr_bytes(path, "merge", git2::AttrCheckFlags::FILE_THEN_INDEX)?;
+    let git2::AttrValue::String(driver) = git2::AttrValue::from_bytes(value) else {
+        return Ok(None);
+    };
+    let config = repo.config()?;
+    crate::config::get_string(&config, &format!("merge.{driver}.driver"))
+}
+
+/// The `conflict-marker-size` attribute of `path`, defaulting to `7` like `git`
+fn conflict_marker_size(
+    repo: &git2::Repository,
+    path: &std::path::Path,
+) -> Result<usize, git2::Error> {
+    Ok(repo
+        .get_attr(
+            path,
+            "conflict-marker-size",
+            git2::AttrCheckFlags::FILE_THEN_INDEX,
+        )?
+        .and_then(|size| size.parse::<usize>().ok())
+        .filter(|size| 0 < *size)
+        .unwrap_or(7))
+}
+
+/// Run a merge driver `command` like `git` does, `None` when the driver reports a conflict
+///
+/// The sides are written to temporary files, passed as `%O` (ancestor), `%A` (ours), and `%B`
+/// (theirs), with the driver leaving the result in `%A`.  `%P` is the path, `%L` the conflict
+/// marker size, and `%S`, `%X`, and `%Y` label the ancestor, ours, and theirs.
+fn run_merge_driver(
+    repo: &git2::Repository,
+    command: &str,
+    path: &std::path::Path,
+    ancestor: Option<&git2::IndexEntry>,
+    ours: &git2::IndexEntry,
+    theirs: &git2::IndexEntry,
+) -> Result<Option<Vec<u8>>, git2::Error> {
+    let io_error = |err: std::io::Error| {
+        git2::Error::new(
+            git2::ErrorCode::GenericError,
+            git2::ErrorClass::Merge,
+            format!("merge driver failed for `{}`: {err}", path.display()),
+        )
+    };
+    let write_side = |entry: Option<&git2::IndexEntry>| -> Result<_, git2::Error> {
+        let mut file = tempfile::NamedTempFile::new().map_err(io_error)?;
+        if let Some(entry) = entry {
+            let blob = repo.find_blob(entry.id)?;
+            std::io::Write::write_all(&mut file, blob.content()).map_err(io_error)?;
+        }
+        Ok(file)
+    };
+    let ancestor_file = write_side(ancestor)?;
+    let ours_file = write_side(Some(ours))?;
+    let theirs_file = write_side(Some(theirs))?;
+
+    let marker_size = conflict_marker_size(repo, path)?;
+    // Placeholders are passed as arguments rather than quoted into the command
+    let mut script = String::with_capacity(command.len());
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            script.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('O') => script.push_str("\"$1\""),
+            Some('A') => script.push_str("\"$2\""),
+            Some('B') => script.push_str("\"$3\""),
+            Some('P') => script.push_str("\"$4\""),
+            Some('S') => script.push_str("\"$5\""),
+            Some('X') => script.push_str("\"$6\""),
+            Some('Y') => script.push_str("\"$7\""),
+            Some('L') => script.push_str(&marker_size.to_string()),
+            Some(other) => {
+                script.push('%');
+                if other != '%' {
+                    script.push(other);
+                }
+            }
+            None => script.push('%'),
+        }
+    }
+
+    let sh = crate::utils::git_sh().ok_or_else(|| {
+        io_error(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "No `sh` for running merge drivers",
+        ))
+    })?;
+    let mut cmd = crate::utils::ProcessCommand::new(sh)
+        .arg("-c")
+        .arg(script)
+        .arg(command)
+        .arg(ancestor_file.path())
+        .arg(ours_file.path())
+        .arg(theirs_file.path())
+        .arg(path)
+        .args(["base", "ours", "theirs"])
+        .stdout(crate::utils::ProcessStdio::Stderr)
+        .stderr(crate::utils::ProcessStdio::Inherit);
+    if let Some(workdir) = repo.workdir() {
+        cmd = cmd.current_dir(workdir);
+    }
+    let output = crate::utils::run_process(cmd).map_err(io_error)?;
+    if !output.status.success() {
+        log::debug!(
+            "merge driver for `{}` left conflicts ({})",
+            path.display(),
+            output.status
+        );
+        return Ok(None);
+    }
+    let merged = std::fs::read(ours_file.path()).map_err(io_error)?;
+    Ok(Some(merged))
+}
+
+fn lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|b| *b == b'\n').collect()
+}
+
+/// A change from the base, in lines
+#[derive(Copy, Clone, Debug)]
+struct Hunk {
+    base_start: usize,
+    base_end: usize,
+    side_start: usize,
+    side_end: usize,
+}
+
+impl Hunk {
+    fn base_len(&self) -> usize {
+        self.base_end - self.base_start
+    }
+
+    fn side_len(&self) -> usize {
+        self.side_end - self.side_start
+    }
+
+    /// Adjacent changes conflict too, like in `git`
+    fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.base_start <= end && start <= self.base_end
+    }
+}
+
+fn hunks(base: &[u8], side: &[u8]) -> Result<Vec<Hunk>, git2::Error> {
+    let mut options = git2::DiffOptions::new();
+    options.context_lines(0).interhunk_lines(0);
+    let patch = git2::Patch::from_buffers(base, None, side, None, Some(&mut options))?;
+    let mut hunks = Vec::with_capacity(patch.num_hunks());
+    for i in 0..patch.num_hunks() {
+        let (hunk, _) = patch.hunk(i)?;
+        // Line numbers are 1-based, except an empty range is the line it comes after
+        let range = |start: u32, len: u32| {
+            let start = start as usize;
+            let len = len as usize;
+            if len == 0 {
+                (start, start)
+            } else {
+                (start - 1, start - 1 + len)
+            }
+        };
+        let (base_start, base_end) = range(hunk.old_start(), hunk.old_lines());
+        let (side_start, side_end) = range(hunk.new_start(), hunk.new_lines());
+        hunks.push(Hunk {
+            base_start,
+            base_end,
+            side_start,
+            side_end,
+        });
+    }
+    Ok(hunks)
+}
+
+fn push_marker(output: &mut Vec<u8>, marker: u8, size: usize, label: &str) {
+    if output.last().is_some_and(|b| *b != b'\n') {
+        output.push(b'\n');
+    }
+    output.extend(std::iter::repeat(marker).take(size));
+    if !label.is_empty() {
+        output.push(b' ');
+        output.extend(label.as_bytes());
+    }
+    output.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_merge() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.write_file("shared", "one\ntwo\nthree\nfour\nfive\n")?;
+        git.write_file("clean", "clean\n")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "base"])?;
+        git.run(&["branch", "base"])?;
+
+        git.write_file("shared", "ONE\ntwo\nthree\nfour\nours\n")?;
+        git.run(&["commit", "-am", "ours"])?;
+        git.run(&["branch", "ours"])?;
+
+        git.run(&["checkout", "-q", "-b", "theirs", "base"])?;
+        git.write_file("shared", "one\ntwo\nthree\nfour\ntheirs\n")?;
+        git.write_file("clean", "clean\ntheirs\n")?;
+        git.run(&["commit", "-am", "theirs"])?;
+
+        let repo = git.get_repo()?;
+        let tree = |name: &str| repo.revparse_single(name)?.peel_to_tree();
+        let (base, ours, theirs) = (tree("base")?, tree("ours")?, tree("HEAD")?);
+
+        let TreeMerge::Conflicted(conflicts) =
+            merge(&repo, &base, &ours, &theirs, &MergeOptions::new())?
+        else {
+            panic!("expected conflicts");
+        };
+        assert_eq!(conflicts.len(), 1);
+        let conflict = &conflicts[0];
+        assert_eq!(conflict.path(), std::path::Path::new("shared.txt"));
+        assert_eq!(
+            conflict.ours.as_ref().map(|e| e.id),
+            Some(ours.get_path(conflict.path())?.id())
+        );
+        let rendered = render_conflict(&repo, conflict, "ours", "theirs")?;
+        assert_eq!(
+            String::from_utf8(rendered)?,
+            "\
+ONE
+two
+three
+four
+<<<<<<< ours
+ours
+||||||| base
+five
+=======
+theirs
+>>>>>>> theirs
+"
+        );
+        std::fs::write(
+            git.repo_path.join(".gitattributes"),
+            "shared.txt conflict-marker-size=3\n",
+        )?;
+        let rendered = render_conflict(&repo, conflict, "ours", "theirs")?;
+        assert_eq!(
+            String::from_utf8(rendered)?,
+            "ONE\ntwo\nthree\nfour\n<<< ours\nours\n||| base\nfive\n===\ntheirs\n>>> theirs\n"
+        );
+        std::fs::remove_file(git.repo_path.join(".gitattributes"))?;
+
+        let TreeMerge::Clean(merged) = merge(
+            &repo,
+            &base,
+            &ours,
+            &theirs,
+            &MergeOptions::new().file_favor(git2::FileFavor::Ours),
+        )?
+        else {
+            panic!("expected a clean merge");
+        };
+        let merged = repo.find_tree(merged)?;
+        let shared = merged.get_path(std::path::Path::new("shared.txt"))?;
+        assert_eq!(
+            repo.find_blob(shared.id())?.content(),
+            b"ONE\ntwo\nthree\nfour\nours\n"
+        );
+        let clean = merged.get_path(std::path::Path::new("clean.txt"))?;
+        assert_eq!(repo.find_blob(clean.id())?.content(), b"clean\ntheirs\n");
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_merge_drivers() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        std::fs::write(
+            git.repo_path.join(".gitattributes"),
+            "custom.txt merge=concat\nfailing.txt merge=failing\nunion.txt merge=union\n",
+        )?;
+        git.run(&[
+            "config",
+            "merge.concat.driver",
+            "cat %B >> %A && echo %P %L >> %A",
+        ])?;
+        git.run(&["config", "merge.failing.driver", "exit 1"])?;
+        for name in ["custom", "failing", "union"] {
+            git.write_file(name, "base\n")?;
+        }
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "base"])?;
+        git.run(&["branch", "base"])?;
+        for name in ["custom", "failing", "union"] {
+            git.write_file(name, "ours\n")?;
+        }
+        git.run(&["commit", "-am", "ours"])?;
+        git.run(&["branch", "ours"])?;
+        git.run(&["checkout", "-q", "-b", "theirs", "base"])?;
+        for name in ["custom", "failing", "union"] {
+            git.write_file(name, "theirs\n")?;
+        }
+        git.run(&["commit", "-am", "theirs"])?;
+
+        let repo = git.get_repo()?;
+        let tree = |name: &str| repo.revparse_single(name)?.peel_to_tree();
+        let (base, ours, theirs) = (tree("base")?, tree("ours")?, tree("HEAD")?);
+        let index = merge_trees(&repo, &base, &ours, &theirs, &MergeOptions::new())?;
+        let merged = |path: &str| -> eyre::Result<Vec<u8>> {
+            let entry = index
+                .get_path(std::path::Path::new(path), 0)
+                .ok_or_else(|| eyre::eyre!("{path} is conflicted"))?;
+            Ok(repo.find_blob(entry.id)?.content().to_vec())
+        };
+        assert_eq!(merged("custom.txt")?, b"ours\ntheirs\ncustom.txt 7\n");
+        assert_eq!(merged("union.txt")?, b"ours\ntheirs\n");
+        let conflicts = index.conflicts()?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].our.as_ref().unwrap().path, b"failing.txt");
+
+        Ok(())
+    }
+}
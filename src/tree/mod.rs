@@ -0,0 +1,2210 @@
+//! Lower-level Tree operations
+
+mod apply;
+mod cache;
+mod editor;
+mod filter;
+mod filter_process;
+mod lookup;
+mod merge;
+mod sparse;
+mod stats;
+mod status;
+mod store;
+mod walk;
+
+pub use apply::apply_patch;
+pub use apply::AppliedPatch;
+pub use apply::ApplyOptions;
+pub use apply::RejectedHunk;
+pub use cache::get_changed_entries_between_trees_cached;
+pub use cache::TreeDiffCache;
+pub use editor::TreeEditor;
+pub use filter::clean;
+pub use filter::read_blob;
+pub use filter::smudge;
+pub use filter::write_blob;
+pub use filter::FilterDrivers;
+pub use merge::merge;
+pub(crate) use merge::merge_trees;
+pub use merge::render_conflict;
+pub(crate) use merge::run_merge_drivers;
+pub use merge::ConflictEntry;
+pub use merge::MergeOptions;
+pub use merge::TreeConflict;
+pub use merge::TreeMerge;
+pub use sparse::SparseCheckout;
+pub use stats::diff_stats;
+pub use stats::is_binary_change;
+pub use stats::DiffStats;
+pub use stats::FileStat;
+pub use status::status_against;
+pub use status::PathStatus;
+pub use store::changed_entries_in;
+pub use walk::walk;
+pub use walk::TreeEntryInfo;
+pub use walk::TreeWalk;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+
+use itertools::Itertools;
+
+/// How a path differs between two trees, see [`get_changed_entries_between_trees`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChangeKind {
+    Added,
+    Deleted,
+    /// The content changed (and possibly the executable bit)
+    Modified,
+    /// Only the file mode changed, like the executable bit
+    ModeChanged,
+    /// The kind of entry changed, like a file becoming a symlink or a directory
+    TypeChanged,
+    /// A submodule now points at a different commit, see [`is_gitlink`]
+    SubmoduleChanged,
+    /// A deleted and an added file were paired up by [`detect_renames`]
+    Renamed {
+        from: std::path::PathBuf,
+        to: std::path::PathBuf,
+        /// How much of the content is the same, from `0` to `100`
+        similarity: u8,
+    },
+}
+
+/// A path that differs between two trees
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChangedEntry {
+    pub path: std::path::PathBuf,
+    pub kind: ChangeKind,
+    /// `None` when the path was added
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_oid::option"))]
+    pub old_id: Option<git2::Oid>,
+    /// `None` when the path was deleted
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_oid::option"))]
+    pub new_id: Option<git2::Oid>,
+    pub old_mode: Option<i32>,
+    pub new_mode: Option<i32>,
+    /// Whether Git LFS stores the path, so its blobs are pointer files rather than content
+    ///
+    /// This is only checked with [`ChangedEntriesOptions::flag_lfs`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub lfs: bool,
+}
+
+impl ChangedEntry {
+    fn new(
+        path: std::path::PathBuf,
+        old: Option<(git2::Oid, i32)>,
+        new: Option<(git2::Oid, i32)>,
+    ) -> Self {
+        const TYPE_MASK: i32 = 0o170000;
+        let kind = match (old, new) {
+            (None, _) => ChangeKind::Added,
+            (_, None) => ChangeKind::Deleted,
+            (Some((_, old_mode)), Some((_, new_mode)))
+                if old_mode & TYPE_MASK != new_mode & TYPE_MASK =>
+            {
+                ChangeKind::TypeChanged
+            }
+            (Some((old_id, old_mode)), Some((new_id, _)))
+                if old_id != new_id && is_gitlink(old_mode) =>
+            {
+                ChangeKind::SubmoduleChanged
+            }
+            (Some((old_id, _)), Some((new_id, _))) if old_id != new_id => ChangeKind::Modified,
+            _ => ChangeKind::ModeChanged,
+        };
+        Self {
+            path,
+            kind,
+            old_id: old.map(|(id, _)| id),
+            new_id: new.map(|(id, _)| id),
+            old_mode: old.map(|(_, mode)| mode),
+            new_mode: new.map(|(_, mode)| mode),
+            lfs: false,
+        }
+    }
+}
+
+/// Whether a tree entry's mode is for a submodule's commit, rather than a file or directory
+///
+/// The object for a gitlink is in the submodule's repository, so it usually can't be looked up.
+pub fn is_gitlink(mode: i32) -> bool {
+    mode == i32::from(git2::FileMode::Commit)
+}
+
+/// The mode `git` would write for `mode`, or `None` if it isn't a kind of entry `git` supports
+///
+/// Only the kind of entry and, for files, the owner's executable bit are kept, like `git`
+/// canonicalizes modes from the filesystem, so `0o100664` becomes `0o100644`.
+pub fn normalize_mode(mode: i32) -> Option<git2::FileMode> {
+    const TYPE_MASK: i32 = 0o170000;
+    match mode & TYPE_MASK {
+        0o100000 if mode & 0o100 != 0 => Some(git2::FileMode::BlobExecutable),
+        0o100000 => Some(git2::FileMode::Blob),
+        0o120000 => Some(git2::FileMode::Link),
+        0o040000 => Some(git2::FileMode::Tree),
+        0o160000 => Some(git2::FileMode::Commit),
+        _ => None,
+    }
+}
+
+/// Whether `mode` is one `git` writes: `100644`, `100755`, `120000`, `160000`, or `40000`
+pub fn is_valid_mode(mode: i32) -> bool {
+    normalize_mode(mode).is_some_and(|normalized| i32::from(normalized) == mode)
+}
+
+/// This function is a hot code path. Do not annotate with `#[instrument]`, and
+/// be mindful of performance/memory allocations.
+///
+/// Subtrees are queued rather than recursed into so deeply nested trees can't overflow the
+/// stack.  The diff stops once `changes` asks to.
+fn get_changed_paths_between_trees_internal<'r>(
+    repo: &'r git2::Repository,
+    changes: &mut Changes<'_>,
+    pathspec: Option<&Pathspec>,
+    lhs: Option<&git2::Tree<'r>>,
+    rhs: Option<&git2::Tree<'r>>,
+) -> Result<(), git2::Error> {
+    let mut trees = lookup::TreeLookup::new(repo);
+    diff_trees(&mut trees, changes, pathspec, lhs.cloned(), rhs.cloned())
+}
+
+/// Diff `lhs` and `rhs`, looking up their subtrees in `trees`
+fn diff_trees<T: DiffTree>(
+    trees: &mut impl FindTree<T>,
+    changes: &mut Changes<'_>,
+    pathspec: Option<&Pathspec>,
+    lhs: Option<T>,
+    rhs: Option<T>,
+) -> Result<(), git2::Error> {
+    let mut pending = PendingTrees::default();
+    pending.push(b"", lhs, rhs);
+    let mut current_path = Vec::new();
+    while let Some((lhs, rhs)) = pending.pop(&mut current_path) {
+        if changes.is_done() {
+            break;
+        }
+        get_changed_paths_between_subtrees(
+            trees,
+            changes,
+            &mut pending,
+            &mut current_path,
+            pathspec,
+            lhs.as_ref(),
+            rhs.as_ref(),
+        )?;
+    }
+    Ok(())
+}
+
+/// A tree for [`diff_trees`], like a [`git2::Tree`]
+trait DiffTree {
+    type Entry<'t>: DiffEntry
+    where
+        Self: 't;
+
+    /// The entries, by their plain names
+    ///
+    /// Trees are sorted with directories as if they end in `/`, which puts a file and a
+    /// directory of the same name in different places, so entries are paired up by their plain
+    /// names instead.
+    fn sorted_entries(&self) -> Vec<Self::Entry<'_>>;
+}
+
+trait DiffEntry {
+    fn name(&self) -> &[u8];
+
+    fn id(&self) -> git2::Oid;
+
+    fn mode(&self) -> i32;
+
+    fn is_tree(&self) -> bool;
+}
+
+/// Where [`diff_trees`] looks up subtrees
+trait FindTree<T> {
+    fn find_tree(&mut self, id: git2::Oid) -> Result<T, git2::Error>;
+}
+
+impl DiffTree for git2::Tree<'_> {
+    type Entry<'t>
+        = git2::TreeEntry<'t>
+    where
+        Self: 't;
+
+    fn sorted_entries(&self) -> Vec<git2::TreeEntry<'_>> {
+        let mut entries = self.iter().collect_vec();
+        entries.sort_by(|a, b| a.name_bytes().cmp(b.name_bytes()));
+        entries
+    }
+}
+
+impl DiffEntry for git2::TreeEntry<'_> {
+    fn name(&self) -> &[u8] {
+        self.name_bytes()
+    }
+
+    fn id(&self) -> git2::Oid {
+        self.id()
+    }
+
+    fn mode(&self) -> i32 {
+        self.filemode_raw()
+    }
+
+    fn is_tree(&self) -> bool {
+        // `kind` comes from the mode, so gitlinks are `Commit` and never looked up
+        self.kind() == Some(git2::ObjectType::Tree)
+    }
+}
+
+impl<'r> FindTree<git2::Tree<'r>> for lookup::TreeLookup<'r> {
+    fn find_tree(&mut self, id: git2::Oid) -> Result<git2::Tree<'r>, git2::Error> {
+        lookup::TreeLookup::find_tree(self, id)
+    }
+}
+
+/// Collect up to `limit` changes
+fn collect_changed_entries<'r>(
+    repo: &'r git2::Repository,
+    limit: usize,
+    pathspec: Option<&Pathspec>,
+    lhs: Option<&git2::Tree<'r>>,
+    rhs: Option<&git2::Tree<'r>>,
+) -> Result<Vec<ChangedEntry>, git2::Error> {
+    let mut acc = Vec::new();
+    if limit == 0 {
+        return Ok(acc);
+    }
+    let mut visit = |entry| {
+        acc.push(entry);
+        if limit <= acc.len() {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    };
+    get_changed_paths_between_trees_internal(
+        repo,
+        &mut Changes::new(&mut visit),
+        pathspec,
+        lhs,
+        rhs,
+    )?;
+    Ok(acc)
+}
+
+/// Where [`get_changed_paths_between_subtrees`] reports changes
+struct Changes<'a> {
+    visit: &'a mut dyn FnMut(ChangedEntry) -> ControlFlow<()>,
+    done: bool,
+}
+
+impl<'a> Changes<'a> {
+    fn new(visit: &'a mut dyn FnMut(ChangedEntry) -> ControlFlow<()>) -> Self {
+        Self { visit, done: false }
+    }
+
+    fn push(&mut self, entry: ChangedEntry) {
+        if (self.visit)(entry).is_break() {
+            self.done = true;
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+/// Subtrees left to diff, most recently queued last
+///
+/// Their paths are packed into one buffer rather than allocated one by one.
+struct PendingTrees<T> {
+    /// The subtrees' paths, back to back
+    paths: Vec<u8>,
+    /// Where each subtree's path starts in `paths`, alongside the subtrees
+    trees: Vec<(usize, Option<T>, Option<T>)>,
+}
+
+impl<T> Default for PendingTrees<T> {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            trees: Vec::new(),
+        }
+    }
+}
+
+impl<T> PendingTrees<T> {
+    fn push(&mut self, path: &[u8], lhs: Option<T>, rhs: Option<T>) {
+        self.trees.push((self.paths.len(), lhs, rhs));
+        self.paths.extend_from_slice(path);
+    }
+
+    /// Take the most recently queued subtrees, replacing `path` with where they are
+    fn pop(&mut self, path: &mut Vec<u8>) -> Option<(Option<T>, Option<T>)> {
+        let (start, lhs, rhs) = self.trees.pop()?;
+        path.clear();
+        path.extend_from_slice(&self.paths[start..]);
+        self.paths.truncate(start);
+        Some((lhs, rhs))
+    }
+}
+
+/// Diff one level of a tree, queuing changed subtrees in `pending`
+///
+/// `current_path` is where the trees are, as `/`-separated bytes.  Entries' paths are built on
+/// the end of it, and only copied out for changes being reported.
+fn get_changed_paths_between_subtrees<T: DiffTree>(
+    trees: &mut impl FindTree<T>,
+    acc: &mut Changes<'_>,
+    pending: &mut PendingTrees<T>,
+    current_path: &mut Vec<u8>,
+    pathspec: Option<&Pathspec>,
+    lhs: Option<&T>,
+    rhs: Option<&T>,
+) -> Result<(), git2::Error> {
+    let lhs_entries = lhs.map(DiffTree::sorted_entries).unwrap_or_default();
+    let rhs_entries = rhs.map(DiffTree::sorted_entries).unwrap_or_default();
+
+    let dir_len = current_path.len();
+    let paired = lhs_entries
+        .iter()
+        .merge_join_by(rhs_entries.iter(), |lhs, rhs| lhs.name().cmp(rhs.name()));
+    for pair in paired {
+        if acc.is_done() {
+            break;
+        }
+        let (lhs_entry, rhs_entry) = pair.left_and_right();
+        let entry_name = lhs_entry
+            .or(rhs_entry)
+            .expect("one side is always present")
+            .name();
+        current_path.truncate(dir_len);
+        if dir_len != 0 {
+            current_path.push(b'/');
+        }
+        current_path.extend_from_slice(entry_name);
+
+        enum ClassifiedEntry {
+            Absent,
+            /// A file, symlink, or gitlink, which are only compared by id and mode
+            NotATree(git2::Oid, i32),
+            Tree(git2::Oid, i32),
+        }
+
+        impl ClassifiedEntry {
+            fn id_mode(&self) -> Option<(git2::Oid, i32)> {
+                match self {
+                    Self::Absent => None,
+                    Self::NotATree(oid, file_mode) | Self::Tree(oid, file_mode) => {
+                        Some((*oid, *file_mode))
+                    }
+                }
+            }
+        }
+
+        fn classify_entry(entry: Option<&impl DiffEntry>) -> Result<ClassifiedEntry, git2::Error> {
+            let entry = match entry {
+                Some(entry) => entry,
+                None => return Ok(ClassifiedEntry::Absent),
+            };
+
+            let file_mode = entry.mode();
+            if entry.is_tree() {
+                Ok(ClassifiedEntry::Tree(entry.id(), file_mode))
+            } else {
+                Ok(ClassifiedEntry::NotATree(entry.id(), file_mode))
+            }
+        }
+
+        let full_entry_path =
+            || -> std::path::PathBuf { crate::bytes::bytes2path_lossy(current_path).into_owned() };
+        let lhs_entry = classify_entry(lhs_entry)?;
+        let rhs_entry = classify_entry(rhs_entry)?;
+        // Whether to report this entry and whether to look inside it, when it is a directory
+        let (wanted, descend) = match pathspec {
+            Some(pathspec) => {
+                let path = crate::bytes::bytes2path_lossy(current_path);
+                let is_tree = matches!(lhs_entry, ClassifiedEntry::Tree(..))
+                    || matches!(rhs_entry, ClassifiedEntry::Tree(..));
+                let wanted = pathspec.matches(&path);
+                let descend = is_tree && pathspec.may_match_under(&path);
+                if !wanted && !descend {
+                    continue;
+                }
+                (wanted, descend)
+            }
+            None => (true, true),
+        };
+        let changed_entry =
+            |path| ChangedEntry::new(path, lhs_entry.id_mode(), rhs_entry.id_mode());
+        match (&lhs_entry, &rhs_entry) {
+            (ClassifiedEntry::Absent, ClassifiedEntry::Absent) => {
+                // Shouldn't happen, but there's no issue here.
+            }
+
+            (
+                ClassifiedEntry::NotATree(lhs_oid, lhs_file_mode),
+                ClassifiedEntry::NotATree(rhs_oid, rhs_file_mode),
+            ) => {
+                if lhs_oid == rhs_oid && lhs_file_mode == rhs_file_mode {
+                    // Unchanged file, do nothing.
+                } else {
+                    // Changed file.
+                    if wanted {
+                        acc.push(changed_entry(full_entry_path()));
+                    }
+                }
+            }
+
+            (ClassifiedEntry::Absent, ClassifiedEntry::NotATree(_, _))
+            | (ClassifiedEntry::NotATree(_, _), ClassifiedEntry::Absent) => {
+                // Added, removed, or changed file.
+                if wanted {
+                    acc.push(changed_entry(full_entry_path()));
+                }
+            }
+
+            (ClassifiedEntry::Absent, ClassifiedEntry::Tree(tree_oid, _)) => {
+                // A directory was added. Add all entries from that directory.
+                if descend {
+                    let tree = trees.find_tree(*tree_oid)?;
+                    pending.push(current_path, None, Some(tree));
+                }
+            }
+
+            (ClassifiedEntry::Tree(tree_oid, _), ClassifiedEntry::Absent) => {
+                // A directory was removed. Add all entries from that directory.
+                if descend {
+                    let tree = trees.find_tree(*tree_oid)?;
+                    pending.push(current_path, Some(tree), None);
+                }
+            }
+
+            (ClassifiedEntry::NotATree(_, _), ClassifiedEntry::Tree(tree_oid, _)) => {
+                // A file was changed into a directory. Add both the file and
+                // all subdirectory entries as changed entries.
+                if descend {
+                    let tree = trees.find_tree(*tree_oid)?;
+                    pending.push(current_path, None, Some(tree));
+                }
+                if wanted {
+                    acc.push(changed_entry(full_entry_path()));
+                }
+            }
+
+            (ClassifiedEntry::Tree(tree_oid, _), ClassifiedEntry::NotATree(_, _)) => {
+                // A directory was changed into a file. Add both the file and
+                // all subdirectory entries as changed entries.
+                if descend {
+                    let tree = trees.find_tree(*tree_oid)?;
+                    pending.push(current_path, Some(tree), None);
+                }
+                if wanted {
+                    acc.push(changed_entry(full_entry_path()));
+                }
+            }
+
+            (
+                ClassifiedEntry::Tree(lhs_tree_oid, lhs_file_mode),
+                ClassifiedEntry::Tree(rhs_tree_oid, rhs_file_mode),
+            ) => {
+                match (
+                    (lhs_tree_oid == rhs_tree_oid),
+                    // Note that there should only be one possible file mode for
+                    // an entry which points to a tree, but it's possible that
+                    // some extra non-meaningful bits are set. Should we report
+                    // a change in that case? This code takes the conservative
+                    // approach and reports a change.
+                    (lhs_file_mode == rhs_file_mode),
+                ) {
+                    (true, true) => {
+                        // Unchanged entry, do nothing.
+                    }
+
+                    (true, false) => {
+                        // Only the directory changed, but none of its contents.
+                        if wanted {
+                            acc.push(changed_entry(full_entry_path()));
+                        }
+                    }
+
+                    (false, true) if !descend => {}
+
+                    (false, true) => {
+                        let lhs_tree = trees.find_tree(*lhs_tree_oid)?;
+                        let rhs_tree = trees.find_tree(*rhs_tree_oid)?;
+
+                        // Only include the files changed in the subtrees, and
+                        // not the directory itself.
+                        pending.push(current_path, Some(lhs_tree), Some(rhs_tree));
+                    }
+
+                    (false, false) => {
+                        if descend {
+                            let lhs_tree = trees.find_tree(*lhs_tree_oid)?;
+                            let rhs_tree = trees.find_tree(*rhs_tree_oid)?;
+                            pending.push(current_path, Some(lhs_tree), Some(rhs_tree));
+                        }
+                        if wanted {
+                            acc.push(changed_entry(full_entry_path()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    current_path.truncate(dir_len);
+
+    Ok(())
+}
+
+pub fn get_changed_paths_between_trees(
+    repo: &git2::Repository,
+    lhs: Option<&git2::Tree<'_>>,
+    rhs: Option<&git2::Tree<'_>>,
+) -> Result<HashSet<std::path::PathBuf>, git2::Error> {
+    let acc = collect_changed_entries(repo, usize::MAX, None, lhs, rhs)?;
+    let changed_paths: HashSet<_> = acc.into_iter().map(|entry| entry.path).collect();
+    Ok(changed_paths)
+}
+
+/// Call `visit` for each changed path, without collecting them
+///
+/// Return [`ControlFlow::Break`] from `visit` to stop the diff early, like when only checking
+/// whether any path under a directory changed.  The result says whether the diff was stopped.
+/// Paths are visited in an unspecified order.
+pub fn visit_changed_paths(
+    repo: &git2::Repository,
+    lhs: Option<&git2::Tree<'_>>,
+    rhs: Option<&git2::Tree<'_>>,
+    mut visit: impl FnMut(&std::path::Path, ChangeKind) -> ControlFlow<()>,
+) -> Result<ControlFlow<()>, git2::Error> {
+    let mut visit = |entry: ChangedEntry| visit(&entry.path, entry.kind);
+    let mut changes = Changes::new(&mut visit);
+    get_changed_paths_between_trees_internal(repo, &mut changes, None, lhs, rhs)?;
+    if changes.is_done() {
+        Ok(ControlFlow::Break(()))
+    } else {
+        Ok(ControlFlow::Continue(()))
+    }
+}
+
+/// Whether anything differs between the trees, stopping at the first change
+pub fn trees_differ(
+    repo: &git2::Repository,
+    lhs: Option<&git2::Tree<'_>>,
+    rhs: Option<&git2::Tree<'_>>,
+) -> Result<bool, git2::Error> {
+    if lhs.map(|tree| tree.id()) == rhs.map(|tree| tree.id()) {
+        return Ok(false);
+    }
+    Ok(!changed_paths_limited(repo, lhs, rhs, 1)?.is_empty())
+}
+
+/// Like [`get_changed_paths_between_trees`] but stops after finding `limit` paths
+///
+/// Which paths are found first is unspecified.
+pub fn changed_paths_limited(
+    repo: &git2::Repository,
+    lhs: Option<&git2::Tree<'_>>,
+    rhs: Option<&git2::Tree<'_>>,
+    limit: usize,
+) -> Result<HashSet<std::path::PathBuf>, git2::Error> {
+    let acc = collect_changed_entries(repo, limit, None, lhs, rhs)?;
+    let changed_paths: HashSet<_> = acc.into_iter().map(|entry| entry.path).collect();
+    Ok(changed_paths)
+}
+
+/// Like [`get_changed_paths_between_trees`] but reporting how each path changed
+///
+/// Entries are sorted by path.  As with [`get_changed_paths_between_trees`], a file turning
+/// into a directory (or the reverse) is reported as a [`ChangeKind::TypeChanged`] entry for the
+/// path along with an entry for each file inside the directory.
+pub fn get_changed_entries_between_trees(
+    repo: &git2::Repository,
+    lhs: Option<&git2::Tree<'_>>,
+    rhs: Option<&git2::Tree<'_>>,
+) -> Result<Vec<ChangedEntry>, git2::Error> {
+    let mut acc = collect_changed_entries(repo, usize::MAX, None, lhs, rhs)?;
+    acc.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(acc)
+}
+
+/// How to [`get_changed_entries_between_trees_with`]
+#[derive(Clone, Debug, Default)]
+pub struct ChangedEntriesOptions {
+    pathspec: Option<Pathspec>,
+    flag_lfs: bool,
+}
+
+impl ChangedEntriesOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only report paths matching `pathspec`, see [`get_changed_paths_between_trees_matching`]
+    pub fn pathspec(mut self, pathspec: Pathspec) -> Self {
+        self.pathspec = Some(pathspec);
+        self
+    }
+
+    /// Set [`ChangedEntry::lfs`] for files `.gitattributes` have Git LFS store
+    ///
+    /// See [`crate::ops::is_lfs_tracked`].  This looks up attributes for each changed file.
+    pub fn flag_lfs(mut self, yes: bool) -> Self {
+        self.flag_lfs = yes;
+        self
+    }
+}
+
+/// Like [`get_changed_entries_between_trees`] but with `options`
+pub fn get_changed_entries_between_trees_with(
+    repo: &git2::Repository,
+    lhs: Option<&git2::Tree<'_>>,
+    rhs: Option<&git2::Tree<'_>>,
+    options: ChangedEntriesOptions,
+) -> Result<Vec<ChangedEntry>, git2::Error> {
+    let mut acc = collect_changed_entries(repo, usize::MAX, options.pathspec.as_ref(), lhs, rhs)?;
+    if options.flag_lfs {
+        for entry in &mut acc {
+            // Directories, symlinks, and submodules aren't stored by LFS
+            let is_file = [entry.old_mode, entry.new_mode]
+                .into_iter()
+                .flatten()
+                .any(|mode| {
+                    mode == i32::from(git2::FileMode::Blob)
+                        || mode == i32::from(git2::FileMode::BlobExecutable)
+                });
+            entry.lfs = is_file && crate::ops::is_lfs_tracked(repo, &entry.path)?;
+        }
+    }
+    acc.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(acc)
+}
+
+/// Like [`get_changed_paths_between_trees`] but only for paths matching `pathspec`
+///
+/// Directories that can't contain a match aren't diffed at all.
+pub fn get_changed_paths_between_trees_matching(
+    repo: &git2::Repository,
+    lhs: Option<&git2::Tree<'_>>,
+    rhs: Option<&git2::Tree<'_>>,
+    pathspec: &Pathspec,
+) -> Result<HashSet<std::path::PathBuf>, git2::Error> {
+    let acc = collect_changed_entries(repo, usize::MAX, Some(pathspec), lhs, rhs)?;
+    let changed_paths: HashSet<_> = acc.into_iter().map(|entry| entry.path).collect();
+    Ok(changed_paths)
+}
+
+/// How to find the paths that changed between two trees, see
+/// [`get_changed_paths_between_trees_with`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DiffStrategy {
+    /// Pick one based on the trees and pathspec being compared
+    #[default]
+    Auto,
+    /// Walk both trees in step, skipping subtrees that are the same on both sides
+    ///
+    /// Best in most cases, like a commit and its parent, or listing a whole tree.
+    TreeWalk,
+    /// Use [`git2::Diff`], with `libgit2` limiting it to the pathspec
+    ///
+    /// Slightly faster when every path of a tree has to be checked against wildcards.
+    Diff,
+}
+
+impl DiffStrategy {
+    fn resolve(
+        self,
+        lhs: Option<&git2::Tree<'_>>,
+        rhs: Option<&git2::Tree<'_>>,
+        pathspec: Option<&Pathspec>,
+    ) -> Self {
+        match self {
+            // With nothing to skip on either side, every path is matched and `libgit2`s
+            // wildcard matching is cheaper than ours
+            Self::Auto
+                if (lhs.is_none() || rhs.is_none())
+                    && pathspec.is_some_and(Pathspec::has_leading_wildcard) =>
+            {
+                Self::Diff
+            }
+            Self::Auto => Self::TreeWalk,
+            strategy => strategy,
+        }
+    }
+}
+
+/// Like [`get_changed_paths_between_trees_matching`] but choosing how the trees are diffed
+///
+/// The strategies report the same paths, except that [`DiffStrategy::Diff`] doesn't report a
+/// directory whose mode changed without its contents changing, which `git` never writes.
+pub fn get_changed_paths_between_trees_with(
+    repo: &git2::Repository,
+    lhs: Option<&git2::Tree<'_>>,
+    rhs: Option<&git2::Tree<'_>>,
+    pathspec: Option<&Pathspec>,
+    strategy: DiffStrategy,
+) -> Result<HashSet<std::path::PathBuf>, git2::Error> {
+    match strategy.resolve(lhs, rhs, pathspec) {
+        DiffStrategy::Auto | DiffStrategy::TreeWalk => {
+            let acc = collect_changed_entries(repo, usize::MAX, pathspec, lhs, rhs)?;
+            Ok(acc.into_iter().map(|entry| entry.path).collect())
+        }
+        DiffStrategy::Diff => {
+            let mut options = git2::DiffOptions::new();
+            // Only paths are wanted, so don't load blobs to check for binary content
+            options.skip_binary_check(true);
+            if let Some(pathspec) = pathspec {
+                for spec in &pathspec.include {
+                    options.pathspec(spec);
+                }
+            }
+            let diff = repo.diff_tree_to_tree(lhs, rhs, Some(&mut options))?;
+            let mut changed_paths = HashSet::with_capacity(diff.deltas().len());
+            for delta in diff.deltas() {
+                let Some(path) = delta.new_file().path().or_else(|| delta.old_file().path()) else {
+                    continue;
+                };
+                // Exclusions, and wildcards `libgit2` treats differently, are left to `Pathspec`
+                if let Some(pathspec) = pathspec {
+                    if !pathspec.matches(path) {
+                        continue;
+                    }
+                }
+                changed_paths.insert(path.to_owned());
+            }
+            Ok(changed_paths)
+        }
+    }
+}
+
+/// Which paths to look at, like `git`s pathspecs
+///
+/// - A path without wildcards matches itself and everything under it, like `src/`
+/// - `*` and `?` are wildcards that also match `/`, so `*.rs` matches `src/lib.rs`
+/// - `:!<pathspec>` (or `:^<pathspec>`) excludes paths
+///
+/// With no pathspecs (besides exclusions), everything matches.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pathspec {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl Pathspec {
+    pub fn new<S: AsRef<str>>(specs: impl IntoIterator<Item = S>) -> Self {
+        let mut pathspec = Self::default();
+        for spec in specs {
+            let spec = spec.as_ref();
+            let excluded = [":!", ":^", ":(exclude)"]
+                .into_iter()
+                .find_map(|magic| spec.strip_prefix(magic));
+            match excluded {
+                Some(spec) => pathspec.exclude.push(Self::normalize(spec)),
+                None => pathspec.include.push(Self::normalize(spec)),
+            }
+        }
+        pathspec
+    }
+
+    fn normalize(spec: &str) -> String {
+        spec.trim_start_matches("./")
+            .trim_end_matches('/')
+            .to_owned()
+    }
+
+    fn is_wildcard(spec: &str) -> bool {
+        spec.contains(['*', '?'])
+    }
+
+    /// Whether a pathspec could match at the top of the tree, so no directory can be skipped
+    fn has_leading_wildcard(&self) -> bool {
+        self.include.iter().any(|spec| {
+            spec.find(['*', '?'])
+                .is_some_and(|i| !spec[..i].contains('/'))
+        })
+    }
+
+    fn spec_matches(spec: &str, path: &std::path::Path) -> bool {
+        if Self::is_wildcard(spec) {
+            crate::utils::wildcard_match(spec, &path.to_string_lossy(), false)
+        } else {
+            path.starts_with(spec)
+        }
+    }
+
+    /// Whether `path` is selected
+    pub fn matches(&self, path: &std::path::Path) -> bool {
+        if self
+            .exclude
+            .iter()
+            .any(|spec| Self::spec_matches(spec, path))
+        {
+            return false;
+        }
+        self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|spec| Self::spec_matches(spec, path))
+    }
+
+    /// Whether anything under the directory `dir` could be selected
+    pub fn may_match_under(&self, dir: &std::path::Path) -> bool {
+        let excluded = self
+            .exclude
+            .iter()
+            .any(|spec| !Self::is_wildcard(spec) && dir.starts_with(spec));
+        if excluded {
+            return false;
+        }
+        self.include.is_empty()
+            || self.include.iter().any(|spec| {
+                // Only the directories before the first wildcard are fixed
+                let literal = match spec.find(['*', '?']) {
+                    Some(wildcard) => spec[..wildcard]
+                        .rsplit_once('/')
+                        .map(|(dirs, _)| dirs)
+                        .unwrap_or_default(),
+                    None => spec.as_str(),
+                };
+                let literal = std::path::Path::new(literal);
+                dir.starts_with(literal) || literal.starts_with(dir)
+            })
+    }
+}
+
+/// Pair up deleted and added files in `entries` as [`ChangeKind::Renamed`], like `git diff -M`
+///
+/// Files with identical content are paired first.  The rest are scored by how many of the
+/// lines of the deleted file are in the added file and paired, best first, when the score is at
+/// least `threshold` percent (`git` defaults to 50).  Scoring compares every deleted file with
+/// every added file, so large diffs may want to only check for exact renames with a
+/// `threshold` of `100`.
+pub fn detect_renames(
+    repo: &git2::Repository,
+    entries: Vec<ChangedEntry>,
+    threshold: u8,
+) -> Result<Vec<ChangedEntry>, git2::Error> {
+    let is_file = |mode: Option<i32>| {
+        mode.is_some_and(|mode| {
+            mode == i32::from(git2::FileMode::Blob)
+                || mode == i32::from(git2::FileMode::BlobExecutable)
+                || mode == i32::from(git2::FileMode::Link)
+        })
+    };
+    let deleted: Vec<usize> = (0..entries.len())
+        .filter(|i| entries[*i].kind == ChangeKind::Deleted && is_file(entries[*i].old_mode))
+        .collect();
+    let added: Vec<usize> = (0..entries.len())
+        .filter(|i| entries[*i].kind == ChangeKind::Added && is_file(entries[*i].new_mode))
+        .collect();
+    if deleted.is_empty() || added.is_empty() {
+        return Ok(entries);
+    }
+
+    // (deleted, added, similarity)
+    let mut pairs = Vec::new();
+    let mut paired_deleted = HashSet::new();
+    let mut paired_added = HashSet::new();
+    for added_index in &added {
+        let new_id = entries[*added_index].new_id;
+        let exact = deleted
+            .iter()
+            .find(|i| !paired_deleted.contains(*i) && entries[**i].old_id == new_id);
+        if let Some(deleted_index) = exact {
+            paired_deleted.insert(*deleted_index);
+            paired_added.insert(*added_index);
+            pairs.push((*deleted_index, *added_index, 100));
+        }
+    }
+
+    if threshold < 100 {
+        let load = |id: Option<git2::Oid>| -> Result<Vec<u8>, git2::Error> {
+            let id = id.expect("files have ids");
+            Ok(repo.find_blob(id)?.content().to_vec())
+        };
+        let mut candidates = Vec::new();
+        for deleted_index in deleted.iter().filter(|i| !paired_deleted.contains(*i)) {
+            let old_content = load(entries[*deleted_index].old_id)?;
+            for added_index in added.iter().filter(|i| !paired_added.contains(*i)) {
+                let new_content = load(entries[*added_index].new_id)?;
+                let similarity = similarity(&old_content, &new_content);
+                if threshold <= similarity {
+                    candidates.push((*deleted_index, *added_index, similarity));
+                }
+            }
+        }
+        // Best matches first, keeping the diff order for ties
+        candidates.sort_by_key(|(_, _, similarity)| std::cmp::Reverse(*similarity));
+        for (deleted_index, added_index, similarity) in candidates {
+            if paired_deleted.contains(&deleted_index) || paired_added.contains(&added_index) {
+                continue;
+            }
+            paired_deleted.insert(deleted_index);
+            paired_added.insert(added_index);
+            pairs.push((deleted_index, added_index, similarity));
+        }
+    }
+
+    let mut renamed: HashMap<usize, ChangedEntry> = pairs
+        .into_iter()
+        .map(|(deleted_index, added_index, similarity)| {
+            let from = &entries[deleted_index];
+            let to = &entries[added_index];
+            let entry = ChangedEntry {
+                path: to.path.clone(),
+                kind: ChangeKind::Renamed {
+                    from: from.path.clone(),
+                    to: to.path.clone(),
+                    similarity,
+                },
+                old_id: from.old_id,
+                new_id: to.new_id,
+                old_mode: from.old_mode,
+                new_mode: to.new_mode,
+                lfs: from.lfs || to.lfs,
+            };
+            (added_index, entry)
+        })
+        .collect();
+    let entries = entries
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !paired_deleted.contains(i))
+        .map(|(i, entry)| renamed.remove(&i).unwrap_or(entry))
+        .collect();
+    Ok(entries)
+}
+
+/// Percent of `old`s lines, by size, that are still in `new`
+fn similarity(old: &[u8], new: &[u8]) -> u8 {
+    let max_len = old.len().max(new.len());
+    if max_len == 0 {
+        return 100;
+    }
+    let mut old_lines: HashMap<&[u8], usize> = HashMap::new();
+    for line in old.split_inclusive(|b| *b == b'\n') {
+        *old_lines.entry(line).or_default() += 1;
+    }
+    let mut common = 0;
+    for line in new.split_inclusive(|b| *b == b'\n') {
+        if let Some(count) = old_lines.get_mut(line) {
+            if 0 < *count {
+                *count -= 1;
+                common += line.len();
+            }
+        }
+    }
+    u8::try_from(common * 100 / max_len).unwrap_or(100)
+}
+
+/// Add the provided entries into the tree.
+///
+/// If the provided `Tree` is `None`, then this function adds the entries to the
+/// empty tree.
+///
+/// The paths for the provided entries can contain slashes.
+///
+/// If the value for an entry is `None`, then that element in the tree is
+/// removed. If a directory ever becomes empty, then it's removed from its
+/// parent directory.
+///
+/// If a path for a given entry is already present in the provided tree, then
+/// that entry is overwritten.
+///
+/// If a path refers to intermediate directories that don't exist in the
+/// provided tree, then those intermediate directories are created.
+///
+/// Modes are normalized with [`normalize_mode`] and modes `git` doesn't support are an error.
+pub fn rebuild_tree<'r>(
+    repo: &'r git2::Repository,
+    tree: Option<&git2::Tree<'r>>,
+    entries: HashMap<std::path::PathBuf, Option<(git2::Oid, i32)>>,
+) -> Result<git2::Oid, git2::Error> {
+    rebuild_tree_from_updates(
+        repo,
+        tree,
+        entries
+            .iter()
+            .map(|(path, value)| (path.as_path(), value.map(TreeUpdate::from))),
+    )
+}
+
+/// What to put at a path, see [`rebuild_tree_from_updates`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TreeUpdate {
+    pub id: git2::Oid,
+    pub mode: i32,
+}
+
+impl From<(git2::Oid, i32)> for TreeUpdate {
+    fn from((id, mode): (git2::Oid, i32)) -> Self {
+        Self { id, mode }
+    }
+}
+
+/// Like [`rebuild_tree`] but borrowing the paths to update
+///
+/// This avoids building intermediate maps per directory and reuses `TreeBuilder`s between
+/// directories, for when there are many entries.  When a path is listed more than once, the
+/// last update wins.
+pub fn rebuild_tree_from_updates<'r, 'p>(
+    repo: &'r git2::Repository,
+    tree: Option<&git2::Tree<'r>>,
+    entries: impl IntoIterator<Item = (&'p std::path::Path, Option<TreeUpdate>)>,
+) -> Result<git2::Oid, git2::Error> {
+    let mut updates: Vec<(Vec<&std::ffi::OsStr>, Option<TreeUpdate>)> = entries
+        .into_iter()
+        .filter_map(|(path, update)| {
+            let components = path.components().map(|c| c.as_os_str()).collect_vec();
+            if components.is_empty() {
+                log::trace!("Empty path when hydrating tree");
+                return None;
+            }
+            Some((components, update))
+        })
+        .collect();
+    // Stable, so the last update to a path stays last
+    updates.sort_by(|(lhs, _), (rhs, _)| lhs.cmp(rhs));
+
+    let mut builders = Vec::new();
+    let (tree_id, _) = rebuild_subtree(repo, tree, &updates, 0, &mut builders)?;
+    Ok(tree_id)
+}
+
+/// Apply `updates`, sorted by path, to the directory at `depth`
+///
+/// Returns the new tree and whether it is empty.
+fn rebuild_subtree<'r>(
+    repo: &'r git2::Repository,
+    tree: Option<&git2::Tree<'r>>,
+    updates: &[(Vec<&std::ffi::OsStr>, Option<TreeUpdate>)],
+    depth: usize,
+    builders: &mut Vec<git2::TreeBuilder<'r>>,
+) -> Result<(git2::Oid, bool), git2::Error> {
+    let mut builder = match builders.pop() {
+        Some(mut builder) => {
+            builder.clear()?;
+            builder
+        }
+        None => repo.treebuilder(None)?,
+    };
+    if let Some(tree) = tree {
+        for entry in tree.iter() {
+            builder.insert(entry.name_bytes(), entry.id(), entry.filemode_raw())?;
+        }
+    }
+
+    let mut start = 0;
+    while start < updates.len() {
+        let name = updates[start].0[depth];
+        let end = start
+            + updates[start..]
+                .iter()
+                .take_while(|(components, _)| components[depth] == name)
+                .count();
+        // Sorting puts the entry for `name` itself before entries inside of it
+        let direct = updates[start..end]
+            .iter()
+            .take_while(|(components, _)| components.len() == depth + 1)
+            .count();
+        let (direct, nested) = updates[start..end].split_at(direct);
+
+        if let Some((_, update)) = direct.last() {
+            match update {
+                Some(update) => {
+                    let Some(mode) = normalize_mode(update.mode) else {
+                        let path: std::path::PathBuf = direct[0].0.iter().collect();
+                        return Err(git2::Error::new(
+                            git2::ErrorCode::Invalid,
+                            git2::ErrorClass::Tree,
+                            format!(
+                                "invalid file mode {:o} for `{}`",
+                                update.mode,
+                                path.display()
+                            ),
+                        ));
+                    };
+                    builder.insert(name, update.id, mode.into())?;
+                }
+                None => {
+                    remove_entry_if_exists(&mut builder, std::path::Path::new(name))?;
+                }
+            }
+        }
+
+        if !nested.is_empty() {
+            let existing_dir_entry: Option<git2::Tree<'_>> = match builder.get(name)? {
+                Some(existing_dir_entry) if is_gitlink(existing_dir_entry.filemode_raw()) => {
+                    let path: std::path::PathBuf = nested[0].0[..=depth].iter().collect();
+                    return Err(git2::Error::new(
+                        git2::ErrorCode::Invalid,
+                        git2::ErrorClass::Tree,
+                        format!("cannot update paths inside submodule `{}`", path.display()),
+                    ));
+                }
+                Some(existing_dir_entry)
+                    if !existing_dir_entry.id().is_zero()
+                        && existing_dir_entry.kind() == Some(git2::ObjectType::Tree) =>
+                {
+                    Some(repo.find_tree(existing_dir_entry.id())?)
+                }
+                _ => None,
+            };
+            let (new_entry_oid, is_empty) = rebuild_subtree(
+                repo,
+                existing_dir_entry.as_ref(),
+                nested,
+                depth + 1,
+                builders,
+            )?;
+            if is_empty {
+                remove_entry_if_exists(&mut builder, std::path::Path::new(name))?;
+            } else {
+                builder.insert(name, new_entry_oid, git2::FileMode::Tree.into())?;
+            }
+        }
+
+        start = end;
+    }
+
+    let is_empty = builder.is_empty();
+    let tree_oid = builder.write()?;
+    builders.push(builder);
+    Ok((tree_oid, is_empty))
+}
+
+/// `libgit2` raises an error if the entry isn't present, but that's often not
+/// an error condition here. We may be referring to a created or deleted path,
+/// which wouldn't exist in one of the pre-/post-patch trees.
+fn remove_entry_if_exists(
+    builder: &mut git2::TreeBuilder<'_>,
+    name: &std::path::Path,
+) -> Result<(), git2::Error> {
+    if builder.get(name)?.is_some() {
+        builder.remove(name)?;
+    }
+    Ok(())
+}
+
+/// Filter the entries in the provided tree by only keeping the provided paths.
+///
+/// If a provided path does not appear in the tree at all, then it's ignored.
+pub fn filter_tree<'r>(
+    repo: &'r git2::Repository,
+    tree: &git2::Tree<'r>,
+    paths: &[&std::path::Path],
+) -> Result<git2::Oid, git2::Error> {
+    let entries: HashMap<std::path::PathBuf, Option<(git2::Oid, i32)>> = paths
+        .iter()
+        .map(|path| -> Result<(std::path::PathBuf, _), git2::Error> {
+            let key = path.to_path_buf();
+            match tree.get_path(path) {
+                Ok(tree_entry) => {
+                    let value = Some((tree_entry.id(), tree_entry.filemode()));
+                    Ok((key, value))
+                }
+                Err(err) if err.code() == git2::ErrorCode::NotFound => Ok((key, None)),
+                Err(err) => Err(err),
+            }
+        })
+        .try_collect()?;
+
+    rebuild_tree(repo, None, entries)
+}
+
+/// Remove the provided paths from the tree, the inverse of [`filter_tree`]
+///
+/// Paths may be files or whole directories.  Directories left empty are removed.  If a provided
+/// path does not appear in the tree at all, then it's ignored.
+pub fn exclude_paths<'r>(
+    repo: &'r git2::Repository,
+    tree: &git2::Tree<'r>,
+    paths: &[&std::path::Path],
+) -> Result<git2::Oid, git2::Error> {
+    rebuild_tree_from_updates(repo, Some(tree), paths.iter().map(|path| (*path, None)))
+}
+
+/// The tree at `prefix`, like `git subtree split` does for each commit
+///
+/// If `prefix` doesn't exist or isn't a directory, the empty tree is returned.
+pub fn extract_subtree(
+    repo: &git2::Repository,
+    tree: &git2::Tree<'_>,
+    prefix: &std::path::Path,
+) -> Result<git2::Oid, git2::Error> {
+    if prefix.components().next().is_none() {
+        return Ok(tree.id());
+    }
+    match tree.get_path(prefix) {
+        Ok(entry) if entry.kind() == Some(git2::ObjectType::Tree) => Ok(entry.id()),
+        Ok(_) => repo.treebuilder(None)?.write(),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => repo.treebuilder(None)?.write(),
+        Err(err) => Err(err),
+    }
+}
+
+/// Wrap `tree` in directories so it is at `prefix`
+///
+/// The result only contains `tree`, see [`graft_tree`] for adding it to another tree.
+pub fn prefix_tree(
+    repo: &git2::Repository,
+    tree: &git2::Tree<'_>,
+    prefix: &std::path::Path,
+) -> Result<git2::Oid, git2::Error> {
+    let mut tree_id = tree.id();
+    for component in prefix.components().rev() {
+        let mut builder = repo.treebuilder(None)?;
+        builder.insert(component.as_os_str(), tree_id, git2::FileMode::Tree.into())?;
+        tree_id = builder.write()?;
+    }
+    Ok(tree_id)
+}
+
+/// Add `subtree` to `tree` at `prefix`, like `git subtree add`
+///
+/// `subtree` may come from another repository, as long as its objects have been fetched into
+/// `repo`.  Fails with [`git2::ErrorCode::Exists`] if `prefix` is already in `tree`.
+pub fn graft_tree<'r>(
+    repo: &'r git2::Repository,
+    tree: &git2::Tree<'r>,
+    subtree: &git2::Tree<'_>,
+    prefix: &std::path::Path,
+) -> Result<git2::Oid, git2::Error> {
+    if prefix.components().next().is_none() {
+        return Err(git2::Error::new(
+            git2::ErrorCode::Invalid,
+            git2::ErrorClass::Tree,
+            "a prefix is required to graft a tree",
+        ));
+    }
+    match tree.get_path(prefix) {
+        Ok(_) => {
+            return Err(git2::Error::new(
+                git2::ErrorCode::Exists,
+                git2::ErrorClass::Tree,
+                format!("`{}` already exists", prefix.display()),
+            ));
+        }
+        Err(err) if err.code() == git2::ErrorCode::NotFound => {}
+        Err(err) => return Err(err),
+    }
+
+    rebuild_tree_from_updates(
+        repo,
+        Some(tree),
+        [(
+            prefix,
+            Some(TreeUpdate {
+                id: subtree.id(),
+                mode: git2::FileMode::Tree.into(),
+            }),
+        )],
+    )
+}
+
+/// What to put at a path, see [`from_entries`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Content {
+    /// A file, written as a new blob
+    Bytes { data: Vec<u8>, executable: bool },
+    /// A file as it would be in the working tree, written like `git add` would, see [`clean`]
+    Worktree { data: Vec<u8>, executable: bool },
+    /// A symbolic link pointing at the target
+    Symlink(std::path::PathBuf),
+    /// An object already in the repository, like a blob, tree, or submodule commit
+    ExistingOid { id: git2::Oid, mode: i32 },
+}
+
+impl Content {
+    /// A regular, non-executable file
+    pub fn file(data: impl Into<Vec<u8>>) -> Self {
+        Self::Bytes {
+            data: data.into(),
+            executable: false,
+        }
+    }
+}
+
+/// Build a tree from scratch, writing blobs as needed
+///
+/// Intermediate directories are created.  When a path is listed more than once, the last entry
+/// wins.
+pub fn from_entries<P: AsRef<std::path::Path>>(
+    repo: &git2::Repository,
+    entries: impl IntoIterator<Item = (P, Content)>,
+) -> Result<git2::Oid, git2::Error> {
+    let mut drivers = FilterDrivers::new();
+    let updates: Vec<(P, TreeUpdate)> = entries
+        .into_iter()
+        .map(|(path, content)| -> Result<_, git2::Error> {
+            let update = match content {
+                Content::Bytes { data, executable } => TreeUpdate {
+                    id: repo.blob(&data)?,
+                    mode: if executable {
+                        git2::FileMode::BlobExecutable.into()
+                    } else {
+                        git2::FileMode::Blob.into()
+                    },
+                },
+                Content::Worktree { data, executable } => TreeUpdate {
+                    id: drivers.write_blob(repo, path.as_ref(), &data)?,
+                    mode: if executable {
+                        git2::FileMode::BlobExecutable.into()
+                    } else {
+                        git2::FileMode::Blob.into()
+                    },
+                },
+                Content::Symlink(target) => TreeUpdate {
+                    id: repo.blob(&crate::bytes::path2bytes_lossy(&target))?,
+                    mode: git2::FileMode::Link.into(),
+                },
+                Content::ExistingOid { id, mode } => TreeUpdate { id, mode },
+            };
+            Ok((path, update))
+        })
+        .try_collect()?;
+
+    rebuild_tree_from_updates(
+        repo,
+        None,
+        updates
+            .iter()
+            .map(|(path, update)| (path.as_ref(), Some(*update))),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use snapbox::assert_data_eq;
+    use snapbox::prelude::*;
+    use snapbox::str;
+
+    use super::*;
+
+    use crate::testing::make_git;
+
+    fn dump_tree_entries(tree: &git2::Tree<'_>) -> String {
+        #[allow(clippy::format_collect)]
+        tree.iter()
+            .map(|entry| format!("{:?} {:?}\n", entry.name().unwrap(), entry.id()))
+            .collect()
+    }
+
+    #[test]
+    fn test_rebuild_tree() -> eyre::Result<()> {
+        let git = make_git()?;
+
+        git.init_repo()?;
+
+        git.write_file("foo", "foo")?;
+        git.write_file("bar/bar", "bar")?;
+        git.write_file("bar/baz", "qux")?;
+        git.write_file("xyzzy", "xyzzy")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "commit"])?;
+
+        let repo = git.get_repo()?;
+        let head_oid = repo.head()?.target().unwrap();
+        let head_commit = repo.find_commit(head_oid)?;
+        let head_tree = head_commit.tree()?;
+
+        assert_data_eq!(
+            dump_tree_entries(&head_tree),
+            str![[r#"
+"bar" 778e23a1e80b1feb10e00b15b29a33315929c5b5
+"foo.txt" 19102815663d23f8b75a47e7a01965dcdc96468c
+"initial.txt" 63af22885f8665a312ba8b83db722134f1f8290d
+"xyzzy.txt" 7c465afc533f95ff7d2c91e18921f94aac8292fc
+
+"#]]
+        );
+
+        {
+            let hydrated_tree = {
+                let hydrated_tree_oid = rebuild_tree(&repo, Some(&head_tree), {
+                    let mut result = HashMap::new();
+                    result.insert(
+                        std::path::PathBuf::from("foo-copy.txt"),
+                        Some((
+                            head_tree
+                                .get_path(&std::path::PathBuf::from("foo.txt"))?
+                                .id(),
+                            0o100644,
+                        )),
+                    );
+                    result.insert(std::path::PathBuf::from("foo.txt"), None);
+                    result
+                })?;
+                repo.find_tree(hydrated_tree_oid)?
+            };
+            assert_data_eq!(
+                dump_tree_entries(&hydrated_tree),
+                str![[r#"
+"bar" 778e23a1e80b1feb10e00b15b29a33315929c5b5
+"foo-copy.txt" 19102815663d23f8b75a47e7a01965dcdc96468c
+"initial.txt" 63af22885f8665a312ba8b83db722134f1f8290d
+"xyzzy.txt" 7c465afc533f95ff7d2c91e18921f94aac8292fc
+
+"#]]
+            );
+        }
+
+        {
+            let hydrated_tree = {
+                let hydrated_tree_oid = rebuild_tree(&repo, Some(&head_tree), {
+                    let mut result = HashMap::new();
+                    result.insert(std::path::PathBuf::from("bar/bar.txt"), None);
+                    result
+                })?;
+                repo.find_tree(hydrated_tree_oid)?
+            };
+            assert_data_eq!(
+                dump_tree_entries(&hydrated_tree),
+                str![[r#"
+"bar" 08ee88e1c53fbd01ab76f136a4f2c9d759b981d0
+"foo.txt" 19102815663d23f8b75a47e7a01965dcdc96468c
+"initial.txt" 63af22885f8665a312ba8b83db722134f1f8290d
+"xyzzy.txt" 7c465afc533f95ff7d2c91e18921f94aac8292fc
+
+"#]]
+            );
+        }
+
+        {
+            let hydrated_tree = {
+                let hydrated_tree_oid = rebuild_tree(&repo, Some(&head_tree), {
+                    let mut result = HashMap::new();
+                    result.insert(std::path::PathBuf::from("bar/bar.txt"), None);
+                    result.insert(std::path::PathBuf::from("bar/baz.txt"), None);
+                    result
+                })?;
+                repo.find_tree(hydrated_tree_oid)?
+            };
+            assert_data_eq!(
+                dump_tree_entries(&hydrated_tree),
+                str![[r#"
+"foo.txt" 19102815663d23f8b75a47e7a01965dcdc96468c
+"initial.txt" 63af22885f8665a312ba8b83db722134f1f8290d
+"xyzzy.txt" 7c465afc533f95ff7d2c91e18921f94aac8292fc
+
+"#]]
+            );
+        }
+
+        {
+            let dehydrated_tree_oid = filter_tree(
+                &repo,
+                &head_tree,
+                &[
+                    std::path::Path::new("bar/baz.txt"),
+                    std::path::Path::new("foo.txt"),
+                ],
+            )?;
+            let dehydrated_tree = repo.find_tree(dehydrated_tree_oid)?;
+            assert_data_eq!(
+                dump_tree_entries(&dehydrated_tree),
+                str![[r#"
+"bar" 08ee88e1c53fbd01ab76f136a4f2c9d759b981d0
+"foo.txt" 19102815663d23f8b75a47e7a01965dcdc96468c
+
+"#]]
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_path_only_changed_file_mode() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+
+        git.run(&["update-index", "--chmod=+x", "initial.txt"])?;
+        git.run(&["commit", "-m", "update file mode"])?;
+
+        let repo = git.get_repo()?;
+        let oid = repo.head()?.target().unwrap();
+        let commit = repo.find_commit(oid)?;
+
+        let lhs = commit.parent(0).unwrap();
+        let lhs_tree = lhs.tree()?;
+        let rhs_tree = commit.tree()?;
+        let changed_paths =
+            get_changed_paths_between_trees(&repo, Some(&lhs_tree), Some(&rhs_tree))?;
+        let mut changed_paths = changed_paths
+            .into_iter()
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>();
+        changed_paths.sort();
+
+        assert_data_eq!(
+            changed_paths.to_debug(),
+            str![[r#"
+[
+    "initial.txt",
+]
+
+"#]]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_changed_entries() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+
+        git.write_file("modified", "before")?;
+        git.write_file("deleted", "deleted")?;
+        git.write_file("becomes-dir", "file")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "before"])?;
+
+        git.write_file("modified", "after")?;
+        git.write_file("added", "added")?;
+        git.run(&["rm", "-q", "deleted.txt", "becomes-dir.txt"])?;
+        git.write_file("becomes-dir.txt/inner", "inner")?;
+        git.run(&["add", "."])?;
+        git.run(&["update-index", "--chmod=+x", "initial.txt"])?;
+        git.run(&["commit", "-m", "after"])?;
+
+        let repo = git.get_repo()?;
+        let commit = repo.head()?.peel_to_commit()?;
+        let lhs_tree = commit.parent(0)?.tree()?;
+        let rhs_tree = commit.tree()?;
+        let changed = get_changed_entries_between_trees(&repo, Some(&lhs_tree), Some(&rhs_tree))?;
+        let summary = changed
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{} {:?} {} -> {}",
+                    entry.path.display(),
+                    entry.kind,
+                    entry.old_mode.map(|m| format!("{m:o}")).unwrap_or_default(),
+                    entry.new_mode.map(|m| format!("{m:o}")).unwrap_or_default()
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_data_eq!(
+            summary.to_debug(),
+            str![[r#"
+[
+    "added.txt Added  -> 100644",
+    "becomes-dir.txt TypeChanged 100644 -> 40000",
+    "becomes-dir.txt/inner.txt Added  -> 100644",
+    "deleted.txt Deleted 100644 -> ",
+    "initial.txt ModeChanged 100644 -> 100755",
+    "modified.txt Modified 100644 -> 100644",
+]
+
+"#]]
+        );
+        let modified = &changed[5];
+        assert_eq!(
+            modified.old_id,
+            Some(
+                lhs_tree
+                    .get_path(std::path::Path::new("modified.txt"))?
+                    .id()
+            )
+        );
+        assert_eq!(
+            modified.new_id,
+            Some(
+                rhs_tree
+                    .get_path(std::path::Path::new("modified.txt"))?
+                    .id()
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_renames() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+
+        git.write_file("exact", "exact\n")?;
+        git.write_file("edited", "one\ntwo\nthree\nfour\n")?;
+        git.write_file("unrelated", "unrelated\n")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "before"])?;
+
+        git.run(&["mv", "exact.txt", "exact-moved.txt"])?;
+        git.run(&["mv", "edited.txt", "edited-moved.txt"])?;
+        git.write_file("edited-moved", "one\ntwo\nthree\nfive\n")?;
+        git.run(&["rm", "-q", "unrelated.txt"])?;
+        git.write_file("new", "new\n")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "after"])?;
+
+        let repo = git.get_repo()?;
+        let commit = repo.head()?.peel_to_commit()?;
+        let lhs_tree = commit.parent(0)?.tree()?;
+        let rhs_tree = commit.tree()?;
+        let changed = get_changed_entries_between_trees(&repo, Some(&lhs_tree), Some(&rhs_tree))?;
+        let changed = detect_renames(&repo, changed, 50)?;
+        let kinds = changed
+            .iter()
+            .map(|entry| format!("{}: {:?}", entry.path.display(), entry.kind))
+            .collect::<Vec<_>>();
+        assert_data_eq!(
+            kinds.to_debug(),
+            str![[r#"
+[
+    "edited-moved.txt: Renamed { from: \"edited.txt\", to: \"edited-moved.txt\", similarity: 73 }",
+    "exact-moved.txt: Renamed { from: \"exact.txt\", to: \"exact-moved.txt\", similarity: 100 }",
+    "new.txt: Added",
+    "unrelated.txt: Deleted",
+]
+
+"#]]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_changed_paths_matching() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+
+        git.write_file("src/lib", "lib")?;
+        git.write_file("src/generated/out", "out")?;
+        git.write_file("docs/guide", "guide")?;
+        git.write_file("top", "top")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "commit"])?;
+
+        let repo = git.get_repo()?;
+        let commit = repo.head()?.peel_to_commit()?;
+        let lhs_tree = commit.parent(0)?.tree()?;
+        let rhs_tree = commit.tree()?;
+        let matching = |specs: &[&str]| -> eyre::Result<Vec<String>> {
+            let pathspec = Pathspec::new(specs);
+            let changed = get_changed_paths_between_trees_matching(
+                &repo,
+                Some(&lhs_tree),
+                Some(&rhs_tree),
+                &pathspec,
+            )?;
+            let mut changed = changed
+                .into_iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>();
+            changed.sort();
+            Ok(changed)
+        };
+
+        assert_eq!(matching(&["src/", ":!src/generated/"])?, ["src/lib.txt"]);
+        assert_eq!(
+            matching(&["*.txt", ":^docs"])?,
+            ["src/generated/out.txt", "src/lib.txt", "top.txt"]
+        );
+        assert_eq!(matching(&["docs/g*"])?, ["docs/guide.txt"]);
+        assert!(matching(&["missing"])?.is_empty());
+
+        let pathspec = Pathspec::new(["src/*.txt"]);
+        assert!(pathspec.may_match_under(std::path::Path::new("src/generated")));
+        assert!(!pathspec.may_match_under(std::path::Path::new("docs")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_strategies() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.write_file("src/lib", "lib")?;
+        git.write_file("src/generated/out", "out")?;
+        git.write_file("docs/guide", "guide")?;
+        git.write_file("becomes_dir", "file")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "base"])?;
+        git.write_file("src/lib", "changed")?;
+        git.write_file("docs/new", "new")?;
+        git.run(&["rm", "-q", "becomes_dir.txt"])?;
+        git.write_file("becomes_dir.txt/inner", "inner")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "change"])?;
+
+        let repo = git.get_repo()?;
+        let commit = repo.head()?.peel_to_commit()?;
+        let lhs_tree = commit.parent(0)?.tree()?;
+        let rhs_tree = commit.tree()?;
+        let pathspec = Pathspec::new(["*.txt", ":!docs/guide.txt"]);
+        let cases = [
+            (Some(&lhs_tree), Some(&rhs_tree), None),
+            (Some(&lhs_tree), Some(&rhs_tree), Some(&pathspec)),
+            (None, Some(&rhs_tree), None),
+            (Some(&lhs_tree), None, Some(&pathspec)),
+        ];
+        for (lhs, rhs, pathspec) in cases {
+            let changed = |strategy| {
+                get_changed_paths_between_trees_with(&repo, lhs, rhs, pathspec, strategy)
+            };
+            let walked = changed(DiffStrategy::TreeWalk)?;
+            assert!(!walked.is_empty());
+            assert_eq!(changed(DiffStrategy::Diff)?, walked, "{pathspec:?}");
+            assert_eq!(changed(DiffStrategy::Auto)?, walked, "{pathspec:?}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_changed_paths_deeply_nested() -> eyre::Result<()> {
+        const DEPTH: usize = 10_000;
+
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+
+        let blob_id = repo.blob(b"deep")?;
+        let mut builder = repo.treebuilder(None)?;
+        builder.insert("file", blob_id, git2::FileMode::Blob.into())?;
+        let mut tree_id = builder.write()?;
+        for _ in 0..DEPTH {
+            let mut builder = repo.treebuilder(None)?;
+            builder.insert("d", tree_id, git2::FileMode::Tree.into())?;
+            tree_id = builder.write()?;
+        }
+        let tree = repo.find_tree(tree_id)?;
+
+        let changed_paths = get_changed_paths_between_trees(&repo, None, Some(&tree))?;
+        assert_eq!(changed_paths.len(), 1);
+        let path = changed_paths.into_iter().next().unwrap();
+        assert_eq!(path.components().count(), DEPTH + 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trees_differ() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.write_file("a", "a")?;
+        git.write_file("dir/b", "b")?;
+        git.write_file("dir/c", "c")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "commit"])?;
+
+        let repo = git.get_repo()?;
+        let commit = repo.head()?.peel_to_commit()?;
+        let lhs_tree = commit.parent(0)?.tree()?;
+        let rhs_tree = commit.tree()?;
+
+        assert!(trees_differ(&repo, Some(&lhs_tree), Some(&rhs_tree))?);
+        assert!(!trees_differ(&repo, Some(&rhs_tree), Some(&rhs_tree))?);
+        assert!(!trees_differ(&repo, None, None)?);
+        assert!(trees_differ(&repo, None, Some(&rhs_tree))?);
+
+        let limited = changed_paths_limited(&repo, Some(&lhs_tree), Some(&rhs_tree), 2)?;
+        assert_eq!(limited.len(), 2);
+        let all = get_changed_paths_between_trees(&repo, Some(&lhs_tree), Some(&rhs_tree))?;
+        assert_eq!(all.len(), 3);
+        assert!(limited.is_subset(&all));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebuild_tree_from_updates() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.write_file("dir/a", "a")?;
+        git.write_file("dir/b", "b")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "commit"])?;
+
+        let repo = git.get_repo()?;
+        let head_tree = repo.head()?.peel_to_tree()?;
+        let initial = head_tree
+            .get_path(std::path::Path::new("initial.txt"))?
+            .id();
+        let update = Some(TreeUpdate {
+            id: initial,
+            mode: git2::FileMode::Blob.into(),
+        });
+        let tree_id = rebuild_tree_from_updates(
+            &repo,
+            Some(&head_tree),
+            [
+                (std::path::Path::new("dir/a.txt"), None),
+                (std::path::Path::new("dir/b.txt"), None),
+                (std::path::Path::new("new/deep/copy.txt"), None),
+                (std::path::Path::new("new/deep/copy.txt"), update),
+                (std::path::Path::new("new/gone.txt"), update),
+                (std::path::Path::new("new/gone.txt"), None),
+            ],
+        )?;
+        let tree = repo.find_tree(tree_id)?;
+        let paths = walk(&repo, &tree)
+            .map(|item| item.map(|(path, _)| path.display().to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            paths,
+            ["initial.txt", "new", "new/deep", "new/deep/copy.txt"]
+        );
+
+        Ok(())
+    }
+    #[test]
+    fn test_from_entries() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+
+        let repo = git.get_repo()?;
+        let existing = repo.blob(b"existing")?;
+        let tree_id = from_entries(
+            &repo,
+            [
+                ("README.md", Content::file("readme")),
+                (
+                    "bin/run",
+                    Content::Bytes {
+                        data: b"#!/bin/sh".to_vec(),
+                        executable: true,
+                    },
+                ),
+                ("bin/latest", Content::Symlink("run".into())),
+                (
+                    "data/existing",
+                    Content::ExistingOid {
+                        id: existing,
+                        mode: git2::FileMode::Blob.into(),
+                    },
+                ),
+            ],
+        )?;
+
+        let tree = repo.find_tree(tree_id)?;
+        let paths = walk(&repo, &tree)
+            .map(|item| item.map(|(path, info)| format!("{} {:o}", path.display(), info.mode)))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            paths,
+            [
+                "README.md 100644",
+                "bin 40000",
+                "bin/latest 120000",
+                "bin/run 100755",
+                "data 40000",
+                "data/existing 100644",
+            ]
+        );
+        let link = tree.get_path(std::path::Path::new("bin/latest"))?;
+        assert_eq!(repo.find_blob(link.id())?.content(), b"run");
+
+        Ok(())
+    }
+    #[test]
+    fn test_extract_subtree() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.write_file("lib/core/a", "a")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "commit"])?;
+
+        let repo = git.get_repo()?;
+        let head_tree = repo.head()?.peel_to_tree()?;
+        let lib = extract_subtree(&repo, &head_tree, std::path::Path::new("lib"))?;
+        assert_eq!(lib, head_tree.get_path(std::path::Path::new("lib"))?.id());
+        let empty = repo.treebuilder(None)?.write()?;
+        for missing in ["missing", "initial.txt", "lib/core/a.txt/b"] {
+            let id = extract_subtree(&repo, &head_tree, std::path::Path::new(missing))?;
+            assert_eq!(id, empty, "{missing}");
+        }
+
+        Ok(())
+    }
+    #[test]
+    fn test_graft_tree() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.write_file("src/lib", "lib")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "commit"])?;
+
+        let repo = git.get_repo()?;
+        let head_tree = repo.head()?.peel_to_tree()?;
+        let vendored_id = from_entries(&repo, [("LICENSE", Content::file("MIT"))])?;
+        let vendored = repo.find_tree(vendored_id)?;
+
+        let prefixed = prefix_tree(&repo, &vendored, std::path::Path::new("vendor/dep"))?;
+        let prefixed = repo.find_tree(prefixed)?;
+        let paths = walk(&repo, &prefixed)
+            .map(|item| item.map(|(path, _)| path.display().to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(paths, ["vendor", "vendor/dep", "vendor/dep/LICENSE"]);
+
+        let grafted = graft_tree(
+            &repo,
+            &head_tree,
+            &vendored,
+            std::path::Path::new("src/vendor"),
+        )?;
+        let grafted = repo.find_tree(grafted)?;
+        let paths = walk(&repo, &grafted)
+            .map(|item| item.map(|(path, _)| path.display().to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            paths,
+            [
+                "initial.txt",
+                "src",
+                "src/lib.txt",
+                "src/vendor",
+                "src/vendor/LICENSE"
+            ]
+        );
+
+        let err =
+            graft_tree(&repo, &head_tree, &vendored, std::path::Path::new("src")).unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::Exists);
+
+        Ok(())
+    }
+    #[test]
+    fn test_exclude_paths() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.write_file("docs/guide", "guide")?;
+        git.write_file("src/generated/a", "a")?;
+        git.write_file("src/lib", "lib")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "commit"])?;
+
+        let repo = git.get_repo()?;
+        let head_tree = repo.head()?.peel_to_tree()?;
+        let tree_id = exclude_paths(
+            &repo,
+            &head_tree,
+            &[
+                std::path::Path::new("docs/guide.txt"),
+                std::path::Path::new("src/generated"),
+                std::path::Path::new("missing"),
+            ],
+        )?;
+        let tree = repo.find_tree(tree_id)?;
+        let paths = walk(&repo, &tree)
+            .map(|item| item.map(|(path, _)| path.display().to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(paths, ["initial.txt", "src", "src/lib.txt"]);
+
+        Ok(())
+    }
+    #[test]
+    fn test_gitlinks() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+
+        let repo = git.get_repo()?;
+        let old_commit = git2::Oid::from_str("1111111111111111111111111111111111111111")?;
+        let new_commit = git2::Oid::from_str("2222222222222222222222222222222222222222")?;
+        let old_id = from_entries(
+            &repo,
+            [
+                (
+                    "vendor/dep",
+                    Content::ExistingOid {
+                        id: old_commit,
+                        mode: git2::FileMode::Commit.into(),
+                    },
+                ),
+                ("vendor/other", Content::file("other")),
+            ],
+        )?;
+        let old_tree = repo.find_tree(old_id)?;
+
+        let new_id = TreeEditor::new(&repo, Some(&old_tree))
+            .set_submodule("vendor/dep", new_commit)
+            .upsert_blob("vendor/other", "changed")
+            .write()?;
+        let new_tree = repo.find_tree(new_id)?;
+        let kinds = get_changed_entries_between_trees(&repo, Some(&old_tree), Some(&new_tree))?
+            .into_iter()
+            .map(|entry| (entry.path.display().to_string(), entry.kind))
+            .collect_vec();
+        assert_eq!(
+            kinds,
+            [
+                ("vendor/dep".to_owned(), ChangeKind::SubmoduleChanged),
+                ("vendor/other".to_owned(), ChangeKind::Modified),
+            ]
+        );
+        let gitlinks = walk(&repo, &new_tree)
+            .filter_ok(|(_, info)| info.is_gitlink())
+            .map_ok(|(path, info)| (path.display().to_string(), info.id))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(gitlinks, [("vendor/dep".to_owned(), new_commit)]);
+
+        let err = TreeEditor::new(&repo, Some(&new_tree))
+            .upsert_blob("vendor/dep/file", "file")
+            .write()
+            .unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::Invalid);
+
+        Ok(())
+    }
+    #[test]
+    fn test_visit_changed_paths() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.write_file("docs/a", "a")?;
+        git.write_file("src/b", "b")?;
+        git.write_file("src/c", "c")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "commit"])?;
+
+        let repo = git.get_repo()?;
+        let commit = repo.head()?.peel_to_commit()?;
+        let lhs_tree = commit.parent(0)?.tree()?;
+        let rhs_tree = commit.tree()?;
+
+        let mut visited = Vec::new();
+        let flow = visit_changed_paths(&repo, Some(&lhs_tree), Some(&rhs_tree), |path, kind| {
+            visited.push((path.display().to_string(), kind));
+            ControlFlow::Continue(())
+        })?;
+        assert_eq!(flow, ControlFlow::Continue(()));
+        visited.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            visited,
+            [
+                ("docs/a.txt".to_owned(), ChangeKind::Added),
+                ("src/b.txt".to_owned(), ChangeKind::Added),
+                ("src/c.txt".to_owned(), ChangeKind::Added),
+            ]
+        );
+
+        let mut calls = 0;
+        let flow = visit_changed_paths(&repo, Some(&lhs_tree), Some(&rhs_tree), |path, _| {
+            calls += 1;
+            if path.starts_with("src") {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })?;
+        assert_eq!(flow, ControlFlow::Break(()));
+        assert!(calls < 3);
+
+        Ok(())
+    }
+    #[test]
+    fn test_normalize_mode() -> eyre::Result<()> {
+        assert_eq!(normalize_mode(0o100664), Some(git2::FileMode::Blob));
+        assert_eq!(
+            normalize_mode(0o100775),
+            Some(git2::FileMode::BlobExecutable)
+        );
+        assert_eq!(normalize_mode(0o120777), Some(git2::FileMode::Link));
+        assert_eq!(normalize_mode(0o040755), Some(git2::FileMode::Tree));
+        assert_eq!(normalize_mode(0o160000), Some(git2::FileMode::Commit));
+        assert_eq!(normalize_mode(0o020644), None);
+        assert!(is_valid_mode(0o100755));
+        assert!(!is_valid_mode(0o100664));
+
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let blob = repo.blob(b"blob")?;
+        let tree_id = rebuild_tree_from_updates(
+            &repo,
+            None,
+            [(
+                std::path::Path::new("dir/file"),
+                Some(TreeUpdate {
+                    id: blob,
+                    mode: 0o100664,
+                }),
+            )],
+        )?;
+        let tree = repo.find_tree(tree_id)?;
+        let entry = tree.get_path(std::path::Path::new("dir/file"))?;
+        assert_eq!(entry.filemode(), i32::from(git2::FileMode::Blob));
+
+        let err = rebuild_tree_from_updates(
+            &repo,
+            None,
+            [(
+                std::path::Path::new("dir/file"),
+                Some(TreeUpdate {
+                    id: blob,
+                    mode: 0o020644,
+                }),
+            )],
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::Invalid);
+        assert_eq!(err.message(), "invalid file mode 20644 for `dir/file`");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_changed_entries_flag_lfs() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let base = repo.head()?.peel_to_tree()?;
+        std::fs::write(
+            git.repo_path.join(".gitattributes"),
+            "*.bin filter=lfs diff=lfs merge=lfs -text\n",
+        )?;
+        std::fs::create_dir_all(git.repo_path.join("assets"))?;
+        std::fs::write(
+            git.repo_path.join("assets/large.bin"),
+            "version https://git-lfs.github.com/spec/v1\n\
+             oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n\
+             size 12345\n",
+        )?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "lfs"])?;
+        let head = repo.head()?.peel_to_tree()?;
+
+        let entries = get_changed_entries_between_trees_with(
+            &repo,
+            Some(&base),
+            Some(&head),
+            ChangedEntriesOptions::new().flag_lfs(true),
+        )?;
+        let flagged = entries
+            .iter()
+            .map(|entry| (entry.path.to_str().unwrap(), entry.lfs))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            flagged,
+            [(".gitattributes", false), ("assets/large.bin", true)]
+        );
+        assert!(is_binary_change(&repo, &entries[1])?);
+        let blob = repo.find_blob(entries[1].new_id.unwrap())?;
+        assert!(crate::ops::is_lfs_pointer(&blob));
+
+        // Not looked up unless asked for
+        let entries = get_changed_entries_between_trees_with(
+            &repo,
+            Some(&base),
+            Some(&head),
+            ChangedEntriesOptions::new().pathspec(Pathspec::new(["*.bin"])),
+        )?;
+        assert_eq!(entries.len(), 1);
+        assert!(!entries[0].lfs);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+/// Cone-mode sparse-checkout patterns, from `info/sparse-checkout`
+///
+/// Files in the root directory are always included.  Directories added with
+/// `git sparse-checkout set <dir>` are included with everything under them, while their parents
+/// only include the files directly in them.
+///
+/// Use it to skip what the sparse checkout leaves out:
+/// ```no_run
+/// let repo = git2::Repository::open(".").unwrap();
+/// let tree = repo.head().unwrap().peel_to_tree().unwrap();
+/// let sparse = git2_ext::tree::SparseCheckout::from_repo(&repo).unwrap().unwrap_or_default();
+/// let visible = git2_ext::tree::walk(&repo, &tree)
+///     .skip_subtrees(|path, _| !sparse.may_contain_under(path))
+///     .filter(|entry| match entry {
+///         Ok((path, info)) => info.is_tree() || sparse.contains(path),
+///         Err(_) => true,
+///     });
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SparseCheckout {
+    /// Directories included with everything under them
+    recursive: Vec<std::path::PathBuf>,
+    /// Directories that only include the files directly in them
+    parents: std::collections::HashSet<std::path::PathBuf>,
+}
+
+impl SparseCheckout {
+    /// Read the patterns for the repository's worktree
+    ///
+    /// Returns `None` when sparse checkout isn't enabled.  Non-cone patterns are an error.
+    pub fn from_repo(repo: &git2::Repository) -> Result<Option<Self>, git2::Error> {
+        // `git sparse-checkout` may write to the per-worktree config, which `libgit2` doesn't read
+        let key = "core.sparseCheckout";
+        let worktree_config = repo.path().join("config.worktree");
+        let enabled = if worktree_config.exists() {
+            git2::Config::open(&worktree_config)?.get_bool(key).ok()
+        } else {
+            None
+        };
+        let enabled = match enabled {
+            Some(enabled) => enabled,
+            None => repo.config()?.get_bool(key).unwrap_or(false),
+        };
+        if !enabled {
+            return Ok(None);
+        }
+        let path = repo.path().join("info").join("sparse-checkout");
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(git2::Error::new(
+                    git2::ErrorCode::GenericError,
+                    git2::ErrorClass::Os,
+                    format!("could not read `{}`: {}", path.display(), err),
+                ));
+            }
+        };
+        Self::parse(&content).map(Some)
+    }
+
+    /// Parse the content of `info/sparse-checkout`
+    pub fn parse(content: &str) -> Result<Self, git2::Error> {
+        let mut dirs = Vec::new();
+        let mut parents = std::collections::HashSet::new();
+        for line in content.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') || line == "/*" || line == "!/*/" {
+                continue;
+            }
+            if let Some(parent) = line
+                .strip_prefix("!/")
+                .and_then(|line| line.strip_suffix("/*/"))
+            {
+                parents.insert(std::path::PathBuf::from(unescape(parent)));
+            } else if let Some(dir) = line
+                .strip_prefix('/')
+                .and_then(|line| line.strip_suffix('/'))
+                .filter(|dir| !dir.is_empty())
+            {
+                dirs.push(std::path::PathBuf::from(unescape(dir)));
+            } else {
+                return Err(git2::Error::new(
+                    git2::ErrorCode::Invalid,
+                    git2::ErrorClass::Config,
+                    format!("`{line}` is not a cone-mode sparse-checkout pattern"),
+                ));
+            }
+        }
+        let recursive = dirs
+            .into_iter()
+            .filter(|dir| !parents.contains(dir))
+            .collect();
+        Ok(Self { recursive, parents })
+    }
+
+    /// Whether the file at `path` is in the sparse checkout
+    pub fn contains(&self, path: &std::path::Path) -> bool {
+        let parent = path.parent().unwrap_or_else(|| std::path::Path::new(""));
+        parent.as_os_str().is_empty()
+            || self.parents.contains(parent)
+            || self.recursive.iter().any(|dir| path.starts_with(dir))
+    }
+
+    /// Whether anything under the directory `dir` is in the sparse checkout
+    pub fn may_contain_under(&self, dir: &std::path::Path) -> bool {
+        dir.as_os_str().is_empty()
+            || self.parents.contains(dir)
+            || self
+                .recursive
+                .iter()
+                .any(|recursive| dir.starts_with(recursive) || recursive.starts_with(dir))
+    }
+
+    /// Drop changes to files outside of the sparse checkout
+    pub fn filter_changes(&self, entries: Vec<super::ChangedEntry>) -> Vec<super::ChangedEntry> {
+        entries
+            .into_iter()
+            .filter(|entry| match &entry.kind {
+                super::ChangeKind::Renamed { from, to, .. } => {
+                    self.contains(from) || self.contains(to)
+                }
+                _ => self.contains(&entry.path),
+            })
+            .collect()
+    }
+}
+
+fn unescape(pattern: &str) -> String {
+    let mut unescaped = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            unescaped.extend(chars.next());
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+    use crate::tree::from_entries;
+    use crate::tree::get_changed_entries_between_trees;
+    use crate::tree::Content;
+
+    #[test]
+    fn test_sparse_checkout() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.run(&["sparse-checkout", "set", "--cone", "lib/core", "docs"])?;
+
+        let repo = git.get_repo()?;
+        let sparse = SparseCheckout::from_repo(&repo)?.expect("enabled");
+        let tree = from_entries(
+            &repo,
+            [
+                ("README", Content::file("readme")),
+                ("docs/guide/intro", Content::file("intro")),
+                ("lib/Cargo.toml", Content::file("toml")),
+                ("lib/core/src/lib.rs", Content::file("lib")),
+                ("lib/extra/lib.rs", Content::file("extra")),
+                ("tests/test.rs", Content::file("test")),
+            ],
+        )?;
+        let tree = repo.find_tree(tree)?;
+        let entries = get_changed_entries_between_trees(&repo, None, Some(&tree))?;
+        let paths = sparse
+            .filter_changes(entries)
+            .into_iter()
+            .map(|entry| entry.path.display().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "README",
+                "docs/guide/intro",
+                "lib/Cargo.toml",
+                "lib/core/src/lib.rs",
+            ]
+        );
+        assert!(sparse.may_contain_under(std::path::Path::new("lib")));
+        assert!(!sparse.may_contain_under(std::path::Path::new("lib/extra")));
+        assert!(!sparse.may_contain_under(std::path::Path::new("tests")));
+
+        assert!(SparseCheckout::parse("/*\n!/*/\n/src/*.rs\n").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+use super::ChangedEntry;
+
+/// Line counts for [`ChangedEntry`]s, like `git diff --stat`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiffStats {
+    pub files: Vec<FileStat>,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+/// Line counts for one [`ChangedEntry`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileStat {
+    pub path: std::path::PathBuf,
+    pub insertions: usize,
+    pub deletions: usize,
+    /// Binary files have no line counts, like `Bin` in `git diff --stat`
+    pub binary: bool,
+}
+
+/// Load the blobs of each entry to count inserted and deleted lines
+///
+/// Files are binary according to [`is_binary_change`].  Directories and submodules don't have
+/// lines, so their counts are zero.  Nothing is loaded for files flagged as stored by Git LFS,
+/// see [`ChangedEntry::lfs`].
+pub fn diff_stats(
+    repo: &git2::Repository,
+    entries: &[ChangedEntry],
+) -> Result<DiffStats, git2::Error> {
+    let mut stats = DiffStats::default();
+    for entry in entries {
+        if entry.lfs {
+            // Pointer files say nothing about the content's lines
+            stats.files.push(FileStat {
+                path: entry.path.clone(),
+                insertions: 0,
+                deletions: 0,
+                binary: true,
+            });
+            continue;
+        }
+        let old = load_blob(repo, entry.old_id, entry.old_mode)?;
+        let new = load_blob(repo, entry.new_id, entry.new_mode)?;
+        let old = old.as_ref().map(|blob| blob.content()).unwrap_or_default();
+        let new = new.as_ref().map(|blob| blob.content()).unwrap_or_default();
+
+        let binary = match binary_attr(repo, &entry.path)? {
+            Some(binary) => binary,
+            None => is_binary(old) || is_binary(new),
+        };
+        let (insertions, deletions) = if binary {
+            (0, 0)
+        } else {
+            let mut options = git2::DiffOptions::new();
+            options.context_lines(0).force_text(true);
+            let patch = git2::Patch::from_buffers(old, None, new, None, Some(&mut options))?;
+            let (_, insertions, deletions) = patch.line_stats()?;
+            (insertions, deletions)
+        };
+        stats.insertions += insertions;
+        stats.deletions += deletions;
+        stats.files.push(FileStat {
+            path: entry.path.clone(),
+            insertions,
+            deletions,
+            binary,
+        });
+    }
+    Ok(stats)
+}
+
+/// Whether a change is to a binary file, so it shouldn't be diffed line by line
+///
+/// `.gitattributes` decide first: `binary`, `-diff`, and `-text` mark a file as binary while
+/// `text` and `diff` mark it as text.  Otherwise a file is binary when either side has a NUL
+/// byte early on, like `git`.  Directories and submodules are never binary, while files stored
+/// by Git LFS, see [`ChangedEntry::lfs`], always are.
+pub fn is_binary_change(
+    repo: &git2::Repository,
+    entry: &ChangedEntry,
+) -> Result<bool, git2::Error> {
+    if entry.lfs {
+        return Ok(true);
+    }
+    if let Some(binary) = binary_attr(repo, &entry.path)? {
+        return Ok(binary);
+    }
+    for (id, mode) in [
+        (entry.old_id, entry.old_mode),
+        (entry.new_id, entry.new_mode),
+    ] {
+        if let Some(blob) = load_blob(repo, id, mode)? {
+            if is_binary(blob.content()) {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Whether `.gitattributes` say `path` is binary or text, if they say at all
+fn binary_attr(
+    repo: &git2::Repository,
+    path: &std::path::Path,
+) -> Result<Option<bool>, git2::Error> {
+    let flags = git2::AttrCheckFlags::FILE_THEN_INDEX;
+    let attr = |name| -> Result<git2::AttrValue<'_>, git2::Error> {
+        let value = repo.get_attr_bytes(path, name, flags)?;
+        Ok(git2::AttrValue::from_bytes(value))
+    };
+    if attr("binary")? == git2::AttrValue::True {
+        return Ok(Some(true));
+    }
+    for name in ["diff", "text"] {
+        match attr(name)? {
+            git2::AttrValue::False => return Ok(Some(true)),
+            git2::AttrValue::True => return Ok(Some(false)),
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+/// The blob for a file or symlink
+fn load_blob<'r>(
+    repo: &'r git2::Repository,
+    id: Option<git2::Oid>,
+    mode: Option<i32>,
+) -> Result<Option<git2::Blob<'r>>, git2::Error> {
+    let (Some(id), Some(mode)) = (id, mode) else {
+        return Ok(None);
+    };
+    let is_blob = mode == i32::from(git2::FileMode::Blob)
+        || mode == i32::from(git2::FileMode::BlobExecutable)
+        || mode == i32::from(git2::FileMode::Link);
+    if !is_blob {
+        return Ok(None);
+    }
+    repo.find_blob(id).map(Some)
+}
+
+/// `git`s heuristic: a NUL in the first 8000 bytes
+pub(crate) fn is_binary(content: &[u8]) -> bool {
+    const FIRST_FEW_BYTES: usize = 8000;
+    content[..content.len().min(FIRST_FEW_BYTES)].contains(&0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+    use crate::tree::from_entries;
+    use crate::tree::get_changed_entries_between_trees;
+    use crate::tree::Content;
+
+    #[test]
+    fn test_diff_stats() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+
+        let repo = git.get_repo()?;
+        let old = from_entries(
+            &repo,
+            [
+                ("lines", Content::file("one\ntwo\nthree\n")),
+                ("gone", Content::file("a\nb\n")),
+                ("image", Content::file(b"\0old".to_vec())),
+            ],
+        )?;
+        let new = from_entries(
+            &repo,
+            [
+                ("lines", Content::file("one\n2\nthree\nfour\n")),
+                ("image", Content::file(b"\0new".to_vec())),
+            ],
+        )?;
+        let (old, new) = (repo.find_tree(old)?, repo.find_tree(new)?);
+        let entries = get_changed_entries_between_trees(&repo, Some(&old), Some(&new))?;
+
+        let stats = diff_stats(&repo, &entries)?;
+        let files = stats
+            .files
+            .iter()
+            .map(|file| {
+                (
+                    file.path.display().to_string(),
+                    file.insertions,
+                    file.deletions,
+                    file.binary,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            [
+                ("gone".to_owned(), 0, 2, false),
+                ("image".to_owned(), 0, 0, true),
+                ("lines".to_owned(), 2, 1, false),
+            ]
+        );
+        assert_eq!((stats.insertions, stats.deletions), (2, 3));
+
+        Ok(())
+    }
+    #[test]
+    fn test_is_binary_change() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        std::fs::write(
+            git.repo_path.join(".gitattributes"),
+            "*.dat binary\nraw -text\nforced text\n",
+        )?;
+
+        let repo = git.get_repo()?;
+        let new = from_entries(
+            &repo,
+            [
+                ("plain", Content::file("plain\n")),
+                ("nul", Content::file(b"\0nul".to_vec())),
+                ("image.dat", Content::file("looks like text\n")),
+                ("raw", Content::file("looks like text\n")),
+                ("forced", Content::file(b"\0forced".to_vec())),
+            ],
+        )?;
+        let new = repo.find_tree(new)?;
+        let entries = get_changed_entries_between_trees(&repo, None, Some(&new))?;
+        let binary = entries
+            .iter()
+            .map(|entry| -> Result<_, git2::Error> {
+                Ok((
+                    entry.path.display().to_string(),
+                    is_binary_change(&repo, entry)?,
+                ))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            binary,
+            [
+                ("forced".to_owned(), false),
+                ("image.dat".to_owned(), true),
+                ("nul".to_owned(), true),
+                ("plain".to_owned(), false),
+                ("raw".to_owned(), true),
+            ]
+        );
+
+        Ok(())
+    }
+}
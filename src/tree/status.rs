@@ -0,0 +1,151 @@
+use super::ChangeKind;
+
+/// How a path differs from the tree given to [`status_against`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PathStatus {
+    pub path: std::path::PathBuf,
+    /// Change between the tree and the index
+    pub staged: Option<ChangeKind>,
+    /// Change between the index and the working directory, with untracked files as
+    /// [`ChangeKind::Added`]
+    pub unstaged: Option<ChangeKind>,
+    /// The path has unresolved merge conflicts in the index
+    pub conflicted: bool,
+}
+
+/// Compare `tree` with both the index and the working directory, like `git status` does with
+/// `HEAD`
+///
+/// Only paths with changes are returned, sorted by path.  Ignored files are skipped.  This
+/// needs a working directory, so fails for bare repositories.
+pub fn status_against(
+    repo: &git2::Repository,
+    tree: &git2::Tree<'_>,
+) -> Result<Vec<PathStatus>, git2::Error> {
+    let mut index = repo.index()?;
+    index.read(false)?;
+    let mut paths = std::collections::BTreeMap::new();
+
+    let mut options = git2::DiffOptions::new();
+    options.ignore_submodules(false);
+    let staged = repo.diff_tree_to_index(Some(tree), Some(&index), Some(&mut options))?;
+    record(&mut paths, &staged, |status, kind| {
+        status.staged = Some(kind);
+    });
+
+    options
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .include_ignored(false);
+    let unstaged = repo.diff_index_to_workdir(Some(&index), Some(&mut options))?;
+    record(&mut paths, &unstaged, |status, kind| {
+        status.unstaged = Some(kind);
+    });
+
+    Ok(paths.into_values().collect())
+}
+
+fn record(
+    paths: &mut std::collections::BTreeMap<std::path::PathBuf, PathStatus>,
+    diff: &git2::Diff<'_>,
+    set: impl Fn(&mut PathStatus, ChangeKind),
+) {
+    for delta in diff.deltas() {
+        let file = if delta.status() == git2::Delta::Deleted {
+            delta.old_file()
+        } else {
+            delta.new_file()
+        };
+        let Some(path) = file.path() else {
+            continue;
+        };
+        let kind = change_kind(delta.status());
+        let conflicted = delta.status() == git2::Delta::Conflicted;
+        if kind.is_none() && !conflicted {
+            continue;
+        }
+        let status = paths.entry(path.to_owned()).or_insert_with(|| PathStatus {
+            path: path.to_owned(),
+            staged: None,
+            unstaged: None,
+            conflicted: false,
+        });
+        status.conflicted |= conflicted;
+        if let Some(kind) = kind {
+            set(status, kind);
+        }
+    }
+}
+
+fn change_kind(delta: git2::Delta) -> Option<ChangeKind> {
+    match delta {
+        git2::Delta::Added | git2::Delta::Untracked | git2::Delta::Copied => {
+            Some(ChangeKind::Added)
+        }
+        git2::Delta::Deleted => Some(ChangeKind::Deleted),
+        git2::Delta::Modified | git2::Delta::Renamed => Some(ChangeKind::Modified),
+        git2::Delta::Typechange => Some(ChangeKind::TypeChanged),
+        git2::Delta::Unmodified
+        | git2::Delta::Ignored
+        | git2::Delta::Unreadable
+        | git2::Delta::Conflicted => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_status_against() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.commit_file("base", 1)?;
+        git.commit_file("staged", 2)?;
+        git.commit_file("unstaged", 3)?;
+        git.commit_file("both", 4)?;
+
+        let repo = git.get_repo()?;
+        let base = repo.revparse_single("HEAD~3")?.peel_to_tree()?;
+        git.write_file("staged", "staged")?;
+        git.run(&["add", "staged.txt"])?;
+        git.write_file("unstaged", "unstaged")?;
+        git.write_file("both", "staged")?;
+        git.run(&["add", "both.txt"])?;
+        git.write_file("both", "unstaged")?;
+        git.write_file("untracked", "untracked")?;
+
+        let statuses = status_against(&repo, &base)?
+            .into_iter()
+            .map(|status| {
+                (
+                    status.path.display().to_string(),
+                    status.staged,
+                    status.unstaged,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            [
+                (
+                    "both.txt".to_owned(),
+                    Some(ChangeKind::Added),
+                    Some(ChangeKind::Modified)
+                ),
+                ("staged.txt".to_owned(), Some(ChangeKind::Added), None),
+                (
+                    "unstaged.txt".to_owned(),
+                    Some(ChangeKind::Added),
+                    Some(ChangeKind::Modified)
+                ),
+                ("untracked.txt".to_owned(), None, Some(ChangeKind::Added)),
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use super::ChangedEntry;
+use super::Changes;
+use super::DiffEntry;
+use super::DiffTree;
+use super::FindTree;
+use crate::backend::ObjectStore;
+use crate::backend::StoredEntry;
+
+/// Like [`super::get_changed_entries_between_trees`] but reading trees from any
+/// [`ObjectStore`]
+///
+/// Trees are given by id, with `None` for a side that has none.  Entries are sorted by path.
+pub fn changed_entries_in<S: ObjectStore + ?Sized>(
+    store: &S,
+    lhs: Option<git2::Oid>,
+    rhs: Option<git2::Oid>,
+) -> Result<Vec<ChangedEntry>, git2::Error> {
+    let mut acc = Vec::new();
+    if lhs != rhs {
+        let mut trees = StoreTrees(store);
+        let lhs = lhs.map(|id| trees.find_tree(id)).transpose()?;
+        let rhs = rhs.map(|id| trees.find_tree(id)).transpose()?;
+        let mut visit = |entry| {
+            acc.push(entry);
+            std::ops::ControlFlow::Continue(())
+        };
+        super::diff_trees(&mut trees, &mut Changes::new(&mut visit), None, lhs, rhs)?;
+    }
+    acc.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(acc)
+}
+
+struct StoreTrees<'s, S: ?Sized>(&'s S);
+
+impl<S: ObjectStore + ?Sized> FindTree<Vec<StoredEntry>> for StoreTrees<'_, S> {
+    fn find_tree(&mut self, id: git2::Oid) -> Result<Vec<StoredEntry>, git2::Error> {
+        self.0.read_tree(id)
+    }
+}
+
+impl DiffTree for Vec<StoredEntry> {
+    type Entry<'t> = &'t StoredEntry;
+
+    fn sorted_entries(&self) -> Vec<&StoredEntry> {
+        let mut entries = self.iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        entries
+    }
+}
+
+impl DiffEntry for &StoredEntry {
+    fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    fn id(&self) -> git2::Oid {
+        self.id
+    }
+
+    fn mode(&self) -> i32 {
+        self.mode
+    }
+
+    fn is_tree(&self) -> bool {
+        StoredEntry::is_tree(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_changed_entries_in() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.write_file("src/lib", "lib")?;
+        git.write_file("src/deep/mod", "mod")?;
+        git.write_file("becomes_dir", "file")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "base"])?;
+        git.write_file("src/lib", "changed")?;
+        git.write_file("docs/new", "new")?;
+        git.run(&["rm", "-q", "-r", "src/deep"])?;
+        git.run(&["rm", "-q", "becomes_dir.txt"])?;
+        git.write_file("becomes_dir.txt/inner", "inner")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "change"])?;
+
+        let repo = git.get_repo()?;
+        let commit = repo.head()?.peel_to_commit()?;
+        let lhs_tree = commit.parent(0)?.tree()?;
+        let rhs_tree = commit.tree()?;
+        let cases = [
+            (Some(&lhs_tree), Some(&rhs_tree)),
+            (Some(&rhs_tree), Some(&lhs_tree)),
+            (None, Some(&rhs_tree)),
+            (Some(&lhs_tree), None),
+        ];
+        for (lhs, rhs) in cases {
+            let expected = crate::tree::get_changed_entries_between_trees(&repo, lhs, rhs)?;
+            assert!(!expected.is_empty());
+            let actual =
+                changed_entries_in(&repo, lhs.map(|tree| tree.id()), rhs.map(|tree| tree.id()))?;
+            assert_eq!(actual, expected);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+/// What a tree entry points to, see [`walk`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TreeEntryInfo {
+    pub id: git2::Oid,
+    pub mode: i32,
+    pub kind: Option<git2::ObjectType>,
+}
+
+impl TreeEntryInfo {
+    fn new(entry: &git2::TreeEntry<'_>) -> Self {
+        Self {
+            id: entry.id(),
+            mode: entry.filemode_raw(),
+            kind: entry.kind(),
+        }
+    }
+
+    pub fn is_tree(&self) -> bool {
+        self.kind == Some(git2::ObjectType::Tree)
+    }
+
+    /// A submodule, see [`super::is_gitlink`]
+    pub fn is_gitlink(&self) -> bool {
+        super::is_gitlink(self.mode)
+    }
+}
+
+/// Lazily walk every entry in `tree`, depth-first
+///
+/// Directories are yielded before their contents.  Subtrees are only loaded when the walk
+/// reaches them, so large trees can be streamed.
+///
+/// ```no_run
+/// # use itertools::Itertools as _;
+/// let repo = git2::Repository::open(".").unwrap();
+/// let tree = repo.head().unwrap().peel_to_tree().unwrap();
+/// let files = git2_ext::tree::walk(&repo, &tree)
+///     .skip_subtrees(|path, _| path.starts_with("target"))
+///     .filter_ok(|(_, info)| !info.is_tree());
+/// for file in files {
+///     let (path, _) = file.unwrap();
+///     println!("{}", path.display());
+/// }
+/// ```
+pub fn walk<'r>(repo: &'r git2::Repository, tree: &git2::Tree<'r>) -> TreeWalk<'r> {
+    TreeWalk {
+        repo,
+        stack: vec![(std::path::PathBuf::new(), tree.clone(), 0)],
+        skip: None,
+    }
+}
+
+/// Iterator returned by [`walk`]
+pub struct TreeWalk<'r> {
+    repo: &'r git2::Repository,
+    /// Directories being walked, with the index of the next entry to yield
+    stack: Vec<(std::path::PathBuf, git2::Tree<'r>, usize)>,
+    skip: Option<Box<SkipSubtree<'r>>>,
+}
+
+type SkipSubtree<'r> = dyn FnMut(&std::path::Path, &TreeEntryInfo) -> bool + 'r;
+
+impl<'r> TreeWalk<'r> {
+    /// Don't walk into directories where `skip` returns `true`
+    ///
+    /// The directory itself is still yielded.
+    pub fn skip_subtrees(
+        mut self,
+        skip: impl FnMut(&std::path::Path, &TreeEntryInfo) -> bool + 'r,
+    ) -> Self {
+        self.skip = Some(Box::new(skip));
+        self
+    }
+}
+
+impl Iterator for TreeWalk<'_> {
+    type Item = Result<(std::path::PathBuf, TreeEntryInfo), git2::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (dir, tree, next) = self.stack.last_mut()?;
+            let Some(entry) = tree.get(*next) else {
+                self.stack.pop();
+                continue;
+            };
+            *next += 1;
+            let path = dir.join(crate::bytes::bytes2path_lossy(entry.name_bytes()));
+            let info = TreeEntryInfo::new(&entry);
+            drop(entry);
+
+            if info.is_tree() {
+                let skipped = match &mut self.skip {
+                    Some(skip) => skip(&path, &info),
+                    None => false,
+                };
+                if !skipped {
+                    match self.repo.find_tree(info.id) {
+                        Ok(subtree) => self.stack.push((path.clone(), subtree, 0)),
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+            }
+            return Some(Ok((path, info)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_walk() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.write_file("a/b/c", "c")?;
+        git.write_file("a/d", "d")?;
+        git.write_file("skipped/e", "e")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "commit"])?;
+
+        let repo = git.get_repo()?;
+        let tree = repo.head()?.peel_to_tree()?;
+        let paths = walk(&repo, &tree)
+            .skip_subtrees(|path, _| path == std::path::Path::new("skipped"))
+            .map(|item| item.map(|(path, info)| (path.display().to_string(), info.is_tree())))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            paths,
+            [
+                ("a".to_owned(), true),
+                ("a/b".to_owned(), true),
+                ("a/b/c.txt".to_owned(), false),
+                ("a/d.txt".to_owned(), false),
+                ("initial.txt".to_owned(), false),
+                ("skipped".to_owned(), true),
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+/// A future for work done on Tokio's blocking thread pool, see [`spawn_blocking`]
+///
+/// Dropping the future doesn't stop the work, only its result is dropped.
+#[must_use = "futures do nothing unless polled, though the work is already running"]
+pub struct Blocking<T> {
+    handle: tokio::task::JoinHandle<T>,
+}
+
+/// Run `work`, like starting a subprocess and waiting on it, with
+/// [`tokio::task::spawn_blocking`]
+///
+/// This keeps async applications from blocking their executor on the hooks, signing programs,
+/// and credential helpers this crate runs.
+///
+/// # Panics
+///
+/// When called outside of a Tokio runtime.
+pub fn spawn_blocking<T, F>(work: F) -> Blocking<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    Blocking {
+        handle: tokio::task::spawn_blocking(work),
+    }
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        match Pin::new(&mut self.handle).poll(cx) {
+            Poll::Ready(Ok(value)) => Poll::Ready(value),
+            // Surface the panic in the task awaiting the work, like a thread being joined
+            Poll::Ready(Err(err)) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Poll::Ready(Err(err)) => panic!("blocking work didn't finish: {err}"),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("runtime can be built")
+        .block_on(future)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spawn_blocking() {
+        let value = block_on(async {
+            spawn_blocking(|| {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                42
+            })
+            .await
+        });
+        assert_eq!(value, 42);
+
+        let panicked = std::panic::catch_unwind(|| {
+            block_on(async { spawn_blocking(|| panic!("boom")).await });
+        });
+        assert!(panicked.is_err());
+    }
+}
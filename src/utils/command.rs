@@ -0,0 +1,177 @@
+/// Run the `git` binary against a [`git2::Repository`], for what `git2` can't do
+///
+/// The command runs with `GIT_DIR` and `GIT_WORK_TREE` set for the repository, from the root of
+/// its working directory (or `$GIT_DIR` when bare), so it sees the same repository no matter the
+/// process's current directory or environment.
+/// ```no_run
+/// let repo = git2::Repository::open(".").unwrap();
+/// let output = git2_ext::utils::GitCommand::new()
+///     .args(["maintenance", "run", "--task=commit-graph"])
+///     .run(&repo)
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct GitCommand {
+    args: Vec<std::ffi::OsString>,
+    envs: Vec<(std::ffi::OsString, std::ffi::OsString)>,
+    stdin: Option<Vec<u8>>,
+    stdio: GitStdio,
+}
+
+impl GitCommand {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<std::ffi::OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<std::ffi::OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    pub fn env(
+        mut self,
+        key: impl AsRef<std::ffi::OsStr>,
+        value: impl AsRef<std::ffi::OsStr>,
+    ) -> Self {
+        self.envs
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Feed `stdin` to the command, which otherwise gets no input
+    pub fn stdin(mut self, stdin: impl Into<Vec<u8>>) -> Self {
+        self.stdin = Some(stdin.into());
+        self
+    }
+
+    /// Where the command's output goes, defaults to [`GitStdio::Capture`]
+    pub fn stdio(mut self, stdio: GitStdio) -> Self {
+        self.stdio = stdio;
+        self
+    }
+
+    /// Run the command, reporting how it exited
+    ///
+    /// This only fails when `git` can't be run.
+    pub fn output(&self, repo: &git2::Repository) -> Result<GitOutput, std::io::Error> {
+        // Leave a missing `git` for the runner to report, which might not need one
+        let git = which::which("git").unwrap_or_else(|_| "git".into());
+
+        let mut cmd = super::ProcessCommand::new(git)
+            .args(&self.args)
+            .env("GIT_DIR", repo.path())
+            .current_dir(repo.workdir().unwrap_or_else(|| repo.path()));
+        if let Some(stdin) = self.stdin.as_deref() {
+            cmd = cmd.stdin(stdin);
+        }
+        cmd = match repo.workdir() {
+            Some(workdir) => cmd.env("GIT_WORK_TREE", workdir),
+            None => cmd.env_remove("GIT_WORK_TREE"),
+        };
+        let stdio = match self.stdio {
+            GitStdio::Capture => super::ProcessStdio::Capture,
+            GitStdio::Tee => super::ProcessStdio::Tee,
+            GitStdio::Inherit => super::ProcessStdio::Inherit,
+        };
+        for (key, value) in &self.envs {
+            cmd = cmd.env(key, value);
+        }
+        super::run_process(cmd.stdout(stdio).stderr(stdio))
+    }
+
+    /// Run the command, failing unless it exits successfully
+    ///
+    /// The error includes what the command wrote to stderr, when captured.
+    pub fn run(&self, repo: &git2::Repository) -> Result<GitOutput, std::io::Error> {
+        let output = self.output(repo)?;
+        if output.success() {
+            return Ok(output);
+        }
+        let args = self
+            .args
+            .iter()
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.trim_end();
+        let message = if stderr.is_empty() {
+            format!("`git {args}` {}", output.status)
+        } else {
+            format!("`git {args}` {}: {stderr}", output.status)
+        };
+        Err(std::io::Error::other(message))
+    }
+}
+
+/// Where the output of a [`GitCommand`] goes
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum GitStdio {
+    /// Capture stdout and stderr into [`GitOutput`]
+    #[default]
+    Capture,
+    /// Capture the output while also copying both stdout and stderr to our stderr as it comes,
+    /// keeping our stdout machine-readable
+    Tee,
+    /// Share our stdout and stderr, capturing nothing
+    Inherit,
+}
+
+/// How a [`GitCommand`] exited
+pub type GitOutput = super::ProcessOutput;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_git_command() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+
+        let repo = git.get_repo()?;
+        let output = GitCommand::new()
+            .args(["rev-parse", "--show-toplevel"])
+            .run(&repo)?;
+        assert_eq!(
+            std::path::Path::new(output.stdout_str().as_ref()).canonicalize()?,
+            git.repo_path.canonicalize()?
+        );
+
+        let output = GitCommand::new()
+            .args(["hash-object", "--stdin"])
+            .stdin("hello\n")
+            .run(&repo)?;
+        assert_eq!(
+            output.stdout_str(),
+            git2::Oid::hash_object(git2::ObjectType::Blob, b"hello\n")?.to_string()
+        );
+
+        let output = GitCommand::new()
+            .args(["rev-parse", "--verify", "no-such-branch"])
+            .output(&repo)?;
+        assert_eq!(output.code(), Some(128));
+        let err = GitCommand::new()
+            .args(["rev-parse", "--verify", "no-such-branch"])
+            .run(&repo)
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("Needed a single revision"),
+            "{err}"
+        );
+
+        Ok(())
+    }
+}
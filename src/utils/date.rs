@@ -0,0 +1,495 @@
+/// Parse a date like `git` does for `--since` and `--date`, relative to now
+///
+/// Along with the strict formats `git` writes, like `1112911993 +0200`, RFC 2822, and
+/// ISO 8601, this understands approximate dates such as `2 weeks ago`, `2.weeks.ago`,
+/// `yesterday`, `last friday`, `noon`, and `Apr 7 2005 10:00pm`.  Like `git`, parts of the
+/// date that aren't given come from the current time, so `2005-04-07` is at the current time of
+/// day.
+///
+/// Returns `None` for text that isn't a date.
+pub fn parse_date(date: &str) -> Option<git2::Time> {
+    if let Some(time) = parse_strict_date(date) {
+        return Some(time);
+    }
+    approxidate(date, now().ok()?)
+}
+
+/// Parse a date in one of `git`s strict formats, like for `GIT_AUTHOR_DATE`
+///
+/// - `git`s internal format, `<seconds since epoch> <offset>`, or `@<seconds since epoch>`
+/// - RFC 2822, like `Thu, 07 Apr 2005 22:13:13 +0200`
+/// - ISO 8601, like `2005-04-07T22:13:13+02:00`
+///
+/// Without an offset, the time is taken as local time, using the current offset.
+pub(crate) fn parse_strict_date(date: &str) -> Option<git2::Time> {
+    let date = date.trim();
+    parse_raw(date)
+        .or_else(|| parse_rfc2822(date))
+        .or_else(|| parse_iso8601(date))
+}
+
+/// The current time with the local offset
+pub(crate) fn now() -> Result<git2::Time, git2::Error> {
+    // `libgit2` already knows how to find the local offset
+    Ok(git2::Signature::now("now", "now")?.when())
+}
+
+fn local_offset() -> i32 {
+    now().map(|now| now.offset_minutes()).unwrap_or(0)
+}
+
+fn parse_raw(date: &str) -> Option<git2::Time> {
+    let (seconds, offset) = match date.split_once(' ') {
+        Some((seconds, offset)) => (seconds, Some(offset.trim())),
+        None => (date, None),
+    };
+    let explicit = seconds.starts_with('@');
+    let seconds = seconds.strip_prefix('@').unwrap_or(seconds);
+    if seconds.is_empty() || !seconds.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    // A bare number is too ambiguous without the `@`
+    if offset.is_none() && !explicit {
+        return None;
+    }
+    let offset = match offset {
+        Some(offset) => parse_offset(offset)?,
+        None => 0,
+    };
+    Some(git2::Time::new(seconds.parse().ok()?, offset))
+}
+
+fn parse_rfc2822(date: &str) -> Option<git2::Time> {
+    // The day of the week is optional and not checked
+    let date = match date.split_once(',') {
+        Some((_, rest)) => rest.trim_start(),
+        None => date,
+    };
+    let mut parts = date.split_whitespace();
+    let day = parts.next()?.parse::<u32>().ok()?;
+    let month = parse_month(parts.next()?)?;
+    let year = parts.next()?.parse::<i64>().ok()?;
+    let (hour, minute, second) = parse_clock(parts.next()?)?;
+    let offset = match parts.next() {
+        Some(offset) => parse_offset(offset)?,
+        None => local_offset(),
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    to_time(year, month, day, (hour, minute, second), offset)
+}
+
+fn parse_iso8601(date: &str) -> Option<git2::Time> {
+    let split = date.find(['T', ' '])?;
+    let (day, rest) = (&date[..split], date[split + 1..].trim_start());
+    let mut day = day.splitn(3, '-');
+    let year = day.next()?.parse::<i64>().ok()?;
+    let month = day.next()?.parse::<u32>().ok()?;
+    let day = day.next()?.parse::<u32>().ok()?;
+
+    let clock_end = rest
+        .find(|c: char| !(c.is_ascii_digit() || c == ':' || c == '.'))
+        .unwrap_or(rest.len());
+    let (clock, offset) = (&rest[..clock_end], rest[clock_end..].trim());
+    // Fractional seconds are dropped, like `git`
+    let clock = clock.split('.').next()?;
+    let (hour, minute, second) = parse_clock(clock)?;
+    let offset = if offset.is_empty() {
+        local_offset()
+    } else {
+        parse_offset(offset)?
+    };
+    to_time(year, month, day, (hour, minute, second), offset)
+}
+
+#[derive(Default)]
+struct ApproxDate {
+    year: Option<i64>,
+    month: Option<u32>,
+    day: Option<u32>,
+    clock: Option<(u32, u32, u32)>,
+    offset: Option<i32>,
+    weekday: Option<u32>,
+    /// Relative shifts, with months kept separately since they vary in length
+    seconds_ago: i64,
+    months_ago: i64,
+}
+
+fn approxidate(date: &str, now: git2::Time) -> Option<git2::Time> {
+    let date = date.trim().to_ascii_lowercase();
+    // `git` allows `.` in place of spaces, like `2.weeks.ago`
+    let tokens = date
+        .split(|c: char| c.is_whitespace() || c == ',' || (c == '.' && !date.contains(':')))
+        .filter(|token| !token.is_empty())
+        .collect::<Vec<_>>();
+    if tokens.is_empty() {
+        return None;
+    }
+
+    let mut approx = ApproxDate::default();
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = tokens[i];
+        let next_unit = tokens.get(i + 1).and_then(|next| parse_unit(next));
+        if let (Some(count), Some(unit)) = (parse_count(token), next_unit) {
+            // Counts are unbounded, so anything that doesn't fit isn't a date
+            match unit {
+                Unit::Seconds(seconds) => {
+                    approx.seconds_ago = approx
+                        .seconds_ago
+                        .checked_add(count.checked_mul(seconds)?)?;
+                }
+                Unit::Months(months) => {
+                    approx.months_ago =
+                        approx.months_ago.checked_add(count.checked_mul(months)?)?;
+                }
+            }
+            i += 2;
+            continue;
+        }
+        match token {
+            "now" | "today" | "ago" | "last" => {}
+            "yesterday" => approx.seconds_ago = approx.seconds_ago.checked_add(86400)?,
+            "midnight" => approx.clock = Some((0, 0, 0)),
+            "noon" => approx.clock = Some((12, 0, 0)),
+            "tea" => approx.clock = Some((17, 0, 0)),
+            "am" | "pm" => approx.clock = Some(meridiem(approx.clock?, token == "pm")?),
+            _ => approx.parse_token(token)?,
+        }
+        i += 1;
+    }
+    approx.resolve(now)
+}
+
+impl ApproxDate {
+    fn parse_token(&mut self, token: &str) -> Option<()> {
+        if let Some(weekday) = parse_weekday(token) {
+            self.weekday = Some(weekday);
+        } else if let Some(month) = parse_month(token) {
+            self.month = Some(month);
+        } else if let Some(offset) = parse_offset(token).or_else(|| (token == "gmt").then_some(0)) {
+            self.offset = Some(offset);
+        } else if let Some(clock) = token
+            .strip_suffix("pm")
+            .or_else(|| token.strip_suffix("am"))
+            .filter(|clock| !clock.is_empty())
+        {
+            let clock = parse_clock(clock).or_else(|| Some((clock.parse().ok()?, 0, 0)))?;
+            self.clock = Some(meridiem(clock, token.ends_with("pm"))?);
+        } else if token.contains(':') {
+            self.clock = Some(parse_clock(token)?);
+        } else if let Some((year, month, day)) = parse_numeric_date(token) {
+            self.year = Some(year);
+            self.month = Some(month);
+            self.day = Some(day);
+        } else if token.bytes().all(|b| b.is_ascii_digit()) {
+            match (token.len(), token.parse::<u32>().ok()?) {
+                (4, year) => self.year = Some(i64::from(year)),
+                (1 | 2, day) if (1..=31).contains(&day) => self.day = Some(day),
+                _ => return None,
+            }
+        } else {
+            // Ordinals like `7th`
+            let day = token
+                .strip_suffix("st")
+                .or_else(|| token.strip_suffix("nd"))
+                .or_else(|| token.strip_suffix("rd"))
+                .or_else(|| token.strip_suffix("th"))?
+                .parse::<u32>()
+                .ok()?;
+            self.day = Some(day);
+        }
+        Some(())
+    }
+
+    fn resolve(self, now: git2::Time) -> Option<git2::Time> {
+        let offset = self.offset.unwrap_or(now.offset_minutes());
+        let local = now.seconds().checked_add(i64::from(offset) * 60)?;
+        let (now_year, now_month, now_day) = civil_from_days(local.div_euclid(86400));
+        let seconds_of_day = local.rem_euclid(86400) as u32;
+        let now_clock = (
+            seconds_of_day / 3600,
+            seconds_of_day / 60 % 60,
+            seconds_of_day % 60,
+        );
+
+        let mut year = self.year.unwrap_or(now_year);
+        let mut month = self.month.unwrap_or(now_month);
+        let month_index = year
+            .checked_mul(12)?
+            .checked_add(i64::from(month) - 1)?
+            .checked_sub(self.months_ago)?;
+        year = month_index.div_euclid(12);
+        month = month_index.rem_euclid(12) as u32 + 1;
+        let day = self.day.unwrap_or(now_day).min(days_in_month(year, month));
+        let time = to_time(year, month, day, self.clock.unwrap_or(now_clock), offset)?;
+
+        let mut seconds = time.seconds().checked_sub(self.seconds_ago)?;
+        if let Some(weekday) = self.weekday {
+            // The most recent such day before today, like `git`
+            let today = weekday_of(seconds.checked_add(i64::from(offset) * 60)?);
+            let days_back = match (today + 7 - weekday) % 7 {
+                0 => 7,
+                days => days,
+            };
+            seconds = seconds.checked_sub(i64::from(days_back) * 86400)?;
+        }
+        Some(git2::Time::new(seconds, offset))
+    }
+}
+
+enum Unit {
+    Seconds(i64),
+    Months(i64),
+}
+
+fn parse_unit(unit: &str) -> Option<Unit> {
+    let unit = unit.strip_suffix('s').unwrap_or(unit);
+    match unit {
+        "second" | "sec" => Some(Unit::Seconds(1)),
+        "minute" | "min" => Some(Unit::Seconds(60)),
+        "hour" => Some(Unit::Seconds(3600)),
+        "day" => Some(Unit::Seconds(86400)),
+        "week" => Some(Unit::Seconds(7 * 86400)),
+        "month" => Some(Unit::Months(1)),
+        "year" => Some(Unit::Months(12)),
+        _ => None,
+    }
+}
+
+fn parse_count(token: &str) -> Option<i64> {
+    const NUMBERS: [&str; 11] = [
+        "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+    ];
+    match token {
+        "a" | "an" | "last" => Some(1),
+        _ => NUMBERS
+            .iter()
+            .position(|name| *name == token)
+            .map(|count| count as i64)
+            .or_else(|| token.parse().ok()),
+    }
+}
+
+fn meridiem((hour, minute, second): (u32, u32, u32), pm: bool) -> Option<(u32, u32, u32)> {
+    if !(1..=12).contains(&hour) {
+        return None;
+    }
+    let hour = match (hour, pm) {
+        (12, false) => 0,
+        (12, true) => 12,
+        (hour, true) => hour + 12,
+        (hour, false) => hour,
+    };
+    Some((hour, minute, second))
+}
+
+/// `YYYY-MM-DD` or `YYYY/MM/DD`
+fn parse_numeric_date(token: &str) -> Option<(i64, u32, u32)> {
+    let mut parts = token.split(['-', '/']);
+    let year = parts.next().filter(|year| year.len() == 4)?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+    parts.next().is_none().then_some((year, month, day))
+}
+
+/// Sunday is 0
+fn parse_weekday(weekday: &str) -> Option<u32> {
+    const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+    if weekday.len() < 3 {
+        return None;
+    }
+    WEEKDAYS
+        .iter()
+        .position(|name| weekday.starts_with(name))
+        .map(|index| index as u32)
+}
+
+/// The weekday of local `seconds`, with Sunday as 0
+fn weekday_of(seconds: i64) -> u32 {
+    // 1970-01-01 was a Thursday
+    (seconds.div_euclid(86400) + 4).rem_euclid(7) as u32
+}
+
+/// `+hhmm`, `+hh:mm`, `+hh`, or `Z`, as minutes east of UTC
+pub(crate) fn parse_offset(offset: &str) -> Option<i32> {
+    if offset.eq_ignore_ascii_case("Z") || offset.eq_ignore_ascii_case("UTC") {
+        return Some(0);
+    }
+    let (sign, digits) = match offset.as_bytes().first()? {
+        b'+' => (1, &offset[1..]),
+        b'-' => (-1, &offset[1..]),
+        _ => return None,
+    };
+    let digits = digits.replace(':', "");
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i32>().ok()?, 0),
+        4 => (
+            digits[..2].parse::<i32>().ok()?,
+            digits[2..].parse::<i32>().ok()?,
+        ),
+        _ => return None,
+    };
+    (minutes < 60).then_some(sign * (hours * 60 + minutes))
+}
+
+fn parse_clock(clock: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = clock.splitn(3, ':');
+    let hour = parts.next()?.parse::<u32>().ok()?;
+    let minute = parts.next()?.parse::<u32>().ok()?;
+    let second = match parts.next() {
+        Some(second) => second.parse::<u32>().ok()?,
+        None => 0,
+    };
+    (hour < 24 && minute < 60 && second <= 60).then_some((hour, minute, second))
+}
+
+pub(crate) fn parse_month(month: &str) -> Option<u32> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let month = month.to_ascii_lowercase();
+    if month.len() < 3 {
+        return None;
+    }
+    MONTHS
+        .iter()
+        .position(|name| month.starts_with(name))
+        .map(|index| index as u32 + 1)
+}
+
+/// The time for a local date and clock in the timezone `offset` minutes east of UTC
+pub(crate) fn to_time(
+    year: i64,
+    month: u32,
+    day: u32,
+    (hour, minute, second): (u32, u32, u32),
+    offset: i32,
+) -> Option<git2::Time> {
+    if !(1..=12).contains(&month) || day == 0 || days_in_month(year, month) < day {
+        return None;
+    }
+    let days = days_from_civil(year, month, day)?;
+    let local = days
+        .checked_mul(86400)?
+        .checked_add(i64::from(hour * 3600 + minute * 60 + second))?;
+    Some(git2::Time::new(
+        local.checked_sub(i64::from(offset) * 60)?,
+        offset,
+    ))
+}
+
+pub(crate) fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar, if they fit
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> Option<i64> {
+    let year = if month <= 2 {
+        year.checked_sub(1)?
+    } else {
+        year
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era.checked_mul(146097)?.checked_add(day_of_era - 719468)
+}
+
+/// The date for days since 1970-01-01 in the proleptic Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+    let month = if month < 10 { month + 3 } else { month - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_strict_date() {
+        let parse = |date: &str| {
+            parse_strict_date(date).map(|time| (time.seconds(), time.offset_minutes()))
+        };
+        assert_eq!(parse("1112911993 +0200"), Some((1112911993, 120)));
+        assert_eq!(parse("@1112911993"), Some((1112911993, 0)));
+        assert_eq!(
+            parse("Thu, 07 Apr 2005 22:13:13 +0200"),
+            Some((1112904793, 120))
+        );
+        assert_eq!(parse("2005-04-07T22:13:13+02:00"), Some((1112904793, 120)));
+        assert_eq!(parse("2005-04-07 20:13:13.5 Z"), Some((1112904793, 0)));
+        assert_eq!(parse("1112911993"), None);
+        assert_eq!(parse("2005-02-30T00:00:00Z"), None);
+        assert_eq!(parse("yesterday"), None);
+    }
+
+    #[test]
+    fn test_approxidate() {
+        // Thu, 07 Apr 2005 22:13:13 +0200
+        let now = git2::Time::new(1112904793, 120);
+        let parse =
+            |date: &str| approxidate(date, now).map(|time| (time.seconds(), time.offset_minutes()));
+        let hour = 3600;
+        let day = 24 * hour;
+        assert_eq!(parse("now"), Some((1112904793, 120)));
+        assert_eq!(parse("2 weeks ago"), Some((1112904793 - 14 * day, 120)));
+        assert_eq!(parse("2.weeks.ago"), Some((1112904793 - 14 * day, 120)));
+        assert_eq!(parse("an hour ago"), Some((1112904793 - hour, 120)));
+        assert_eq!(parse("yesterday"), Some((1112904793 - day, 120)));
+        assert_eq!(parse("yesterday noon"), Some((1112868000 - day, 120)));
+        assert_eq!(parse("midnight"), Some((1112824800, 120)));
+        assert_eq!(parse("last friday"), Some((1112904793 - 6 * day, 120)));
+        assert_eq!(parse("thursday"), Some((1112904793 - 7 * day, 120)));
+        assert_eq!(parse("1 month ago"), Some((1112904793 - 31 * day, 120)));
+        assert_eq!(parse("Apr 1 2005 10:00pm"), Some((1112385600, 120)));
+        assert_eq!(parse("2005-04-01 +0000"), Some((1112386393, 0)));
+        assert_eq!(parse("1st march"), Some((1109707993, 120)));
+        assert_eq!(parse("tomorrowish"), None);
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("99999999999999999 weeks ago"), None);
+        assert_eq!(parse("9223372036854775807 minutes ago"), None);
+        assert_eq!(parse("99999999999999999 years ago"), None);
+        assert_eq!(parse("768614336404564650 years ago"), None);
+    }
+
+    #[test]
+    fn test_date_overflow() {
+        assert_eq!(
+            parse_strict_date("Thu, 07 Apr 9999999999999 22:13:13 +0200"),
+            None
+        );
+        assert_eq!(parse_strict_date("9999999999999-04-07T22:13:13Z"), None);
+        assert_eq!(days_from_civil(i64::MAX, 4, 7), None);
+        assert_eq!(days_from_civil(i64::MIN, 1, 1), None);
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        for days in [-719468, -1, 0, 59, 12879, 20000, 2932896] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), Some(days));
+        }
+    }
+}
@@ -0,0 +1,253 @@
+/// The editor `git` would use for `repo`
+///
+/// In order of precedence: `GIT_EDITOR`, `core.editor`, `VISUAL`, `EDITOR`, then `vi`.  This is
+/// a shell command, not just a path.
+pub fn git_editor(repo: &git2::Repository) -> Result<String, git2::Error> {
+    let config = repo.config()?;
+    git_editor_with(&config, &|name| std::env::var(name).ok())
+}
+
+fn git_editor_with(
+    config: &git2::Config,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<String, git2::Error> {
+    let non_empty = |editor: Option<String>| editor.filter(|editor| !editor.is_empty());
+    if let Some(editor) = non_empty(env("GIT_EDITOR")) {
+        return Ok(editor);
+    }
+    if let Some(editor) = non_empty(crate::config::get_string(config, "core.editor")?) {
+        return Ok(editor);
+    }
+    Ok(non_empty(env("VISUAL"))
+        .or_else(|| non_empty(env("EDITOR")))
+        .unwrap_or_else(|| "vi".to_owned()))
+}
+
+/// Have the user edit a message, like `git commit` does
+///
+/// `initial_contents` is written to `COMMIT_EDITMSG` in `$GIT_DIR`, followed by a hint in
+/// comments, and opened in [`git_editor`].  The result is cleaned up according to
+/// `commit.cleanup`, by default dropping comments and extra blank lines.  An editor of `:`
+/// leaves the message as-is, like `git`.
+pub fn edit_message(
+    repo: &git2::Repository,
+    initial_contents: &str,
+) -> Result<String, git2::Error> {
+    let editor = git_editor(repo)?;
+    edit_message_with(repo, &editor, initial_contents)
+}
+
+fn edit_message_with(
+    repo: &git2::Repository,
+    editor: &str,
+    initial_contents: &str,
+) -> Result<String, git2::Error> {
+    let config = repo.config()?;
+    let comment = comment_char(&config)?;
+    let mode = crate::config::get_string(&config, "commit.cleanup")?;
+    let mode = match mode.as_deref() {
+        None | Some("default") | Some("strip") => Cleanup::Strip,
+        Some("whitespace") => Cleanup::Whitespace,
+        Some("verbatim") => Cleanup::Verbatim,
+        Some("scissors") => Cleanup::Scissors,
+        Some(mode) => {
+            return Err(git2::Error::new(
+                git2::ErrorCode::Invalid,
+                git2::ErrorClass::Config,
+                format!("invalid `commit.cleanup` mode `{mode}`"),
+            ));
+        }
+    };
+
+    let mut contents = initial_contents.to_owned();
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    match mode {
+        Cleanup::Strip => {
+            contents.push_str(&format!(
+                "\n{comment} Please enter the message for your changes. Lines starting\n\
+                 {comment} with '{comment}' will be ignored, and an empty message aborts the commit.\n"
+            ));
+        }
+        Cleanup::Scissors => {
+            contents.push_str(&format!(
+                "{comment} {SCISSORS}\n\
+                 {comment} Do not modify or remove the line above.\n\
+                 {comment} Everything below it will be ignored.\n"
+            ));
+        }
+        Cleanup::Whitespace | Cleanup::Verbatim => {}
+    }
+
+    if editor != ":" {
+        let path = repo.path().join("COMMIT_EDITMSG");
+        std::fs::write(&path, &contents).map_err(|err| os_error(&path, err))?;
+        run_editor(repo, editor, &path)?;
+        contents = std::fs::read(&path)
+            .map(|contents| String::from_utf8_lossy(&contents).into_owned())
+            .map_err(|err| os_error(&path, err))?;
+    }
+
+    Ok(cleanup(&contents, mode, comment))
+}
+
+fn run_editor(
+    repo: &git2::Repository,
+    editor: &str,
+    path: &std::path::Path,
+) -> Result<(), git2::Error> {
+    // Like `git`, the editor is a shell command that gets the file as an argument
+    let status = super::ProcessCommand::shell(editor, [path])
+        .and_then(|cmd| {
+            super::run_process(
+                cmd.current_dir(repo.workdir().unwrap_or_else(|| repo.path()))
+                    .inherit_stdin()
+                    .stdout(super::ProcessStdio::Inherit)
+                    .stderr(super::ProcessStdio::Inherit),
+            )
+        })
+        .map(|output| output.status)
+        .map_err(|err| {
+            git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Os,
+                format!("could not run editor `{editor}`: {err}"),
+            )
+        })?;
+    if !status.success() {
+        return Err(git2::Error::new(
+            git2::ErrorCode::User,
+            git2::ErrorClass::Os,
+            format!("editor `{editor}` {status}"),
+        ));
+    }
+    Ok(())
+}
+
+fn comment_char(config: &git2::Config) -> Result<char, git2::Error> {
+    let comment = crate::config::get_string(config, "core.commentChar")?;
+    // `auto` picks a character not used in the message, which we don't need to do for messages
+    // we write from scratch
+    Ok(comment
+        .filter(|comment| comment != "auto")
+        .and_then(|comment| comment.chars().next())
+        .unwrap_or('#'))
+}
+
+const SCISSORS: &str = "------------------------ >8 ------------------------";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Cleanup {
+    Strip,
+    Whitespace,
+    Verbatim,
+    Scissors,
+}
+
+/// Clean up a message like `git stripspace`
+fn cleanup(message: &str, mode: Cleanup, comment: char) -> String {
+    if mode == Cleanup::Verbatim {
+        return message.to_owned();
+    }
+    let mut cleaned = String::with_capacity(message.len());
+    let mut blank_lines = 0;
+    for line in message.lines() {
+        if mode == Cleanup::Scissors
+            && line.starts_with(comment)
+            && line[comment.len_utf8()..].trim() == SCISSORS
+        {
+            break;
+        }
+        if mode == Cleanup::Strip && line.starts_with(comment) {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank_lines += 1;
+            continue;
+        }
+        // Blank lines are collapsed and dropped from the start
+        if blank_lines > 0 && !cleaned.is_empty() {
+            cleaned.push('\n');
+        }
+        blank_lines = 0;
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+    cleaned
+}
+
+fn os_error(path: &std::path::Path, err: std::io::Error) -> git2::Error {
+    git2::Error::new(
+        git2::ErrorCode::GenericError,
+        git2::ErrorClass::Os,
+        format!("could not access `{}`: {}", path.display(), err),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    #[test]
+    fn test_git_editor() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+        let config = repo.config()?.snapshot()?;
+
+        let env = |name: &str| (name == "EDITOR").then(|| "nano".to_owned());
+        assert_eq!(git_editor_with(&config, &env)?, "nano");
+        git.run(&["config", "core.editor", "code --wait"])?;
+        let config = repo.config()?.snapshot()?;
+        assert_eq!(git_editor_with(&config, &env)?, "code --wait");
+        let env = |name: &str| (name == "GIT_EDITOR").then(|| "emacs".to_owned());
+        assert_eq!(git_editor_with(&config, &env)?, "emacs");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cleanup() {
+        let message = "\n\nSubject  \n\n\n# comment\nBody\n\n";
+        assert_eq!(cleanup(message, Cleanup::Strip, '#'), "Subject\n\nBody\n");
+        assert_eq!(
+            cleanup(message, Cleanup::Whitespace, '#'),
+            "Subject\n\n# comment\nBody\n"
+        );
+        assert_eq!(cleanup(message, Cleanup::Verbatim, '#'), message);
+        assert_eq!(
+            cleanup(
+                &format!("Subject\n# kept\n# {SCISSORS}\ndropped\n"),
+                Cleanup::Scissors,
+                '#'
+            ),
+            "Subject\n# kept\n"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_edit_message() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+
+        // Keep the original, adding a line before the hint
+        let editor = "edit() { sed -n '1p' \"$1\" > \"$1.new\"; printf 'Body  \\n\\n\\n' >> \"$1.new\"; sed -n '2,$p' \"$1\" >> \"$1.new\"; mv \"$1.new\" \"$1\"; }; edit";
+        let edited = edit_message_with(&repo, editor, "Subject")?;
+        assert_eq!(edited, "Subject\nBody\n");
+        let written = std::fs::read_to_string(repo.path().join("COMMIT_EDITMSG"))?;
+        assert!(written.contains("# Please enter the message"), "{written}");
+
+        assert_eq!(edit_message_with(&repo, ":", "Subject\n\n")?, "Subject\n");
+
+        let err = edit_message_with(&repo, "false", "Subject").unwrap_err();
+        assert!(err.message().contains("editor `false`"), "{err}");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,391 @@
+#[cfg(feature = "tokio")]
+mod blocking;
+mod command;
+pub(crate) mod date;
+mod editor;
+mod process;
+
+pub use crate::bytes::bytes2path;
+pub use crate::bytes::bytes2path_lossy;
+pub use crate::bytes::path2bytes;
+pub use crate::bytes::path2bytes_lossy;
+#[cfg(all(test, feature = "tokio"))]
+pub(crate) use blocking::block_on;
+#[cfg(feature = "tokio")]
+pub use blocking::spawn_blocking;
+#[cfg(feature = "tokio")]
+pub use blocking::Blocking;
+pub use command::GitCommand;
+pub use command::GitOutput;
+pub use command::GitStdio;
+pub use date::parse_date;
+pub use editor::edit_message;
+pub use editor::git_editor;
+pub use process::clear_process_runner;
+pub use process::run_process;
+pub use process::set_process_runner;
+pub use process::spawn_process;
+pub use process::ChildProcess;
+pub use process::ProcessCommand;
+pub use process::ProcessOutput;
+pub use process::ProcessRunner;
+pub use process::ProcessStatus;
+pub use process::ProcessStdio;
+pub use process::StdRunner;
+
+/// Path to a shell suitable for running hooks.
+///
+/// On Windows, this is the `sh` that ships with the `git` in `PATH`, whether Git for Windows
+/// (including scoop and chocolatey installs), MSYS2, or Cygwin, see [`git_flavor`].  Callers
+/// that know better can take over with [`set_git_sh_resolver`].
+pub fn git_sh() -> Option<std::path::PathBuf> {
+    if let Some(resolver) = GIT_SH_RESOLVER
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_ref()
+    {
+        return resolver();
+    }
+
+    if cfg!(target_os = "windows") {
+        return DETECTED_GIT.get_or_init(detect_git).sh.clone();
+    }
+
+    which::which("sh").ok()
+}
+
+type GitShResolver = Box<dyn Fn() -> Option<std::path::PathBuf> + Send + Sync>;
+
+static GIT_SH_RESOLVER: std::sync::RwLock<Option<GitShResolver>> = std::sync::RwLock::new(None);
+
+/// Override how [`git_sh`] finds the shell, until [`clear_git_sh_resolver`]
+///
+/// This is process-wide, for applications that ship their own shell or have the user configure
+/// one.
+pub fn set_git_sh_resolver(
+    resolver: impl Fn() -> Option<std::path::PathBuf> + Send + Sync + 'static,
+) {
+    *GIT_SH_RESOLVER
+        .write()
+        .unwrap_or_else(|err| err.into_inner()) = Some(Box::new(resolver));
+}
+
+/// Go back to [`git_sh`]s own detection
+pub fn clear_git_sh_resolver() {
+    *GIT_SH_RESOLVER
+        .write()
+        .unwrap_or_else(|err| err.into_inner()) = None;
+}
+
+/// Which build of `git` is in `PATH`, deciding the shell it runs hooks with
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GitFlavor {
+    /// A `git` native to this platform, like on Linux or macOS
+    Native,
+    /// Git for Windows, with its bundled MSYS2 runtime
+    GitForWindows,
+    /// `git` from an MSYS2 installation
+    Msys2,
+    /// `git` from a Cygwin installation
+    Cygwin,
+    /// `git` inside of WSL, run through interop from Windows
+    Wsl,
+}
+
+/// Detect the [`GitFlavor`] of the `git` in `PATH`
+///
+/// This runs `git --exec-path` once per process.
+pub fn git_flavor() -> GitFlavor {
+    if cfg!(target_os = "windows") {
+        DETECTED_GIT.get_or_init(detect_git).flavor
+    } else {
+        GitFlavor::Native
+    }
+}
+
+struct DetectedGit {
+    flavor: GitFlavor,
+    sh: Option<std::path::PathBuf>,
+}
+
+static DETECTED_GIT: std::sync::OnceLock<DetectedGit> = std::sync::OnceLock::new();
+
+fn detect_git() -> DetectedGit {
+    let git_path = which::which("git").ok();
+    let exec_path = run_process(
+        ProcessCommand::new(git_path.as_deref().unwrap_or("git".as_ref())).arg("--exec-path"),
+    )
+    .ok()
+    .filter(|output| output.status.success())
+    .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned());
+    log::trace!("Detecting git flavor from {git_path:?} with exec-path {exec_path:?}");
+
+    // Shims from scoop, chocolatey, etc. aren't in the installation but `--exec-path` is
+    let mut roots = Vec::new();
+    if let Some(exec_path) = exec_path.as_deref().filter(|path| !path.starts_with('/')) {
+        roots.extend(
+            std::path::Path::new(exec_path)
+                .ancestors()
+                .map(|p| p.to_owned()),
+        );
+    }
+    if let Some(git_path) = &git_path {
+        roots.extend(git_path.ancestors().skip(1).map(|p| p.to_owned()));
+    }
+    let root = roots
+        .iter()
+        .find(|root| find_sh_in(root).is_some())
+        .map(|root| root.as_path());
+
+    let posix_exec_path = exec_path
+        .as_deref()
+        .is_some_and(|path| path.starts_with('/'));
+    let flavor = match root {
+        // A `git` that only knows POSIX paths and has no shell beside it lives in WSL
+        None if posix_exec_path => GitFlavor::Wsl,
+        None => GitFlavor::Native,
+        Some(root) if has_dll(root, "cygwin1.dll") => GitFlavor::Cygwin,
+        // MSYS2's own `git` reports POSIX paths, unlike Git for Windows
+        Some(_) if posix_exec_path => GitFlavor::Msys2,
+        Some(_) => GitFlavor::GitForWindows,
+    };
+
+    let sh = match flavor {
+        GitFlavor::Wsl => which::which("wsl.exe").ok().and_then(|wsl| {
+            // WSL's `bash.exe` lives beside `wsl.exe` in `System32`
+            let bash = wsl.with_file_name("bash.exe");
+            bash.is_file().then_some(bash)
+        }),
+        _ => root
+            .and_then(find_sh_in)
+            .or_else(|| which_excluding_wsl("sh.exe"))
+            .or_else(|| which_excluding_wsl("bash.exe")),
+    };
+    log::trace!("Detected {flavor:?} git with shell {sh:?}");
+    DetectedGit { flavor, sh }
+}
+
+/// The shell within a `git` installation rooted at `root`
+fn find_sh_in(root: &std::path::Path) -> Option<std::path::PathBuf> {
+    [
+        &["usr", "bin", "sh.exe"][..],
+        &["bin", "sh.exe"],
+        &["usr", "bin", "bash.exe"],
+        &["bin", "bash.exe"],
+    ]
+    .into_iter()
+    .map(|parts| {
+        parts
+            .iter()
+            .fold(root.to_owned(), |path, part| path.join(part))
+    })
+    .find(|path| path.is_file())
+}
+
+fn has_dll(root: &std::path::Path, name: &str) -> bool {
+    root.join("bin").join(name).is_file() || root.join("usr").join("bin").join(name).is_file()
+}
+
+/// Look up `name` in `PATH`, skipping WSL's launchers, which would run hooks inside of WSL
+fn which_excluding_wsl(name: &str) -> Option<std::path::PathBuf> {
+    which::which_all(name).ok()?.find(|path| {
+        !path.components().any(|component| {
+            let component = component.as_os_str().to_string_lossy();
+            component.eq_ignore_ascii_case("System32")
+                || component.eq_ignore_ascii_case("WindowsApps")
+        })
+    })
+}
+
+/// The directory shared between all worktrees of `repo`
+///
+/// For linked worktrees, this is the main repository's `$GIT_DIR`.  Like `git`, this respects
+/// `$GIT_COMMON_DIR`.
+pub fn common_dir(repo: &git2::Repository) -> std::path::PathBuf {
+    if let Some(common_dir) = std::env::var_os("GIT_COMMON_DIR") {
+        return std::path::PathBuf::from(common_dir);
+    }
+
+    let git_dir = repo.path();
+    match std::fs::read_to_string(git_dir.join("commondir")) {
+        Ok(common_dir) => git_dir.join(common_dir.trim_end_matches(['\n', '\r'])),
+        Err(_) => git_dir.to_owned(),
+    }
+}
+
+/// Expand a leading `~` or `~user` like a shell, and on Windows `%VAR%` environment variables
+///
+/// `git` does this for paths from its config, like `core.hooksPath`.  What can't be expanded,
+/// like an unknown user, is left as-is.
+pub fn expanduser(path: &std::path::Path) -> std::path::PathBuf {
+    let Some(path) = path.to_str() else {
+        return path.to_owned();
+    };
+    let path = if cfg!(windows) {
+        expand_env_vars(path)
+    } else {
+        path.to_owned()
+    };
+    let Some(rest) = path.strip_prefix('~') else {
+        return std::path::PathBuf::from(path);
+    };
+    let is_separator = |c: char| c == '/' || (cfg!(windows) && c == '\\');
+    let (user, rest) = match rest.find(is_separator) {
+        Some(i) => (&rest[..i], &rest[i + 1..]),
+        None => (rest, ""),
+    };
+    let home = if user.is_empty() {
+        home_dir()
+    } else {
+        user_home_dir(user)
+    };
+    match home {
+        Some(home) if rest.is_empty() => home,
+        Some(home) => home.join(rest),
+        None => std::path::PathBuf::from(path),
+    }
+}
+
+/// The current user's home directory
+pub(crate) fn home_dir() -> Option<std::path::PathBuf> {
+    let home = if cfg!(windows) {
+        std::env::var_os("USERPROFILE")
+    } else {
+        std::env::var_os("HOME")
+    };
+    home.filter(|h| !h.is_empty()).map(std::path::PathBuf::from)
+}
+
+#[cfg(unix)]
+fn user_home_dir(user: &str) -> Option<std::path::PathBuf> {
+    let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().find_map(|line| {
+        let fields = line.split(':').collect::<Vec<_>>();
+        (fields.len() >= 6 && fields[0] == user).then(|| std::path::PathBuf::from(fields[5]))
+    })
+}
+
+#[cfg(not(unix))]
+fn user_home_dir(_user: &str) -> Option<std::path::PathBuf> {
+    None
+}
+
+/// Expand `%VAR%`, leaving unset variables and lone `%`s alone
+fn expand_env_vars(path: &str) -> String {
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(start) = rest.find('%') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after
+            .find('%')
+            .and_then(|end| Some((end, std::env::var(&after[..end]).ok()?)));
+        match value {
+            Some((end, value)) => {
+                expanded.push_str(&value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                expanded.push('%');
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Match `*` and `?` wildcards
+pub(crate) fn wildcard_match(pattern: &str, text: &str, ignore_case: bool) -> bool {
+    let pattern = pattern.as_bytes();
+    let text = text.as_bytes();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c)
+                if *c == b'?'
+                    || *c == text[t]
+                    || (ignore_case && c.eq_ignore_ascii_case(&text[t])) =>
+            {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    p = bp + 1;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expanduser() {
+        let home = home_dir().expect("tests have a home");
+        assert_eq!(expanduser(std::path::Path::new("~")), home);
+        assert_eq!(
+            expanduser(std::path::Path::new("~/.ssh/id_ed25519")),
+            home.join(".ssh/id_ed25519")
+        );
+        assert_eq!(
+            expanduser(std::path::Path::new("relative/~")),
+            std::path::Path::new("relative/~")
+        );
+        assert_eq!(
+            expanduser(std::path::Path::new("~no-such-user-here/hooks")),
+            std::path::Path::new("~no-such-user-here/hooks")
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            expanduser(std::path::Path::new("~root/hooks")),
+            user_home_dir("root").unwrap().join("hooks")
+        );
+    }
+
+    #[test]
+    fn test_expand_env_vars() {
+        let path = std::env::var("PATH").unwrap();
+        assert_eq!(expand_env_vars("%PATH%\\x"), format!("{path}\\x"));
+        assert_eq!(expand_env_vars("100%"), "100%");
+        assert_eq!(
+            expand_env_vars("%NO_SUCH_VARIABLE_HERE%"),
+            "%NO_SUCH_VARIABLE_HERE%"
+        );
+    }
+
+    #[test]
+    fn test_find_sh_in() -> eyre::Result<()> {
+        let root = tempfile::tempdir()?;
+        assert_eq!(find_sh_in(root.path()), None);
+
+        std::fs::create_dir_all(root.path().join("bin"))?;
+        std::fs::write(root.path().join("bin").join("bash.exe"), "")?;
+        assert_eq!(
+            find_sh_in(root.path()),
+            Some(root.path().join("bin").join("bash.exe"))
+        );
+
+        // Git for Windows has both, where `usr\bin` is the real one
+        std::fs::create_dir_all(root.path().join("usr").join("bin"))?;
+        std::fs::write(root.path().join("usr").join("bin").join("sh.exe"), "")?;
+        assert_eq!(
+            find_sh_in(root.path()),
+            Some(root.path().join("usr").join("bin").join("sh.exe"))
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,634 @@
+use std::io::Write as _;
+
+/// Runs the subprocesses of this crate, like hooks, signing programs, and credential helpers
+///
+/// Every subprocess goes through the runner set with [`set_process_runner`], defaulting to
+/// [`StdRunner`].  Embedders that can't spawn processes, like on `wasm32` or in a sandbox, can
+/// take over to stub them out or forward them elsewhere.
+pub trait ProcessRunner: Send + Sync {
+    /// Run `command` to completion
+    ///
+    /// Fails when the command can't be run, was cancelled ([`std::io::ErrorKind::Interrupted`]),
+    /// or timed out ([`std::io::ErrorKind::TimedOut`]), but not when it exits unsuccessfully.
+    fn run(&self, command: ProcessCommand<'_>) -> Result<ProcessOutput, std::io::Error>;
+
+    /// Start `command` for talking back and forth with, over its stdin and stdout
+    ///
+    /// This is for long-running processes, like `filter.<driver>.process`.  Input set with
+    /// [`ProcessCommand::stdin`] and where stdout goes are ignored.  As nothing reads a captured
+    /// stderr, which would block the process once the pipe fills, [`ProcessStdio::Capture`] and
+    /// [`ProcessStdio::Tee`] are treated as [`ProcessStdio::Inherit`].  Runners that can't keep a
+    /// process around report [`std::io::ErrorKind::Unsupported`], the default.
+    fn spawn(&self, command: ProcessCommand<'_>) -> Result<Box<dyn ChildProcess>, std::io::Error> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("can't keep {:?} running", command.get_program()),
+        ))
+    }
+}
+
+/// A process started by [`ProcessRunner::spawn`]
+///
+/// Only stdin and stdout are piped; stderr is never captured.
+pub trait ChildProcess: Send {
+    fn stdin(&mut self) -> &mut dyn std::io::Write;
+
+    fn stdout(&mut self) -> &mut dyn std::io::Read;
+
+    /// Close stdin and wait for the process to exit
+    fn wait(self: Box<Self>) -> Result<ProcessStatus, std::io::Error>;
+}
+
+impl<F> ProcessRunner for F
+where
+    F: Fn(ProcessCommand<'_>) -> Result<ProcessOutput, std::io::Error> + Send + Sync,
+{
+    fn run(&self, command: ProcessCommand<'_>) -> Result<ProcessOutput, std::io::Error> {
+        self(command)
+    }
+}
+
+type SharedRunner = std::sync::Arc<dyn ProcessRunner>;
+
+static PROCESS_RUNNER: std::sync::RwLock<Option<SharedRunner>> = std::sync::RwLock::new(None);
+
+/// Run subprocesses with `runner`, until [`clear_process_runner`]
+///
+/// This is process-wide, like [`super::set_git_sh_resolver`].  Commands run through a shell
+/// still look it up with [`super::git_sh`] first.
+pub fn set_process_runner(runner: impl ProcessRunner + 'static) {
+    *PROCESS_RUNNER
+        .write()
+        .unwrap_or_else(|err| err.into_inner()) = Some(std::sync::Arc::new(runner));
+}
+
+/// Go back to running subprocesses with [`StdRunner`]
+pub fn clear_process_runner() {
+    *PROCESS_RUNNER
+        .write()
+        .unwrap_or_else(|err| err.into_inner()) = None;
+}
+
+/// Run `command` with the current [`ProcessRunner`]
+pub fn run_process(command: ProcessCommand<'_>) -> Result<ProcessOutput, std::io::Error> {
+    let runner = PROCESS_RUNNER
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone();
+    match runner {
+        Some(runner) => runner.run(command),
+        None => StdRunner.run(command),
+    }
+}
+
+/// Start `command` with the current [`ProcessRunner`], see [`ProcessRunner::spawn`]
+///
+/// stderr can't be captured, [`ProcessStdio::Capture`] and [`ProcessStdio::Tee`] inherit ours.
+pub fn spawn_process(command: ProcessCommand<'_>) -> Result<Box<dyn ChildProcess>, std::io::Error> {
+    let runner = PROCESS_RUNNER
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone();
+    match runner {
+        Some(runner) => runner.spawn(command),
+        None => StdRunner.spawn(command),
+    }
+}
+
+/// A subprocess to run, see [`ProcessRunner`]
+pub struct ProcessCommand<'a> {
+    program: std::ffi::OsString,
+    args: Vec<std::ffi::OsString>,
+    envs: Vec<(std::ffi::OsString, Option<std::ffi::OsString>)>,
+    current_dir: Option<std::path::PathBuf>,
+    stdin: ProcessStdin<'a>,
+    stdout: ProcessStdio,
+    stderr: ProcessStdio,
+    timeout: Option<std::time::Duration>,
+    cancel: Option<crate::hooks::CancelHandle>,
+}
+
+enum ProcessStdin<'a> {
+    Null,
+    Inherit,
+    Read(Box<dyn std::io::Read + Send + 'a>),
+}
+
+impl<'a> ProcessCommand<'a> {
+    /// Run `program` with no input, capturing its output
+    pub fn new(program: impl AsRef<std::ffi::OsStr>) -> Self {
+        Self {
+            program: program.as_ref().to_owned(),
+            args: Vec::new(),
+            envs: Vec::new(),
+            current_dir: None,
+            stdin: ProcessStdin::Null,
+            stdout: ProcessStdio::Capture,
+            stderr: ProcessStdio::Capture,
+            timeout: None,
+            cancel: None,
+        }
+    }
+
+    /// Run `command` with the shell from [`super::git_sh`], like `git` does for configured
+    /// commands
+    ///
+    /// `args` are passed as `"$@"`, rather than quoted into the command.
+    pub fn shell<I>(command: &str, args: I) -> Result<Self, std::io::Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<std::ffi::OsStr>,
+    {
+        let sh_path = super::git_sh().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("No `sh` for running `{command}`"),
+            )
+        })?;
+        // "$@" expands "$1" "$2" "$3" ... but we also must specify $0.
+        Ok(Self::new(sh_path)
+            .arg("-c")
+            .arg(format!("{command} \"$@\""))
+            .arg(command)
+            .args(args))
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<std::ffi::OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    pub fn args<I>(mut self, args: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<std::ffi::OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    pub fn env(
+        mut self,
+        key: impl AsRef<std::ffi::OsStr>,
+        value: impl AsRef<std::ffi::OsStr>,
+    ) -> Self {
+        self.envs
+            .push((key.as_ref().to_owned(), Some(value.as_ref().to_owned())));
+        self
+    }
+
+    pub fn env_remove(mut self, key: impl AsRef<std::ffi::OsStr>) -> Self {
+        self.envs.push((key.as_ref().to_owned(), None));
+        self
+    }
+
+    pub fn current_dir(mut self, dir: impl Into<std::path::PathBuf>) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Stream `stdin` into the command
+    pub fn stdin(mut self, stdin: impl std::io::Read + Send + 'a) -> Self {
+        self.stdin = ProcessStdin::Read(Box::new(stdin));
+        self
+    }
+
+    /// Share our stdin, like for an editor
+    pub fn inherit_stdin(mut self) -> Self {
+        self.stdin = ProcessStdin::Inherit;
+        self
+    }
+
+    /// Where stdout goes, defaults to [`ProcessStdio::Capture`]
+    pub fn stdout(mut self, stdout: ProcessStdio) -> Self {
+        self.stdout = stdout;
+        self
+    }
+
+    /// Where stderr goes, defaults to [`ProcessStdio::Capture`]
+    pub fn stderr(mut self, stderr: ProcessStdio) -> Self {
+        self.stderr = stderr;
+        self
+    }
+
+    /// Kill the command if it runs longer than `timeout`
+    ///
+    /// On Unix, the command then runs in its own process group so the processes it started are
+    /// killed with it.
+    pub fn timeout(mut self, timeout: Option<std::time::Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Kill the command when `cancel` is triggered
+    ///
+    /// Like with [`ProcessCommand::timeout`], the processes it started are killed with it.
+    pub fn cancel(mut self, cancel: Option<crate::hooks::CancelHandle>) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    pub fn get_program(&self) -> &std::ffi::OsStr {
+        &self.program
+    }
+
+    pub fn get_args(&self) -> impl Iterator<Item = &std::ffi::OsStr> {
+        self.args.iter().map(|arg| arg.as_os_str())
+    }
+
+    /// Variables to set, or to remove when `None`, in order
+    pub fn get_envs(&self) -> impl Iterator<Item = (&std::ffi::OsStr, Option<&std::ffi::OsStr>)> {
+        self.envs
+            .iter()
+            .map(|(key, value)| (key.as_os_str(), value.as_deref()))
+    }
+
+    pub fn get_current_dir(&self) -> Option<&std::path::Path> {
+        self.current_dir.as_deref()
+    }
+
+    /// Whether the command shares our stdin
+    pub fn inherits_stdin(&self) -> bool {
+        matches!(self.stdin, ProcessStdin::Inherit)
+    }
+
+    /// The input to stream into the command, if any
+    pub fn take_stdin(&mut self) -> Option<Box<dyn std::io::Read + Send + 'a>> {
+        match std::mem::replace(&mut self.stdin, ProcessStdin::Null) {
+            ProcessStdin::Read(stdin) => Some(stdin),
+            stdin => {
+                self.stdin = stdin;
+                None
+            }
+        }
+    }
+
+    pub fn get_stdout(&self) -> ProcessStdio {
+        self.stdout
+    }
+
+    pub fn get_stderr(&self) -> ProcessStdio {
+        self.stderr
+    }
+
+    pub fn get_timeout(&self) -> Option<std::time::Duration> {
+        self.timeout
+    }
+
+    pub fn get_cancel(&self) -> Option<&crate::hooks::CancelHandle> {
+        self.cancel.as_ref()
+    }
+}
+
+impl std::fmt::Debug for ProcessCommand<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProcessCommand")
+            .field("program", &self.program)
+            .field("args", &self.args)
+            .field("envs", &self.envs)
+            .field("current_dir", &self.current_dir)
+            .field("stdout", &self.stdout)
+            .field("stderr", &self.stderr)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Where the output of a [`ProcessCommand`] goes
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ProcessStdio {
+    /// Capture into [`ProcessOutput`]
+    #[default]
+    Capture,
+    /// Capture while also copying to our stderr as it comes
+    Tee,
+    /// Send to our stderr, like `git` does with the stdout of hooks
+    Stderr,
+    /// Share ours
+    Inherit,
+    /// Discard it
+    Null,
+}
+
+/// How a [`ProcessCommand`] exited
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProcessOutput {
+    pub status: ProcessStatus,
+    /// Empty unless captured
+    pub stdout: Vec<u8>,
+    /// Empty unless captured
+    pub stderr: Vec<u8>,
+}
+
+impl ProcessOutput {
+    pub fn success(&self) -> bool {
+        self.status.success()
+    }
+
+    /// The exit code, `None` when killed by a signal
+    pub fn code(&self) -> Option<i32> {
+        self.status.code()
+    }
+
+    /// stdout as text, with the trailing newline removed
+    pub fn stdout_str(&self) -> std::borrow::Cow<'_, str> {
+        match String::from_utf8_lossy(&self.stdout) {
+            std::borrow::Cow::Borrowed(stdout) => {
+                std::borrow::Cow::Borrowed(stdout.trim_end_matches(['\n', '\r']))
+            }
+            std::borrow::Cow::Owned(stdout) => {
+                std::borrow::Cow::Owned(stdout.trim_end_matches(['\n', '\r']).to_owned())
+            }
+        }
+    }
+}
+
+/// The exit status of a [`ProcessCommand`]
+///
+/// Unlike [`std::process::ExitStatus`], this can be made up by a [`ProcessRunner`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ProcessStatus(Option<i32>);
+
+impl ProcessStatus {
+    /// The process exited with `code`
+    pub fn exited(code: i32) -> Self {
+        Self(Some(code))
+    }
+
+    /// The process was killed by a signal
+    pub fn killed() -> Self {
+        Self(None)
+    }
+
+    pub fn success(&self) -> bool {
+        self.0 == Some(0)
+    }
+
+    /// The exit code, `None` when killed by a signal
+    pub fn code(&self) -> Option<i32> {
+        self.0
+    }
+}
+
+impl From<std::process::ExitStatus> for ProcessStatus {
+    fn from(status: std::process::ExitStatus) -> Self {
+        Self(status.code())
+    }
+}
+
+impl std::fmt::Display for ProcessStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(code) => write!(f, "exit status: {code}"),
+            None => f.write_str("killed by a signal"),
+        }
+    }
+}
+
+/// Run commands with [`std::process::Command`]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StdRunner;
+
+impl StdRunner {
+    fn command(command: &ProcessCommand<'_>) -> std::process::Command {
+        let mut cmd = std::process::Command::new(&command.program);
+        cmd.args(&command.args);
+        for (key, value) in &command.envs {
+            match value {
+                Some(value) => cmd.env(key, value),
+                None => cmd.env_remove(key),
+            };
+        }
+        if let Some(dir) = &command.current_dir {
+            cmd.current_dir(dir);
+        }
+        cmd
+    }
+}
+
+impl ProcessRunner for StdRunner {
+    fn run(&self, mut command: ProcessCommand<'_>) -> Result<ProcessOutput, std::io::Error> {
+        let mut cmd = Self::command(&command);
+        let stdin = command.take_stdin();
+        cmd.stdin(match (&stdin, command.inherits_stdin()) {
+            (Some(_), _) => std::process::Stdio::piped(),
+            (None, true) => std::process::Stdio::inherit(),
+            (None, false) => std::process::Stdio::null(),
+        });
+        cmd.stdout(to_stdio(command.stdout));
+        cmd.stderr(to_stdio(command.stderr));
+        let killable = command.timeout.is_some() || command.cancel.is_some();
+        #[cfg(unix)]
+        if killable {
+            // So the processes it starts, which share our pipes, can be killed with it
+            std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+        }
+        log::trace!("Running {cmd:?}");
+
+        let mut process = cmd.spawn()?;
+        std::thread::scope(|scope| {
+            let writer = stdin.map(|mut stdin| {
+                let mut pipe = process.stdin.take().expect("stdin is piped");
+                // Commands are free to not read their input, so a closed pipe isn't an error
+                scope.spawn(move || match std::io::copy(&mut stdin, &mut pipe) {
+                    Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+                    result => result.map(|_| ()),
+                })
+            });
+            let stdout_tee = command.stdout == ProcessStdio::Tee;
+            let stdout = process
+                .stdout
+                .take()
+                .map(|pipe| scope.spawn(move || read_pipe(pipe, stdout_tee)));
+            let stderr_tee = command.stderr == ProcessStdio::Tee;
+            let stderr = process
+                .stderr
+                .take()
+                .map(|pipe| scope.spawn(move || read_pipe(pipe, stderr_tee)));
+            let status = wait_timeout(
+                &mut process,
+                killable,
+                command.timeout,
+                command.cancel.as_ref(),
+            )?;
+            if let Some(writer) = writer {
+                writer.join().expect("stdin writer doesn't panic")?;
+            }
+            let stdout = stdout
+                .map(|reader| reader.join().expect("stdout reader doesn't panic"))
+                .transpose()?
+                .unwrap_or_default();
+            let stderr = stderr
+                .map(|reader| reader.join().expect("stderr reader doesn't panic"))
+                .transpose()?
+                .unwrap_or_default();
+            Ok(ProcessOutput {
+                status: status.into(),
+                stdout,
+                stderr,
+            })
+        })
+    }
+
+    fn spawn(&self, command: ProcessCommand<'_>) -> Result<Box<dyn ChildProcess>, std::io::Error> {
+        let mut cmd = Self::command(&command);
+        let stderr = match command.stderr {
+            // Nothing would drain the pipe
+            ProcessStdio::Capture | ProcessStdio::Tee => ProcessStdio::Inherit,
+            stderr => stderr,
+        };
+        cmd.stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(to_stdio(stderr));
+        log::trace!("Starting {cmd:?}");
+        let mut child = cmd.spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        Ok(Box::new(StdChild {
+            child,
+            stdin,
+            stdout,
+        }))
+    }
+}
+
+struct StdChild {
+    child: std::process::Child,
+    stdin: std::process::ChildStdin,
+    stdout: std::process::ChildStdout,
+}
+
+impl ChildProcess for StdChild {
+    fn stdin(&mut self) -> &mut dyn std::io::Write {
+        &mut self.stdin
+    }
+
+    fn stdout(&mut self) -> &mut dyn std::io::Read {
+        &mut self.stdout
+    }
+
+    fn wait(self: Box<Self>) -> Result<ProcessStatus, std::io::Error> {
+        let Self {
+            mut child, stdin, ..
+        } = *self;
+        drop(stdin);
+        child.wait().map(Into::into)
+    }
+}
+
+fn to_stdio(stdio: ProcessStdio) -> std::process::Stdio {
+    match stdio {
+        ProcessStdio::Capture | ProcessStdio::Tee => std::process::Stdio::piped(),
+        ProcessStdio::Stderr => std::io::stderr().into(),
+        ProcessStdio::Inherit => std::process::Stdio::inherit(),
+        ProcessStdio::Null => std::process::Stdio::null(),
+    }
+}
+
+/// Wait on `process`, killing it if it outlives `timeout` or is cancelled
+///
+/// On Unix, `process` leads its own process group, which is killed as a whole.
+fn wait_timeout(
+    process: &mut std::process::Child,
+    killable: bool,
+    timeout: Option<std::time::Duration>,
+    cancel: Option<&crate::hooks::CancelHandle>,
+) -> Result<std::process::ExitStatus, std::io::Error> {
+    if !killable {
+        return process.wait();
+    }
+
+    let start = std::time::Instant::now();
+    loop {
+        if let Some(exit) = process.try_wait()? {
+            return Ok(exit);
+        }
+        let timed_out = timeout
+            .map(|timeout| timeout <= start.elapsed())
+            .unwrap_or(false);
+        let cancelled = cancel.map(|c| c.is_cancelled()).unwrap_or(false);
+        if timed_out || cancelled {
+            kill_group(process)?;
+            process.wait()?;
+            return Err(if cancelled {
+                std::io::Error::new(std::io::ErrorKind::Interrupted, "cancelled")
+            } else {
+                std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out")
+            });
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
+
+/// Kill `process` and, on Unix, everything else in its process group
+fn kill_group(process: &mut std::process::Child) -> Result<(), std::io::Error> {
+    #[cfg(unix)]
+    {
+        let pid = libc::pid_t::try_from(process.id())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: `kill` only sends a signal.  `process` isn't reaped yet, so its id still names
+        // its group.
+        if unsafe { libc::kill(-pid, libc::SIGKILL) } == 0 {
+            return Ok(());
+        }
+        log::trace!(
+            "Failed to kill process group {pid}: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    process.kill()
+}
+
+fn read_pipe(mut pipe: impl std::io::Read, tee: bool) -> Result<Vec<u8>, std::io::Error> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 8192];
+    loop {
+        let read = match pipe.read(&mut chunk) {
+            Ok(0) => return Ok(buffer),
+            Ok(read) => read,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        buffer.extend_from_slice(&chunk[..read]);
+        if tee {
+            // Losing the echo isn't worth failing the command over
+            let _ = std::io::stderr().write_all(&chunk[..read]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops::Sign as _;
+
+    #[test]
+    fn test_process_runner() -> eyre::Result<()> {
+        // Other tests run concurrently, so only a program none of them use is stubbed out
+        set_process_runner(|mut command: ProcessCommand<'_>| {
+            if command.get_program() != "stub-gpg" {
+                return StdRunner.run(command);
+            }
+            let args = command.get_args().collect::<Vec<_>>();
+            assert_eq!(args, ["--status-fd=2", "-bsau", "key"]);
+            let mut buffer = Vec::new();
+            command
+                .take_stdin()
+                .expect("buffer is piped")
+                .read_to_end(&mut buffer)?;
+            Ok(ProcessOutput {
+                status: ProcessStatus::exited(0),
+                stdout: [b"signed ".as_slice(), &buffer, b"\r\n"].concat(),
+                stderr: b"\n[GNUPG:] SIG_CREATED D\n".to_vec(),
+            })
+        });
+        let signature =
+            crate::ops::GpgSign::new("stub-gpg".to_owned(), "key".to_owned()).sign("buffer");
+        clear_process_runner();
+        assert_eq!(signature?, "signed buffer\n");
+
+        Ok(())
+    }
+}
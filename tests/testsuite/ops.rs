@@ -37,6 +37,62 @@ fn cherry_pick_clean() {
     temp.close().unwrap();
 }
 
+#[test]
+fn cherry_pick_stack_clean() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::TodoList::load(std::path::Path::new(
+        "tests/testsuite/fixtures/branches.yml",
+    ))
+    .unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+
+    {
+        let base = repo
+            .find_branch("off_master", git2::BranchType::Local)
+            .unwrap();
+        let base_id = base.get().target().unwrap();
+        let feature2 = repo
+            .find_branch("feature2", git2::BranchType::Local)
+            .unwrap();
+        let feature2_id = feature2.get().target().unwrap();
+        let mut revwalk = repo.revwalk().unwrap();
+        revwalk.push(feature2_id).unwrap();
+        revwalk.hide_ref("refs/heads/base").unwrap();
+        let mut cherry_ids = revwalk.collect::<Result<Vec<_>, _>>().unwrap();
+        cherry_ids.reverse();
+
+        let tips = git2_ext::ops::cherry_pick_stack(&repo, base_id, &cherry_ids, None).unwrap();
+
+        let mut expected_tip_id = base_id;
+        for cherry_id in cherry_ids.iter().copied() {
+            expected_tip_id =
+                git2_ext::ops::cherry_pick(&repo, expected_tip_id, cherry_id, None).unwrap();
+        }
+        assert_eq!(tips.len(), cherry_ids.len());
+        assert_eq!(tips.last().copied(), Some(expected_tip_id));
+
+        // Picking onto the stack's own base keeps the commits
+        let tips = git2_ext::ops::cherry_pick_stack(
+            &repo,
+            repo.revparse_single("base").unwrap().id(),
+            &cherry_ids,
+            None,
+        )
+        .unwrap();
+        assert_eq!(tips, cherry_ids);
+
+        // Picking onto the stack itself drops everything
+        let tips =
+            git2_ext::ops::cherry_pick_stack(&repo, feature2_id, &cherry_ids[1..], None).unwrap();
+        assert_eq!(tips, vec![feature2_id; cherry_ids.len() - 1]);
+        assert!(!git2_ext::ops::is_dirty(&repo));
+    }
+
+    temp.close().unwrap();
+}
+
 #[test]
 fn cherry_pick_conflict() {
     let temp = assert_fs::TempDir::new().unwrap();
@@ -125,3 +181,55 @@ fn reword() {
 
     temp.close().unwrap();
 }
+
+#[test]
+#[cfg(unix)]
+fn hooked_cherry_pick_range() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::TodoList::load(std::path::Path::new(
+        "tests/testsuite/fixtures/branches.yml",
+    ))
+    .unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let hook_path = repo.path().join("hooks").join("post-rewrite");
+    std::fs::create_dir_all(hook_path.parent().unwrap()).unwrap();
+    std::fs::write(&hook_path, "#!/bin/sh\ncat > .git/post-rewrite.log\n").unwrap();
+    std::fs::set_permissions(&hook_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    {
+        let base = repo
+            .find_branch("off_master", git2::BranchType::Local)
+            .unwrap();
+        let base_id = base.get().target().unwrap();
+        let feature2 = repo
+            .find_branch("feature2", git2::BranchType::Local)
+            .unwrap();
+        let feature2_id = feature2.get().target().unwrap();
+        let mut revwalk = repo.revwalk().unwrap();
+        revwalk.push(feature2_id).unwrap();
+        revwalk.hide_ref("refs/heads/base").unwrap();
+        let mut cherry_ids = revwalk.collect::<Result<Vec<_>, _>>().unwrap();
+        cherry_ids.reverse();
+
+        let hooks = git2_ext::hooks::Hooks::with_repo(&repo).unwrap();
+        let mut ops = git2_ext::ops::HookedOps::new(&repo, &hooks);
+        let tip_id = ops.cherry_pick_range(base_id, &cherry_ids).unwrap();
+        ops.update_ref("refs/heads/rebased", tip_id, "cherry-pick")
+            .unwrap();
+        let rewritten = ops.rewritten().to_vec();
+        ops.finish();
+
+        assert_eq!(rewritten.len(), 4);
+        assert_eq!(rewritten[3], (feature2_id, tip_id));
+        let log = std::fs::read_to_string(repo.path().join("post-rewrite.log")).unwrap();
+        assert_eq!(log.lines().count(), 4);
+        assert_eq!(repo.refname_to_id("refs/heads/rebased").unwrap(), tip_id);
+        assert!(!git2_ext::ops::is_dirty(&repo));
+    }
+
+    temp.close().unwrap();
+}